@@ -7,4 +7,4 @@ do
 done
 echo "Postgres is up - executing command"
 
-./hermes
+./hermes "$@"
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE events;
+ALTER TABLE feeds DROP COLUMN leased_until;
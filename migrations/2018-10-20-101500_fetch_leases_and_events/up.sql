@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN leased_until TIMESTAMPTZ;
+
+CREATE TABLE events (
+  id                  SERIAL PRIMARY KEY,
+  feed_id             INTEGER REFERENCES feeds NOT NULL,
+  item_ids            INTEGER[] NOT NULL,
+  user_ids            INTEGER[] NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+ALTER TABLE subscribed_items ADD COLUMN starred BOOLEAN NOT NULL DEFAULT false;
+
+DROP VIEW subscribed_items_view;
+CREATE VIEW subscribed_items_view AS
+  SELECT i.*, s.id as subscribed_item_id, s.user_id, s.seen, s.starred
+  FROM items i
+  INNER JOIN subscribed_items s
+  ON i.id = s.item_id;
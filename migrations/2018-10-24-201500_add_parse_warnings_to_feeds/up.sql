@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN parse_warnings TEXT;
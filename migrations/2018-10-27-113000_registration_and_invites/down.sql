@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE invites;
+ALTER TABLE users DROP COLUMN approved;
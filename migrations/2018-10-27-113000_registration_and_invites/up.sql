@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE users ADD COLUMN approved BOOLEAN NOT NULL DEFAULT true;
+
+CREATE TABLE invites (
+  id                  SERIAL PRIMARY KEY,
+  code                VARCHAR UNIQUE NOT NULL,
+  created_by          INTEGER REFERENCES users NOT NULL,
+  used_by             INTEGER REFERENCES users,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
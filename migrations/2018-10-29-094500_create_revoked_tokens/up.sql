@@ -0,0 +1,6 @@
+-- Your SQL goes here
+CREATE TABLE revoked_tokens (
+  id                  SERIAL PRIMARY KEY,
+  jti                 VARCHAR UNIQUE NOT NULL,
+  expires_at          TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+-- random bytes shaped as a uuid, gen_random_uuid() would need pgcrypto
+ALTER TABLE items ADD COLUMN uuid VARCHAR UNIQUE NOT NULL
+  DEFAULT md5(random()::text || clock_timestamp()::text)::uuid::text;
+
+DROP VIEW subscribed_items_view;
+CREATE VIEW subscribed_items_view AS
+  SELECT i.*, s.id as subscribed_item_id, s.user_id, s.seen, s.starred
+  FROM items i
+  INNER JOIN subscribed_items s
+  ON i.id = s.item_id;
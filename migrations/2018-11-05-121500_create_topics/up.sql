@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE topics (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  name                VARCHAR NOT NULL,
+  keywords            TEXT[] NOT NULL,
+  notify              BOOLEAN NOT NULL DEFAULT false,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE topic_items (
+  id                  SERIAL PRIMARY KEY,
+  topic_id            INTEGER REFERENCES topics ON DELETE CASCADE NOT NULL,
+  item_id             INTEGER REFERENCES items NOT NULL,
+  matched_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+  UNIQUE(topic_id, item_id)
+);
+
+ALTER TABLE events ADD COLUMN topic_id INTEGER REFERENCES topics ON DELETE CASCADE;
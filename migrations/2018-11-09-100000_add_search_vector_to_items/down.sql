@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER set_search_vector ON items;
+DROP FUNCTION items_set_search_vector();
+DROP FUNCTION items_search_vector(items);
+ALTER TABLE items DROP COLUMN search_vector;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+ALTER TABLE items ADD COLUMN search_vector TSVECTOR;
+
+CREATE OR REPLACE FUNCTION items_search_vector(i items) RETURNS TSVECTOR AS $$
+BEGIN
+    RETURN setweight(to_tsvector('english', coalesce(i.title, '')), 'A') ||
+           setweight(to_tsvector('english', coalesce(i.summary, '')), 'B') ||
+           setweight(to_tsvector('english', coalesce(i.content, '')), 'C');
+END;
+$$ LANGUAGE plpgsql IMMUTABLE;
+
+CREATE OR REPLACE FUNCTION items_set_search_vector() RETURNS trigger AS $$
+BEGIN
+    NEW.search_vector := items_search_vector(NEW);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+UPDATE items SET search_vector = items_search_vector(items);
+
+CREATE TRIGGER set_search_vector BEFORE INSERT OR UPDATE ON items
+    FOR EACH ROW EXECUTE PROCEDURE items_set_search_vector();
+
+CREATE INDEX items_search_vector_idx ON items USING GIN (search_vector);
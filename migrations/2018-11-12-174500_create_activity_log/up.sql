@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE activity_log (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  action              VARCHAR NOT NULL,
+  detail              TEXT,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX activity_log_user_id_idx ON activity_log (user_id, created_at);
@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE subscription_tags (
+  id                  SERIAL PRIMARY KEY,
+  subscribed_feed_id  INTEGER REFERENCES subscribed_feeds ON DELETE CASCADE NOT NULL,
+  tag                 VARCHAR NOT NULL,
+  UNIQUE(subscribed_feed_id, tag)
+);
+
+CREATE INDEX subscription_tags_tag_idx ON subscription_tags (tag);
@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE filters (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  feed_id             INTEGER REFERENCES feeds ON DELETE CASCADE,
+  pattern             VARCHAR NOT NULL,
+  match_field         VARCHAR NOT NULL,
+  action              VARCHAR NOT NULL,
+  enabled             BOOLEAN NOT NULL DEFAULT false,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN fetch_full_content BOOLEAN NOT NULL DEFAULT false;
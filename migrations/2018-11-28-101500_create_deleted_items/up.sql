@@ -0,0 +1,13 @@
+-- Your SQL goes here
+-- tombstones for sync clients; rows without a user are purged for everyone
+CREATE TABLE deleted_items (
+  id                  SERIAL PRIMARY KEY,
+  item_id             INTEGER NOT NULL,
+  feed_id             INTEGER REFERENCES feeds NOT NULL,
+  guid                VARCHAR NOT NULL,
+  user_id             INTEGER REFERENCES users,
+  deleted_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX deleted_items_feed_id_idx ON deleted_items (feed_id, guid);
+CREATE INDEX deleted_items_deleted_at_idx ON deleted_items (deleted_at);
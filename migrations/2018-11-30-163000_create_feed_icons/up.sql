@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE feed_icons (
+  feed_id             INTEGER PRIMARY KEY REFERENCES feeds,
+  content_type        VARCHAR NOT NULL,
+  data                BYTEA NOT NULL,
+  fetched_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE enclosures (
+  id                  SERIAL PRIMARY KEY,
+  item_id             INTEGER REFERENCES items NOT NULL,
+  url                 VARCHAR NOT NULL,
+  mime_type           VARCHAR,
+  length              BIGINT
+);
+
+CREATE INDEX enclosures_item_id_idx ON enclosures (item_id);
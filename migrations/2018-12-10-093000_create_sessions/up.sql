@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE sessions (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  jti                 VARCHAR NOT NULL UNIQUE,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+  expires_at          TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+CREATE TABLE content_retries (
+  item_id             INTEGER PRIMARY KEY REFERENCES items,
+  attempts            INTEGER NOT NULL,
+  last_error          TEXT NOT NULL,
+  next_attempt_at     TIMESTAMPTZ,
+  updated_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX content_retries_next_attempt_at_idx ON content_retries (next_attempt_at);
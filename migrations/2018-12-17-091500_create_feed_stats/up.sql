@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE feed_stats (
+  feed_id             INTEGER REFERENCES feeds NOT NULL,
+  day                 DATE NOT NULL,
+  items               INTEGER NOT NULL DEFAULT 0,
+  bytes               BIGINT NOT NULL DEFAULT 0,
+  fetches             INTEGER NOT NULL DEFAULT 0,
+  failures            INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (feed_id, day)
+);
+
+-- items are counted on the day they were published; fetches start from zero
+INSERT INTO feed_stats (feed_id, day, items)
+SELECT feed_id, (coalesce(published_at, updated_at) AT TIME ZONE 'UTC')::date, count(*)
+FROM items
+WHERE coalesce(published_at, updated_at) IS NOT NULL
+GROUP BY 1, 2;
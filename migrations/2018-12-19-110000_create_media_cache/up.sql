@@ -0,0 +1,19 @@
+-- Your SQL goes here
+CREATE TABLE media_blobs (
+  hash                VARCHAR PRIMARY KEY,
+  content_type        VARCHAR NOT NULL,
+  data                BYTEA NOT NULL,
+  size                BIGINT NOT NULL,
+  refs                INTEGER NOT NULL DEFAULT 0,
+  last_used_at        TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX media_blobs_last_used_at_idx ON media_blobs (last_used_at);
+
+CREATE TABLE media (
+  url                 VARCHAR PRIMARY KEY,
+  hash                VARCHAR REFERENCES media_blobs NOT NULL,
+  last_used_at        TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX media_hash_idx ON media (hash);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE notifications (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  kind                VARCHAR NOT NULL,
+  message             TEXT NOT NULL,
+  detail              TEXT,
+  seen                BOOLEAN NOT NULL DEFAULT false,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX notifications_user_id_idx ON notifications (user_id, id);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE feed_rates (
+  feed_id             INTEGER PRIMARY KEY REFERENCES feeds,
+  items_per_day       DOUBLE PRECISION NOT NULL,
+  day                 DATE NOT NULL
+);
+
+ALTER TABLE users ADD COLUMN rate_warning DOUBLE PRECISION NOT NULL DEFAULT 10;
@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX items_feed_id_fingerprint_idx;
+DROP INDEX items_feed_id_link_idx;
+ALTER TABLE items DROP COLUMN fingerprint;
@@ -0,0 +1,13 @@
+-- Your SQL goes here
+ALTER TABLE items ADD COLUMN fingerprint VARCHAR;
+
+-- the same normalization as `NewItem::fingerprint`
+UPDATE items
+SET fingerprint = md5(
+  lower(btrim(regexp_replace(title, '\s+', ' ', 'g'))) || ' ' ||
+  extract(epoch FROM published_at)::bigint
+)
+WHERE published_at IS NOT NULL;
+
+CREATE INDEX items_feed_id_link_idx ON items (feed_id, link);
+CREATE INDEX items_feed_id_fingerprint_idx ON items (feed_id, fingerprint);
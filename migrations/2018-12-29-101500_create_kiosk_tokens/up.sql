@@ -0,0 +1,13 @@
+-- Your SQL goes here
+CREATE TABLE kiosk_tokens (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  token_hash          VARCHAR UNIQUE NOT NULL,
+  tag                 VARCHAR,
+  feed_id             INTEGER REFERENCES feeds,
+  item_limit          INTEGER NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+  expires_at          TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX kiosk_tokens_user_id_idx ON kiosk_tokens (user_id);
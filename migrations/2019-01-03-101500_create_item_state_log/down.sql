@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER log_item_state ON subscribed_items;
+DROP FUNCTION subscribed_items_log_state();
+DROP TABLE item_state_log;
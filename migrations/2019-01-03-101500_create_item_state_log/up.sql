@@ -0,0 +1,41 @@
+-- Your SQL goes here
+-- every change to subscribed_items, with the state before and after it
+CREATE TABLE item_state_log (
+  id                  BIGSERIAL PRIMARY KEY,
+  user_id             INTEGER NOT NULL,
+  item_id             INTEGER NOT NULL,
+  change              VARCHAR NOT NULL,
+  seen                BOOLEAN,
+  starred             BOOLEAN,
+  was_seen            BOOLEAN,
+  was_starred         BOOLEAN,
+  changed_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX item_state_log_user_id_idx ON item_state_log (user_id, changed_at);
+CREATE INDEX item_state_log_changed_at_idx ON item_state_log (changed_at);
+
+CREATE OR REPLACE FUNCTION subscribed_items_log_state() RETURNS trigger AS $$
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        INSERT INTO item_state_log (user_id, item_id, change, seen, starred)
+        VALUES (NEW.user_id, NEW.item_id, 'added', NEW.seen, NEW.starred);
+        RETURN NEW;
+    ELSIF TG_OP = 'UPDATE' THEN
+        IF NEW.seen IS DISTINCT FROM OLD.seen OR NEW.starred IS DISTINCT FROM OLD.starred THEN
+            INSERT INTO item_state_log
+                (user_id, item_id, change, seen, starred, was_seen, was_starred)
+            VALUES
+                (NEW.user_id, NEW.item_id, 'updated', NEW.seen, NEW.starred, OLD.seen, OLD.starred);
+        END IF;
+        RETURN NEW;
+    ELSE
+        INSERT INTO item_state_log (user_id, item_id, change, was_seen, was_starred)
+        VALUES (OLD.user_id, OLD.item_id, 'removed', OLD.seen, OLD.starred);
+        RETURN OLD;
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER log_item_state AFTER INSERT OR UPDATE OR DELETE ON subscribed_items
+    FOR EACH ROW EXECUTE PROCEDURE subscribed_items_log_state();
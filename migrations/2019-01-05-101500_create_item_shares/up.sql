@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE item_shares (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  item_id             INTEGER REFERENCES items NOT NULL,
+  token               VARCHAR UNIQUE NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX item_shares_user_id_idx ON item_shares (user_id);
+CREATE INDEX item_shares_item_id_idx ON item_shares (item_id);
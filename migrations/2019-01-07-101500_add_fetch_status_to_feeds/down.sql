@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE feeds DROP COLUMN last_error;
+ALTER TABLE feeds DROP COLUMN consecutive_failure_count;
+ALTER TABLE feeds DROP COLUMN last_fetch_status;
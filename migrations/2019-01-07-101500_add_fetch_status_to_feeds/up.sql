@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE feeds ADD COLUMN last_fetch_status INTEGER;
+ALTER TABLE feeds ADD COLUMN consecutive_failure_count INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE feeds ADD COLUMN last_error TEXT;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- items of these are never pruned, while the user is subscribed
+CREATE TABLE retention_exemptions (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  tag                 VARCHAR,
+  feed_id             INTEGER REFERENCES feeds,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+  CHECK ((tag IS NULL) <> (feed_id IS NULL))
+);
+
+CREATE INDEX retention_exemptions_user_id_idx ON retention_exemptions (user_id);
@@ -0,0 +1,19 @@
+-- This file should undo anything in `up.sql`
+DROP VIEW subscribed_feeds_with_count_view;
+CREATE VIEW subscribed_feeds_with_count_view AS
+  SELECT f.id, f.title, f.description, f.site_link, f.feed_link, f.updated_at, s.user_id,
+    CAST (unseen_count AS INTEGER)
+  FROM feeds f
+  INNER JOIN (
+    SELECT i.feed_id, si.user_id, count(si.seen) AS unseen_count
+    FROM items i
+    INNER JOIN subscribed_items si
+    ON i.id = si.item_id
+    WHERE si.seen = 'f'
+    GROUP BY i.feed_id, si.user_id
+  ) s
+  ON f.id = s.feed_id;
+
+DROP TRIGGER count_unseen_items ON subscribed_items;
+DROP FUNCTION subscribed_items_count_unseen();
+DROP TABLE unread_counts;
@@ -0,0 +1,66 @@
+-- Your SQL goes here
+-- unread items by user and feed, kept up to date by a trigger on subscribed_items
+CREATE TABLE unread_counts (
+  user_id             INTEGER NOT NULL,
+  feed_id             INTEGER NOT NULL,
+  unseen_count        INTEGER NOT NULL DEFAULT 0,
+  PRIMARY KEY (user_id, feed_id)
+);
+
+INSERT INTO unread_counts (user_id, feed_id, unseen_count)
+  SELECT si.user_id, i.feed_id, count(*)
+  FROM items i
+  INNER JOIN subscribed_items si
+  ON i.id = si.item_id
+  WHERE NOT si.seen
+  GROUP BY si.user_id, i.feed_id;
+
+CREATE OR REPLACE FUNCTION subscribed_items_count_unseen() RETURNS trigger AS $$
+DECLARE
+    delta INTEGER := 0;
+    uid INTEGER;
+    iid INTEGER;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        uid := NEW.user_id;
+        iid := NEW.item_id;
+        IF NOT NEW.seen THEN
+            delta := 1;
+        END IF;
+    ELSIF TG_OP = 'UPDATE' THEN
+        uid := NEW.user_id;
+        iid := NEW.item_id;
+        IF NEW.seen AND NOT OLD.seen THEN
+            delta := -1;
+        ELSIF OLD.seen AND NOT NEW.seen THEN
+            delta := 1;
+        END IF;
+    ELSE
+        uid := OLD.user_id;
+        iid := OLD.item_id;
+        IF NOT OLD.seen THEN
+            delta := -1;
+        END IF;
+    END IF;
+    IF delta <> 0 THEN
+        INSERT INTO unread_counts (user_id, feed_id, unseen_count)
+        SELECT uid, feed_id, delta FROM items WHERE id = iid
+        ON CONFLICT (user_id, feed_id)
+        DO UPDATE SET unseen_count = unread_counts.unseen_count + EXCLUDED.unseen_count;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER count_unseen_items AFTER INSERT OR UPDATE OF seen OR DELETE ON subscribed_items
+    FOR EACH ROW EXECUTE PROCEDURE subscribed_items_count_unseen();
+
+-- the same columns as before, read from the counts instead of every item
+DROP VIEW subscribed_feeds_with_count_view;
+CREATE VIEW subscribed_feeds_with_count_view AS
+  SELECT f.id, f.title, f.description, f.site_link, f.feed_link, f.updated_at, c.user_id,
+    c.unseen_count
+  FROM feeds f
+  INNER JOIN unread_counts c
+  ON f.id = c.feed_id
+  WHERE c.unseen_count > 0;
@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- webhook and push deliveries that failed every attempt, kept for replay
+CREATE TABLE failed_deliveries (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  kind                VARCHAR NOT NULL,
+  target              VARCHAR NOT NULL,
+  headers             TEXT NOT NULL,
+  payload             TEXT NOT NULL,
+  error               TEXT NOT NULL,
+  attempts            INTEGER NOT NULL,
+  failed_at           TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX failed_deliveries_user_id_idx ON failed_deliveries (user_id);
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- the readable part of a watched page, as of its last change
+CREATE TABLE page_snapshots (
+  feed_id             INTEGER PRIMARY KEY REFERENCES feeds,
+  content             TEXT NOT NULL,
+  changed_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
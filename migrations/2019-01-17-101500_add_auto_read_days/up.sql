@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- items older than this are marked as read, NULL keeps them unread
+ALTER TABLE users ADD COLUMN auto_read_days INTEGER;
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- see `windows`; NULL fetches the feed at any time
+ALTER TABLE subscribed_feeds ADD COLUMN refresh_window VARCHAR;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- the copies of a guid that older versions stored twice keep their rows, with
+-- their id added so they stop clashing
+UPDATE items SET guid = guid || '#' || id
+WHERE id NOT IN (SELECT min(id) FROM items GROUP BY feed_id, guid);
+
+CREATE UNIQUE INDEX items_feed_id_guid_idx ON items (feed_id, guid);
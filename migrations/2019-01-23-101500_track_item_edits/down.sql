@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+DROP VIEW subscribed_items_view;
+ALTER TABLE subscribed_feeds DROP COLUMN unread_on_edit;
+ALTER TABLE items DROP COLUMN edited_at;
+ALTER TABLE items DROP COLUMN content_hash;
+
+CREATE VIEW subscribed_items_view AS
+  SELECT i.*, s.id as subscribed_item_id, s.user_id, s.seen, s.starred
+  FROM items i
+  INNER JOIN subscribed_items s
+  ON i.id = s.item_id;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+-- md5 of an item's title, summary and content as the feed had them, see
+-- `NewItem::content_hash`; feeds storing the full articles instead start
+-- without one, so the first fetch doesn't take every item as edited
+ALTER TABLE items ADD COLUMN content_hash VARCHAR;
+ALTER TABLE items ADD COLUMN edited_at TIMESTAMPTZ;
+UPDATE items SET content_hash = md5(title || E'\n' || coalesce(summary, '') || E'\n' || coalesce(content, ''))
+WHERE feed_id NOT IN (SELECT id FROM feeds WHERE fetch_full_content);
+
+ALTER TABLE subscribed_feeds ADD COLUMN unread_on_edit BOOLEAN NOT NULL DEFAULT false;
+
+DROP VIEW subscribed_items_view;
+CREATE VIEW subscribed_items_view AS
+  SELECT i.*, s.id as subscribed_item_id, s.user_id, s.seen, s.starred
+  FROM items i
+  INNER JOIN subscribed_items s
+  ON i.id = s.item_id;
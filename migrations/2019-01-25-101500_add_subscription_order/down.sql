@@ -0,0 +1,12 @@
+-- This file should undo anything in `up.sql`
+DROP VIEW subscribed_feeds_with_count_view;
+CREATE VIEW subscribed_feeds_with_count_view AS
+  SELECT f.id, f.title, f.description, f.site_link, f.feed_link, f.updated_at, c.user_id,
+    c.unseen_count
+  FROM feeds f
+  INNER JOIN unread_counts c
+  ON f.id = c.feed_id
+  WHERE c.unseen_count > 0;
+
+ALTER TABLE subscribed_feeds DROP COLUMN sort_order;
+ALTER TABLE subscribed_feeds DROP COLUMN pinned;
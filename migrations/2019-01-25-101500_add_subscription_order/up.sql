@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- pinned feeds come first, then the ordered ones, then the rest by title
+ALTER TABLE subscribed_feeds ADD COLUMN pinned BOOLEAN NOT NULL DEFAULT false;
+ALTER TABLE subscribed_feeds ADD COLUMN sort_order INTEGER;
+
+DROP VIEW subscribed_feeds_with_count_view;
+CREATE VIEW subscribed_feeds_with_count_view AS
+  SELECT f.id, f.title, f.description, f.site_link, f.feed_link, f.updated_at, c.user_id,
+    c.unseen_count, s.pinned, s.sort_order
+  FROM feeds f
+  INNER JOIN unread_counts c
+  ON f.id = c.feed_id
+  INNER JOIN subscribed_feeds s
+  ON s.feed_id = c.feed_id AND s.user_id = c.user_id
+  WHERE c.unseen_count > 0;
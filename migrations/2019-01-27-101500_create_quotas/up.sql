@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- a row without a user is everyone's default, one with a user overrides it;
+-- a NULL max_value means no limit
+CREATE TABLE quotas (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users,
+  name                VARCHAR NOT NULL,
+  max_value           BIGINT
+);
+
+CREATE UNIQUE INDEX quotas_user_id_name_idx ON quotas (user_id, name) WHERE user_id IS NOT NULL;
+CREATE UNIQUE INDEX quotas_name_idx ON quotas (name) WHERE user_id IS NULL;
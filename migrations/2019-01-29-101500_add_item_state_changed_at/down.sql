@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TRIGGER touch_item_state ON subscribed_items;
+DROP FUNCTION subscribed_items_touch_state();
+ALTER TABLE subscribed_items DROP COLUMN state_changed_at;
@@ -0,0 +1,19 @@
+-- Your SQL goes here
+-- when seen or starred last changed, for resolving sync conflicts; NULL for
+-- items that haven't changed since
+ALTER TABLE subscribed_items ADD COLUMN state_changed_at TIMESTAMPTZ;
+
+CREATE INDEX subscribed_items_state_changed_at_idx ON subscribed_items (user_id, state_changed_at)
+  WHERE state_changed_at IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION subscribed_items_touch_state() RETURNS trigger AS $$
+BEGIN
+    IF NEW.seen IS DISTINCT FROM OLD.seen OR NEW.starred IS DISTINCT FROM OLD.starred THEN
+        NEW.state_changed_at = now();
+    END IF;
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER touch_item_state BEFORE UPDATE ON subscribed_items
+    FOR EACH ROW EXECUTE PROCEDURE subscribed_items_touch_state();
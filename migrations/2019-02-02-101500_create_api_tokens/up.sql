@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE api_tokens (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  name                VARCHAR NOT NULL,
+  token_hash          VARCHAR UNIQUE NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now(),
+  last_used_at        TIMESTAMPTZ
+);
+
+CREATE INDEX api_tokens_user_id_idx ON api_tokens (user_id);
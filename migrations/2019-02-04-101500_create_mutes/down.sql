@@ -0,0 +1,54 @@
+-- This file should undo anything in `up.sql`
+DROP VIEW subscribed_items_view;
+CREATE VIEW subscribed_items_view AS
+  SELECT i.*, s.id as subscribed_item_id, s.user_id, s.seen, s.starred
+  FROM items i
+  INNER JOIN subscribed_items s
+  ON i.id = s.item_id;
+
+-- the muted items come back unread
+UPDATE subscribed_items SET suppressed = false WHERE suppressed;
+
+DROP TRIGGER count_unseen_items ON subscribed_items;
+CREATE TRIGGER count_unseen_items AFTER INSERT OR UPDATE OF seen OR DELETE ON subscribed_items
+    FOR EACH ROW EXECUTE PROCEDURE subscribed_items_count_unseen();
+
+CREATE OR REPLACE FUNCTION subscribed_items_count_unseen() RETURNS trigger AS $$
+DECLARE
+    delta INTEGER := 0;
+    uid INTEGER;
+    iid INTEGER;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        uid := NEW.user_id;
+        iid := NEW.item_id;
+        IF NOT NEW.seen THEN
+            delta := 1;
+        END IF;
+    ELSIF TG_OP = 'UPDATE' THEN
+        uid := NEW.user_id;
+        iid := NEW.item_id;
+        IF NEW.seen AND NOT OLD.seen THEN
+            delta := -1;
+        ELSIF OLD.seen AND NOT NEW.seen THEN
+            delta := 1;
+        END IF;
+    ELSE
+        uid := OLD.user_id;
+        iid := OLD.item_id;
+        IF NOT OLD.seen THEN
+            delta := -1;
+        END IF;
+    END IF;
+    IF delta <> 0 THEN
+        INSERT INTO unread_counts (user_id, feed_id, unseen_count)
+        SELECT uid, feed_id, delta FROM items WHERE id = iid
+        ON CONFLICT (user_id, feed_id)
+        DO UPDATE SET unseen_count = unread_counts.unseen_count + EXCLUDED.unseen_count;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+ALTER TABLE subscribed_items DROP COLUMN suppressed;
+DROP TABLE mutes;
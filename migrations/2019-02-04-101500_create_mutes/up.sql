@@ -0,0 +1,67 @@
+-- Your SQL goes here
+-- phrases a user doesn't want to read about, in one feed or all of them
+CREATE TABLE mutes (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  feed_id             INTEGER REFERENCES feeds ON DELETE CASCADE,
+  phrase              VARCHAR NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX mutes_user_id_idx ON mutes (user_id);
+
+-- muted items are kept, but out of sight and out of the unread counts
+ALTER TABLE subscribed_items ADD COLUMN suppressed BOOLEAN NOT NULL DEFAULT false;
+
+CREATE INDEX subscribed_items_suppressed_idx ON subscribed_items (user_id)
+  WHERE suppressed;
+
+CREATE OR REPLACE FUNCTION subscribed_items_count_unseen() RETURNS trigger AS $$
+DECLARE
+    delta INTEGER := 0;
+    uid INTEGER;
+    iid INTEGER;
+BEGIN
+    IF TG_OP = 'INSERT' THEN
+        uid := NEW.user_id;
+        iid := NEW.item_id;
+        IF NOT NEW.seen AND NOT NEW.suppressed THEN
+            delta := 1;
+        END IF;
+    ELSIF TG_OP = 'UPDATE' THEN
+        uid := NEW.user_id;
+        iid := NEW.item_id;
+        IF (NEW.seen OR NEW.suppressed) AND NOT (OLD.seen OR OLD.suppressed) THEN
+            delta := -1;
+        ELSIF (OLD.seen OR OLD.suppressed) AND NOT (NEW.seen OR NEW.suppressed) THEN
+            delta := 1;
+        END IF;
+    ELSE
+        uid := OLD.user_id;
+        iid := OLD.item_id;
+        IF NOT OLD.seen AND NOT OLD.suppressed THEN
+            delta := -1;
+        END IF;
+    END IF;
+    IF delta <> 0 THEN
+        INSERT INTO unread_counts (user_id, feed_id, unseen_count)
+        SELECT uid, feed_id, delta FROM items WHERE id = iid
+        ON CONFLICT (user_id, feed_id)
+        DO UPDATE SET unseen_count = unread_counts.unseen_count + EXCLUDED.unseen_count;
+    END IF;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER count_unseen_items ON subscribed_items;
+CREATE TRIGGER count_unseen_items AFTER INSERT OR UPDATE OF seen, suppressed OR DELETE
+    ON subscribed_items
+    FOR EACH ROW EXECUTE PROCEDURE subscribed_items_count_unseen();
+
+DROP VIEW subscribed_items_view;
+CREATE VIEW subscribed_items_view AS
+  SELECT i.*, s.id as subscribed_item_id, s.user_id, s.seen, s.starred
+  FROM items i
+  INNER JOIN subscribed_items s
+  ON i.id = s.item_id
+  WHERE NOT s.suppressed;
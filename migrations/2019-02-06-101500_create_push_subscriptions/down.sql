@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE subscribed_feeds DROP COLUMN push_notify;
+DROP TABLE push_subscriptions;
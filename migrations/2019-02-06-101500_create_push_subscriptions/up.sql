@@ -0,0 +1,15 @@
+-- Your SQL goes here
+-- browsers subscribed to Web Push, see `push`
+CREATE TABLE push_subscriptions (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  endpoint            VARCHAR UNIQUE NOT NULL,
+  p256dh              VARCHAR NOT NULL,
+  auth                VARCHAR NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX push_subscriptions_user_id_idx ON push_subscriptions (user_id);
+
+-- feeds whose new items are pushed to the user's browsers
+ALTER TABLE subscribed_feeds ADD COLUMN push_notify BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,24 @@
+-- Your SQL goes here
+-- URLs new items are POSTed to, see `webhooks`; the secret is sealed, see `crypto`
+CREATE TABLE webhooks (
+  id                  SERIAL PRIMARY KEY,
+  user_id             INTEGER REFERENCES users NOT NULL,
+  url                 VARCHAR NOT NULL,
+  feed_id             INTEGER REFERENCES feeds ON DELETE CASCADE,
+  secret              VARCHAR NOT NULL,
+  created_at          TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
+
+-- how each call went, failed or not; items can be purged in the meantime
+CREATE TABLE webhook_deliveries (
+  id                  SERIAL PRIMARY KEY,
+  webhook_id          INTEGER REFERENCES webhooks ON DELETE CASCADE NOT NULL,
+  item_id             INTEGER NOT NULL,
+  attempts            INTEGER NOT NULL,
+  error               TEXT,
+  finished_at         TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhook_deliveries_webhook_id_idx ON webhook_deliveries (webhook_id, finished_at);
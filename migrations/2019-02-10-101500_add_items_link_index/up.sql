@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- for finding items by their link, see `GET /api/item/lookup`
+CREATE INDEX items_link_idx ON items (link);
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- feeds subscribed to at their WebSub hub, see `websub`; the secret is sealed,
+-- see `crypto`, and `expires_at` stays NULL until the hub confirms
+CREATE TABLE websub_subscriptions (
+  feed_id             INTEGER PRIMARY KEY REFERENCES feeds ON DELETE CASCADE,
+  hub                 VARCHAR NOT NULL,
+  topic               VARCHAR NOT NULL,
+  token               VARCHAR UNIQUE NOT NULL,
+  secret              VARCHAR NOT NULL,
+  requested_at        TIMESTAMPTZ NOT NULL DEFAULT now(),
+  expires_at          TIMESTAMPTZ
+);
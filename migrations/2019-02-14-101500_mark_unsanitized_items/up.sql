@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- items stored before their HTML was sanitized, cleaned on startup by
+-- `sanitize::backfill`, which lowers the bound as it goes and then removes it
+INSERT INTO settings (name, value)
+SELECT 'unsanitized_items_below', (max(id) + 1)::text FROM items HAVING count(*) > 0
+ON CONFLICT (name) DO NOTHING;
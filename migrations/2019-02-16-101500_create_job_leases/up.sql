@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- every worker runs the periodic jobs, the one holding a job's lease does it
+CREATE TABLE job_leases (
+  name                VARCHAR PRIMARY KEY,
+  leased_until        TIMESTAMPTZ NOT NULL
+);
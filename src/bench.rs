@@ -0,0 +1,489 @@
+use chrono::{Duration as ChronoDuration, TimeZone, Utc};
+use futures::future::{self, Loop};
+use futures::{stream, Future, Stream};
+use hyper::client::HttpConnector;
+use hyper::rt;
+use hyper::service::service_fn_ok;
+use hyper::{Body, Client, Method, Request, Response, Server, StatusCode};
+use serde_json::{self, Value};
+use std::collections::HashMap;
+use std::env;
+use std::process;
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::timer::{Delay, Timeout};
+
+use config;
+use db;
+use error::HermesError;
+use feed;
+use models::{AddFeedOutcome, User};
+use web::jwt::generate_jwt;
+use web::types::UserWebsocketState;
+
+// `hermes bench` seeds users and feeds, then replays a mix of requests
+// against a running instance and reports latency percentiles per kind of
+// request, so that changes meant to make Hermes faster can be measured, and
+// ones making it slower caught:
+//
+//   river     GET /api/timeline?unread=true, what clients open with
+//   item      GET /api/item/:id, reading one of the items listed
+//   read      POST /api/sync, marking a burst of them as read
+//   counts    GET /api/counts
+//   refresh   POST /api/feed/:id/refresh, every feed at once each cycle
+//
+// Each user also listens on `GET /api/events`, which new items are fanned out
+// to after every fetch cycle. The feeds are served by the bench itself,
+// with a few new items each cycle.
+//
+// BENCH_URL             the instance, http://127.0.0.1:$PORT if unset
+// BENCH_FEED_PORT       where the feeds are served from, 8099 if unset; the
+//                       instance has to reach it on 127.0.0.1
+// BENCH_USERS           users seeded and active, 20 if unset
+// BENCH_FEEDS           feeds seeded, 50 if unset
+// BENCH_FEEDS_PER_USER  feeds each user follows, 10 if unset
+// BENCH_ITEMS           items per feed document, 50 if unset
+// BENCH_CONCURRENCY     requests each user has in flight, 2 if unset
+// BENCH_SECS            how long to run, 30 if unset
+// BENCH_FETCH_SECS      time between fetch cycles, 5 if unset
+// BENCH_P95_MS          budgets: when a kind of request has a higher 95th
+// BENCH_P99_MS          or 99th percentile, the bench exits with 1
+//
+// Users are seeded through the database, so the bench runs with the
+// instance's DATABASE_URL, which should be a throwaway one. API_RATE_LIMIT
+// on the instance has to allow for the load; rate limited requests are
+// counted apart.
+
+static PASSWORD: &'static str = "bench-password";
+// new items in each feed per fetch cycle
+static NEW_PER_ROUND: usize = 3;
+static MAX_READ_BURST: usize = 20;
+
+static ROUND: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+  static ref URL: String =
+    env::var("BENCH_URL").unwrap_or_else(|_| format!("http://127.0.0.1:{}", config::WEB.port));
+  static ref FEED_PORT: u16 = setting("BENCH_FEED_PORT", 8099) as u16;
+  static ref USERS: usize = setting("BENCH_USERS", 20);
+  static ref FEEDS: usize = setting("BENCH_FEEDS", 50).max(1);
+  static ref FEEDS_PER_USER: usize = setting("BENCH_FEEDS_PER_USER", 10);
+  static ref ITEMS: usize = setting("BENCH_ITEMS", 50);
+  static ref CONCURRENCY: usize = setting("BENCH_CONCURRENCY", 2).max(1);
+  static ref SECS: u64 = setting("BENCH_SECS", 30) as u64;
+  static ref FETCH_SECS: u64 = setting("BENCH_FETCH_SECS", 5).max(1) as u64;
+  static ref P95_MS: Option<u64> = env::var("BENCH_P95_MS").ok().and_then(|v| v.parse().ok());
+  static ref P99_MS: Option<u64> = env::var("BENCH_P99_MS").ok().and_then(|v| v.parse().ok());
+}
+
+fn setting(name: &str, default: usize) -> usize {
+  env::var(name)
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(default)
+}
+
+type HttpClient = Client<HttpConnector, Body>;
+type Step = Box<Future<Item = Vec<i32>, Error = ()> + Send>;
+
+#[derive(Default)]
+struct Stats {
+  // microseconds
+  latencies: HashMap<&'static str, Vec<u64>>,
+  errors: HashMap<&'static str, usize>,
+  limited: usize,
+  events: usize,
+}
+impl Stats {
+  fn record(&mut self, op: &'static str, started: Instant) {
+    let elapsed = started.elapsed();
+    let micros = elapsed.as_secs() * 1_000_000 + elapsed.subsec_micros() as u64;
+    self.latencies.entry(op).or_insert_with(Vec::new).push(micros);
+  }
+
+  fn fail(&mut self, op: &'static str) {
+    *self.errors.entry(op).or_insert(0) += 1;
+  }
+
+  // prints the report, false when a budget was exceeded
+  fn report(&mut self, elapsed: Duration) -> bool {
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_millis() as f64 / 1000.0;
+    let total: usize = self.latencies.values().map(|l| l.len()).sum();
+    println!("{} requests in {:.1}s, {:.1}/s", total, secs, total as f64 / secs);
+    println!(
+      "{:<8} {:>8} {:>7} {:>9} {:>9} {:>9} {:>9}",
+      "request", "count", "errors", "p50 ms", "p95 ms", "p99 ms", "max ms"
+    );
+    let mut ops: Vec<&'static str> = self
+      .latencies
+      .keys()
+      .chain(self.errors.keys())
+      .cloned()
+      .collect();
+    ops.sort();
+    ops.dedup();
+    let mut within = true;
+    for op in ops {
+      let mut latencies = self.latencies.remove(op).unwrap_or_default();
+      latencies.sort();
+      let ms = |p: f64| percentile(&latencies, p) as f64 / 1000.0;
+      println!(
+        "{:<8} {:>8} {:>7} {:>9.1} {:>9.1} {:>9.1} {:>9.1}",
+        op,
+        latencies.len(),
+        self.errors.get(op).cloned().unwrap_or(0),
+        ms(0.5),
+        ms(0.95),
+        ms(0.99),
+        ms(1.0)
+      );
+      for &(p, budget) in [(0.95, *P95_MS), (0.99, *P99_MS)].iter() {
+        match budget {
+          Some(budget) if ms(p) > budget as f64 => {
+            println!("{}: p{} of {:.1}ms is over the budget of {}ms", op, p * 100.0, ms(p), budget);
+            within = false;
+          }
+          _ => (),
+        }
+      }
+    }
+    println!("{} rate limited, {} events received", self.limited, self.events);
+    within
+  }
+}
+
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+  match sorted.len() {
+    0 => 0,
+    n => sorted[((n as f64 * p).ceil() as usize).max(1).min(n) - 1],
+  }
+}
+
+type Shared = Arc<Mutex<Stats>>;
+
+// xorshift, good enough to pick requests
+fn next(seed: u64) -> u64 {
+  let mut x = seed;
+  x ^= x << 13;
+  x ^= x >> 7;
+  x ^= x << 17;
+  x
+}
+
+fn feed_url(n: usize) -> String {
+  format!("http://127.0.0.1:{}/feed/{}.xml", *FEED_PORT, n)
+}
+
+fn feed_document(n: usize) -> String {
+  let round = ROUND.load(Ordering::Relaxed);
+  let base = Utc.ymd(2019, 1, 1).and_hms(0, 0, 0);
+  let body = "&lt;p&gt;Lorem ipsum dolor sit amet, consectetur adipiscing elit, sed do \
+              eiusmod tempor incididunt ut labore et dolore magna aliqua.&lt;/p&gt;"
+    .repeat(8);
+  let items: String = (0..*ITEMS)
+    .map(|i| {
+      let idx = round * NEW_PER_ROUND + *ITEMS - i;
+      let published = base + ChronoDuration::minutes(idx as i64);
+      format!(
+        "<item><title>Item {1} of feed {0}</title>\
+         <link>http://127.0.0.1:{2}/item/{0}/{1}</link>\
+         <guid>bench-{0}-{1}</guid><pubDate>{3}</pubDate>\
+         <description>{4}</description></item>",
+        n,
+        idx,
+        *FEED_PORT,
+        published.to_rfc2822(),
+        body
+      )
+    }).collect();
+  format!(
+    "<?xml version=\"1.0\"?><rss version=\"2.0\"><channel><title>Bench feed {}</title>\
+     <link>http://127.0.0.1:{}/</link><description>load test</description>{}</channel></rss>",
+    n, *FEED_PORT, items
+  )
+}
+
+fn serve_feeds() {
+  let addr = ([127, 0, 0, 1], *FEED_PORT).into();
+  let server = Server::bind(&addr)
+    .serve(|| {
+      service_fn_ok(|req: Request<Body>| {
+        let n = req
+          .uri()
+          .path()
+          .trim_left_matches("/feed/")
+          .trim_right_matches(".xml")
+          .parse::<usize>();
+        match n {
+          Ok(n) => Response::builder()
+            .header("content-type", "application/rss+xml")
+            .body(Body::from(feed_document(n)))
+            .unwrap(),
+          Err(_) => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+        }
+      })
+    }).map_err(|e| error!("bench: feed server failed: {}", e));
+  rt::spawn(server);
+}
+
+fn seed_users() -> Result<Vec<User>, HermesError> {
+  (1..*USERS + 1)
+    .map(|n| {
+      let name = format!("bench{}", n);
+      match db::get_user(&name) {
+        Err(HermesError::NotFound) => {
+          db::create_user(&name, &User::hash_pw(PASSWORD))?;
+          db::get_user(&name)
+        }
+        found => found,
+      }
+    }).collect()
+}
+
+// subscribes each user to their feeds, which the first one fetches; the
+// feeds and a token of a user following each
+fn seed_feeds(
+  users: Vec<(User, String)>,
+) -> impl Future<Item = HashMap<i32, String>, Error = ()> {
+  let state = UserWebsocketState {
+    state: Arc::new(Mutex::new(HashMap::new())),
+  };
+  stream::iter_ok(users)
+    .and_then(move |(user, token)| {
+      let urls = (0..(*FEEDS_PER_USER).min(*FEEDS))
+        .map(|k| feed_url((user.id as usize * 7 + k) % *FEEDS))
+        .collect();
+      feed::add_feeds(urls, user.id, state.clone()).map(move |results| (token, results))
+    }).fold(HashMap::new(), |mut owners, (token, results)| {
+      for result in results {
+        match (result.result, result.feed_id) {
+          (AddFeedOutcome::Added, Some(feed_id)) | (AddFeedOutcome::Duplicate, Some(feed_id)) => {
+            owners.entry(feed_id).or_insert_with(|| token.clone());
+          }
+          (outcome, _) => warn!("bench: could not add {}: {:?}", result.url, outcome),
+        }
+      }
+      Ok::<_, ()>(owners)
+    })
+}
+
+fn call(
+  client: &HttpClient,
+  stats: &Shared,
+  op: &'static str,
+  method: Method,
+  path: String,
+  body: Option<String>,
+) -> impl Future<Item = Option<Vec<u8>>, Error = ()> {
+  let request = Request::builder()
+    .method(method)
+    .uri(format!("{}{}", *URL, path).as_str())
+    .header("content-type", "application/json")
+    .body(body.map(Body::from).unwrap_or_else(Body::empty));
+  let request = match request {
+    Ok(request) => request,
+    Err(e) => {
+      error!("bench: invalid request to {}: {}", path, e);
+      return future::Either::A(future::ok(None));
+    }
+  };
+  let stats = stats.clone();
+  let started = Instant::now();
+  let response = client.request(request).and_then(|res| {
+    let status = res.status();
+    res.into_body().concat2().map(move |body| (status, body))
+  });
+  future::Either::B(response.then(move |res| {
+    let mut stats = stats.lock().unwrap();
+    match res {
+      Ok((status, body)) => {
+        if status.is_success() {
+          stats.record(op, started);
+          return Ok(Some(body.to_vec()));
+        }
+        if status == StatusCode::TOO_MANY_REQUESTS {
+          stats.limited += 1;
+        } else {
+          debug!("bench: {} answered {}", op, status);
+          stats.fail(op);
+        }
+        Ok(None)
+      }
+      Err(e) => {
+        debug!("bench: {} failed: {}", op, e);
+        stats.fail(op);
+        Ok(None)
+      }
+    }
+  }))
+}
+
+fn item_ids(body: &[u8]) -> Vec<i32> {
+  match serde_json::from_slice::<Vec<Value>>(body) {
+    Ok(items) => items
+      .iter()
+      .filter_map(|i| i.get("id").and_then(|id| id.as_i64()).map(|id| id as i32))
+      .collect(),
+    Err(_) => vec![],
+  }
+}
+
+// one request of the mix, given the unread items last listed
+fn step(client: &HttpClient, stats: &Shared, token: &str, pick: u64, unread: Vec<i32>) -> Step {
+  let auth = format!("access_token={}", token);
+  let roll = pick % 100;
+  if roll < 40 || (roll < 80 && unread.is_empty()) {
+    let path = format!("/api/timeline?unread=true&limit=50&{}", auth);
+    Box::new(
+      call(client, stats, "river", Method::GET, path, None)
+        .map(|body| body.map(|b| item_ids(&b)).unwrap_or_default()),
+    )
+  } else if roll < 65 {
+    let id = unread[(pick / 100) as usize % unread.len()];
+    let path = format!("/api/item/{}?{}", id, auth);
+    Box::new(call(client, stats, "item", Method::GET, path, None).map(move |_| unread))
+  } else if roll < 80 {
+    let burst: Vec<i32> = unread.iter().take(MAX_READ_BURST).cloned().collect();
+    let rest = unread.iter().skip(MAX_READ_BURST).cloned().collect();
+    let body = json!({ "since": Utc::now().to_rfc3339(), "read": burst });
+    let path = format!("/api/sync?{}", auth);
+    Box::new(call(client, stats, "read", Method::POST, path, Some(body.to_string())).map(|_| rest))
+  } else {
+    let path = format!("/api/counts?{}", auth);
+    Box::new(call(client, stats, "counts", Method::GET, path, None).map(move |_| unread))
+  }
+}
+
+fn user_loop(
+  client: HttpClient,
+  stats: Shared,
+  token: String,
+  seed: u64,
+  deadline: Instant,
+) -> impl Future<Item = (), Error = ()> {
+  future::loop_fn((seed, vec![]), move |(seed, unread)| {
+    if Instant::now() >= deadline {
+      return future::Either::A(future::ok(Loop::Break(())));
+    }
+    let seed = next(seed);
+    future::Either::B(
+      step(&client, &stats, &token, seed, unread).map(move |unread| Loop::Continue((seed, unread))),
+    )
+  })
+}
+
+// a new round of items, then every feed refreshed through the instance
+fn fetch_cycles(
+  client: HttpClient,
+  stats: Shared,
+  owners: HashMap<i32, String>,
+  deadline: Instant,
+) -> impl Future<Item = (), Error = ()> {
+  let owners: Vec<(i32, String)> = owners.into_iter().collect();
+  future::loop_fn((), move |_| {
+    let next_cycle = Instant::now() + Duration::from_secs(*FETCH_SECS);
+    if next_cycle >= deadline {
+      return future::Either::A(future::ok(Loop::Break(())));
+    }
+    let client = client.clone();
+    let stats = stats.clone();
+    let owners = owners.clone();
+    let cycle = Delay::new(next_cycle)
+      .map_err(|e| error!("bench: timer failed: {}", e))
+      .and_then(move |_| {
+        ROUND.fetch_add(1, Ordering::Relaxed);
+        stream::iter_ok(owners)
+          .map(move |(feed_id, token)| {
+            let path = format!("/api/feed/{}/refresh?access_token={}", feed_id, token);
+            call(&client, &stats, "refresh", Method::POST, path, None)
+          }).buffer_unordered(16)
+          .for_each(|_| Ok(()))
+      });
+    future::Either::B(cycle.map(|_| Loop::Continue(())))
+  })
+}
+
+// counts what reaches the user's event stream until the deadline
+fn listen(
+  client: HttpClient,
+  stats: Shared,
+  token: String,
+  deadline: Instant,
+) -> impl Future<Item = (), Error = ()> {
+  let path = format!("{}/api/events?access_token={}", *URL, token);
+  let request = match path.parse() {
+    Ok(uri) => client.get(uri),
+    Err(e) => {
+      error!("bench: invalid URL {}: {}", path, e);
+      return future::Either::A(future::ok(()));
+    }
+  };
+  let events = request
+    .and_then(move |res| {
+      res.into_body().for_each(move |chunk| {
+        let received = str::from_utf8(&chunk)
+          .map(|c| c.matches("data: ").count())
+          .unwrap_or(0);
+        stats.lock().unwrap().events += received;
+        Ok(())
+      })
+    }).map_err(|e| debug!("bench: event stream failed: {}", e));
+  let now = Instant::now();
+  let remaining = match deadline > now {
+    true => deadline - now,
+    false => Duration::from_secs(0),
+  };
+  future::Either::B(Timeout::new(events, remaining).then(|_| Ok(())))
+}
+
+pub fn run() -> ! {
+  let users = match seed_users() {
+    Ok(users) => users,
+    Err(e) => {
+      error!("bench: could not seed users: {}", e);
+      process::exit(1);
+    }
+  };
+  let users: Vec<(User, String)> = users
+    .into_iter()
+    .filter_map(|user| generate_jwt(&user).map(|token| (user, token)))
+    .collect();
+  info!("bench: seeding {} feeds for {} users", *FEEDS, users.len());
+
+  rt::run(rt::lazy(move || {
+    serve_feeds();
+    let tokens: Vec<String> = users.iter().map(|&(_, ref token)| token.clone()).collect();
+    seed_feeds(users).and_then(move |owners| {
+      info!("bench: running against {} for {}s", *URL, *SECS);
+      let client: HttpClient = Client::new();
+      let stats: Shared = Arc::new(Mutex::new(Stats::default()));
+      let started = Instant::now();
+      let deadline = started + Duration::from_secs(*SECS);
+      let mut load: Vec<Box<Future<Item = (), Error = ()> + Send>> = vec![];
+      for (n, token) in tokens.into_iter().enumerate() {
+        load.push(Box::new(listen(client.clone(), stats.clone(), token.clone(), deadline)));
+        for slot in 0..*CONCURRENCY {
+          let seed = (n * *CONCURRENCY + slot) as u64 * 2_654_435_761 + 1;
+          load.push(Box::new(user_loop(
+            client.clone(),
+            stats.clone(),
+            token.clone(),
+            seed,
+            deadline,
+          )));
+        }
+      }
+      load.push(Box::new(fetch_cycles(client.clone(), stats.clone(), owners, deadline)));
+      future::join_all(load).map(move |_| {
+        let within = stats.lock().unwrap().report(started.elapsed());
+        process::exit(if within { 0 } else { 1 })
+      })
+    })
+  }));
+  process::exit(1)
+}
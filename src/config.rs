@@ -0,0 +1,141 @@
+use std::env;
+use url::Url;
+
+lazy_static! {
+  pub static ref WEB: WebConfig = WebConfig::from_env();
+}
+
+// Everything that depends on where the instance and the UI are served from.
+//
+// CORS_ORIGINS    comma separated origins allowed to call the API, `*` for any
+// COOKIE_DOMAIN   domain of the session cookie, the request host if unset
+// COOKIE_PATH     path of the session cookie, `/` if unset
+// COOKIE_SECURE   `true` to only send the session cookie over HTTPS, the
+//                 default when serving HTTPS
+// PORT            port to listen on, 3030 if unset
+// TLS_CERT        PEM certificate chain and private key; with both set,
+// TLS_KEY         HTTPS is served instead of HTTP
+// HTTP_REDIRECT_PORT  with TLS, plain HTTP on this port is redirected to HTTPS
+// INSTANCE_NAME   name the UI shows, `Hermes` if unset
+// LOGO_URL        logo the UI shows instead of its own
+// API_BASE        where the UI finds the API, `/api` if unset; relative ones
+//                 are resolved against `/config.json`
+#[derive(Debug)]
+pub struct WebConfig {
+  pub allowed_origins: Vec<String>,
+  pub cookie_domain: Option<String>,
+  pub cookie_path: String,
+  pub cookie_secure: bool,
+  pub port: u16,
+  pub tls: Option<TlsFiles>,
+  pub http_redirect_port: Option<u16>,
+  pub instance_name: String,
+  pub logo_url: Option<String>,
+  pub api_base: String,
+}
+
+#[derive(Debug)]
+pub struct TlsFiles {
+  pub cert: String,
+  pub key: String,
+}
+
+pub static SESSION_COOKIE: &'static str = "hermes_token";
+
+impl WebConfig {
+  pub fn from_env() -> Self {
+    let tls = match (env::var("TLS_CERT"), env::var("TLS_KEY")) {
+      (Ok(cert), Ok(key)) => Some(TlsFiles {
+        cert: cert,
+        key: key,
+      }),
+      (Ok(_), Err(_)) | (Err(_), Ok(_)) => {
+        error!("TLS_CERT and TLS_KEY have to be set together, serving plain HTTP");
+        None
+      }
+      _ => None,
+    };
+    WebConfig {
+      allowed_origins: env::var("CORS_ORIGINS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|o| o.trim().trim_right_matches('/').to_owned())
+        .filter(|o| !o.is_empty())
+        .collect(),
+      cookie_domain: env::var("COOKIE_DOMAIN").ok().filter(|d| !d.is_empty()),
+      cookie_path: env::var("COOKIE_PATH").unwrap_or_else(|_| "/".to_owned()),
+      cookie_secure: env::var("COOKIE_SECURE")
+        .map(|v| v == "true")
+        .unwrap_or(tls.is_some()),
+      port: env::var("PORT")
+        .ok()
+        .and_then(|p| p.parse().ok())
+        .unwrap_or(3030),
+      http_redirect_port: match tls {
+        Some(_) => env::var("HTTP_REDIRECT_PORT")
+          .ok()
+          .and_then(|p| p.parse().ok()),
+        None => None,
+      },
+      tls: tls,
+      instance_name: env::var("INSTANCE_NAME")
+        .ok()
+        .filter(|n| !n.trim().is_empty())
+        .unwrap_or_else(|| "Hermes".to_owned()),
+      logo_url: env::var("LOGO_URL").ok().filter(|l| !l.is_empty()),
+      api_base: env::var("API_BASE")
+        .ok()
+        .filter(|b| !b.is_empty())
+        .map(|b| b.trim_right_matches('/').to_owned())
+        .unwrap_or_else(|| "/api".to_owned()),
+    }
+  }
+
+  // where plain HTTP requests for `host` get sent
+  pub fn https_url(&self, host: &str, path_and_query: &str) -> Option<String> {
+    let host = Url::parse(&format!("http://{}", host)).ok()?;
+    let host = host.host_str()?;
+    match self.port {
+      443 => Some(format!("https://{}{}", host, path_and_query)),
+      port => Some(format!("https://{}:{}{}", host, port, path_and_query)),
+    }
+  }
+
+  pub fn any_origin(&self) -> bool {
+    self.allowed_origins.iter().any(|o| o == "*")
+  }
+
+  // without configured origins only the instance itself is allowed
+  pub fn origin_allowed(&self, origin: &str, host: Option<&str>) -> bool {
+    let origin = origin.trim_right_matches('/');
+    if self.any_origin() || self.allowed_origins.iter().any(|o| o == origin) {
+      return true;
+    }
+    match (Url::parse(origin), host) {
+      (Ok(url), Some(host)) => match (url.host_str(), url.port()) {
+        (Some(h), Some(p)) => format!("{}:{}", h, p) == host,
+        (Some(h), None) => h == host,
+        _ => false,
+      },
+      _ => false,
+    }
+  }
+
+  pub fn session_cookie(&self, token: &str, max_age: i64) -> String {
+    let mut cookie = format!(
+      "{}={}; Path={}; Max-Age={}; HttpOnly; SameSite=Lax",
+      SESSION_COOKIE, token, self.cookie_path, max_age
+    );
+    if let Some(ref domain) = self.cookie_domain {
+      cookie.push_str(&format!("; Domain={}", domain));
+    }
+    if self.cookie_secure {
+      cookie.push_str("; Secure");
+    }
+    cookie
+  }
+
+  pub fn expired_cookie(&self) -> String {
+    self.session_cookie("", 0)
+  }
+}
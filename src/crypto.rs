@@ -0,0 +1,113 @@
+use base64;
+use ring::aead::{self, OpeningKey, SealingKey, AES_256_GCM};
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+use std::env;
+
+use error::HermesError;
+
+// Credentials Hermes has to read back, unlike passwords which are hashed, are
+// sealed with AES-256-GCM before they are stored, as `enc:<key id>:<data>`.
+// The context a value belongs to (say, the setting's name) is authenticated
+// along with it, so sealed values can't be swapped between rows.
+//
+// SECRETS_KEY       base64 of 32 random bytes, what new values are sealed with
+// SECRETS_OLD_KEYS  comma separated keys that are still accepted for reading
+//
+// To rotate, set a new SECRETS_KEY and move the previous one to
+// SECRETS_OLD_KEYS; values are sealed again with the new key at startup, after
+// which the old key can go. Values stored before a key was set are plaintext,
+// they are sealed the same way. Without SECRETS_KEY values are stored as is.
+
+static PREFIX: &'static str = "enc:";
+static NONCE_LEN: usize = 12;
+
+struct Key {
+  id: String,
+  bytes: Vec<u8>,
+}
+
+impl Key {
+  fn parse(encoded: &str) -> Option<Key> {
+    let bytes = base64::decode(encoded.trim()).ok()?;
+    if bytes.len() != AES_256_GCM.key_len() {
+      error!("ignoring a secrets key that is not {} bytes long", AES_256_GCM.key_len());
+      return None;
+    }
+    Some(Key {
+      id: format!("{:x}", Sha256::digest(&bytes))[..8].to_owned(),
+      bytes: bytes,
+    })
+  }
+}
+
+lazy_static! {
+  static ref CURRENT_KEY: Option<Key> = env::var("SECRETS_KEY").ok().and_then(|k| Key::parse(&k));
+  static ref OLD_KEYS: Vec<Key> = env::var("SECRETS_OLD_KEYS")
+    .unwrap_or_default()
+    .split(',')
+    .filter(|k| !k.trim().is_empty())
+    .filter_map(Key::parse)
+    .collect();
+}
+
+fn crypto_error(what: &str) -> HermesError {
+  HermesError::Crypto(what.to_owned())
+}
+
+pub fn seal(context: &str, plain: &str) -> Result<String, HermesError> {
+  let key = match *CURRENT_KEY {
+    Some(ref key) => key,
+    None => return Ok(plain.to_owned()),
+  };
+  let sealing = SealingKey::new(&AES_256_GCM, &key.bytes).map_err(|_| crypto_error("bad key"))?;
+  let mut nonce = vec![0u8; NONCE_LEN];
+  SystemRandom::new()
+    .fill(&mut nonce)
+    .map_err(|_| crypto_error("no random nonce"))?;
+  let tag_len = AES_256_GCM.tag_len();
+  let mut in_out = plain.as_bytes().to_vec();
+  in_out.extend(vec![0u8; tag_len]);
+  let len = aead::seal_in_place(&sealing, &nonce, context.as_bytes(), &mut in_out, tag_len)
+    .map_err(|_| crypto_error("could not seal"))?;
+  nonce.extend_from_slice(&in_out[..len]);
+  Ok(format!("{}{}:{}", PREFIX, key.id, base64::encode(&nonce)))
+}
+
+pub fn open(context: &str, stored: &str) -> Result<String, HermesError> {
+  if !stored.starts_with(PREFIX) {
+    return Ok(stored.to_owned());
+  }
+  let mut parts = stored[PREFIX.len()..].splitn(2, ':');
+  let (id, data) = match (parts.next(), parts.next()) {
+    (Some(id), Some(data)) => (id, data),
+    _ => return Err(crypto_error("malformed value")),
+  };
+  let key = CURRENT_KEY
+    .iter()
+    .chain(OLD_KEYS.iter())
+    .find(|k| k.id == id)
+    .ok_or_else(|| crypto_error(&format!("no key '{}'", id)))?;
+  let data = base64::decode(data).map_err(|_| crypto_error("malformed value"))?;
+  if data.len() < NONCE_LEN {
+    return Err(crypto_error("malformed value"));
+  }
+  let (nonce, sealed) = data.split_at(NONCE_LEN);
+  let opening = OpeningKey::new(&AES_256_GCM, &key.bytes).map_err(|_| crypto_error("bad key"))?;
+  let mut sealed = sealed.to_vec();
+  let plain = aead::open_in_place(&opening, nonce, context.as_bytes(), 0, &mut sealed)
+    .map_err(|_| crypto_error("could not open"))?;
+  String::from_utf8(plain.to_vec()).map_err(|_| crypto_error("not UTF-8"))
+}
+
+// whether the value is plaintext or sealed with an old key, while there is a key
+pub fn needs_reseal(stored: &str) -> bool {
+  match *CURRENT_KEY {
+    Some(ref key) => !stored.starts_with(&format!("{}{}:", PREFIX, key.id)),
+    None => false,
+  }
+}
+
+pub fn enabled() -> bool {
+  CURRENT_KEY.is_some()
+}
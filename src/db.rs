@@ -1,15 +1,31 @@
-use chrono::{DateTime, Utc};
-use diesel::dsl::exists;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use diesel::dsl::{exists, not};
 use diesel::prelude::*;
 use diesel::{self, select, PgConnection};
-use r2d2::Pool;
+use r2d2::{Pool, PooledConnection};
 use r2d2_diesel::ConnectionManager;
-use std::collections::HashMap;
-use std::{env, thread};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::env;
 
-use models::{Feed, Item, NewFeed, NewItem, SubscribedFeed, SubscribedItem, User};
+use crypto;
+use deliveries::Outcome;
+use error::HermesError;
+use i18n;
+use models::{
+  Action, Activity, ApiToken, CacheStats, ContentRetry, DbStats, DeletedItem, Enclosure, Event,
+  FailedDelivery, Feed, FeedDay, FeedIcon, FeedRate, FeedStats, FeedTotal, FetchStatus, Filter,
+  IndexStats, Invite, Item, ItemKey, ItemShare, ItemState, KioskToken, MediaBlob, Mute, MutedItem,
+  NewEnclosure, NewFeed, NewItem, Notification, NotificationKind, Page, PushSubscription,
+  RetentionExemption, Session, SlowQuery, SubscribedFeed, SubscribedItem, TableStats, TagCount,
+  Topic, User, UserSummary, Webhook, WebhookDelivery, WebsubSubscription,
+};
 use schema::{feeds, subscribed_feeds};
 use views::{subscribed_feeds_with_count_view, subscribed_items_view};
+use web::handlers::random_token;
+use web::types::{
+  FeedOrderEntry, FilterParams, MuteParams, PushSubscribeParams, SyncParams, WebhookParams,
+};
 
 lazy_static! {
   static ref POOL: Pool<ConnectionManager<PgConnection>> = {
@@ -27,294 +43,3864 @@ lazy_static! {
   };
 }
 
-pub fn establish_pool() -> Pool<ConnectionManager<PgConnection>> {
-  POOL.clone()
+// fails instead of panicking when the pool times out
+fn get_connection() -> Result<PooledConnection<ConnectionManager<PgConnection>>, HermesError> {
+  Ok(POOL.get()?)
 }
 
-// seed admin user
+// share of the pool's connections that are checked out
+pub fn pool_saturation() -> f64 {
+  let (connections, idle, max) = pool_state();
+  (connections - idle) as f64 / max as f64
+}
+
+// open connections, idle ones among them, and the most there can be
+pub fn pool_state() -> (u32, u32, u32) {
+  let state = POOL.state();
+  (state.connections, state.idle_connections, POOL.max_size())
+}
+
+// seeds the admin from ADMIN_PASS, without it the first run goes through /setup
 pub fn create_admin_user() {
+  match has_users() {
+    Ok(false) => (),
+    Ok(true) => return,
+    Err(e) => {
+      error!("could not look for users: {}", e);
+      return;
+    }
+  }
+  let admin_pass = match env::var("ADMIN_PASS") {
+    Ok(pass) => pass,
+    Err(_) => {
+      info!("no users yet, visit /setup to create the admin");
+      return;
+    }
+  };
+  let mut settings = vec![];
+  if env::var("JWT_SECRET").is_err() {
+    match random_token(32) {
+      Ok(secret) => settings.push((JWT_SECRET_SETTING, secret)),
+      Err(e) => error!("could not read random bytes: {}", e),
+    }
+  }
+  let pwh = User::hash_pw(&admin_pass);
+  match complete_setup("admin", &pwh, &settings) {
+    Ok(Some(_)) => info!("created the admin user"),
+    Ok(None) => (),
+    Err(e) => error!("could not create the admin user: {}", e),
+  }
+}
+
+pub fn has_users() -> Result<bool, HermesError> {
   use schema::users::dsl::*;
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-
-  match select(exists(users.filter(username.eq("admin")))).get_result::<bool>(&*connection) {
-    Ok(true) => (),
-    Ok(false) => {
-      let admin_pass = env::var("ADMIN_PASS").expect("ADMIN_PASS must be set");
-      let pwh = User::hash_pw(&admin_pass);
-      diesel::insert_into(users)
-        .values((username.eq("admin"), password_hash.eq(&pwh.as_bytes())))
-        .load::<User>(&*connection)
-        .expect("Error creating admin user");
-    }
-    Err(_) => error!("could not check if admin user existed"),
+  let connection = get_connection()?;
+  select(exists(users))
+    .get_result::<bool>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// Creates the first user, user 1 and the admin, and stores the
+// settings chosen along with it. None if there already was a user.
+pub fn complete_setup(
+  uname: &str,
+  pw_hash: &str,
+  values: &[(&str, String)],
+) -> Result<Option<User>, HermesError> {
+  use diesel::result::Error;
+  use diesel::sql_query;
+  use schema::{settings, users};
+
+  let connection = get_connection()?;
+  let mut taken = false;
+  let res = connection.transaction::<User, Error, _>(|| {
+    sql_query("LOCK TABLE users IN EXCLUSIVE MODE").execute(&*connection)?;
+    if select(exists(users::table)).get_result::<bool>(&*connection)? {
+      taken = true;
+      return Err(Error::RollbackTransaction);
+    }
+    let user = diesel::insert_into(users::table)
+      .values((
+        users::id.eq(1),
+        users::username.eq(uname),
+        users::password_hash.eq(pw_hash.as_bytes()),
+        users::approved.eq(true),
+        users::is_admin.eq(true),
+      )).get_result::<User>(&*connection)?;
+    sql_query("SELECT setval('users_id_seq', 1)").execute(&*connection)?;
+    for &(n, ref v) in values.iter() {
+      let v = match SECRET_SETTINGS.contains(&n) {
+        true => crypto::seal(n, v).map_err(|e| {
+          error!("could not store setting '{}': {}", n, e);
+          Error::RollbackTransaction
+        })?,
+        false => v.to_owned(),
+      };
+      diesel::insert_into(settings::table)
+        .values((settings::name.eq(n), settings::value.eq(&v)))
+        .on_conflict(settings::name)
+        .do_update()
+        .set(settings::value.eq(&v))
+        .execute(&*connection)?;
+    }
+    Ok(user)
+  });
+  match res {
+    Ok(user) => Ok(Some(user)),
+    Err(_) if taken => Ok(None),
+    Err(e) => Err(e.into()),
+  }
+}
+
+// settings
+
+pub static JWT_SECRET_SETTING: &'static str = "jwt_secret";
+pub static VAPID_KEY_SETTING: &'static str = "vapid_private_key";
+// stored sealed, see `crypto`
+static SECRET_SETTINGS: &'static [&'static str] = &["jwt_secret", "vapid_private_key"];
+
+pub fn get_setting(setting: &str) -> Result<Option<String>, HermesError> {
+  use schema::settings::dsl::*;
+
+  let connection = get_connection()?;
+  let stored = settings
+    .find(setting)
+    .select(value)
+    .first::<String>(&*connection)
+    .optional()?;
+  match stored {
+    Some(ref stored) if SECRET_SETTINGS.contains(&setting) => {
+      crypto::open(setting, stored).map(Some)
+    }
+    stored => Ok(stored),
+  }
+}
+
+// stores the setting unless it's already there, returns what is stored
+pub fn add_setting(setting: &str, v: &str) -> Result<String, HermesError> {
+  use schema::settings::dsl::*;
+
+  let sealed = match SECRET_SETTINGS.contains(&setting) {
+    true => crypto::seal(setting, v)?,
+    false => v.to_owned(),
+  };
+  let connection = get_connection()?;
+  diesel::insert_into(settings)
+    .values((name.eq(setting), value.eq(&sealed)))
+    .on_conflict(name)
+    .do_nothing()
+    .execute(&*connection)?;
+  get_setting(setting)?.ok_or(HermesError::NotFound)
+}
+
+// Seals the stored credentials that are still plaintext or use an old key.
+// Every table with credentials in it is gone through here.
+pub fn reseal_secrets() -> Result<usize, HermesError> {
+  use schema::{settings, webhooks, websub_subscriptions};
+
+  let connection = get_connection()?;
+  let stored = settings::table
+    .filter(settings::name.eq_any(SECRET_SETTINGS))
+    .load::<(String, String)>(&*connection)?;
+  let mut resealed = 0;
+  for (n, v) in stored.into_iter().filter(|&(_, ref v)| crypto::needs_reseal(v)) {
+    let sealed = crypto::seal(&n, &crypto::open(&n, &v)?)?;
+    resealed += diesel::update(settings::table.find(&n).filter(settings::value.eq(&v)))
+      .set(settings::value.eq(sealed))
+      .execute(&*connection)?;
+  }
+  let hooks = webhooks::table
+    .select((webhooks::id, webhooks::user_id, webhooks::secret))
+    .load::<(i32, i32, String)>(&*connection)?;
+  for (hid, uid, v) in hooks.into_iter().filter(|&(_, _, ref v)| crypto::needs_reseal(v)) {
+    let context = webhook_context(uid);
+    let sealed = crypto::seal(&context, &crypto::open(&context, &v)?)?;
+    resealed += diesel::update(webhooks::table.find(hid).filter(webhooks::secret.eq(&v)))
+      .set(webhooks::secret.eq(sealed))
+      .execute(&*connection)?;
+  }
+  let subscriptions = websub_subscriptions::table
+    .select((websub_subscriptions::feed_id, websub_subscriptions::secret))
+    .load::<(i32, String)>(&*connection)?;
+  for (fid, v) in subscriptions.into_iter().filter(|&(_, ref v)| crypto::needs_reseal(v)) {
+    let context = websub_context(fid);
+    let sealed = crypto::seal(&context, &crypto::open(&context, &v)?)?;
+    resealed += diesel::update(
+      websub_subscriptions::table
+        .find(fid)
+        .filter(websub_subscriptions::secret.eq(&v)),
+    ).set(websub_subscriptions::secret.eq(sealed))
+    .execute(&*connection)?;
+  }
+  Ok(resealed)
+}
+
+// sanitizing stored items
+
+// items below it were stored before their HTML was sanitized
+pub static UNSANITIZED_ITEMS_SETTING: &'static str = "unsanitized_items_below";
+
+// the HTML of the items below `before` and the site it's relative to, newest first
+pub fn get_item_html_before(
+  before: i32,
+  limit: i64,
+) -> Result<Vec<(i32, Option<String>, Option<String>, String)>, HermesError> {
+  use schema::items;
+
+  let connection = get_connection()?;
+  items::table
+    .inner_join(feeds::table)
+    .filter(items::id.lt(before))
+    .select((items::id, items::summary, items::content, feeds::site_link))
+    .order(items::id.desc())
+    .limit(limit)
+    .load(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn set_item_html(
+  iid: i32,
+  new_summary: Option<&str>,
+  new_content: Option<&str>,
+) -> Result<usize, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(items.find(iid))
+    .set((summary.eq(new_summary), content.eq(new_content)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// lowers the bound as items are cleaned, removing it when none are left
+pub fn set_unsanitized_items_below(bound: Option<i32>) -> Result<usize, HermesError> {
+  use schema::settings::dsl::*;
+
+  let connection = get_connection()?;
+  let setting = settings.find(UNSANITIZED_ITEMS_SETTING);
+  match bound {
+    Some(bound) => diesel::update(setting)
+      .set(value.eq(bound.to_string()))
+      .execute(&*connection),
+    None => diesel::delete(setting).execute(&*connection),
+  }.map_err(HermesError::from)
+}
+
+// channels
+
+pub fn find_feed_by_url(url: &str) -> Result<Feed, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  feeds
+    .filter(feed_link.eq(url))
+    .first::<Feed>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_feed(fid: i32) -> Result<Feed, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  feeds
+    .find(fid)
+    .first::<Feed>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn set_fetch_full_content(fid: i32, enabled: bool) -> Result<usize, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(feeds.find(fid))
+    .set(fetch_full_content.eq(enabled))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_feed_icon(fid: i32) -> Result<FeedIcon, HermesError> {
+  use schema::feed_icons::dsl::*;
+
+  let connection = get_connection()?;
+  feed_icons
+    .find(fid)
+    .first::<FeedIcon>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// feeds with a snapshot are pages watched for changes, see `pages`
+pub fn is_watched_page(fid: i32) -> Result<bool, HermesError> {
+  use schema::page_snapshots::dsl::*;
+
+  let connection = get_connection()?;
+  select(exists(page_snapshots.find(fid)))
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_page_snapshot(fid: i32) -> Result<String, HermesError> {
+  use schema::page_snapshots::dsl::*;
+
+  let connection = get_connection()?;
+  page_snapshots
+    .find(fid)
+    .select(content)
+    .first::<String>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// adds the item for a change of the page and keeps its content as the snapshot
+pub fn record_page_change(item: &NewItem) -> Result<Item, HermesError> {
+  use schema::{items, page_snapshots};
+
+  let snapshot = item.content.as_ref().map(|c| c.as_str()).unwrap_or("");
+  let connection = get_connection()?;
+  connection
+    .transaction(|| {
+      diesel::insert_into(page_snapshots::table)
+        .values((
+          page_snapshots::feed_id.eq(item.feed_id),
+          page_snapshots::content.eq(snapshot),
+        )).on_conflict(page_snapshots::feed_id)
+        .do_update()
+        .set((
+          page_snapshots::content.eq(snapshot),
+          page_snapshots::changed_at.eq(Utc::now()),
+        )).execute(&*connection)?;
+      diesel::insert_into(items::table)
+        .values(item)
+        .get_result::<Item>(&*connection)
+    }).map_err(HermesError::from)
+}
+
+pub fn set_feed_icon(fid: i32, mime: &str, bytes: &[u8]) -> Result<usize, HermesError> {
+  use schema::feed_icons::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(feed_icons)
+    .values((feed_id.eq(fid), content_type.eq(mime), data.eq(bytes)))
+    .on_conflict(feed_id)
+    .do_update()
+    .set((
+      content_type.eq(mime),
+      data.eq(bytes),
+      fetched_at.eq(Utc::now()),
+    )).execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// media cache
+
+// the blob served for `u`, marked as used
+pub fn get_media(u: &str) -> Result<MediaBlob, HermesError> {
+  use schema::{media, media_blobs};
+
+  let connection = get_connection()?;
+  let blob = media::table
+    .inner_join(media_blobs::table)
+    .filter(media::url.eq(u))
+    .select(media_blobs::all_columns)
+    .first::<MediaBlob>(&*connection)?;
+  let now = Utc::now();
+  let touched = diesel::update(media::table.find(u))
+    .set(media::last_used_at.eq(now))
+    .execute(&*connection)
+    .and_then(|_| {
+      diesel::update(media_blobs::table.find(&blob.hash))
+        .set(media_blobs::last_used_at.eq(now))
+        .execute(&*connection)
+    });
+  if let Err(e) = touched {
+    error!("could not mark media '{}' as used: {}", u, e);
+  }
+  Ok(blob)
+}
+
+// Stores the blob unless one with the same hash exists, and points `u` at it.
+pub fn store_media(
+  u: &str,
+  digest: &str,
+  mime: &str,
+  bytes: &[u8],
+) -> Result<MediaBlob, HermesError> {
+  use schema::{media, media_blobs};
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    diesel::insert_into(media_blobs::table)
+      .values((
+        media_blobs::hash.eq(digest),
+        media_blobs::content_type.eq(mime),
+        media_blobs::data.eq(bytes),
+        media_blobs::size.eq(bytes.len() as i64),
+      )).on_conflict_do_nothing()
+      .execute(&*connection)?;
+    let added = diesel::insert_into(media::table)
+      .values((media::url.eq(u), media::hash.eq(digest)))
+      .on_conflict_do_nothing()
+      .execute(&*connection)?;
+    diesel::update(media_blobs::table.find(digest))
+      .set((
+        media_blobs::refs.eq(media_blobs::refs + added as i32),
+        media_blobs::last_used_at.eq(Utc::now()),
+      )).get_result::<MediaBlob>(&*connection)
+  }).map_err(HermesError::from)
+}
+
+// Drops the URLs not served since `older_than`, then the blobs no URL uses.
+pub fn prune_media(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::Timestamptz;
+  use schema::media_blobs;
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    sql_query(
+      "WITH gone AS (DELETE FROM media WHERE last_used_at < $1 RETURNING hash) \
+       UPDATE media_blobs b SET refs = b.refs - g.n \
+       FROM (SELECT hash, count(*) AS n FROM gone GROUP BY hash) g WHERE b.hash = g.hash",
+    ).bind::<Timestamptz, _>(older_than)
+    .execute(&*connection)?;
+    diesel::delete(media_blobs::table.filter(media_blobs::refs.le(0))).execute(&*connection)
+  }).map_err(HermesError::from)
+}
+
+// Evicts the least recently used blobs, with the URLs pointing at them, until
+// the cache holds at most `max_bytes`. Returns how many blobs were evicted.
+pub fn evict_media(max_bytes: i64) -> Result<usize, HermesError> {
+  use diesel::dsl::sum;
+  use schema::{media, media_blobs};
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let total = media_blobs::table
+      .select(sum(media_blobs::size))
+      .first::<Option<i64>>(&*connection)?
+      .unwrap_or(0);
+    if total <= max_bytes {
+      return Ok(0);
+    }
+    let blobs = media_blobs::table
+      .order(media_blobs::last_used_at.asc())
+      .select((media_blobs::hash, media_blobs::size))
+      .load::<(String, i64)>(&*connection)?;
+    let mut excess = total - max_bytes;
+    let evicted: Vec<String> = blobs
+      .into_iter()
+      .take_while(|&(_, size)| {
+        let more = excess > 0;
+        excess -= size;
+        more
+      }).map(|(digest, _)| digest)
+      .collect();
+    diesel::delete(media::table.filter(media::hash.eq_any(&evicted))).execute(&*connection)?;
+    diesel::delete(media_blobs::table.filter(media_blobs::hash.eq_any(&evicted)))
+      .execute(&*connection)
+  }).map_err(HermesError::from)
+}
+
+pub fn set_feed_warnings(fid: i32, warnings: &str) -> Result<usize, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(feeds.find(fid))
+    .set(parse_warnings.eq(warnings))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn search_feeds(q: &str, max: i64) -> Result<Vec<Feed>, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let pattern = format!(
+    "%{}%",
+    q.replace('\\', "\\\\")
+      .replace('%', "\\%")
+      .replace('_', "\\_")
+  );
+  let connection = get_connection()?;
+  feeds
+    .filter(
+      title
+        .ilike(&pattern)
+        .or(description.ilike(&pattern))
+        .or(feed_link.ilike(&pattern))
+        .or(site_link.ilike(&pattern)),
+    ).order(title.asc())
+    .limit(max)
+    .load::<Feed>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_feed_id(url: &str) -> Result<i32, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  feeds
+    .filter(feed_link.eq(url))
+    .select(id)
+    .first(&*connection)
+    .map_err(HermesError::from)
+}
+
+// after a permanent redirect, so the old URL is not fetched again
+pub fn update_feed_link(fid: i32, url: &str) -> Result<usize, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(feeds.find(fid))
+    .set(feed_link.eq(url))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn insert_channel(channel: NewFeed) -> Result<Feed, HermesError> {
+  let connection = get_connection()?;
+
+  diesel::insert_into(feeds::table)
+    .values(&channel)
+    .get_result::<Feed>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// used during update loop
+pub fn get_channel_urls_and_subscribers() -> Result<Vec<(i32, String, Vec<i32>)>, HermesError> {
+  let connection = get_connection()?;
+
+  let subscribed = subscribed_feeds::table
+    .select((subscribed_feeds::feed_id, subscribed_feeds::user_id))
+    .load::<(i32, i32)>(&*connection)?;
+
+  let mut h: HashMap<i32, Vec<i32>> = HashMap::new();
+  subscribed.iter().for_each(|x| {
+    h.entry(x.0)
+      .and_modify(|e| e.push(x.1))
+      .or_insert(vec![x.1]);
+  });
+
+  // let feeds: Result<Vec<(i32, String, Vec<i32>)>, diesel::result::Error> = feeds::table
+  //   .select((feeds::id, feeds::feed_link))
+  //   .load::<(i32, String)>(&*connection)
+  //   .map(|f| {
+  //     f.into_iter()
+  //       .map(|(i, u)| (i, u, h.remove(&i).unwrap()))
+  //       .collect()
+  //   });
+  // info!("result: {:?}", feeds);
+
+  // dead feeds wait until someone asks for them again
+  let feeds = feeds::table
+    .filter(feeds::consecutive_failure_count.lt(Feed::DEAD_AFTER_FAILURES))
+    .select((feeds::id, feeds::feed_link))
+    .load::<(i32, String)>(&*connection)?;
+
+  // feeds without subscribers are left for `delete_orphan_feeds`
+  let res: Vec<(i32, String, Vec<i32>)> = feeds
+    .into_iter()
+    .filter_map(|(i, u)| h.remove(&i).map(|s| (i, u, s)))
+    .collect();
+  Ok(res)
+}
+
+// removes feeds nobody is subscribed to anymore, along with their items
+pub fn delete_orphan_feeds() -> Result<usize, HermesError> {
+  use schema::{
+    content_retries, deleted_items, enclosures, events, feed_icons, feed_rates, feed_stats,
+    item_shares, items, kiosk_tokens, page_snapshots, retention_exemptions, subscribed_items,
+    topic_items, unread_counts,
+  };
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let orphans = feeds::table
+      .filter(not(exists(
+        subscribed_feeds::table.filter(subscribed_feeds::feed_id.eq(feeds::id)),
+      ))).select(feeds::id)
+      .load::<i32>(&*connection)?;
+    if orphans.is_empty() {
+      return Ok(0);
+    }
+    let orphan_items = items::table
+      .filter(items::feed_id.eq_any(&orphans))
+      .select(items::id);
+    diesel::delete(
+      subscribed_items::table.filter(subscribed_items::item_id.eq_any(orphan_items.clone())),
+    ).execute(&*connection)?;
+    diesel::delete(topic_items::table.filter(topic_items::item_id.eq_any(orphan_items.clone())))
+      .execute(&*connection)?;
+    diesel::delete(enclosures::table.filter(enclosures::item_id.eq_any(orphan_items.clone())))
+      .execute(&*connection)?;
+    diesel::delete(item_shares::table.filter(item_shares::item_id.eq_any(orphan_items.clone())))
+      .execute(&*connection)?;
+    diesel::delete(content_retries::table.filter(content_retries::item_id.eq_any(orphan_items)))
+      .execute(&*connection)?;
+    diesel::delete(events::table.filter(events::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(deleted_items::table.filter(deleted_items::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(feed_icons::table.filter(feed_icons::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(feed_stats::table.filter(feed_stats::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(feed_rates::table.filter(feed_rates::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(kiosk_tokens::table.filter(kiosk_tokens::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(page_snapshots::table.filter(page_snapshots::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(
+      retention_exemptions::table.filter(retention_exemptions::feed_id.eq_any(&orphans)),
+    ).execute(&*connection)?;
+    diesel::delete(unread_counts::table.filter(unread_counts::feed_id.eq_any(&orphans)))
+      .execute(&*connection)?;
+    diesel::delete(items::table.filter(items::feed_id.eq_any(&orphans))).execute(&*connection)?;
+    diesel::delete(feeds::table.filter(feeds::id.eq_any(&orphans))).execute(&*connection)
+  }).map_err(HermesError::from)
+}
+
+// fetch leases, so that only one worker refreshes a given feed at a time
+pub fn lease_feed(fid: i32, secs: i64) -> Result<bool, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  let now = Utc::now();
+  diesel::update(
+    feeds
+      .filter(id.eq(fid))
+      .filter(leased_until.is_null().or(leased_until.lt(now))),
+  ).set(leased_until.eq(now + Duration::seconds(secs)))
+  .execute(&*connection)
+  .map(|n| n == 1)
+  .map_err(HermesError::from)
+}
+
+// The same for jobs that every worker runs but one should do, see
+// `feed::start_interval_loops`. The first lease of a job creates its row.
+pub fn lease_job(job: &str, secs: i64) -> Result<bool, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Timestamptz, Varchar};
+
+  let connection = get_connection()?;
+  let now = Utc::now();
+  sql_query(
+    "INSERT INTO job_leases (name, leased_until) VALUES ($1, $2) \
+     ON CONFLICT (name) DO UPDATE SET leased_until = excluded.leased_until \
+     WHERE job_leases.leased_until < $3",
+  ).bind::<Varchar, _>(job)
+  .bind::<Timestamptz, _>(now + Duration::seconds(secs))
+  .bind::<Timestamptz, _>(now)
+  .execute(&*connection)
+  .map(|n| n == 1)
+  .map_err(HermesError::from)
+}
+
+//items
+
+// Adds the new items and refreshes the ones already stored under the same
+// guid, in one statement. Only the added ones are returned; a guid showing up
+// twice is written once, as Postgres won't touch a row twice per statement.
+// Refreshed items count as edited, and are unread again for the subscribers
+// who asked for that.
+pub fn insert_items(new_items: &Vec<NewItem>) -> Result<Vec<Item>, HermesError> {
+  use diesel::dsl::sql;
+  use diesel::pg::upsert::excluded;
+  use diesel::sql_query;
+  use diesel::sql_types::{Array, Bool, Integer};
+  use schema::items::dsl::*;
+
+  let mut guids = HashSet::new();
+  let unique: Vec<&NewItem> = new_items
+    .iter()
+    .filter(|i| guids.insert(i.guid.as_str()))
+    .collect();
+  if unique.is_empty() {
+    return Ok(vec![]);
+  }
+  let connection = get_connection()?;
+  let written = diesel::insert_into(items)
+    .values(unique)
+    .on_conflict((feed_id, guid))
+    .do_update()
+    .set((
+      title.eq(excluded(title)),
+      link.eq(excluded(link)),
+      summary.eq(excluded(summary)),
+      content.eq(excluded(content)),
+      published_at.eq(excluded(published_at)),
+      updated_at.eq(excluded(updated_at)),
+      fingerprint.eq(excluded(fingerprint)),
+      content_hash.eq(excluded(content_hash)),
+      edited_at.eq(Some(Utc::now())),
+    ))
+    // xmax is only set on rows that were there before
+    .returning((schema::items::all_columns, sql::<Bool>("xmax = 0")))
+    .get_results::<(Item, bool)>(&*connection)?;
+  let refreshed: Vec<i32> = written
+    .iter()
+    .filter(|&&(_, added)| !added)
+    .map(|&(ref item, _)| item.id)
+    .collect();
+  debug!("found {} new items, refreshed {}", written.len() - refreshed.len(), refreshed.len());
+  if !refreshed.is_empty() {
+    let unread = sql_query(
+      "UPDATE subscribed_items s SET seen = false \
+       FROM items i, subscribed_feeds f \
+       WHERE i.id = s.item_id AND f.feed_id = i.feed_id AND f.user_id = s.user_id \
+       AND f.unread_on_edit AND s.seen AND s.item_id = ANY($1)",
+    ).bind::<Array<Integer>, _>(&refreshed)
+    .execute(&*connection)?;
+    debug!("marked {} edited items as unread", unread);
+  }
+  Ok(
+    written
+      .into_iter()
+      .filter(|&(_, added)| added)
+      .map(|(item, _)| item)
+      .collect(),
+  )
+}
+
+pub fn insert_enclosures(enclosures: &Vec<NewEnclosure>) -> Result<usize, HermesError> {
+  use schema::enclosures;
+
+  let connection = get_connection()?;
+  diesel::insert_into(enclosures::table)
+    .values(enclosures)
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_enclosures(iids: &Vec<i32>) -> Result<HashMap<i32, Vec<Enclosure>>, HermesError> {
+  use schema::enclosures;
+
+  let mut by_item = HashMap::new();
+  if iids.is_empty() {
+    return Ok(by_item);
+  }
+  let connection = get_connection()?;
+  let found = enclosures::table
+    .filter(enclosures::item_id.eq_any(iids))
+    .order(enclosures::id.asc())
+    .load::<Enclosure>(&*connection)?;
+  for enclosure in found {
+    by_item
+      .entry(enclosure.item_id)
+      .or_insert_with(Vec::new)
+      .push(enclosure);
+  }
+  Ok(by_item)
+}
+
+pub fn set_item_content(iid: i32, html: &str) -> Result<usize, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(items.find(iid))
+    .set(content.eq(html))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// counts the failure; the n-th retry is due `delay_secs * 2^(n-1)` from now,
+// none is scheduled once `max_retries` have been tried
+pub fn record_content_failure(
+  iid: i32,
+  reason: &str,
+  max_retries: i32,
+  delay_secs: i64,
+) -> Result<ContentRetry, HermesError> {
+  use schema::content_retries::dsl::*;
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let failed = content_retries
+      .find(iid)
+      .select(attempts)
+      .first::<i32>(&*connection)
+      .optional()?
+      .unwrap_or(0)
+      + 1;
+    let now = Utc::now();
+    let next = match failed <= max_retries {
+      true => Some(now + Duration::seconds(delay_secs << (failed - 1).min(16))),
+      false => None,
+    };
+    diesel::insert_into(content_retries)
+      .values((
+        item_id.eq(iid),
+        attempts.eq(failed),
+        last_error.eq(reason),
+        next_attempt_at.eq(next),
+      )).on_conflict(item_id)
+      .do_update()
+      .set((
+        attempts.eq(failed),
+        last_error.eq(reason),
+        next_attempt_at.eq(next),
+        updated_at.eq(now),
+      )).get_result::<ContentRetry>(&*connection)
+  }).map_err(HermesError::from)
+}
+
+pub fn clear_content_retry(iid: i32) -> Result<usize, HermesError> {
+  use schema::content_retries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(content_retries.find(iid))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// Takes up to `limit` due retries and pushes them back by `lease_secs`, so
+// other workers skip them while they run. Returns the items' ids and links.
+pub fn claim_content_retries(
+  limit: i64,
+  lease_secs: i64,
+) -> Result<Vec<(i32, String)>, HermesError> {
+  use schema::{content_retries, items};
+
+  let connection = get_connection()?;
+  let now = Utc::now();
+  connection.transaction::<_, diesel::result::Error, _>(|| {
+    let due = content_retries::table
+      .filter(content_retries::next_attempt_at.le(now))
+      .order(content_retries::next_attempt_at.asc())
+      .limit(limit)
+      .select(content_retries::item_id)
+      .load::<i32>(&*connection)?;
+    let ids = diesel::update(
+      content_retries::table
+        .filter(content_retries::item_id.eq_any(&due))
+        .filter(content_retries::next_attempt_at.le(now)),
+    ).set(content_retries::next_attempt_at.eq(now + Duration::seconds(lease_secs)))
+    .returning(content_retries::item_id)
+    .get_results::<i32>(&*connection)?;
+    items::table
+      .filter(items::id.eq_any(&ids))
+      .select((items::id, items::link))
+      .load::<(i32, String)>(&*connection)
+  }).map_err(HermesError::from)
+}
+
+// pending retries first, then the ones that ran out
+pub fn get_content_retries() -> Result<Vec<ContentRetry>, HermesError> {
+  use schema::content_retries::dsl::*;
+
+  let connection = get_connection()?;
+  content_retries
+    .order((next_attempt_at.asc(), updated_at.desc()))
+    .load::<ContentRetry>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_item_content_retries(
+  iids: &Vec<i32>,
+) -> Result<HashMap<i32, ContentRetry>, HermesError> {
+  use schema::content_retries::dsl::*;
+
+  if iids.is_empty() {
+    return Ok(HashMap::new());
+  }
+  let connection = get_connection()?;
+  let retries = content_retries
+    .filter(item_id.eq_any(iids))
+    .load::<ContentRetry>(&*connection)?;
+  Ok(retries.into_iter().map(|retry| (retry.item_id, retry)).collect())
+}
+
+pub fn update_item(iid: i32, item: NewItem) -> Result<usize, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(items.find(iid))
+    .set((
+      title.eq(item.title),
+      link.eq(item.link),
+      summary.eq(item.summary),
+      published_at.eq(item.published_at),
+      content.eq(item.content),
+      guid.eq(item.guid),
+      fingerprint.eq(item.fingerprint),
+      content_hash.eq(item.content_hash),
+    )).execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the feed's stored items sharing a guid, link or fingerprint with `new_items`
+pub fn find_duplicates(fid: i32, new_items: &Vec<NewItem>) -> Result<Vec<ItemKey>, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  let guids: Vec<&str> = new_items.iter().map(|i| i.guid.as_str()).collect();
+  let links: Vec<&str> = new_items.iter().map(|i| i.link.as_str()).collect();
+  let fingerprints: Vec<&str> = new_items
+    .iter()
+    .filter_map(|i| i.fingerprint.as_ref().map(|f| f.as_str()))
+    .collect();
+  items
+    .filter(feed_id.eq(fid))
+    .filter(
+      guid
+        .eq_any(guids)
+        .or(link.eq_any(links))
+        .or(fingerprint.eq_any(fingerprints)),
+    ).select((id, guid, link, fingerprint, published_at, updated_at, content_hash))
+    .load::<ItemKey>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_item_ids(fid: &i32) -> Result<Vec<i32>, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  items
+    .filter(feed_id.eq(fid))
+    .select(id)
+    .load(&*connection)
+    .map_err(HermesError::from)
+}
+
+// one page of a feed's items after `after`, by id; items dated outside of
+// [from, until) are left out, undated ones too when either is given
+pub fn get_feed_items_after(
+  fid: i32,
+  after: i32,
+  from: Option<DateTime<Utc>>,
+  until: Option<DateTime<Utc>>,
+  limit: i64,
+) -> Result<Vec<Item>, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  let mut query = items
+    .filter(feed_id.eq(fid))
+    .filter(id.gt(after))
+    .into_boxed();
+  if let Some(from) = from {
+    query = query.filter(
+      published_at
+        .ge(from)
+        .or(published_at.is_null().and(updated_at.ge(from))),
+    );
+  }
+  if let Some(until) = until {
+    query = query.filter(
+      published_at
+        .lt(until)
+        .or(published_at.is_null().and(updated_at.lt(until))),
+    );
+  }
+  query
+    .order(id.asc())
+    .limit(limit)
+    .load::<Item>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_items_by_ids(ids: &Vec<i32>) -> Result<Vec<Item>, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  items
+    .filter(id.eq_any(ids))
+    .order(published_at.desc())
+    .load::<Item>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_latest_item_date(fid: i32) -> Result<Option<DateTime<Utc>>, HermesError> {
+  use schema::items::dsl::*;
+
+  let connection = get_connection()?;
+  let latest = items
+    .filter(feed_id.eq(fid))
+    .order(published_at.desc())
+    .first::<Item>(&*connection)
+    .optional()?;
+  Ok(latest.and_then(|item| item.published_at))
+}
+
+// events
+
+pub fn insert_event(
+  fid: i32,
+  iids: &Vec<i32>,
+  uids: &Vec<i32>,
+  tid: Option<i32>,
+) -> Result<usize, HermesError> {
+  use schema::events::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(events)
+    .values((
+      feed_id.eq(fid),
+      item_ids.eq(iids),
+      user_ids.eq(uids),
+      topic_id.eq(tid),
+    ))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_latest_event_id() -> Result<i32, HermesError> {
+  use diesel::dsl::max;
+  use schema::events::dsl::*;
+
+  let connection = get_connection()?;
+  events
+    .select(max(id))
+    .first::<Option<i32>>(&*connection)
+    .map(|i| i.unwrap_or(0))
+    .map_err(HermesError::from)
+}
+
+// the events after `eid`, and those of the `missing` ones committed since
+pub fn get_events_since(eid: i32, missing: &Vec<i32>) -> Result<Vec<Event>, HermesError> {
+  use schema::events::dsl::*;
+
+  let connection = get_connection()?;
+  events
+    .filter(id.gt(eid).or(id.eq_any(missing)))
+    .order(id.asc())
+    .load::<Event>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// topic matches for one user, the rest of the events only change counts
+pub fn get_topic_events_since(uid: i32, eid: i32) -> Result<Vec<Event>, HermesError> {
+  use schema::events::dsl::*;
+
+  let connection = get_connection()?;
+  events
+    .filter(id.gt(eid))
+    .filter(topic_id.is_not_null())
+    .filter(user_ids.contains(vec![uid]))
+    .order(id.asc())
+    .load::<Event>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn prune_events(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::events::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(events.filter(created_at.lt(older_than)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// topics
+
+pub fn insert_topic(
+  uid: i32,
+  tname: &str,
+  kws: &Vec<String>,
+  tnotify: bool,
+) -> Result<Topic, HermesError> {
+  use schema::topics::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(topics)
+    .values((
+      user_id.eq(uid),
+      name.eq(tname),
+      keywords.eq(kws),
+      notify.eq(tnotify),
+    )).get_result::<Topic>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_topic(tid: i32, uid: i32) -> Result<Topic, HermesError> {
+  use schema::topics::dsl::*;
+
+  let connection = get_connection()?;
+  topics
+    .filter(id.eq(tid))
+    .filter(user_id.eq(uid))
+    .first::<Topic>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_topics(uid: i32) -> Result<Vec<Topic>, HermesError> {
+  use schema::topics::dsl::*;
+
+  let connection = get_connection()?;
+  topics
+    .filter(user_id.eq(uid))
+    .order(name.asc())
+    .load::<Topic>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_all_topics() -> Result<Vec<Topic>, HermesError> {
+  use schema::topics::dsl::*;
+
+  let connection = get_connection()?;
+  topics.load::<Topic>(&*connection).map_err(HermesError::from)
+}
+
+pub fn delete_topic(tid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::topics::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(topics.filter(id.eq(tid)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn insert_topic_items(matches: &Vec<(i32, i32)>) -> Result<usize, HermesError> {
+  use schema::topic_items::dsl::*;
+
+  let insertables: Vec<_> = matches
+    .iter()
+    .map(|&(tid, iid)| (topic_id.eq(tid), item_id.eq(iid)))
+    .collect();
+  let connection = get_connection()?;
+  diesel::insert_into(topic_items)
+    .values(&insertables)
+    .on_conflict_do_nothing()
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_topic_items(tid: i32, max: i64) -> Result<Vec<(Item, DateTime<Utc>)>, HermesError> {
+  use schema::{items, topic_items};
+
+  let connection = get_connection()?;
+  topic_items::table
+    .inner_join(items::table)
+    .filter(topic_items::topic_id.eq(tid))
+    .order(topic_items::matched_at.desc())
+    .select((items::all_columns, topic_items::matched_at))
+    .limit(max)
+    .load::<(Item, DateTime<Utc>)>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// filters
+
+pub fn insert_filter(uid: i32, params: &FilterParams) -> Result<Filter, HermesError> {
+  use schema::filters::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(filters)
+    .values((
+      user_id.eq(uid),
+      feed_id.eq(params.feed_id),
+      pattern.eq(&params.pattern),
+      match_field.eq(&params.match_field),
+      action.eq(&params.action),
+      enabled.eq(params.enabled),
+    )).get_result::<Filter>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_user_filter(fid: i32, uid: i32) -> Result<Filter, HermesError> {
+  use schema::filters::dsl::*;
+
+  let connection = get_connection()?;
+  filters
+    .filter(id.eq(fid))
+    .filter(user_id.eq(uid))
+    .first::<Filter>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_user_filters(uid: i32) -> Result<Vec<Filter>, HermesError> {
+  use schema::filters::dsl::*;
+
+  let connection = get_connection()?;
+  filters
+    .filter(user_id.eq(uid))
+    .order(id.asc())
+    .load::<Filter>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the enabled filters of any of the users, in the order they were created
+pub fn get_enabled_filters(uids: &[i32]) -> Result<Vec<Filter>, HermesError> {
+  use schema::filters::dsl::*;
+
+  let connection = get_connection()?;
+  filters
+    .filter(user_id.eq_any(uids))
+    .filter(enabled.eq(true))
+    .order(id.asc())
+    .load::<Filter>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn set_filter_enabled(fid: i32, uid: i32, on: bool) -> Result<usize, HermesError> {
+  use schema::filters::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(filters.filter(id.eq(fid)).filter(user_id.eq(uid)))
+    .set(enabled.eq(on))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_filter(fid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::filters::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(filters.filter(id.eq(fid)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// mutes
+
+pub fn insert_mute(uid: i32, params: &MuteParams) -> Result<Mute, HermesError> {
+  use schema::mutes::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(mutes)
+    .values((
+      user_id.eq(uid),
+      feed_id.eq(params.feed_id),
+      phrase.eq(params.phrase.trim()),
+    )).get_result::<Mute>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_user_mutes(uid: i32) -> Result<Vec<Mute>, HermesError> {
+  get_mutes(&[uid])
+}
+
+// the mutes of any of the users
+pub fn get_mutes(uids: &[i32]) -> Result<Vec<Mute>, HermesError> {
+  use schema::mutes::dsl::*;
+
+  let connection = get_connection()?;
+  mutes
+    .filter(user_id.eq_any(uids))
+    .order(id.asc())
+    .load::<Mute>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_mute(mid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::mutes::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(mutes.filter(id.eq(mid)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// push subscriptions
+
+// a browser subscribing again, e.g. with new keys, takes over the endpoint
+pub fn upsert_push_subscription(
+  uid: i32,
+  params: &PushSubscribeParams,
+) -> Result<PushSubscription, HermesError> {
+  use schema::push_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(push_subscriptions)
+    .values((
+      user_id.eq(uid),
+      endpoint.eq(&params.endpoint),
+      p256dh.eq(&params.keys.p256dh),
+      auth.eq(&params.keys.auth),
+    )).on_conflict(endpoint)
+    .do_update()
+    .set((
+      user_id.eq(uid),
+      p256dh.eq(&params.keys.p256dh),
+      auth.eq(&params.keys.auth),
+    )).get_result::<PushSubscription>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_push_subscription(uid: i32, target: &str) -> Result<usize, HermesError> {
+  use schema::push_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(push_subscriptions.filter(user_id.eq(uid)).filter(endpoint.eq(target)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// The subscriptions of the users who want pushes for the feed and still have
+// any of the items unread, e.g. not marked read by a filter or muted.
+pub fn get_push_targets(fid: i32, item_ids: &[i32]) -> Result<Vec<PushSubscription>, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Array, Integer};
+
+  let connection = get_connection()?;
+  sql_query(
+    "SELECT p.* FROM push_subscriptions p \
+     INNER JOIN subscribed_feeds f ON f.user_id = p.user_id \
+     WHERE f.feed_id = $1 AND f.push_notify AND EXISTS ( \
+       SELECT 1 FROM subscribed_items s \
+       WHERE s.user_id = p.user_id AND s.item_id = ANY($2) AND NOT s.seen AND NOT s.suppressed)",
+  ).bind::<Integer, _>(fid)
+  .bind::<Array<Integer>, _>(item_ids)
+  .load::<PushSubscription>(&*connection)
+  .map_err(HermesError::from)
+}
+
+// hides the items from the user, see `mutes`
+pub fn suppress_items(uid: i32, item_ids: &[i32]) -> Result<usize, HermesError> {
+  use schema::subscribed_items;
+
+  let connection = get_connection()?;
+  diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::user_id.eq(uid))
+      .filter(subscribed_items::item_id.eq_any(item_ids)),
+  ).set(subscribed_items::suppressed.eq(true))
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+// the user's muted items, newest first
+pub fn get_muted_items(uid: i32, max: i64) -> Result<Vec<MutedItem>, HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  items::table
+    .inner_join(subscribed_items::table)
+    .filter(subscribed_items::user_id.eq(uid))
+    .filter(subscribed_items::suppressed.eq(true))
+    .order(items::published_at.desc())
+    .select((
+      items::id,
+      items::feed_id,
+      items::link,
+      items::title,
+      items::published_at,
+    )).limit(max)
+    .load::<MutedItem>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// kiosk tokens
+
+pub fn insert_kiosk_token(
+  uid: i32,
+  hash: &str,
+  scope_tag: Option<&str>,
+  scope_feed: Option<i32>,
+  limit: i32,
+  expires: DateTime<Utc>,
+) -> Result<KioskToken, HermesError> {
+  use schema::kiosk_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(kiosk_tokens)
+    .values((
+      user_id.eq(uid),
+      token_hash.eq(hash),
+      tag.eq(scope_tag),
+      feed_id.eq(scope_feed),
+      item_limit.eq(limit),
+      expires_at.eq(expires),
+    )).get_result::<KioskToken>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn insert_api_token(uid: i32, token_name: &str, hash: &str) -> Result<ApiToken, HermesError> {
+  use schema::api_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(api_tokens)
+    .values((user_id.eq(uid), name.eq(token_name), token_hash.eq(hash)))
+    .get_result::<ApiToken>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_api_tokens(uid: i32) -> Result<Vec<ApiToken>, HermesError> {
+  use schema::api_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  api_tokens
+    .filter(user_id.eq(uid))
+    .order(created_at.desc())
+    .load::<ApiToken>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn count_api_tokens(uid: i32) -> Result<i64, HermesError> {
+  use schema::api_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  api_tokens
+    .filter(user_id.eq(uid))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_api_token(token_id: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::api_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(api_tokens.filter(id.eq(token_id)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the token's owner, who has to be able to log in; notes when it was last used,
+// at most once an hour
+pub fn find_user_by_api_token(hash: &str) -> Result<User, HermesError> {
+  use schema::{api_tokens, users};
+
+  let connection = get_connection()?;
+  let (token, user) = api_tokens::table
+    .inner_join(users::table)
+    .filter(api_tokens::token_hash.eq(hash))
+    .filter(users::approved.eq(true))
+    .filter(users::disabled.eq(false))
+    .first::<(ApiToken, User)>(&*connection)?;
+  let now = Utc::now();
+  if token.last_used_at.map(|t| now - t > Duration::hours(1)).unwrap_or(true) {
+    let touched = diesel::update(api_tokens::table.find(token.id))
+      .set(api_tokens::last_used_at.eq(now))
+      .execute(&*connection);
+    if let Err(e) = touched {
+      error!("could not note the use of API token {}: {}", token.id, e);
+    }
+  }
+  Ok(user)
+}
+
+pub fn get_kiosk_tokens(uid: i32) -> Result<Vec<KioskToken>, HermesError> {
+  use schema::kiosk_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  kiosk_tokens
+    .filter(user_id.eq(uid))
+    .filter(expires_at.gt(Utc::now()))
+    .order(created_at.desc())
+    .load::<KioskToken>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// expired tokens are not found, nor those of users who can't log in
+pub fn find_kiosk_token(hash: &str) -> Result<KioskToken, HermesError> {
+  use schema::{kiosk_tokens, users};
+
+  let connection = get_connection()?;
+  kiosk_tokens::table
+    .inner_join(users::table)
+    .filter(kiosk_tokens::token_hash.eq(hash))
+    .filter(kiosk_tokens::expires_at.gt(Utc::now()))
+    .filter(users::approved.eq(true))
+    .filter(users::disabled.eq(false))
+    .select(kiosk_tokens::all_columns)
+    .first::<KioskToken>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_kiosk_token(kid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::kiosk_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(kiosk_tokens.filter(id.eq(kid)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn prune_kiosk_tokens() -> Result<usize, HermesError> {
+  use schema::kiosk_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(kiosk_tokens.filter(expires_at.le(Utc::now())))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// item shares
+
+pub fn insert_item_share(uid: i32, iid: i32, share_token: &str) -> Result<ItemShare, HermesError> {
+  use schema::item_shares::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(item_shares)
+    .values((user_id.eq(uid), item_id.eq(iid), token.eq(share_token)))
+    .get_result::<ItemShare>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_item_shares(uid: i32) -> Result<Vec<ItemShare>, HermesError> {
+  use schema::item_shares::dsl::*;
+
+  let connection = get_connection()?;
+  item_shares
+    .filter(user_id.eq(uid))
+    .order(created_at.desc())
+    .load::<ItemShare>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the shared item with the title of its feed, while its sharer can log in
+pub fn find_shared_item(share_token: &str) -> Result<(Item, String), HermesError> {
+  use schema::{item_shares, items, users};
+
+  let connection = get_connection()?;
+  item_shares::table
+    .inner_join(items::table.inner_join(feeds::table))
+    .inner_join(users::table)
+    .filter(item_shares::token.eq(share_token))
+    .filter(users::approved.eq(true))
+    .filter(users::disabled.eq(false))
+    .select((items::all_columns, feeds::title))
+    .first::<(Item, String)>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// returns the token of the deleted share
+pub fn delete_item_share(sid: i32, uid: i32) -> Result<Option<String>, HermesError> {
+  use schema::item_shares::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(item_shares.filter(id.eq(sid)).filter(user_id.eq(uid)))
+    .returning(token)
+    .get_result::<String>(&*connection)
+    .optional()
+    .map_err(HermesError::from)
+}
+
+// failed deliveries
+
+pub fn insert_failed_delivery(
+  uid: i32,
+  delivery_kind: &str,
+  delivery_target: &str,
+  delivery_headers: &str,
+  delivery_payload: &str,
+  delivery_error: &str,
+  delivery_attempts: i32,
+) -> Result<FailedDelivery, HermesError> {
+  use schema::failed_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(failed_deliveries)
+    .values((
+      user_id.eq(uid),
+      kind.eq(delivery_kind),
+      target.eq(delivery_target),
+      headers.eq(delivery_headers),
+      payload.eq(delivery_payload),
+      error.eq(delivery_error),
+      attempts.eq(delivery_attempts),
+    )).get_result::<FailedDelivery>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_failed_deliveries(uid: i32) -> Result<Vec<FailedDelivery>, HermesError> {
+  use schema::failed_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  failed_deliveries
+    .filter(user_id.eq(uid))
+    .order(failed_at.desc())
+    .load::<FailedDelivery>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_failed_delivery(did: i32, uid: i32) -> Result<FailedDelivery, HermesError> {
+  use schema::failed_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  failed_deliveries
+    .filter(id.eq(did))
+    .filter(user_id.eq(uid))
+    .first::<FailedDelivery>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn record_failed_replay(did: i32, replay_error: &str) -> Result<usize, HermesError> {
+  use schema::failed_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(failed_deliveries.find(did))
+    .set((
+      error.eq(replay_error),
+      attempts.eq(attempts + 1),
+      failed_at.eq(Utc::now()),
+    )).execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_failed_delivery(did: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::failed_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(failed_deliveries.filter(id.eq(did)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn prune_failed_deliveries(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::failed_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(failed_deliveries.filter(failed_at.lt(older_than)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// webhooks
+
+fn webhook_context(uid: i32) -> String {
+  format!("webhooks:{}", uid)
+}
+
+pub fn insert_webhook(
+  uid: i32,
+  params: &WebhookParams,
+  plain_secret: &str,
+) -> Result<Webhook, HermesError> {
+  use schema::webhooks::dsl::*;
+
+  let sealed = crypto::seal(&webhook_context(uid), plain_secret)?;
+  let connection = get_connection()?;
+  diesel::insert_into(webhooks)
+    .values((
+      user_id.eq(uid),
+      url.eq(&params.url),
+      feed_id.eq(params.feed_id),
+      secret.eq(&sealed),
+    )).get_result::<Webhook>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn open_webhook_secret(webhook: &Webhook) -> Result<String, HermesError> {
+  crypto::open(&webhook_context(webhook.user_id), &webhook.secret)
+}
+
+pub fn get_webhooks(uid: i32) -> Result<Vec<Webhook>, HermesError> {
+  use schema::webhooks::dsl::*;
+
+  let connection = get_connection()?;
+  webhooks
+    .filter(user_id.eq(uid))
+    .order(id.asc())
+    .load::<Webhook>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the webhooks of the users that cover the feed
+pub fn get_feed_webhooks(fid: i32, uids: &[i32]) -> Result<Vec<Webhook>, HermesError> {
+  use schema::webhooks::dsl::*;
+
+  let connection = get_connection()?;
+  let covering = webhooks
+    .filter(user_id.eq_any(uids))
+    .load::<Webhook>(&*connection)?
+    .into_iter()
+    .filter(|w| w.feed_id.map(|f| f == fid).unwrap_or(true))
+    .collect();
+  Ok(covering)
+}
+
+pub fn count_webhooks(uid: i32) -> Result<i64, HermesError> {
+  use schema::webhooks::dsl::*;
+
+  let connection = get_connection()?;
+  webhooks
+    .filter(user_id.eq(uid))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+// its deliveries go with it
+pub fn delete_webhook(hid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::webhooks::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(webhooks.filter(id.eq(hid)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn insert_webhook_delivery(
+  hid: i32,
+  iid: i32,
+  outcome: &Outcome,
+) -> Result<usize, HermesError> {
+  use schema::webhook_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(webhook_deliveries)
+    .values((
+      webhook_id.eq(hid),
+      item_id.eq(iid),
+      attempts.eq(outcome.attempts),
+      error.eq(&outcome.error),
+    )).execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// Err(NotFound) when the webhook isn't the user's
+pub fn get_webhook_deliveries(
+  hid: i32,
+  uid: i32,
+  max: i64,
+) -> Result<Vec<WebhookDelivery>, HermesError> {
+  use schema::{webhook_deliveries, webhooks};
+
+  let connection = get_connection()?;
+  webhooks::table
+    .filter(webhooks::id.eq(hid))
+    .filter(webhooks::user_id.eq(uid))
+    .select(webhooks::id)
+    .first::<i32>(&*connection)?;
+  webhook_deliveries::table
+    .filter(webhook_deliveries::webhook_id.eq(hid))
+    .order(webhook_deliveries::finished_at.desc())
+    .limit(max)
+    .load::<WebhookDelivery>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn prune_webhook_deliveries(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::webhook_deliveries::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(webhook_deliveries.filter(finished_at.lt(older_than)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// websub
+
+fn websub_context(fid: i32) -> String {
+  format!("websub:{}", fid)
+}
+
+// a new subscription, replacing any the feed had with another hub or topic
+pub fn upsert_websub_subscription(
+  fid: i32,
+  hub_url: &str,
+  topic_url: &str,
+  new_token: &str,
+  plain_secret: &str,
+) -> Result<WebsubSubscription, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let sealed = crypto::seal(&websub_context(fid), plain_secret)?;
+  let connection = get_connection()?;
+  diesel::insert_into(websub_subscriptions)
+    .values((
+      feed_id.eq(fid),
+      hub.eq(hub_url),
+      topic.eq(topic_url),
+      token.eq(new_token),
+      secret.eq(&sealed),
+    )).on_conflict(feed_id)
+    .do_update()
+    .set((
+      hub.eq(hub_url),
+      topic.eq(topic_url),
+      token.eq(new_token),
+      secret.eq(&sealed),
+      requested_at.eq(Utc::now()),
+      expires_at.eq(None::<DateTime<Utc>>),
+    )).get_result::<WebsubSubscription>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn open_websub_secret(subscription: &WebsubSubscription) -> Result<String, HermesError> {
+  crypto::open(&websub_context(subscription.feed_id), &subscription.secret)
+}
+
+pub fn get_websub_subscription(fid: i32) -> Result<WebsubSubscription, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  websub_subscriptions
+    .find(fid)
+    .first::<WebsubSubscription>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn find_websub_subscription(callback_token: &str) -> Result<WebsubSubscription, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  websub_subscriptions
+    .filter(token.eq(callback_token))
+    .first::<WebsubSubscription>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// The subscriptions running out before `before`, and those the hub hasn't
+// confirmed since `requested_before`, both due for another request.
+pub fn get_due_websub_subscriptions(
+  before: DateTime<Utc>,
+  requested_before: DateTime<Utc>,
+) -> Result<Vec<WebsubSubscription>, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  let due = websub_subscriptions
+    .filter(requested_at.lt(requested_before))
+    .load::<WebsubSubscription>(&*connection)?
+    .into_iter()
+    .filter(|s| s.expires_at.map(|e| e < before).unwrap_or(true))
+    .collect();
+  Ok(due)
+}
+
+// the feeds a hub has confirmed pushing updates of
+pub fn get_pushed_feeds() -> Result<HashSet<i32>, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  websub_subscriptions
+    .filter(expires_at.gt(Utc::now()))
+    .select(feed_id)
+    .load::<i32>(&*connection)
+    .map(|ids| ids.into_iter().collect())
+    .map_err(HermesError::from)
+}
+
+// feeds whose last fetches failed, with how many in a row
+pub fn get_failing_feeds() -> Result<HashMap<i32, i32>, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  feeds
+    .filter(consecutive_failure_count.gt(0))
+    .select((id, consecutive_failure_count))
+    .load::<(i32, i32)>(&*connection)
+    .map(|failing| failing.into_iter().collect())
+    .map_err(HermesError::from)
+}
+
+pub fn touch_websub_request(fid: i32) -> Result<usize, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(websub_subscriptions.find(fid))
+    .set(requested_at.eq(Utc::now()))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn confirm_websub_subscription(fid: i32, until: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(websub_subscriptions.find(fid))
+    .set(expires_at.eq(until))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_websub_subscription(fid: i32) -> Result<usize, HermesError> {
+  use schema::websub_subscriptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(websub_subscriptions.find(fid))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// retention exemptions
+
+pub fn insert_retention_exemption(
+  uid: i32,
+  exempt_tag: Option<&str>,
+  fid: Option<i32>,
+) -> Result<RetentionExemption, HermesError> {
+  use schema::retention_exemptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(retention_exemptions)
+    .values((user_id.eq(uid), tag.eq(exempt_tag), feed_id.eq(fid)))
+    .get_result::<RetentionExemption>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_retention_exemptions(uid: i32) -> Result<Vec<RetentionExemption>, HermesError> {
+  use schema::retention_exemptions::dsl::*;
+
+  let connection = get_connection()?;
+  retention_exemptions
+    .filter(user_id.eq(uid))
+    .order(created_at.asc())
+    .load::<RetentionExemption>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn delete_retention_exemption(eid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::retention_exemptions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(retention_exemptions.filter(id.eq(eid)).filter(user_id.eq(uid)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// users
+
+pub fn get_user(uname: &str) -> Result<User, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  users
+    .filter(username.eq(uname))
+    .first::<User>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn create_user(uname: &str, pw_hash: &str) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(users)
+    .values((username.eq(uname), password_hash.eq(pw_hash.as_bytes())))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn find_user_by_fever_key(key_hash: &str) -> Result<User, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  users
+    .filter(fever_key_hash.eq(key_hash))
+    .filter(approved.eq(true))
+    .filter(disabled.eq(false))
+    .first::<User>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_user_locale(uid: i32) -> Result<String, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  users
+    .find(uid)
+    .select(locale)
+    .first::<String>(&*connection)
+    .optional()
+    .map(|l| l.unwrap_or_else(|| i18n::DEFAULT_LOCALE.to_owned()))
+    .map_err(HermesError::from)
+}
+
+pub fn get_user_rate_warning(uid: i32) -> Result<f64, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  users
+    .find(uid)
+    .select(rate_warning)
+    .first::<f64>(&*connection)
+    .optional()
+    .map(|r| r.unwrap_or(User::DEFAULT_RATE_WARNING))
+    .map_err(HermesError::from)
+}
+
+pub fn set_user_rate_warning(uid: i32, items_per_day: f64) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(rate_warning.eq(items_per_day))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn set_user_auto_read(uid: i32, days: Option<i32>) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(auto_read_days.eq(days))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// marks the unstarred items older than their users' `auto_read_days` as read;
+// the unread counts follow through their trigger
+pub fn auto_read_items() -> Result<usize, HermesError> {
+  use diesel::sql_query;
+
+  let connection = get_connection()?;
+  sql_query(
+    "UPDATE subscribed_items s SET seen = true \
+     FROM users u, items i \
+     WHERE u.auto_read_days IS NOT NULL AND s.user_id = u.id AND i.id = s.item_id \
+     AND NOT s.seen AND NOT s.starred \
+     AND coalesce(i.published_at, i.updated_at) < now() - u.auto_read_days * INTERVAL '1 day'",
+  ).execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+pub fn set_user_locale(uid: i32, new_locale: &str) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(locale.eq(new_locale))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn set_fever_key(uid: i32, key_hash: &str) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(fever_key_hash.eq(key_hash))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn approve_user(uid: i32) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(approved.eq(true))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn is_admin(uid: i32) -> Result<bool, HermesError> {
+  use schema::users;
+
+  let connection = get_connection()?;
+  users::table
+    .find(uid)
+    .select(users::is_admin)
+    .first::<bool>(&*connection)
+    .optional()
+    .map(|admin| admin.unwrap_or(false))
+    .map_err(HermesError::from)
+}
+
+pub fn set_user_disabled(uid: i32, is_disabled: bool) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(disabled.eq(is_disabled))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn set_password(uid: i32, pw_hash: &str) -> Result<usize, HermesError> {
+  use schema::users::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(users.find(uid))
+    .set(password_hash.eq(pw_hash.as_bytes()))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the user and everything that is theirs; 0 when there was no such user
+pub fn delete_user(uid: i32) -> Result<usize, HermesError> {
+  use schema::{
+    activity_log, api_tokens, deleted_items, failed_deliveries, filters, invites, item_shares,
+    item_state_log, kiosk_tokens, mutes, notifications, push_subscriptions, quotas,
+    retention_exemptions, sessions, subscribed_items, topics, unread_counts, users, webhooks,
+  };
+
+  let connection = get_connection()?;
+  connection
+    .transaction::<_, diesel::result::Error, _>(|| {
+      // the item triggers log and count these, so their tables come after
+      diesel::delete(subscribed_items::table.filter(subscribed_items::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(subscribed_feeds::table.filter(subscribed_feeds::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(item_state_log::table.filter(item_state_log::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(unread_counts::table.filter(unread_counts::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(topics::table.filter(topics::user_id.eq(uid))).execute(&*connection)?;
+      diesel::delete(filters::table.filter(filters::user_id.eq(uid))).execute(&*connection)?;
+      diesel::delete(mutes::table.filter(mutes::user_id.eq(uid))).execute(&*connection)?;
+      diesel::delete(push_subscriptions::table.filter(push_subscriptions::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(webhooks::table.filter(webhooks::user_id.eq(uid))).execute(&*connection)?;
+      diesel::delete(item_shares::table.filter(item_shares::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(kiosk_tokens::table.filter(kiosk_tokens::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(api_tokens::table.filter(api_tokens::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(notifications::table.filter(notifications::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(quotas::table.filter(quotas::user_id.eq(uid))).execute(&*connection)?;
+      diesel::delete(retention_exemptions::table.filter(retention_exemptions::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(failed_deliveries::table.filter(failed_deliveries::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(deleted_items::table.filter(deleted_items::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(sessions::table.filter(sessions::user_id.eq(uid))).execute(&*connection)?;
+      diesel::delete(activity_log::table.filter(activity_log::user_id.eq(uid)))
+        .execute(&*connection)?;
+      diesel::delete(invites::table.filter(invites::created_by.eq(uid))).execute(&*connection)?;
+      diesel::delete(invites::table.filter(invites::used_by.eq(uid))).execute(&*connection)?;
+      diesel::delete(users::table.find(uid)).execute(&*connection)
+    }).map_err(HermesError::from)
+}
+
+pub fn get_user_summaries(page: Page) -> Result<(Vec<UserSummary>, i64), HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::BigInt;
+  use schema::users;
+
+  let connection = get_connection()?;
+  let total = users::table.count().get_result(&*connection)?;
+  let summaries = sql_query(
+    "SELECT u.id, u.username, u.approved, u.is_admin, u.disabled, \
+       count(s.feed_id) AS subscriptions \
+     FROM users u LEFT JOIN subscribed_feeds s ON s.user_id = u.id \
+     GROUP BY u.id ORDER BY u.id LIMIT $1 OFFSET $2",
+  ).bind::<BigInt, _>(page.limit)
+  .bind::<BigInt, _>(page.offset)
+  .load::<UserSummary>(&*connection)?;
+  Ok((summaries, total))
+}
+
+#[derive(Debug)]
+pub enum RegisterFailure {
+  InvalidInvite,
+  Database(HermesError),
+}
+
+// creates the user and, if given, uses up the invite in the same transaction
+pub fn register_user(
+  uname: &str,
+  pw_hash: &str,
+  is_approved: bool,
+  invite: Option<&str>,
+) -> Result<User, RegisterFailure> {
+  use diesel::result::Error;
+  use schema::{invites, users};
+
+  let connection = get_connection().map_err(RegisterFailure::Database)?;
+  let mut invalid_invite = false;
+  let res = connection.transaction::<User, Error, _>(|| {
+    let user = diesel::insert_into(users::table)
+      .values((
+        users::username.eq(uname),
+        users::password_hash.eq(pw_hash.as_bytes()),
+        users::approved.eq(is_approved),
+      )).get_result::<User>(&*connection)?;
+    if let Some(c) = invite {
+      let claimed = diesel::update(
+        invites::table
+          .filter(invites::code.eq(c))
+          .filter(invites::used_by.is_null()),
+      ).set(invites::used_by.eq(user.id))
+      .execute(&*connection)?;
+      if claimed != 1 {
+        invalid_invite = true;
+        return Err(Error::RollbackTransaction);
+      }
+    }
+    Ok(user)
+  });
+  match res {
+    Ok(user) => Ok(user),
+    Err(_) if invalid_invite => Err(RegisterFailure::InvalidInvite),
+    Err(e) => Err(RegisterFailure::Database(e.into())),
+  }
+}
+
+// revoked tokens
+
+pub fn revoke_token(
+  token_id: &str,
+  expires: DateTime<Utc>,
+) -> Result<usize, HermesError> {
+  use schema::revoked_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  // expired tokens are rejected anyway, no need to remember them
+  diesel::delete(revoked_tokens.filter(expires_at.lt(Utc::now()))).execute(&*connection)?;
+  diesel::insert_into(revoked_tokens)
+    .values((jti.eq(token_id), expires_at.eq(expires)))
+    .on_conflict_do_nothing()
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn is_token_revoked(token_id: &str) -> Result<bool, HermesError> {
+  use schema::revoked_tokens::dsl::*;
+
+  let connection = get_connection()?;
+  select(exists(revoked_tokens.filter(jti.eq(token_id))))
+    .get_result::<bool>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// sessions
+
+// a login or registration, i.e. a new device
+pub fn start_session(
+  uid: i32,
+  token_id: &str,
+  expires: DateTime<Utc>,
+) -> Result<Session, HermesError> {
+  use schema::sessions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::insert_into(sessions)
+    .values((user_id.eq(uid), jti.eq(token_id), expires_at.eq(expires)))
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+// a refreshed token keeps its place in the session list; tokens issued before
+// sessions were tracked get a session now
+pub fn renew_session(
+  uid: i32,
+  old_token_id: &str,
+  token_id: &str,
+  expires: DateTime<Utc>,
+) -> Result<Session, HermesError> {
+  use schema::sessions::dsl::*;
+
+  let connection = get_connection()?;
+  let renewed = diesel::update(sessions.filter(jti.eq(old_token_id)))
+    .set((jti.eq(token_id), expires_at.eq(expires)))
+    .get_result::<Session>(&*connection)
+    .optional()?;
+  match renewed {
+    Some(session) => Ok(session),
+    None => start_session(uid, token_id, expires),
+  }
+}
+
+pub fn end_session(token_id: &str) -> Result<usize, HermesError> {
+  use schema::sessions::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(sessions.filter(jti.eq(token_id)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_session_id(token_id: &str) -> Result<Option<i32>, HermesError> {
+  use schema::sessions::dsl::*;
+
+  let connection = get_connection()?;
+  sessions
+    .filter(jti.eq(token_id))
+    .select(id)
+    .first::<i32>(&*connection)
+    .optional()
+    .map_err(HermesError::from)
+}
+
+// keeps the user's `keep` newest sessions and revokes the tokens of the rest
+pub fn trim_sessions(uid: i32, keep: i64) -> Result<Vec<Session>, HermesError> {
+  use schema::revoked_tokens;
+  use schema::sessions::dsl::*;
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    diesel::delete(sessions.filter(expires_at.lt(Utc::now()))).execute(&*connection)?;
+    let stale = sessions
+      .filter(user_id.eq(uid))
+      .order((created_at.desc(), id.desc()))
+      .offset(keep)
+      .load::<Session>(&*connection)?;
+    let revoked: Vec<_> = stale
+      .iter()
+      .map(|s| {
+        (
+          revoked_tokens::jti.eq(&s.jti),
+          revoked_tokens::expires_at.eq(s.expires_at),
+        )
+      }).collect();
+    diesel::insert_into(revoked_tokens::table)
+      .values(&revoked)
+      .on_conflict_do_nothing()
+      .execute(&*connection)?;
+    let ids: Vec<i32> = stale.iter().map(|s| s.id).collect();
+    diesel::delete(sessions.filter(id.eq_any(ids))).execute(&*connection)?;
+    Ok(stale)
+  }).map_err(HermesError::from)
+}
+
+// activity
+
+// best effort, a request doesn't fail for want of its log entry
+pub fn log_activity(uid: i32, what: Action, info: Option<&str>) {
+  use schema::activity_log::dsl::*;
+
+  let logged = get_connection().and_then(|connection| {
+    diesel::insert_into(activity_log)
+      .values((user_id.eq(uid), action.eq(what.as_str()), detail.eq(info)))
+      .execute(&*connection)
+      .map_err(HermesError::from)
+  });
+  if let Err(e) = logged {
+    error!("could not log '{}' for user '{}': {}", what.as_str(), uid, e);
+  }
+}
+
+pub fn get_activity(uid: i32, max: i64) -> Result<Vec<Activity>, HermesError> {
+  use schema::activity_log::dsl::*;
+
+  let connection = get_connection()?;
+  activity_log
+    .filter(user_id.eq(uid))
+    .order(created_at.desc())
+    .limit(max)
+    .load::<Activity>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn prune_activity(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::activity_log::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(activity_log.filter(created_at.lt(older_than)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// notifications
+
+pub fn notify(
+  uids: &Vec<i32>,
+  what: NotificationKind,
+  text: &str,
+  info: Option<&str>,
+) -> Result<usize, HermesError> {
+  use schema::notifications::dsl::*;
+
+  let connection = get_connection()?;
+  let rows: Vec<_> = uids
+    .iter()
+    .map(|uid| {
+      (
+        user_id.eq(uid),
+        kind.eq(what.as_str()),
+        message.eq(text),
+        detail.eq(info),
+      )
+    }).collect();
+  diesel::insert_into(notifications)
+    .values(rows)
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// every user gets a copy, so each can mark it read on their own
+pub fn notify_all(
+  what: NotificationKind,
+  text: &str,
+  info: Option<&str>,
+) -> Result<usize, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Nullable, Text};
+
+  let connection = get_connection()?;
+  sql_query(
+    "INSERT INTO notifications (user_id, kind, message, detail) \
+     SELECT id, $1, $2, $3 FROM users",
+  ).bind::<Text, _>(what.as_str())
+  .bind::<Text, _>(text)
+  .bind::<Nullable<Text>, _>(info)
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+pub fn get_notifications(
+  uid: i32,
+  unseen_only: bool,
+  page: Page,
+) -> Result<(Vec<Notification>, i64), HermesError> {
+  use schema::notifications::dsl::*;
+
+  let connection = get_connection()?;
+  let mut query = notifications.filter(user_id.eq(uid)).into_boxed();
+  let mut count = notifications.filter(user_id.eq(uid)).into_boxed();
+  if unseen_only {
+    query = query.filter(seen.eq(false));
+    count = count.filter(seen.eq(false));
+  }
+  let found = query
+    .order(id.desc())
+    .limit(page.limit)
+    .offset(page.offset)
+    .load::<Notification>(&*connection)?;
+  let total = count.count().get_result::<i64>(&*connection)?;
+  Ok((found, total))
+}
+
+// one of the user's notifications, or all of them without `nid`
+pub fn mark_notifications_seen(uid: i32, nid: Option<i32>) -> Result<usize, HermesError> {
+  use schema::notifications::dsl::*;
+
+  let connection = get_connection()?;
+  match nid {
+    Some(nid) => diesel::update(notifications.filter(user_id.eq(uid)).filter(id.eq(nid)))
+      .set(seen.eq(true))
+      .execute(&*connection),
+    None => diesel::update(notifications.filter(user_id.eq(uid)).filter(seen.eq(false)))
+      .set(seen.eq(true))
+      .execute(&*connection),
+  }.map_err(HermesError::from)
+}
+
+pub fn get_latest_notification_id() -> Result<i32, HermesError> {
+  use diesel::dsl::max;
+  use schema::notifications::dsl::*;
+
+  let connection = get_connection()?;
+  notifications
+    .select(max(id))
+    .first::<Option<i32>>(&*connection)
+    .map(|i| i.unwrap_or(0))
+    .map_err(HermesError::from)
+}
+
+pub fn get_notifications_since(
+  nid: i32,
+  missing: &Vec<i32>,
+) -> Result<Vec<Notification>, HermesError> {
+  use schema::notifications::dsl::*;
+
+  let connection = get_connection()?;
+  notifications
+    .filter(id.gt(nid).or(id.eq_any(missing)))
+    .order(id.asc())
+    .load::<Notification>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// item ids the user had unseen at `as_of`, from the item_state_log trigger's
+// rows: the last change up to then, or else what the first change after it
+// started from, or else the current state of items that never changed
+pub fn get_unread_as_of(uid: i32, as_of: DateTime<Utc>) -> Result<Vec<i32>, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Integer, Timestamptz};
+
+  #[derive(QueryableByName)]
+  struct Unread {
+    #[sql_type = "Integer"]
+    item_id: i32,
+  }
+
+  let connection = get_connection()?;
+  let unread = sql_query(
+    "WITH before AS ( \
+     SELECT DISTINCT ON (item_id) item_id, change, seen FROM item_state_log \
+     WHERE user_id = $1 AND changed_at <= $2 ORDER BY item_id, changed_at DESC, id DESC \
+     ), after AS ( \
+     SELECT DISTINCT ON (item_id) item_id, change, was_seen FROM item_state_log \
+     WHERE user_id = $1 AND changed_at > $2 ORDER BY item_id, changed_at, id \
+     ) \
+     SELECT item_id FROM before WHERE change <> 'removed' AND NOT seen \
+     UNION \
+     SELECT item_id FROM after WHERE change <> 'added' AND NOT was_seen \
+     AND item_id NOT IN (SELECT item_id FROM before) \
+     UNION \
+     SELECT item_id FROM subscribed_items WHERE user_id = $1 AND NOT seen \
+     AND item_id NOT IN (SELECT item_id FROM before) \
+     AND item_id NOT IN (SELECT item_id FROM after) \
+     ORDER BY item_id",
+  ).bind::<Integer, _>(uid)
+  .bind::<Timestamptz, _>(as_of)
+  .load::<Unread>(&*connection)?;
+  Ok(unread.into_iter().map(|u| u.item_id).collect())
+}
+
+// the oldest change still logged, reconstructions before it are guesses
+pub fn get_item_state_log_start() -> Result<Option<DateTime<Utc>>, HermesError> {
+  use diesel::dsl::min;
+  use schema::item_state_log::dsl::*;
+
+  let connection = get_connection()?;
+  item_state_log
+    .select(min(changed_at))
+    .first::<Option<DateTime<Utc>>>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_latest_item_state_id() -> Result<i64, HermesError> {
+  use diesel::dsl::max;
+  use schema::item_state_log::dsl::*;
+
+  let connection = get_connection()?;
+  item_state_log
+    .select(max(id))
+    .first::<Option<i64>>(&*connection)
+    .map(|i| i.unwrap_or(0))
+    .map_err(HermesError::from)
+}
+
+// feeds whose items changed state for the user after the given log entry
+pub fn get_changed_feed_ids(uid: i32, since: i64) -> Result<Vec<i32>, HermesError> {
+  use schema::{item_state_log, items};
+
+  let connection = get_connection()?;
+  item_state_log::table
+    .inner_join(items::table.on(items::id.eq(item_state_log::item_id)))
+    .filter(item_state_log::user_id.eq(uid))
+    .filter(item_state_log::id.gt(since))
+    .select(items::feed_id)
+    .distinct()
+    .load::<i32>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn prune_item_state_log(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::item_state_log::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(item_state_log.filter(changed_at.lt(older_than)))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
+
+// read notifications are kept for a while, unread ones until they are read
+pub fn prune_notifications(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::notifications::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::delete(
+    notifications
+      .filter(seen.eq(true))
+      .filter(created_at.lt(older_than)),
+  ).execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+// feed stats
+
+// adds to the feed's counters for `on`, creating the row if needed
+fn add_feed_stats(
+  connection: &PgConnection,
+  fid: i32,
+  on: NaiveDate,
+  new_items: i32,
+  fetched: i64,
+  failed: Option<bool>,
+) -> QueryResult<usize> {
+  use schema::feed_stats;
+
+  let fetches = failed.map(|_| 1).unwrap_or(0);
+  let failures = failed.map(|f| f as i32).unwrap_or(0);
+  diesel::insert_into(feed_stats::table)
+    .values((
+      feed_stats::feed_id.eq(fid),
+      feed_stats::day.eq(on),
+      feed_stats::items.eq(new_items),
+      feed_stats::bytes.eq(fetched),
+      feed_stats::fetches.eq(fetches),
+      feed_stats::failures.eq(failures),
+    )).on_conflict((feed_stats::feed_id, feed_stats::day))
+    .do_update()
+    .set((
+      feed_stats::items.eq(feed_stats::items + new_items),
+      feed_stats::bytes.eq(feed_stats::bytes + fetched),
+      feed_stats::fetches.eq(feed_stats::fetches + fetches),
+      feed_stats::failures.eq(feed_stats::failures + failures),
+    )).execute(connection)
+}
+
+// `error` is None for a fetch that worked, which clears the failure count
+pub fn record_fetch_status(
+  fid: i32,
+  status: Option<i32>,
+  error: Option<String>,
+) -> Result<i32, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  let failures = match error {
+    Some(e) => diesel::update(feeds.find(fid))
+      .set((
+        last_fetch_status.eq(status),
+        consecutive_failure_count.eq(consecutive_failure_count + 1),
+        last_error.eq(e),
+      )).returning(consecutive_failure_count)
+      .get_result::<i32>(&*connection),
+    None => diesel::update(feeds.find(fid))
+      .set((
+        last_fetch_status.eq(status),
+        consecutive_failure_count.eq(0),
+        last_error.eq(None::<String>),
+      )).returning(consecutive_failure_count)
+      .get_result::<i32>(&*connection),
+  }?;
+  if failures == Feed::DEAD_AFTER_FAILURES {
+    warn!("feed '{}' failed {} times in a row, not fetching it anymore", fid, failures);
   }
+  Ok(failures)
 }
 
-// channels
+pub fn reset_fetch_failures(fid: i32) -> Result<usize, HermesError> {
+  use schema::feeds::dsl::*;
+
+  let connection = get_connection()?;
+  diesel::update(feeds.find(fid))
+    .set(consecutive_failure_count.eq(0))
+    .execute(&*connection)
+    .map_err(HermesError::from)
+}
 
-pub fn find_feed_by_url(url: &str) -> Option<Feed> {
+pub fn get_fetch_statuses(fids: &Vec<i32>) -> Result<HashMap<i32, FetchStatus>, HermesError> {
   use schema::feeds::dsl::*;
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  match feeds.filter(feed_link.eq(url)).first::<Feed>(&*connection) {
-    Ok(ch) => Some(ch),
-    Err(_) => None,
+  let connection = get_connection()?;
+  if fids.is_empty() {
+    return Ok(HashMap::new());
   }
+  let statuses = feeds
+    .filter(id.eq_any(fids))
+    .select((id, last_fetch_status, consecutive_failure_count, last_error))
+    .load::<FetchStatus>(&*connection)?;
+  Ok(statuses.into_iter().map(|status| (status.feed_id, status)).collect())
 }
 
-pub fn get_feed_id(url: &str) -> Result<i32, diesel::result::Error> {
-  use schema::feeds::dsl::*;
+pub fn count_feed_fetch(fid: i32, fetched: usize, failed: bool) -> Result<usize, HermesError> {
+  let connection = get_connection()?;
+  let today = Utc::now().naive_utc().date();
+  add_feed_stats(&*connection, fid, today, 0, fetched as i64, Some(failed))
+    .map_err(HermesError::from)
+}
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  feeds
-    .filter(feed_link.eq(url))
-    .select(id)
-    .first(&*connection)
+pub fn count_feed_items(fid: i32, per_day: &HashMap<NaiveDate, i32>) -> Result<(), HermesError> {
+  let connection = get_connection()?;
+  connection
+    .transaction::<_, diesel::result::Error, _>(|| {
+      for (on, n) in per_day {
+        add_feed_stats(&*connection, fid, *on, *n, 0, None)?;
+      }
+      Ok(())
+    }).map_err(HermesError::from)
 }
 
-pub fn insert_channel(channel: NewFeed) -> Feed {
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
+// Recounts the items of the days since `since` from the items themselves, so
+// purged items and missed increments don't add up. Fetch counters are kept.
+pub fn reconcile_feed_stats(since: NaiveDate) -> Result<usize, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::Date;
 
-  diesel::insert_into(feeds::table)
-    .values(&channel)
-    .get_result::<Feed>(&*connection)
-    .expect("Error saving new post")
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    sql_query("UPDATE feed_stats SET items = 0 WHERE day >= $1 AND items <> 0")
+      .bind::<Date, _>(since)
+      .execute(&*connection)?;
+    sql_query(
+      "INSERT INTO feed_stats (feed_id, day, items) \
+       SELECT feed_id, (coalesce(published_at, updated_at) AT TIME ZONE 'UTC')::date AS d, \
+       count(*) FROM items \
+       WHERE (coalesce(published_at, updated_at) AT TIME ZONE 'UTC')::date >= $1 \
+       GROUP BY 1, 2 \
+       ON CONFLICT (feed_id, day) DO UPDATE SET items = EXCLUDED.items",
+    ).bind::<Date, _>(since)
+    .execute(&*connection)
+  }).map_err(HermesError::from)
 }
 
-// used during update loop
-pub fn get_channel_urls_and_subscribers() -> Vec<(i32, String, Vec<i32>)> {
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
+pub fn get_feed_days(
+  fids: &Vec<i32>,
+  since: NaiveDate,
+) -> Result<HashMap<i32, Vec<FeedDay>>, HermesError> {
+  use schema::feed_stats::dsl::*;
 
-  let subscribed = subscribed_feeds::table
-    .select((subscribed_feeds::feed_id, subscribed_feeds::user_id))
-    .load::<(i32, i32)>(&*connection)
-    .unwrap();
+  let connection = get_connection()?;
+  let mut by_feed = HashMap::new();
+  let found = feed_stats
+    .filter(feed_id.eq_any(fids))
+    .filter(day.ge(since))
+    .order(day.asc())
+    .load::<FeedDay>(&*connection)?;
+  for feed_day in found {
+    by_feed
+      .entry(feed_day.feed_id)
+      .or_insert_with(Vec::new)
+      .push(feed_day);
+  }
+  Ok(by_feed)
+}
 
-  let mut h: HashMap<i32, Vec<i32>> = HashMap::new();
-  subscribed.iter().for_each(|x| {
-    h.entry(x.0)
-      .and_modify(|e| e.push(x.1))
-      .or_insert(vec![x.1]);
-  });
+// every item a feed had, and the last day it published one
+pub fn get_feed_totals(fids: &Vec<i32>) -> Result<HashMap<i32, FeedTotal>, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Array, Integer};
 
-  // let feeds: Result<Vec<(i32, String, Vec<i32>)>, diesel::result::Error> = feeds::table
-  //   .select((feeds::id, feeds::feed_link))
-  //   .load::<(i32, String)>(&*connection)
-  //   .map(|f| {
-  //     f.into_iter()
-  //       .map(|(i, u)| (i, u, h.remove(&i).unwrap()))
-  //       .collect()
-  //   });
-  // info!("result: {:?}", feeds);
+  let connection = get_connection()?;
+  let totals = sql_query(
+    "SELECT feed_id, sum(items)::bigint AS items, max(day) FILTER (WHERE items > 0) AS last_post \
+     FROM feed_stats WHERE feed_id = ANY($1) GROUP BY feed_id",
+  ).bind::<Array<Integer>, _>(fids)
+  .load::<FeedTotal>(&*connection)?;
+  Ok(totals.into_iter().map(|total| (total.feed_id, total)).collect())
+}
 
-  let feeds = feeds::table
-    .select((feeds::id, feeds::feed_link))
-    .load::<(i32, String)>(&*connection)
-    .unwrap();
+// Folds the days up to `until` into every feed's rate. A feed without one
+// starts from its mean over the stats window, and a worker that was down for
+// longer than the window only catches up on the window.
+pub fn update_item_rates(until: NaiveDate) -> Result<usize, HermesError> {
+  use schema::feed_rates;
 
-  let res: Vec<(i32, String, Vec<i32>)> = feeds
+  let connection = get_connection()?;
+  let fids = feeds::table.select(feeds::id).load::<i32>(&*connection)?;
+  let mut rates: HashMap<i32, FeedRate> = feed_rates::table
+    .load::<FeedRate>(&*connection)?
     .into_iter()
-    .map(|(i, u)| (i, u, h.remove(&i).unwrap()))
+    .map(|rate| (rate.feed_id, rate))
     .collect();
-  res
+  let since = until - Duration::days(FeedStats::WINDOW_DAYS - 1);
+  let mut days = get_feed_days(&fids, since)?;
+  connection.transaction::<_, diesel::result::Error, _>(|| {
+    for fid in fids.iter() {
+      let counts: HashMap<NaiveDate, i32> = days
+        .remove(fid)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|d| d.day <= until)
+        .map(|d| (d.day, d.items))
+        .collect();
+      let rate = match rates.remove(fid) {
+        Some(mut rate) => {
+          let mut day = cmp::max(rate.day.succ(), since);
+          while day <= until {
+            rate.fold(day, counts.get(&day).cloned().unwrap_or(0));
+            day = day.succ();
+          }
+          rate
+        }
+        None => FeedRate {
+          feed_id: *fid,
+          items_per_day: counts.values().sum::<i32>() as f64 / FeedStats::WINDOW_DAYS as f64,
+          day: until,
+        },
+      };
+      diesel::insert_into(feed_rates::table)
+        .values(&rate)
+        .on_conflict(feed_rates::feed_id)
+        .do_update()
+        .set((
+          feed_rates::items_per_day.eq(rate.items_per_day),
+          feed_rates::day.eq(rate.day),
+        )).execute(&*connection)?;
+    }
+    Ok(fids.len())
+  }).map_err(HermesError::from)
 }
 
-//items
+pub fn get_item_rates(fids: &Vec<i32>) -> Result<HashMap<i32, f64>, HermesError> {
+  use schema::feed_rates::dsl::*;
 
-pub fn insert_items(items: &Vec<NewItem>) -> Option<Vec<Item>> {
-  use schema::items;
+  let connection = get_connection()?;
+  feed_rates
+    .filter(feed_id.eq_any(fids))
+    .select((feed_id, items_per_day))
+    .load::<(i32, f64)>(&*connection)
+    .map(|rates| rates.into_iter().collect())
+    .map_err(HermesError::from)
+}
+
+// invites
+
+pub fn create_invite(c: &str, uid: i32) -> Result<Invite, HermesError> {
+  use schema::invites::dsl::*;
 
-  debug!("found {} new items", items.len());
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  diesel::insert_into(items::table)
-    .values(items)
-    .get_results::<Item>(&*connection)
-    .ok()
+  let connection = get_connection()?;
+  diesel::insert_into(invites)
+    .values((code.eq(c), created_by.eq(uid)))
+    .get_result::<Invite>(&*connection)
+    .map_err(HermesError::from)
 }
 
-pub fn update_item(iid: i32, item: NewItem) {
-  use schema::items::dsl::*;
+// subscribed_feeds
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  diesel::update(items.find(iid))
-    .set((
-      title.eq(item.title),
-      link.eq(item.link),
-      summary.eq(item.summary),
-      published_at.eq(item.published_at),
-      content.eq(item.content),
-    )).execute(&*connection)
-    .expect("failed to update item");
+pub fn subscribe_feed(uid: &i32, fid: &i32) -> Result<usize, HermesError> {
+  use schema::subscribed_feeds::dsl::*;
+
+  let connection = get_connection()?;
+
+  let subscribed = diesel::insert_into(subscribed_feeds)
+    .values((feed_id.eq(fid), user_id.eq(uid)))
+    .execute(&*connection)?;
+  info!("subscribed: '{}' by '{}'", fid, uid);
+  Ok(subscribed)
 }
 
-pub fn find_duplicates(guids: Vec<&str>) -> Option<Vec<(i32, String, Option<DateTime<Utc>>)>> {
-  use schema::items::dsl::*;
+pub fn is_subscribed(uid: &i32, fid: &i32) -> Result<bool, HermesError> {
+  use schema::subscribed_feeds::dsl::*;
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  let results = items
-    .filter(guid.eq_any(guids))
-    .select((id, guid, published_at))
-    .load(&*connection)
-    .expect("Error loading items");
-  match results.is_empty() {
-    true => None,
-    false => Some(results),
-  }
+  let connection = get_connection()?;
+  select(exists(
+    subscribed_feeds
+      .filter(user_id.eq(uid))
+      .filter(feed_id.eq(fid)),
+  )).get_result::<bool>(&*connection)
+  .map_err(HermesError::from)
 }
 
-pub fn get_item_ids(fid: &i32) -> Option<Vec<i32>> {
-  use schema::items::dsl::*;
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  match items.filter(feed_id.eq(fid)).select(id).load(&*connection) {
-    Ok(i) => Some(i),
-    Err(_) => None,
+// unlike the counts view this includes feeds without unseen items
+pub fn get_user_feeds(uid: i32) -> Result<Vec<Feed>, HermesError> {
+  let connection = get_connection()?;
+  feeds::table
+    .inner_join(subscribed_feeds::table)
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .select(feeds::all_columns)
+    .order(feeds::title.asc())
+    .load::<Feed>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_subscribed_feed_ids(uid: &i32) -> Result<Vec<i32>, HermesError> {
+  use schema::subscribed_feeds::dsl::*;
+
+  let connection = get_connection()?;
+  subscribed_feeds
+    .filter(user_id.eq(uid))
+    .select(feed_id)
+    .load::<i32>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the user's live subscriptions, those with the most items read since `since` first
+pub fn get_feeds_by_reads(
+  uid: i32,
+  since: DateTime<Utc>,
+) -> Result<Vec<(i32, String)>, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Integer, Text, Timestamptz};
+
+  #[derive(QueryableByName)]
+  struct ReadFeed {
+    #[sql_type = "Integer"]
+    id: i32,
+    #[sql_type = "Text"]
+    feed_link: String,
   }
+
+  let connection = get_connection()?;
+  sql_query(
+    "SELECT f.id, f.feed_link FROM subscribed_feeds s \
+     INNER JOIN feeds f ON f.id = s.feed_id \
+     LEFT JOIN ( \
+     SELECT i.feed_id, count(*) AS reads FROM item_state_log l \
+     INNER JOIN items i ON i.id = l.item_id \
+     WHERE l.user_id = $1 AND l.changed_at > $2 AND l.seen AND NOT coalesce(l.was_seen, false) \
+     GROUP BY i.feed_id \
+     ) r ON r.feed_id = f.id \
+     WHERE s.user_id = $1 AND f.consecutive_failure_count < $3 \
+     ORDER BY coalesce(r.reads, 0) DESC, f.id",
+  ).bind::<Integer, _>(uid)
+  .bind::<Timestamptz, _>(since)
+  .bind::<Integer, _>(Feed::DEAD_AFTER_FAILURES)
+  .load::<ReadFeed>(&*connection)
+  .map(|feeds| feeds.into_iter().map(|f| (f.id, f.feed_link)).collect())
+  .map_err(HermesError::from)
 }
 
-pub fn get_latest_item_date(fid: i32) -> Option<DateTime<Utc>> {
-  use schema::items::dsl::*;
+pub fn get_feed_subscribers(fid: i32) -> Result<Vec<i32>, HermesError> {
+  use schema::subscribed_feeds::dsl::*;
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  match items
+  let connection = get_connection()?;
+  subscribed_feeds
     .filter(feed_id.eq(fid))
-    .order(published_at.desc())
-    .first::<Item>(&*connection)
-  {
-    Ok(item) => item.published_at,
-    Err(_) => None,
+    .select(user_id)
+    .load::<i32>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// drops the subscription and the user's state for the feed's items
+pub fn unsubscribe_feed(uid: i32, fid: i32) -> Result<usize, HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let feed_items = items::table
+      .filter(items::feed_id.eq(fid))
+      .select(items::id);
+    diesel::delete(
+      subscribed_items::table
+        .filter(subscribed_items::user_id.eq(uid))
+        .filter(subscribed_items::item_id.eq_any(feed_items)),
+    ).execute(&*connection)?;
+    diesel::delete(
+      subscribed_feeds::table
+        .filter(subscribed_feeds::user_id.eq(uid))
+        .filter(subscribed_feeds::feed_id.eq(fid)),
+    ).execute(&*connection)
+  }).map_err(HermesError::from)
+}
+
+// gives `to` the subscriptions and tags of `from`, and with `with_state` what
+// `from` read and starred; without `copy`, `from` is unsubscribed afterwards.
+// Returns the number of feeds and items `to` did not have before.
+pub fn transfer_subscriptions(
+  from: i32,
+  to: i32,
+  copy: bool,
+  with_state: bool,
+) -> Result<(usize, usize), HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Bool, Integer};
+  use schema::{subscribed_items, subscription_tags, users};
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let found = users::table
+      .filter(users::id.eq_any(vec![from, to]))
+      .count()
+      .get_result::<i64>(&*connection)?;
+    if found != 2 {
+      return Err(diesel::result::Error::NotFound);
+    }
+    let feeds = sql_query(
+      "INSERT INTO subscribed_feeds (user_id, feed_id) \
+       SELECT $2, feed_id FROM subscribed_feeds WHERE user_id = $1 \
+       ON CONFLICT (user_id, feed_id) DO NOTHING",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(to)
+    .execute(&*connection)?;
+    sql_query(
+      "INSERT INTO subscription_tags (subscribed_feed_id, tag) \
+       SELECT dst.id, t.tag FROM subscription_tags t \
+       INNER JOIN subscribed_feeds src ON src.id = t.subscribed_feed_id \
+       INNER JOIN subscribed_feeds dst ON dst.feed_id = src.feed_id AND dst.user_id = $2 \
+       WHERE src.user_id = $1 \
+       ON CONFLICT (subscribed_feed_id, tag) DO NOTHING",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(to)
+    .execute(&*connection)?;
+    let items = sql_query(
+      "INSERT INTO subscribed_items (user_id, item_id, seen, starred) \
+       SELECT $2, item_id, $3 AND seen, $3 AND starred FROM subscribed_items WHERE user_id = $1 \
+       ON CONFLICT (user_id, item_id) DO NOTHING",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(to)
+    .bind::<Bool, _>(with_state)
+    .execute(&*connection)?;
+    if with_state {
+      // items both had are read or starred if either read or starred them
+      sql_query(
+        "UPDATE subscribed_items dst SET seen = dst.seen OR src.seen, \
+         starred = dst.starred OR src.starred \
+         FROM subscribed_items src \
+         WHERE src.user_id = $1 AND dst.user_id = $2 AND dst.item_id = src.item_id \
+         AND (src.seen AND NOT dst.seen OR src.starred AND NOT dst.starred)",
+      ).bind::<Integer, _>(from)
+      .bind::<Integer, _>(to)
+      .execute(&*connection)?;
+    }
+    if !copy {
+      let source_feeds = subscribed_feeds::table
+        .filter(subscribed_feeds::user_id.eq(from))
+        .select(subscribed_feeds::id);
+      diesel::delete(
+        subscription_tags::table.filter(subscription_tags::subscribed_feed_id.eq_any(source_feeds)),
+      ).execute(&*connection)?;
+      diesel::delete(subscribed_items::table.filter(subscribed_items::user_id.eq(from)))
+        .execute(&*connection)?;
+      diesel::delete(subscribed_feeds::table.filter(subscribed_feeds::user_id.eq(from)))
+        .execute(&*connection)?;
+    }
+    Ok((feeds, items))
+  }).map_err(HermesError::from)
+}
+
+// Moves the subscribers and items of `from` to `into`, after `from` moved to
+// the URL of `into`. Items they both have keep the row and uuid of `into`,
+// read or starred if either was; the others move over with their own uuid.
+// `from` is left without subscribers, for `delete_orphan_feeds`.
+pub fn merge_feeds(from: i32, into: i32) -> Result<usize, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::Integer;
+  use schema::subscription_tags;
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    sql_query(
+      "INSERT INTO subscribed_feeds (user_id, feed_id) \
+       SELECT user_id, $2 FROM subscribed_feeds WHERE feed_id = $1 \
+       ON CONFLICT (user_id, feed_id) DO NOTHING",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(into)
+    .execute(&*connection)?;
+    sql_query(
+      "INSERT INTO subscription_tags (subscribed_feed_id, tag) \
+       SELECT dst.id, t.tag FROM subscription_tags t \
+       INNER JOIN subscribed_feeds src ON src.id = t.subscribed_feed_id \
+       INNER JOIN subscribed_feeds dst ON dst.user_id = src.user_id AND dst.feed_id = $2 \
+       WHERE src.feed_id = $1 \
+       ON CONFLICT (subscribed_feed_id, tag) DO NOTHING",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(into)
+    .execute(&*connection)?;
+    sql_query(
+      "INSERT INTO subscribed_items (user_id, item_id, seen, starred) \
+       SELECT s.user_id, dst.id, s.seen, s.starred FROM subscribed_items s \
+       INNER JOIN items src ON src.id = s.item_id \
+       INNER JOIN items dst ON dst.feed_id = $2 AND dst.guid = src.guid \
+       WHERE src.feed_id = $1 \
+       ON CONFLICT (user_id, item_id) DO UPDATE \
+       SET seen = subscribed_items.seen OR EXCLUDED.seen, \
+       starred = subscribed_items.starred OR EXCLUDED.starred",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(into)
+    .execute(&*connection)?;
+    let moved = sql_query(
+      "UPDATE items SET feed_id = $2 WHERE feed_id = $1 \
+       AND guid NOT IN (SELECT guid FROM items WHERE feed_id = $2)",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(into)
+    .execute(&*connection)?;
+    // the trigger keeping the counts only sees subscribed_items
+    sql_query("DELETE FROM unread_counts WHERE feed_id IN ($1, $2)")
+      .bind::<Integer, _>(from)
+      .bind::<Integer, _>(into)
+      .execute(&*connection)?;
+    sql_query(
+      "INSERT INTO unread_counts (user_id, feed_id, unseen_count) \
+       SELECT si.user_id, i.feed_id, count(*) FROM items i \
+       INNER JOIN subscribed_items si ON i.id = si.item_id \
+       WHERE NOT si.seen AND i.feed_id IN ($1, $2) \
+       GROUP BY si.user_id, i.feed_id",
+    ).bind::<Integer, _>(from)
+    .bind::<Integer, _>(into)
+    .execute(&*connection)?;
+    let source_feeds = subscribed_feeds::table
+      .filter(subscribed_feeds::feed_id.eq(from))
+      .select(subscribed_feeds::id);
+    diesel::delete(
+      subscription_tags::table.filter(subscription_tags::subscribed_feed_id.eq_any(source_feeds)),
+    ).execute(&*connection)?;
+    diesel::delete(subscribed_feeds::table.filter(subscribed_feeds::feed_id.eq(from)))
+      .execute(&*connection)?;
+    Ok(moved)
+  }).map_err(HermesError::from)
+}
+
+// tags
+
+pub fn set_feed_tags(
+  uid: i32,
+  fid: i32,
+  tags: &Vec<String>,
+) -> Result<usize, HermesError> {
+  use schema::subscription_tags;
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let sid = subscribed_feeds::table
+      .filter(subscribed_feeds::user_id.eq(uid))
+      .filter(subscribed_feeds::feed_id.eq(fid))
+      .select(subscribed_feeds::id)
+      .first::<i32>(&*connection)?;
+    diesel::delete(
+      subscription_tags::table.filter(subscription_tags::subscribed_feed_id.eq(sid)),
+    ).execute(&*connection)?;
+    let insertables: Vec<_> = tags
+      .iter()
+      .map(|t| {
+        (
+          subscription_tags::subscribed_feed_id.eq(sid),
+          subscription_tags::tag.eq(t),
+        )
+      }).collect();
+    diesel::insert_into(subscription_tags::table)
+      .values(&insertables)
+      .execute(&*connection)
+  }).map_err(HermesError::from)
+}
+
+// Err(NotFound) when the user isn't subscribed to the feed
+pub fn set_push_notify(uid: i32, fid: i32, enabled: bool) -> Result<(), HermesError> {
+  let connection = get_connection()?;
+  let updated = diesel::update(
+    subscribed_feeds::table
+      .filter(subscribed_feeds::user_id.eq(uid))
+      .filter(subscribed_feeds::feed_id.eq(fid)),
+  ).set(subscribed_feeds::push_notify.eq(enabled))
+  .execute(&*connection)?;
+  match updated {
+    0 => Err(HermesError::NotFound),
+    _ => Ok(()),
   }
 }
 
-// users
+// Err(NotFound) when the user isn't subscribed to the feed
+pub fn set_unread_on_edit(uid: i32, fid: i32, enabled: bool) -> Result<(), HermesError> {
+  let connection = get_connection()?;
+  let updated = diesel::update(
+    subscribed_feeds::table
+      .filter(subscribed_feeds::user_id.eq(uid))
+      .filter(subscribed_feeds::feed_id.eq(fid)),
+  ).set(subscribed_feeds::unread_on_edit.eq(enabled))
+  .execute(&*connection)?;
+  match updated {
+    0 => Err(HermesError::NotFound),
+    _ => Ok(()),
+  }
+}
 
-pub fn get_user(uname: &str) -> Option<User> {
-  use schema::users::dsl::*;
+// Pinned feeds are listed first, then the ones with a sort order, then the
+// rest by title. Either field is left alone when an entry doesn't have it.
+// Err(NotFound), with nothing changed, when the user isn't subscribed to one
+// of the feeds.
+pub fn set_feed_order(uid: i32, entries: &[FeedOrderEntry]) -> Result<(), HermesError> {
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    for entry in entries {
+      let subscription = subscribed_feeds::table
+        .filter(subscribed_feeds::user_id.eq(uid))
+        .filter(subscribed_feeds::feed_id.eq(entry.feed_id));
+      let updated = match (entry.sort_order, entry.pinned) {
+        (Some(order), Some(pin)) => diesel::update(subscription)
+          .set((
+            subscribed_feeds::sort_order.eq(order),
+            subscribed_feeds::pinned.eq(pin),
+          )).execute(&*connection)?,
+        (Some(order), None) => diesel::update(subscription)
+          .set(subscribed_feeds::sort_order.eq(order))
+          .execute(&*connection)?,
+        (None, Some(pin)) => diesel::update(subscription)
+          .set(subscribed_feeds::pinned.eq(pin))
+          .execute(&*connection)?,
+        (None, None) => subscription.count().get_result::<i64>(&*connection)? as usize,
+      };
+      if updated == 0 {
+        return Err(HermesError::NotFound);
+      }
+    }
+    Ok(())
+  })
+}
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  match users.filter(username.eq(uname)).first::<User>(&*connection) {
-    Ok(user) => Some(user),
-    Err(_) => None,
+// Err(NotFound) when the user isn't subscribed to the feed
+pub fn set_refresh_window(uid: i32, fid: i32, window: Option<&str>) -> Result<(), HermesError> {
+  let connection = get_connection()?;
+  let updated = diesel::update(
+    subscribed_feeds::table
+      .filter(subscribed_feeds::user_id.eq(uid))
+      .filter(subscribed_feeds::feed_id.eq(fid)),
+  ).set(subscribed_feeds::refresh_window.eq(window))
+  .execute(&*connection)?;
+  match updated {
+    0 => Err(HermesError::NotFound),
+    _ => Ok(()),
   }
 }
 
-pub fn create_user(uname: &str, pw_hash: &str) -> Result<usize, diesel::result::Error> {
-  use schema::users::dsl::*;
+pub fn get_refresh_window(uid: i32, fid: i32) -> Result<Option<String>, HermesError> {
+  let connection = get_connection()?;
+  subscribed_feeds::table
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .filter(subscribed_feeds::feed_id.eq(fid))
+    .select(subscribed_feeds::refresh_window)
+    .first(&*connection)
+    .map_err(HermesError::from)
+}
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
-  diesel::insert_into(users)
-    .values((username.eq(uname), password_hash.eq(pw_hash.as_bytes())))
+// every subscription's window, by feed
+pub fn get_refresh_windows() -> Result<Vec<(i32, Option<String>)>, HermesError> {
+  let connection = get_connection()?;
+  subscribed_feeds::table
+    .select((subscribed_feeds::feed_id, subscribed_feeds::refresh_window))
+    .load(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn add_feed_tag(uid: i32, fid: i32, new_tag: &str) -> Result<usize, HermesError> {
+  use schema::subscription_tags;
+
+  let connection = get_connection()?;
+  let sid = subscribed_feeds::table
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .filter(subscribed_feeds::feed_id.eq(fid))
+    .select(subscribed_feeds::id)
+    .first::<i32>(&*connection)?;
+  diesel::insert_into(subscription_tags::table)
+    .values((
+      subscription_tags::subscribed_feed_id.eq(sid),
+      subscription_tags::tag.eq(new_tag),
+    )).on_conflict_do_nothing()
     .execute(&*connection)
+    .map_err(HermesError::from)
 }
 
-// subscribed_feeds
+// (lowest tag row id, tag, feed id), the row id makes a stable numeric tag id
+pub fn get_tag_rows(uid: i32) -> Result<Vec<(i32, String, i32)>, HermesError> {
+  use schema::subscription_tags;
 
-pub fn subscribe_feed(uid: &i32, fid: &i32) {
-  use schema::subscribed_feeds::dsl::*;
+  let connection = get_connection()?;
+  subscription_tags::table
+    .inner_join(subscribed_feeds::table)
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .order(subscription_tags::id.asc())
+    .select((
+      subscription_tags::id,
+      subscription_tags::tag,
+      subscribed_feeds::feed_id,
+    )).load::<(i32, String, i32)>(&*connection)
+    .map_err(HermesError::from)
+}
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
+pub fn get_tagged_feed_ids(uid: i32, t: &str) -> Result<Vec<i32>, HermesError> {
+  use schema::subscription_tags;
 
-  match diesel::insert_into(subscribed_feeds)
-    .values((feed_id.eq(fid), user_id.eq(uid)))
-    .execute(&*connection)
-  {
-    Ok(_) => info!("subscribed: '{}' by '{}'", fid, uid),
-    Err(e) => error!("subscribe failure: '{}' by '{}': {}", fid, uid, e),
+  let connection = get_connection()?;
+  subscription_tags::table
+    .inner_join(subscribed_feeds::table)
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .filter(subscription_tags::tag.eq(t))
+    .select(subscribed_feeds::feed_id)
+    .load::<i32>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_tag_counts(uid: i32) -> Result<Vec<TagCount>, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::Integer;
+
+  let connection = get_connection()?;
+  sql_query(
+    "SELECT t.tag, CAST(COALESCE(SUM(v.unseen_count), 0) AS BIGINT) AS unseen_count \
+     FROM subscription_tags t \
+     INNER JOIN subscribed_feeds sf ON sf.id = t.subscribed_feed_id \
+     LEFT JOIN subscribed_feeds_with_count_view v \
+     ON v.id = sf.feed_id AND v.user_id = sf.user_id \
+     WHERE sf.user_id = $1 \
+     GROUP BY t.tag \
+     ORDER BY t.tag",
+  ).bind::<Integer, _>(uid)
+  .load::<TagCount>(&*connection)
+  .map_err(HermesError::from)
+}
+
+pub fn get_items_for_feeds(
+  uid: i32,
+  fids: &Vec<i32>,
+  page: Page,
+) -> Result<(Vec<SubscribedItem>, i64), HermesError> {
+  let connection = get_connection()?;
+  let items = subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .filter(subscribed_items_view::feed_id.eq_any(fids))
+    .order(subscribed_items_view::published_at.desc())
+    .limit(page.limit)
+    .offset(page.offset)
+    .load::<SubscribedItem>(&*connection)?;
+  let total = subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .filter(subscribed_items_view::feed_id.eq_any(fids))
+    .count()
+    .get_result(&*connection)?;
+  Ok((items, total))
+}
+
+// the items of all subscriptions, newest first; `feed_ids` narrows them to some feeds
+pub fn get_timeline_items(
+  uid: i32,
+  unread: bool,
+  starred: bool,
+  feed_ids: Option<Vec<i32>>,
+  before: Option<DateTime<Utc>>,
+  page: Page,
+) -> Result<(Vec<SubscribedItem>, i64), HermesError> {
+  let connection = get_connection()?;
+  let filtered = || {
+    let mut query = subscribed_items_view::table
+      .filter(subscribed_items_view::user_id.eq(uid))
+      .into_boxed();
+    if unread {
+      query = query.filter(subscribed_items_view::seen.eq(false));
+    }
+    if starred {
+      query = query.filter(subscribed_items_view::starred.eq(true));
+    }
+    if let Some(ref ids) = feed_ids {
+      query = query.filter(subscribed_items_view::feed_id.eq_any(ids.clone()));
+    }
+    if let Some(d) = before {
+      query = query.filter(subscribed_items_view::published_at.lt(d));
+    }
+    query
+  };
+  let items = filtered()
+    .order((
+      subscribed_items_view::published_at.desc(),
+      subscribed_items_view::id.desc(),
+    )).limit(page.limit)
+    .offset(page.offset)
+    .load::<SubscribedItem>(&*connection)?;
+  let total = filtered().count().get_result(&*connection)?;
+  Ok((items, total))
+}
+
+// The unread item following `after_id` in reading order, newest first like
+// the item lists, or preceding it when `forward` is false. Without `after_id`
+// that is the newest unread item, or the oldest. Err(NotFound) when `after_id`
+// isn't one of the user's items.
+pub fn get_adjacent_unread(
+  uid: i32,
+  after_id: Option<i32>,
+  feed_id: Option<i32>,
+  forward: bool,
+) -> Result<Option<SubscribedItem>, HermesError> {
+  let connection = get_connection()?;
+  let anchor = match after_id {
+    Some(iid) => Some(
+      subscribed_items_view::table
+        .filter(subscribed_items_view::user_id.eq(uid))
+        .filter(subscribed_items_view::id.eq(iid))
+        .select(subscribed_items_view::published_at)
+        .first::<Option<DateTime<Utc>>>(&*connection)
+        .optional()?
+        .map(|published_at| (published_at, iid))
+        .ok_or(HermesError::NotFound)?,
+    ),
+    None => None,
+  };
+  let mut query = subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .filter(subscribed_items_view::seen.eq(false))
+    .into_boxed();
+  if let Some(fid) = feed_id {
+    query = query.filter(subscribed_items_view::feed_id.eq(fid));
+  }
+  // items without a date come first, like Postgres sorts NULL when descending
+  let published_at = subscribed_items_view::published_at;
+  let id = subscribed_items_view::id;
+  query = match (anchor, forward) {
+    (None, _) => query,
+    (Some((Some(p), iid)), true) => {
+      query.filter(published_at.lt(p).or(published_at.eq(p).and(id.lt(iid))))
+    }
+    (Some((None, iid)), true) => query.filter(published_at.is_not_null().or(id.lt(iid))),
+    (Some((Some(p), iid)), false) => query.filter(
+      published_at
+        .gt(p)
+        .or(published_at.eq(p).and(id.gt(iid)))
+        .or(published_at.is_null()),
+    ),
+    (Some((None, iid)), false) => query.filter(published_at.is_null().and(id.gt(iid))),
   };
+  query = match forward {
+    true => query.order((published_at.desc(), id.desc())),
+    false => query.order((published_at.asc(), id.asc())),
+  };
+  query
+    .first::<SubscribedItem>(&*connection)
+    .optional()
+    .map_err(HermesError::from)
 }
 
-pub fn get_subscribed_feed(user_id: &i32, feed_id: &i32) -> Option<SubscribedFeed> {
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
+pub fn get_subscribed_feed(user_id: &i32, feed_id: &i32) -> Result<SubscribedFeed, HermesError> {
+  let connection = get_connection()?;
   subscribed_feeds_with_count_view::table
     .filter(subscribed_feeds_with_count_view::user_id.eq(user_id))
     .filter(subscribed_feeds_with_count_view::id.eq(feed_id))
     .first::<SubscribedFeed>(&*connection)
-    .ok()
+    .map_err(HermesError::from)
+}
+
+pub fn get_subscribed_feeds(uid: &i32, page: Page) -> Result<Vec<SubscribedFeed>, HermesError> {
+  let connection = get_connection()?;
+  subscribed_feeds_with_count_view::table
+    .filter(subscribed_feeds_with_count_view::user_id.eq(uid))
+    .order((
+      subscribed_feeds_with_count_view::pinned.desc(),
+      subscribed_feeds_with_count_view::sort_order.asc(),
+      subscribed_feeds_with_count_view::title.asc(),
+    ))
+    .limit(page.limit)
+    .offset(page.offset)
+    .load::<SubscribedFeed>(&*connection)
+    .map_err(HermesError::from)
 }
 
-pub fn get_subscribed_feeds(uid: &i32) -> Option<Vec<SubscribedFeed>> {
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
+pub fn get_all_subscribed_feeds(uid: &i32) -> Result<Vec<SubscribedFeed>, HermesError> {
+  let connection = get_connection()?;
   subscribed_feeds_with_count_view::table
     .filter(subscribed_feeds_with_count_view::user_id.eq(uid))
-    .order(subscribed_feeds_with_count_view::title.asc())
+    .order((
+      subscribed_feeds_with_count_view::pinned.desc(),
+      subscribed_feeds_with_count_view::sort_order.asc(),
+      subscribed_feeds_with_count_view::title.asc(),
+    ))
     .load::<SubscribedFeed>(&*connection)
-    .ok()
+    .map_err(HermesError::from)
+}
+
+// unread items by feed, kept by a trigger as items are added and read
+pub fn get_unread_counts(uid: i32) -> Result<Vec<(i32, i32)>, HermesError> {
+  use schema::unread_counts::dsl::*;
+
+  let connection = get_connection()?;
+  unread_counts
+    .filter(user_id.eq(uid))
+    .filter(unseen_count.gt(0))
+    .select((feed_id, unseen_count))
+    .load::<(i32, i32)>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// every subscription, unlike `count_subscribed_feeds`
+pub fn count_subscriptions(uid: i32) -> Result<i64, HermesError> {
+  let connection = get_connection()?;
+  subscribed_feeds::table
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn count_filters(uid: i32) -> Result<i64, HermesError> {
+  use schema::filters;
+
+  let connection = get_connection()?;
+  filters::table
+    .filter(filters::user_id.eq(uid))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+// the user's own limit, or the default one; None when there is neither
+pub fn get_quota(uid: i32, quota: &str) -> Result<Option<i64>, HermesError> {
+  use schema::quotas;
+
+  let connection = get_connection()?;
+  quotas::table
+    .filter(quotas::name.eq(quota))
+    .filter(quotas::user_id.eq(uid).or(quotas::user_id.is_null()))
+    // NULL, the default, comes last
+    .order(quotas::user_id.asc())
+    .select(quotas::max_value)
+    .first::<Option<i64>>(&*connection)
+    .optional()
+    .map(|max| max.and_then(|m| m))
+    .map_err(HermesError::from)
+}
+
+// `uid` None sets the default
+pub fn set_quota(uid: Option<i32>, quota: &str, max: Option<i64>) -> Result<(), HermesError> {
+  use schema::quotas;
+
+  let connection = get_connection()?;
+  connection.transaction::<_, HermesError, _>(|| {
+    let existing = quotas::table.filter(quotas::name.eq(quota)).into_boxed();
+    let existing = match uid {
+      Some(uid) => existing.filter(quotas::user_id.eq(uid)),
+      None => existing.filter(quotas::user_id.is_null()),
+    };
+    let ids = existing.select(quotas::id).load::<i32>(&*connection)?;
+    diesel::delete(quotas::table.filter(quotas::id.eq_any(ids))).execute(&*connection)?;
+    diesel::insert_into(quotas::table)
+      .values((
+        quotas::user_id.eq(uid),
+        quotas::name.eq(quota),
+        quotas::max_value.eq(max),
+      )).execute(&*connection)?;
+    Ok(())
+  })
+}
+
+// Err(NotFound) when the user has no limit of their own
+pub fn delete_quota(uid: i32, quota: &str) -> Result<(), HermesError> {
+  use schema::quotas;
+
+  let connection = get_connection()?;
+  let deleted = diesel::delete(
+    quotas::table
+      .filter(quotas::user_id.eq(uid))
+      .filter(quotas::name.eq(quota)),
+  ).execute(&*connection)?;
+  match deleted {
+    0 => Err(HermesError::NotFound),
+    _ => Ok(()),
+  }
+}
+
+pub fn count_subscribed_feeds(uid: &i32) -> Result<i64, HermesError> {
+  let connection = get_connection()?;
+  subscribed_feeds_with_count_view::table
+    .filter(subscribed_feeds_with_count_view::user_id.eq(uid))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn count_subscribed_items(feed_id: i32, user_id: i32) -> Result<i64, HermesError> {
+  let connection = get_connection()?;
+  subscribed_items_view::table
+    .filter(subscribed_items_view::feed_id.eq(feed_id))
+    .filter(subscribed_items_view::user_id.eq(user_id))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
 }
 
 pub fn get_subscribed_items(
   feed_id: i32,
   user_id: i32,
   updated: Option<DateTime<Utc>>,
-) -> Option<Vec<SubscribedItem>> {
-  let pool = establish_pool();
-  let handle = thread::spawn(move || {
-    let connection = pool.get().unwrap();
-    let mut query = subscribed_items_view::table
-      .filter(subscribed_items_view::feed_id.eq(feed_id))
-      .filter(subscribed_items_view::user_id.eq(user_id))
-      .order(subscribed_items_view::published_at.desc())
-      .into_boxed();
-    if let Some(d) = updated {
-      query = query.filter(subscribed_items_view::published_at.lt(d))
-    }
-    query.limit(50).load::<SubscribedItem>(&*connection).ok()
-  });
-  handle.join().unwrap()
+  page: Page,
+) -> Result<Vec<SubscribedItem>, HermesError> {
+  let connection = get_connection()?;
+  let mut query = subscribed_items_view::table
+    .filter(subscribed_items_view::feed_id.eq(feed_id))
+    .filter(subscribed_items_view::user_id.eq(user_id))
+    .order(subscribed_items_view::published_at.desc())
+    .into_boxed();
+  if let Some(d) = updated {
+    query = query.filter(subscribed_items_view::published_at.lt(d))
+  }
+  query
+    .limit(page.limit)
+    .offset(page.offset)
+    .load::<SubscribedItem>(&*connection)
+    .map_err(HermesError::from)
 }
 
-pub fn get_subscribed_item(iid: i32, uid: i32) -> Option<SubscribedItem> {
-  use schema::subscribed_items;
+pub fn get_items_by_id_range(
+  uid: i32,
+  since_id: Option<i32>,
+  max_id: Option<i32>,
+  with_ids: Option<Vec<i32>>,
+  max: i64,
+) -> Result<Vec<SubscribedItem>, HermesError> {
+  let connection = get_connection()?;
+  let mut query = subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .into_boxed();
+  if let Some(ids) = with_ids {
+    query = query
+      .filter(subscribed_items_view::id.eq_any(ids))
+      .order(subscribed_items_view::id.asc());
+  } else if let Some(m) = max_id {
+    query = query
+      .filter(subscribed_items_view::id.lt(m))
+      .order(subscribed_items_view::id.desc());
+  } else {
+    query = query
+      .filter(subscribed_items_view::id.gt(since_id.unwrap_or(0)))
+      .order(subscribed_items_view::id.asc());
+  }
+  query
+    .limit(max)
+    .load::<SubscribedItem>(&*connection)
+    .map_err(HermesError::from)
+}
 
-  let pool = establish_pool();
-  let handle = thread::spawn(move || {
-    let connection = pool.get().unwrap();
+pub fn count_user_items(uid: i32) -> Result<i64, HermesError> {
+  let connection = get_connection()?;
+  subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .count()
+    .get_result(&*connection)
+    .map_err(HermesError::from)
+}
 
-    match subscribed_items_view::table
-      .filter(subscribed_items_view::id.eq(iid))
-      .filter(subscribed_items_view::user_id.eq(uid))
-      .first::<SubscribedItem>(&*connection)
-    {
-      Ok(item) => {
-        diesel::update(
-          subscribed_items::table.filter(subscribed_items::id.eq(item.subscribed_item_id)),
-        ).set(subscribed_items::seen.eq(true))
-        .execute(&*connection)
-        .expect("Failed to update 'seen' status");
-        Some(item)
-      }
-      Err(_) => None,
-    }
-  });
-  handle.join().unwrap()
+pub fn get_recent_subscribed_items(
+  uid: i32,
+  fid: Option<i32>,
+  max: i64,
+) -> Result<Vec<SubscribedItem>, HermesError> {
+  let connection = get_connection()?;
+  let mut query = subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .order(subscribed_items_view::published_at.desc())
+    .into_boxed();
+  if let Some(f) = fid {
+    query = query.filter(subscribed_items_view::feed_id.eq(f));
+  }
+  query
+    .limit(max)
+    .load::<SubscribedItem>(&*connection)
+    .map_err(HermesError::from)
 }
 
-pub fn mark_subscribed_item_as_read(iid: i32) {
+// an item's content, or its summary when it has none; unlike
+// `get_subscribed_item`, leaves the item unread
+pub fn get_item_content(iid: i32, uid: i32) -> Result<String, HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  let (content, summary) = items::table
+    .inner_join(subscribed_items::table)
+    .filter(items::id.eq(iid))
+    .filter(subscribed_items::user_id.eq(uid))
+    .select((items::content, items::summary))
+    .first::<(Option<String>, Option<String>)>(&*connection)?;
+  Ok(content.or(summary).unwrap_or_default())
+}
+
+// the user's newest item with any of the links, left unread
+pub fn find_subscribed_item_by_link(
+  uid: i32,
+  links: &[String],
+) -> Result<SubscribedItem, HermesError> {
+  let connection = get_connection()?;
+  subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .filter(subscribed_items_view::link.eq_any(links))
+    .order(subscribed_items_view::published_at.desc())
+    .first::<SubscribedItem>(&*connection)
+    .map_err(HermesError::from)
+}
+
+pub fn get_subscribed_item(iid: i32, uid: i32) -> Result<SubscribedItem, HermesError> {
   use schema::subscribed_items;
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
 
-  diesel::update(subscribed_items::table.filter(subscribed_items::id.eq(iid)))
+  let connection = get_connection()?;
+  let item = subscribed_items_view::table
+    .filter(subscribed_items_view::id.eq(iid))
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .first::<SubscribedItem>(&*connection)?;
+  diesel::update(subscribed_items::table.filter(subscribed_items::id.eq(item.subscribed_item_id)))
     .set(subscribed_items::seen.eq(true))
-    .execute(&*connection)
-    .expect("Failed to update 'seen' status");
+    .execute(&*connection)?;
+  Ok(item)
+}
+
+// the feed of the item, None when the user has no such item
+pub fn mark_item_as_read(iid: i32, uid: i32) -> Result<Option<i32>, HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  let marked = diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::item_id.eq(iid))
+      .filter(subscribed_items::user_id.eq(uid)),
+  ).set(subscribed_items::seen.eq(true))
+  .execute(&*connection)?;
+  if marked == 0 {
+    return Ok(None);
+  }
+  items::table
+    .find(iid)
+    .select(items::feed_id)
+    .first::<i32>(&*connection)
+    .optional()
+    .map_err(HermesError::from)
+}
+
+pub fn mark_feed_as_read(fid: i32, uid: i32) -> Result<usize, HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  let feed_items = items::table
+    .filter(items::feed_id.eq(fid))
+    .select(items::id);
+  diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::user_id.eq(uid))
+      .filter(subscribed_items::seen.eq(false))
+      .filter(subscribed_items::item_id.eq_any(feed_items)),
+  ).set(subscribed_items::seen.eq(true))
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+pub fn mark_feeds_read_before(
+  fids: &Vec<i32>,
+  uid: i32,
+  before: DateTime<Utc>,
+) -> Result<usize, HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  let feed_items = items::table
+    .filter(items::feed_id.eq_any(fids))
+    .filter(items::published_at.lt(before).or(items::published_at.is_null()))
+    .select(items::id);
+  diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::user_id.eq(uid))
+      .filter(subscribed_items::seen.eq(false))
+      .filter(subscribed_items::item_id.eq_any(feed_items)),
+  ).set(subscribed_items::seen.eq(true))
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+pub fn set_item_seen(iid: i32, uid: i32, is_seen: bool) -> Result<usize, HermesError> {
+  use schema::subscribed_items;
+
+  let connection = get_connection()?;
+  diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::item_id.eq(iid))
+      .filter(subscribed_items::user_id.eq(uid)),
+  ).set(subscribed_items::seen.eq(is_seen))
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+// ids of the user's items that are unseen, or starred
+pub fn get_item_ids_where(uid: i32, unseen: bool, starred: bool) -> Result<Vec<i32>, HermesError> {
+  let connection = get_connection()?;
+  let mut query = subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .select(subscribed_items_view::id)
+    .into_boxed();
+  if unseen {
+    query = query.filter(subscribed_items_view::seen.eq(false));
+  }
+  if starred {
+    query = query.filter(subscribed_items_view::starred.eq(true));
+  }
+  query.load::<i32>(&*connection).map_err(HermesError::from)
+}
+
+pub fn mark_all_as_read(uid: i32) -> Result<usize, HermesError> {
+  use schema::subscribed_items;
+
+  let connection = get_connection()?;
+  diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::user_id.eq(uid))
+      .filter(subscribed_items::seen.eq(false)),
+  ).set(subscribed_items::seen.eq(true))
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+#[derive(QueryableByName)]
+struct Count {
+  #[sql_type = "BigInt"]
+  count: i64,
+}
+
+// `search_vector` is maintained by a trigger and ranked with title > summary > content
+pub fn search_subscribed_items(
+  uid: i32,
+  q: &str,
+  page: Page,
+) -> Result<(Vec<SubscribedItem>, i64), HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{BigInt, Integer, Text};
+
+  let connection = get_connection()?;
+  let found = sql_query(
+    "SELECT v.* FROM subscribed_items_view v \
+     INNER JOIN items i ON i.id = v.id \
+     WHERE v.user_id = $1 AND i.search_vector @@ plainto_tsquery('english', $2) \
+     ORDER BY ts_rank(i.search_vector, plainto_tsquery('english', $2)) DESC, \
+     v.published_at DESC \
+     LIMIT $3 OFFSET $4",
+  ).bind::<Integer, _>(uid)
+  .bind::<Text, _>(q)
+  .bind::<BigInt, _>(page.limit)
+  .bind::<BigInt, _>(page.offset)
+  .load::<SubscribedItem>(&*connection)?;
+  let total = sql_query(
+    "SELECT count(*) AS count FROM subscribed_items s \
+     INNER JOIN items i ON i.id = s.item_id \
+     WHERE s.user_id = $1 AND i.search_vector @@ plainto_tsquery('english', $2)",
+  ).bind::<Integer, _>(uid)
+  .bind::<Text, _>(q)
+  .get_result::<Count>(&*connection)?;
+  Ok((found, total.count))
+}
+
+pub fn set_item_starred(iid: i32, uid: i32, star: bool) -> Result<usize, HermesError> {
+  use schema::subscribed_items;
+
+  let connection = get_connection()?;
+  diesel::update(
+    subscribed_items::table
+      .filter(subscribed_items::item_id.eq(iid))
+      .filter(subscribed_items::user_id.eq(uid)),
+  ).set(subscribed_items::starred.eq(star))
+  .execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+// Applies a client's changes, except to items whose state changed on the
+// server after `changed_at`, then returns those items' ids, the feeds of the
+// items read or unread, and the state of every item changed since `since`.
+pub fn sync_item_states(
+  uid: i32,
+  params: &SyncParams,
+  changed_at: DateTime<Utc>,
+) -> Result<(Vec<i32>, Vec<i32>, Vec<ItemState>), HermesError> {
+  use schema::{items, subscribed_items};
+
+  let connection = get_connection()?;
+  connection.transaction::<_, HermesError, _>(|| {
+    let all: Vec<i32> = params
+      .read
+      .iter()
+      .chain(params.unread.iter())
+      .chain(params.starred.iter())
+      .chain(params.unstarred.iter())
+      .cloned()
+      .collect();
+    let conflicts = subscribed_items::table
+      .filter(subscribed_items::user_id.eq(uid))
+      .filter(subscribed_items::item_id.eq_any(&all))
+      .filter(subscribed_items::state_changed_at.gt(changed_at))
+      .select(subscribed_items::item_id)
+      .load::<i32>(&*connection)?;
+    let changes = [
+      (&params.read, Some(true), None),
+      (&params.unread, Some(false), None),
+      (&params.starred, None, Some(true)),
+      (&params.unstarred, None, Some(false)),
+    ];
+    for &(ids, seen, starred) in changes.iter() {
+      let target = subscribed_items::table
+        .filter(subscribed_items::user_id.eq(uid))
+        .filter(subscribed_items::item_id.eq_any(ids))
+        .filter(not(subscribed_items::item_id.eq_any(&conflicts)));
+      match (seen, starred) {
+        (Some(seen), _) => diesel::update(target)
+          .set(subscribed_items::seen.eq(seen))
+          .execute(&*connection)?,
+        (_, Some(starred)) => diesel::update(target)
+          .set(subscribed_items::starred.eq(starred))
+          .execute(&*connection)?,
+        (None, None) => 0,
+      };
+    }
+    let feed_ids = items::table
+      .filter(items::id.eq_any(params.read.iter().chain(params.unread.iter())))
+      .select(items::feed_id)
+      .distinct()
+      .load::<i32>(&*connection)?;
+    let states = subscribed_items::table
+      .inner_join(items::table)
+      .filter(subscribed_items::user_id.eq(uid))
+      .filter(subscribed_items::state_changed_at.gt(params.since))
+      .order(subscribed_items::state_changed_at.asc())
+      .select((
+        subscribed_items::item_id,
+        items::uuid,
+        subscribed_items::seen,
+        subscribed_items::starred,
+        subscribed_items::state_changed_at,
+      )).load::<ItemState>(&*connection)?;
+    Ok((conflicts, feed_ids, states))
+  })
+}
+
+pub fn get_starred_items(uid: i32) -> Result<Vec<SubscribedItem>, HermesError> {
+  let connection = get_connection()?;
+  subscribed_items_view::table
+    .filter(subscribed_items_view::user_id.eq(uid))
+    .filter(subscribed_items_view::starred.eq(true))
+    .order(subscribed_items_view::published_at.desc())
+    .load::<SubscribedItem>(&*connection)
+    .map_err(HermesError::from)
 }
 
-pub fn insert_subscribed_items(items: Vec<(&i32, &i32, bool)>) {
+pub fn insert_subscribed_items(items: Vec<(&i32, &i32, bool)>) -> Result<usize, HermesError> {
   use schema::subscribed_items;
 
   let insertables: Vec<_> = items
@@ -327,12 +3913,11 @@ pub fn insert_subscribed_items(items: Vec<(&i32, &i32, bool)>) {
       )
     }).collect();
 
-  let pool = establish_pool();
-  let connection = pool.get().unwrap();
+  let connection = get_connection()?;
   diesel::insert_into(subscribed_items::table)
     .values(insertables)
     .execute(&*connection)
-    .expect("Error saving new post");
+    .map_err(HermesError::from)
 }
 
 //deprecated
@@ -401,3 +3986,229 @@ pub fn insert_subscribed_items(items: Vec<(&i32, &i32, bool)>) {
 //   });
 //   handle.join().unwrap()
 // }
+
+// deleted items
+
+// drops the item from the user's view only, returns its feed
+pub fn hide_item(iid: i32, uid: i32) -> Result<i32, HermesError> {
+  use schema::{deleted_items, items, subscribed_items};
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let (fid, item_guid) = items::table
+      .inner_join(subscribed_items::table)
+      .filter(items::id.eq(iid))
+      .filter(subscribed_items::user_id.eq(uid))
+      .select((items::feed_id, items::guid))
+      .first::<(i32, String)>(&*connection)?;
+    diesel::delete(
+      subscribed_items::table
+        .filter(subscribed_items::item_id.eq(iid))
+        .filter(subscribed_items::user_id.eq(uid)),
+    ).execute(&*connection)?;
+    diesel::insert_into(deleted_items::table)
+      .values((
+        deleted_items::item_id.eq(iid),
+        deleted_items::feed_id.eq(fid),
+        deleted_items::guid.eq(item_guid),
+        deleted_items::user_id.eq(uid),
+      )).execute(&*connection)?;
+    Ok(fid)
+  }).map_err(HermesError::from)
+}
+
+// removes the item for everyone, returns its feed and the users who had it
+pub fn purge_item(iid: i32) -> Result<(i32, Vec<i32>), HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::Integer;
+  use schema::{
+    content_retries, deleted_items, enclosures, item_shares, items, subscribed_items, topic_items,
+  };
+
+  let connection = get_connection()?;
+  connection.transaction(|| {
+    let (fid, item_guid) = items::table
+      .find(iid)
+      .select((items::feed_id, items::guid))
+      .first::<(i32, String)>(&*connection)?;
+    let uids = diesel::delete(subscribed_items::table.filter(subscribed_items::item_id.eq(iid)))
+      .returning(subscribed_items::user_id)
+      .get_results::<i32>(&*connection)?;
+    diesel::delete(topic_items::table.filter(topic_items::item_id.eq(iid)))
+      .execute(&*connection)?;
+    diesel::delete(enclosures::table.filter(enclosures::item_id.eq(iid))).execute(&*connection)?;
+    diesel::delete(content_retries::table.find(iid)).execute(&*connection)?;
+    diesel::delete(item_shares::table.filter(item_shares::item_id.eq(iid)))
+      .execute(&*connection)?;
+    // so web nodes that have not caught up yet do not push it anymore
+    sql_query("UPDATE events SET item_ids = array_remove(item_ids, $1) WHERE $1 = ANY(item_ids)")
+      .bind::<Integer, _>(iid)
+      .execute(&*connection)?;
+    diesel::delete(items::table.find(iid)).execute(&*connection)?;
+    diesel::insert_into(deleted_items::table)
+      .values((
+        deleted_items::item_id.eq(iid),
+        deleted_items::feed_id.eq(fid),
+        deleted_items::guid.eq(item_guid),
+      )).execute(&*connection)?;
+    Ok((fid, uids))
+  }).map_err(HermesError::from)
+}
+
+// Items beyond the newest `keep` of their feed and older than `older_than`,
+// whichever are given, except starred ones. They are deleted `batch` at a
+// time so that no transaction holds the items table for long.
+pub fn prune_items(
+  keep: Option<i64>,
+  older_than: Option<DateTime<Utc>>,
+  batch: usize,
+) -> Result<usize, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::{Array, BigInt, Integer, Nullable, Timestamptz};
+  use schema::{content_retries, enclosures, items, subscribed_items, topic_items};
+
+  #[derive(QueryableByName)]
+  struct Expired {
+    #[sql_type = "Integer"]
+    id: i32,
+  }
+
+  let connection = get_connection()?;
+  let expired: Vec<i32> = sql_query(
+    "SELECT id FROM ( \
+     SELECT id, feed_id, coalesce(published_at, updated_at) AS dated, row_number() OVER ( \
+     PARTITION BY feed_id ORDER BY coalesce(published_at, updated_at) DESC NULLS LAST, id DESC \
+     ) AS rank FROM items \
+     ) ranked \
+     WHERE ($1 IS NULL OR rank > $1) AND ($2 IS NULL OR dated < $2) \
+     AND NOT EXISTS (SELECT 1 FROM subscribed_items WHERE item_id = ranked.id AND starred) \
+     AND NOT EXISTS (SELECT 1 FROM item_shares WHERE item_id = ranked.id) \
+     AND NOT EXISTS ( \
+     SELECT 1 FROM retention_exemptions e \
+     JOIN subscribed_feeds s ON s.user_id = e.user_id AND s.feed_id = ranked.feed_id \
+     WHERE e.feed_id = ranked.feed_id OR e.tag IN ( \
+     SELECT tag FROM subscription_tags WHERE subscribed_feed_id = s.id))",
+  ).bind::<Nullable<BigInt>, _>(keep)
+  .bind::<Nullable<Timestamptz>, _>(older_than)
+  .load::<Expired>(&*connection)?
+  .into_iter()
+  .map(|e| e.id)
+  .collect();
+
+  let mut pruned = 0;
+  for ids in expired.chunks(batch) {
+    pruned += connection.transaction::<_, diesel::result::Error, _>(|| {
+      sql_query(
+        "INSERT INTO deleted_items (item_id, feed_id, guid) \
+         SELECT id, feed_id, guid FROM items WHERE id = ANY($1)",
+      ).bind::<Array<Integer>, _>(ids)
+      .execute(&*connection)?;
+      sql_query(
+        "UPDATE events SET item_ids = ARRAY(SELECT i FROM unnest(item_ids) i WHERE i <> ALL($1)) \
+         WHERE item_ids && $1",
+      ).bind::<Array<Integer>, _>(ids)
+      .execute(&*connection)?;
+      diesel::delete(subscribed_items::table.filter(subscribed_items::item_id.eq_any(ids)))
+        .execute(&*connection)?;
+      diesel::delete(topic_items::table.filter(topic_items::item_id.eq_any(ids)))
+        .execute(&*connection)?;
+      diesel::delete(enclosures::table.filter(enclosures::item_id.eq_any(ids)))
+        .execute(&*connection)?;
+      diesel::delete(content_retries::table.filter(content_retries::item_id.eq_any(ids)))
+        .execute(&*connection)?;
+      diesel::delete(items::table.filter(items::id.eq_any(ids))).execute(&*connection)
+    })?;
+  }
+  Ok(pruned)
+}
+
+pub fn get_deleted_items(uid: i32, since: DateTime<Utc>) -> Result<Vec<DeletedItem>, HermesError> {
+  use schema::deleted_items;
+
+  let connection = get_connection()?;
+  let subscribed = subscribed_feeds::table
+    .filter(subscribed_feeds::user_id.eq(uid))
+    .select(subscribed_feeds::feed_id);
+  deleted_items::table
+    .filter(deleted_items::deleted_at.gt(since))
+    .filter(
+      deleted_items::user_id.eq(uid).or(
+        deleted_items::user_id
+          .is_null()
+          .and(deleted_items::feed_id.eq_any(subscribed)),
+      ),
+    ).order(deleted_items::deleted_at.asc())
+    .load::<DeletedItem>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// guids purged from the feed, which must not come back on the next update
+pub fn get_purged_guids(fid: i32) -> Result<Vec<String>, HermesError> {
+  use schema::deleted_items;
+
+  let connection = get_connection()?;
+  deleted_items::table
+    .filter(deleted_items::feed_id.eq(fid))
+    .filter(deleted_items::user_id.is_null())
+    .select(deleted_items::guid)
+    .load::<String>(&*connection)
+    .map_err(HermesError::from)
+}
+
+// purged rows are kept, they stop the item from being fetched again
+pub fn prune_deleted_items(older_than: DateTime<Utc>) -> Result<usize, HermesError> {
+  use schema::deleted_items;
+
+  let connection = get_connection()?;
+  diesel::delete(
+    deleted_items::table
+      .filter(deleted_items::deleted_at.lt(older_than))
+      .filter(deleted_items::user_id.is_not_null()),
+  ).execute(&*connection)
+  .map_err(HermesError::from)
+}
+
+// stats
+
+pub fn get_db_stats(max_queries: i64) -> Result<DbStats, HermesError> {
+  use diesel::sql_query;
+  use diesel::sql_types::BigInt;
+
+  let connection = get_connection()?;
+  let tables = sql_query(
+    "SELECT CAST(relname AS TEXT) AS table_name, n_live_tup AS row_count, \
+     pg_total_relation_size(relid) AS total_bytes, pg_indexes_size(relid) AS index_bytes \
+     FROM pg_stat_user_tables \
+     ORDER BY relname",
+  ).load::<TableStats>(&*connection)?;
+  let indexes = sql_query(
+    "SELECT CAST(indexrelname AS TEXT) AS index_name, CAST(relname AS TEXT) AS table_name, \
+     pg_relation_size(indexrelid) AS bytes, idx_scan AS scans \
+     FROM pg_stat_user_indexes \
+     ORDER BY bytes DESC",
+  ).load::<IndexStats>(&*connection)?;
+  let cache = sql_query(
+    "SELECT CAST(SUM(heap_blks_hit) / NULLIF(SUM(heap_blks_hit) + SUM(heap_blks_read), 0) \
+     AS DOUBLE PRECISION) AS heap_hit_ratio, \
+     CAST(SUM(idx_blks_hit) / NULLIF(SUM(idx_blks_hit) + SUM(idx_blks_read), 0) \
+     AS DOUBLE PRECISION) AS index_hit_ratio \
+     FROM pg_statio_user_tables",
+  ).get_result::<CacheStats>(&*connection)
+  .ok();
+  // fails unless the extension is both created and preloaded, either way there is nothing to show
+  let slow_queries = sql_query(
+    "SELECT query, calls, total_time AS total_ms, mean_time AS mean_ms \
+     FROM pg_stat_statements \
+     WHERE dbid = (SELECT oid FROM pg_database WHERE datname = current_database()) \
+     ORDER BY mean_time DESC \
+     LIMIT $1",
+  ).bind::<BigInt, _>(max_queries)
+  .load::<SlowQuery>(&*connection)
+  .ok();
+  Ok(DbStats {
+    tables: tables,
+    indexes: indexes,
+    cache: cache,
+    slow_queries: slow_queries,
+  })
+}
@@ -0,0 +1,141 @@
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use hyper::{Body, Client, Request};
+use hyper_tls::HttpsConnector;
+use serde_json;
+use std::time::{Duration, Instant};
+use tokio::timer::{Delay, Timeout};
+
+use db;
+use error::HermesError;
+use models::FailedDelivery;
+
+// Outgoing HTTP deliveries, such as webhooks and push messages, are POSTed up
+// to MAX_ATTEMPTS times, waiting RETRY_SECS longer after each failure. After
+// the last one the delivery goes to `failed_deliveries` with its error, where
+// its owner finds it through `GET /api/account/deliveries/failed` and can
+// send it again with `POST /api/account/deliveries/failed/:id/replay`, e.g.
+// once their endpoint is back up. Failed deliveries are kept RETENTION_DAYS.
+
+static MAX_ATTEMPTS: i32 = 5;
+static RETRY_SECS: u64 = 30;
+static TIMEOUT_SECS: u64 = 15;
+pub static RETENTION_DAYS: i64 = 14;
+
+#[derive(Debug, Clone)]
+pub struct Delivery {
+  pub kind: &'static str,
+  pub target: String,
+  pub headers: Vec<(String, String)>,
+  pub payload: String,
+}
+
+// how a delivery went, after its last attempt
+#[derive(Debug)]
+pub struct Outcome {
+  pub attempts: i32,
+  pub error: Option<String>,
+}
+
+// spawned by whoever has something to deliver; failures end up in the table
+pub fn deliver(user_id: i32, delivery: Delivery) -> impl Future<Item = Outcome, Error = ()> {
+  future::loop_fn(1, move |attempt| {
+    let delivery = delivery.clone();
+    let sent = send(&delivery.target, &delivery.headers, &delivery.payload);
+    sent.then(move |sent| match sent {
+      Ok(_) => Either::A(future::ok(Loop::Break(Outcome {
+        attempts: attempt,
+        error: None,
+      }))),
+      Err(ref e) if attempt < MAX_ATTEMPTS => {
+        debug!("{} to '{}' failed, attempt {}: {}", delivery.kind, delivery.target, attempt, e);
+        let retry = Instant::now() + Duration::from_secs(RETRY_SECS * attempt as u64);
+        Either::B(
+          Delay::new(retry)
+            .map(move |_| Loop::Continue(attempt + 1))
+            .map_err(|e| error!("delivery retry timer errored: {}", e)),
+        )
+      }
+      Err(e) => {
+        info!("giving up on {} to '{}': {}", delivery.kind, delivery.target, e);
+        let error = e.to_string();
+        if let Err(e) = dead_letter(user_id, &delivery, &error, attempt) {
+          error!("could not store failed {} of user {}: {}", delivery.kind, user_id, e);
+        }
+        Either::A(future::ok(Loop::Break(Outcome {
+          attempts: attempt,
+          error: Some(error),
+        })))
+      }
+    })
+  })
+}
+
+// one more attempt; on success the delivery is forgotten
+pub fn replay(failed: FailedDelivery) -> impl Future<Item = (), Error = HermesError> {
+  let headers = match serde_json::from_str::<Vec<(String, String)>>(&failed.headers) {
+    Ok(headers) => headers,
+    Err(e) => return Either::A(future::err(HermesError::Parse(e.to_string()))),
+  };
+  let sent = send(&failed.target, &headers, &failed.payload);
+  Either::B(sent.then(move |sent| match sent {
+    Ok(_) => db::delete_failed_delivery(failed.id, failed.user_id).map(|_| ()),
+    Err(e) => {
+      db::record_failed_replay(failed.id, &e.to_string())?;
+      Err(e)
+    }
+  }))
+}
+
+fn dead_letter(
+  user_id: i32,
+  delivery: &Delivery,
+  error: &str,
+  attempts: i32,
+) -> Result<FailedDelivery, HermesError> {
+  let headers =
+    serde_json::to_string(&delivery.headers).map_err(|e| HermesError::Parse(e.to_string()))?;
+  db::insert_failed_delivery(
+    user_id,
+    delivery.kind,
+    &delivery.target,
+    &headers,
+    &delivery.payload,
+    error,
+    attempts,
+  )
+}
+
+// succeeds on any 2xx answer
+pub fn send(
+  target: &str,
+  headers: &Vec<(String, String)>,
+  payload: &str,
+) -> impl Future<Item = (), Error = HermesError> {
+  let https = match HttpsConnector::new(1) {
+    Ok(https) => https,
+    Err(e) => return Either::A(future::err(HermesError::Fetch(format!("TLS: {}", e)))),
+  };
+  let mut request = Request::post(target);
+  for &(ref name, ref value) in headers.iter() {
+    request.header(name.as_str(), value.as_str());
+  }
+  let request = match request.body(Body::from(payload.to_owned())) {
+    Ok(request) => request,
+    Err(e) => return Either::A(future::err(HermesError::Fetch(format!("'{}': {}", target, e)))),
+  };
+  let target = target.to_owned();
+  let client = Client::builder().build::<_, Body>(https);
+  let sent = client.request(request).map_err(HermesError::from).and_then(move |res| {
+    match res.status().is_success() {
+      true => Ok(()),
+      false => Err(HermesError::Fetch(format!("'{}' answered {}", target, res.status()))),
+    }
+  });
+  Either::B(
+    Timeout::new(sent, Duration::from_secs(TIMEOUT_SECS)).map_err(|e| {
+      e.into_inner()
+        .unwrap_or_else(|| HermesError::Fetch("timed out".to_owned()))
+    }),
+  )
+}
@@ -0,0 +1,73 @@
+use futures::Future;
+use regex::Regex;
+use url::Url;
+
+use error::HermesError;
+use feed::{fetch_feed, parse_fetched_data, FeedType};
+use models::FeedCandidate;
+
+// Pages announce their feeds with
+//
+//   <link rel="alternate" type="application/rss+xml" href="/feed.xml" title="Posts">
+//
+// A URL that is a feed already is its own single candidate.
+
+lazy_static! {
+  static ref LINK_TAG: Regex = Regex::new(r"(?i)<link\s[^>]*>").unwrap();
+  static ref REL_ALTERNATE: Regex =
+    Regex::new(r#"(?i)\brel\s*=\s*(?:["'][^"']*\balternate\b[^"']*["']|alternate\b)"#).unwrap();
+  static ref FEED_TYPE: Regex =
+    Regex::new(r#"(?i)\btype\s*=\s*["']?application/(rss|atom)\+xml"#).unwrap();
+  static ref HREF: Regex = Regex::new(r#"(?i)\bhref\s*=\s*["']([^"']+)["']"#).unwrap();
+  static ref TITLE: Regex = Regex::new(r#"(?i)\btitle\s*=\s*["']([^"']*)["']"#).unwrap();
+}
+
+pub fn find_feed_links(page: &[u8], base: &Url) -> Vec<FeedCandidate> {
+  let page = String::from_utf8_lossy(page);
+  let mut found: Vec<FeedCandidate> = vec![];
+  for tag in LINK_TAG.find_iter(&page).map(|tag| tag.as_str()) {
+    if !REL_ALTERNATE.is_match(tag) {
+      continue;
+    }
+    let kind = match FEED_TYPE.captures(tag) {
+      Some(ref t) if t[1].eq_ignore_ascii_case("atom") => "atom",
+      Some(_) => "rss",
+      None => continue,
+    };
+    let url = match HREF.captures(tag).and_then(|href| base.join(&href[1]).ok()) {
+      Some(url) => url.to_string(),
+      None => continue,
+    };
+    if found.iter().any(|c| c.url == url) {
+      continue;
+    }
+    found.push(FeedCandidate {
+      url: url,
+      title: TITLE
+        .captures(tag)
+        .map(|t| t[1].trim().to_owned())
+        .filter(|t| !t.is_empty()),
+      kind: kind,
+    });
+  }
+  found
+}
+
+// the feeds behind a URL, without storing anything
+pub fn candidates(url: String) -> impl Future<Item = Vec<FeedCandidate>, Error = HermesError> {
+  fetch_feed(url.clone()).and_then(move |data| {
+    let kind = match parse_fetched_data(&data) {
+      Ok(FeedType::RSS(_)) => "rss",
+      Ok(FeedType::Atom(_)) => "atom",
+      Ok(FeedType::Page(_)) | Err(_) => {
+        let base = Url::parse(&url).map_err(|e| HermesError::Parse(e.to_string()))?;
+        return Ok(find_feed_links(&data, &base));
+      }
+    };
+    Ok(vec![FeedCandidate {
+      url: url,
+      title: None,
+      kind: kind,
+    }])
+  })
+}
@@ -0,0 +1,140 @@
+use diesel;
+use hyper;
+use r2d2;
+use std::error::Error;
+use std::fmt;
+use warp::http::StatusCode;
+use warp::{self, Rejection};
+
+// What can go wrong outside of a request's own input. Routes reject with it
+// through `warp::reject::custom`, and `handle_rejection` turns it into a
+// status and a JSON body; workers log it and carry on with the next feed.
+#[derive(Debug)]
+pub enum HermesError {
+  Database(diesel::result::Error),
+  Pool(r2d2::Error),
+  Fetch(String),
+  Parse(String),
+  Auth(String),
+  Crypto(String),
+  // the resource and its limit
+  QuotaExceeded(&'static str, i64),
+  NotFound,
+}
+
+impl HermesError {
+  pub fn status(&self) -> StatusCode {
+    match *self {
+      HermesError::Database(_) | HermesError::Crypto(_) => StatusCode::INTERNAL_SERVER_ERROR,
+      // no connection to be had, which should pass
+      HermesError::Pool(_) => StatusCode::SERVICE_UNAVAILABLE,
+      HermesError::Fetch(_) => StatusCode::BAD_GATEWAY,
+      HermesError::Parse(_) => StatusCode::UNPROCESSABLE_ENTITY,
+      HermesError::Auth(_) => StatusCode::UNAUTHORIZED,
+      HermesError::QuotaExceeded(..) => StatusCode::FORBIDDEN,
+      HermesError::NotFound => StatusCode::NOT_FOUND,
+    }
+  }
+
+  pub fn code(&self) -> &'static str {
+    match *self {
+      HermesError::Database(_) | HermesError::Pool(_) => "database_error",
+      HermesError::Fetch(_) => "fetch_failed",
+      HermesError::Parse(_) => "parse_failed",
+      HermesError::Auth(_) => "unauthorized",
+      HermesError::Crypto(_) => "crypto_error",
+      HermesError::QuotaExceeded(..) => "quota_exceeded",
+      HermesError::NotFound => "not_found",
+    }
+  }
+
+  // database and key details stay in the log, the rest is about the client's own request
+  pub fn message(&self) -> String {
+    match *self {
+      HermesError::Database(_) | HermesError::Pool(_) | HermesError::Crypto(_) => {
+        self.description().to_owned()
+      }
+      _ => self.to_string(),
+    }
+  }
+}
+
+impl fmt::Display for HermesError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      HermesError::Database(ref e) => write!(f, "database error: {}", e),
+      HermesError::Pool(ref e) => write!(f, "no database connection: {}", e),
+      HermesError::Fetch(ref e) => write!(f, "could not fetch: {}", e),
+      HermesError::Parse(ref e) => write!(f, "could not parse: {}", e),
+      HermesError::Auth(ref e) => write!(f, "not authenticated: {}", e),
+      HermesError::Crypto(ref e) => write!(f, "secret store error: {}", e),
+      HermesError::QuotaExceeded(name, max) => {
+        write!(f, "quota exceeded: at most {} {}", max, name)
+      }
+      HermesError::NotFound => f.write_str(self.description()),
+    }
+  }
+}
+
+impl Error for HermesError {
+  fn description(&self) -> &str {
+    match *self {
+      HermesError::Database(_) | HermesError::Pool(_) => "database error",
+      HermesError::Fetch(_) => "could not fetch",
+      HermesError::Parse(_) => "could not parse",
+      HermesError::Auth(_) => "not authenticated",
+      HermesError::Crypto(_) => "secret store error",
+      HermesError::QuotaExceeded(..) => "quota exceeded",
+      HermesError::NotFound => "not found",
+    }
+  }
+}
+
+impl From<diesel::result::Error> for HermesError {
+  fn from(e: diesel::result::Error) -> Self {
+    match e {
+      diesel::result::Error::NotFound => HermesError::NotFound,
+      e => HermesError::Database(e),
+    }
+  }
+}
+
+impl From<r2d2::Error> for HermesError {
+  fn from(e: r2d2::Error) -> Self {
+    HermesError::Pool(e)
+  }
+}
+
+impl From<hyper::Error> for HermesError {
+  fn from(e: hyper::Error) -> Self {
+    HermesError::Fetch(e.to_string())
+  }
+}
+
+impl From<HermesError> for Rejection {
+  fn from(e: HermesError) -> Self {
+    warp::reject::custom(e)
+  }
+}
+
+// a lookup that found nothing rejects like an unknown route, so other routes get a go
+pub fn reject(e: HermesError) -> Rejection {
+  match e {
+    HermesError::NotFound => warp::reject::not_found(),
+    e => e.into(),
+  }
+}
+
+pub fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, Rejection> {
+  match err.find_cause::<HermesError>() {
+    Some(e) => {
+      match e.status().is_server_error() {
+        true => error!("request failed: {}", e),
+        false => debug!("request rejected: {}", e),
+      };
+      let reply = warp::reply::json(&json!({ "error": e.code(), "message": e.message() }));
+      Ok(warp::reply::with_status(reply, e.status()))
+    }
+    None => Err(err),
+  }
+}
@@ -0,0 +1,248 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::{Future, Stream};
+use hyper::rt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+
+use db::{self, get_events_since, get_latest_event_id, insert_event};
+use error::HermesError;
+use feed::{send_items, with_enclosures};
+use models::{CompositeItem, Item, OutgoingWebsocketMessage, Topic};
+use web::types::UserWebsocketState;
+use web::ws::ws_send_message;
+
+// Workers and web nodes only share the database, so new items are handed
+// over through the `events` table: workers append, web nodes poll and
+// forward to whichever subscribers have a websocket open on that node.
+
+static POLL_SECS: u64 = 5;
+static RETENTION_MINS: i64 = 60;
+static GAP_SECS: u64 = 60;
+static MAX_GAPS: i32 = 1000;
+
+// Ids are taken when rows are inserted, but the rows are only read once their
+// transaction commits, so a later id can show up first. The ids skipped over
+// are asked for again until GAP_SECS have passed, after which their inserts
+// are taken to have been rolled back.
+struct Cursor {
+  last: i32,
+  gaps: HashMap<i32, Instant>,
+}
+
+impl Cursor {
+  fn new(last: i32) -> Cursor {
+    Cursor {
+      last: last,
+      gaps: HashMap::new(),
+    }
+  }
+
+  // the skipped ids still worth asking for
+  fn missing(&mut self) -> Vec<i32> {
+    let now = Instant::now();
+    let wait = Duration::from_secs(GAP_SECS);
+    self.gaps.retain(|_, noticed| now.duration_since(*noticed) < wait);
+    self.gaps.keys().cloned().collect()
+  }
+
+  fn saw(&mut self, id: i32) {
+    if id <= self.last {
+      self.gaps.remove(&id);
+      return;
+    }
+    let now = Instant::now();
+    for skipped in (self.last + 1).max(id - MAX_GAPS)..id {
+      self.gaps.insert(skipped, now);
+    }
+    self.last = id;
+  }
+}
+
+pub fn publish_new_items(feed_id: i32, items: &Vec<Item>, subscriber_ids: &Vec<i32>) {
+  let item_ids: Vec<i32> = items.iter().map(|i| i.id).collect();
+  match insert_event(feed_id, &item_ids, subscriber_ids, None) {
+    Ok(_) => debug!("published event for feed '{}'", feed_id),
+    Err(e) => error!("could not publish event for feed '{}': {}", feed_id, e),
+  };
+  count_items(feed_id, items);
+}
+
+// the stats rollup counts items on the day they were published, undated ones
+// are left out like `db::reconcile_feed_stats` does
+fn count_items(feed_id: i32, items: &Vec<Item>) {
+  let mut per_day = HashMap::new();
+  for date in items.iter().filter_map(|i| i.published_at.or(i.updated_at)) {
+    *per_day.entry(date.naive_utc().date()).or_insert(0) += 1;
+  }
+  if !per_day.is_empty() {
+    if let Err(e) = db::count_feed_items(feed_id, &per_day) {
+      error!("could not count items of feed '{}': {}", feed_id, e);
+    }
+  }
+}
+
+pub fn publish_topic_match(topic: &Topic, feed_id: i32, item_ids: &Vec<i32>) {
+  if let Err(e) = insert_event(feed_id, item_ids, &vec![topic.user_id], Some(topic.id)) {
+    error!("could not publish match of topic '{}': {}", topic.id, e);
+  }
+}
+
+pub fn prune_events() {
+  match db::prune_events(Utc::now() - ChronoDuration::minutes(RETENTION_MINS)) {
+    Ok(n) => debug!("pruned {} events", n),
+    Err(e) => error!("could not prune events: {}", e),
+  };
+}
+
+// The cursors start at the newest rows once the database answers, events
+// from before the listener started are not sent.
+pub fn start_event_listener(state: UserWebsocketState) {
+  let last_seen = Arc::new(Mutex::new(None));
+  let last_notification = Arc::new(Mutex::new(None));
+  let listener = Interval::new(Instant::now(), Duration::from_secs(POLL_SECS))
+    .for_each(move |_| {
+      let mut last_notification = last_notification.lock().unwrap();
+      if let Some(ref mut cursor) = *last_notification {
+        send_notifications(cursor, &state);
+      }
+      if last_notification.is_none() {
+        *last_notification = start_at(db::get_latest_notification_id());
+      }
+      let mut last_seen = last_seen.lock().unwrap();
+      if let Some(ref mut cursor) = *last_seen {
+        send_events(cursor, &state);
+      }
+      if last_seen.is_none() {
+        *last_seen = start_at(get_latest_event_id());
+      }
+      Ok(())
+    }).map_err(|e| panic!("event listener errored; err={:?}", e));
+  rt::spawn(listener);
+}
+
+fn send_events(cursor: &mut Cursor, state: &UserWebsocketState) {
+  let events = match get_events_since(cursor.last, &cursor.missing()) {
+    Ok(events) => events,
+    Err(e) => {
+      error!("could not load events: {}", e);
+      return;
+    }
+  };
+  for event in events {
+    cursor.saw(event.id);
+    let connected: Vec<i32> = {
+      let users = state.state.lock().unwrap();
+      event
+        .user_ids
+        .iter()
+        .filter(|uid| users.contains_key(uid))
+        .cloned()
+        .collect()
+    };
+    // subscribers may have left the feed since the event was written
+    let connected: Vec<i32> = match event.topic_id {
+      Some(_) => connected,
+      None => connected
+        .into_iter()
+        .filter(|uid| db::is_subscribed(uid, &event.feed_id).unwrap_or(false))
+        .collect(),
+    };
+    if connected.is_empty() {
+      continue;
+    }
+    match (db::get_items_by_ids(&event.item_ids), event.topic_id) {
+      (Ok(items), Some(topic_id)) => send_topic_match(topic_id, items, &connected, state),
+      (Ok(items), None) => send_items(event.feed_id, items, &connected, state),
+      (Err(e), _) => error!("could not load items for event '{}': {}", event.id, e),
+    }
+  }
+}
+
+// notifications are written wherever they happen, so they are polled like events
+fn send_notifications(cursor: &mut Cursor, state: &UserWebsocketState) {
+  let notifications = match db::get_notifications_since(cursor.last, &cursor.missing()) {
+    Ok(notifications) => notifications,
+    Err(e) => {
+      error!("could not load notifications: {}", e);
+      return;
+    }
+  };
+  for notification in notifications {
+    cursor.saw(notification.id);
+    let user_id = notification.user_id;
+    let msg = OutgoingWebsocketMessage::notification(notification);
+    ws_send_message(&user_id, msg.to_message(), state);
+  }
+}
+
+fn start_at(latest: Result<i32, HermesError>) -> Option<Cursor> {
+  latest
+    .map(Cursor::new)
+    .map_err(|e| error!("could not find where the listener starts: {}", e))
+    .ok()
+}
+
+fn send_topic_match(
+  topic_id: i32,
+  items: Vec<Item>,
+  user_ids: &Vec<i32>,
+  state: &UserWebsocketState,
+) {
+  let composites = with_enclosures(items.iter().map(|i| CompositeItem::from_item(i)).collect());
+  let msg = OutgoingWebsocketMessage::topic_match(topic_id, composites);
+  for uid in user_ids.iter() {
+    ws_send_message(uid, msg.to_message(), state);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Cursor, GAP_SECS, MAX_GAPS};
+  use std::time::{Duration, Instant};
+
+  fn missing(cursor: &mut Cursor) -> Vec<i32> {
+    let mut missing = cursor.missing();
+    missing.sort();
+    missing
+  }
+
+  #[test]
+  fn consecutive_ids_leave_no_gaps() {
+    let mut cursor = Cursor::new(10);
+    cursor.saw(11);
+    cursor.saw(12);
+    assert_eq!(cursor.last, 12);
+    assert!(missing(&mut cursor).is_empty());
+  }
+
+  #[test]
+  fn skipped_ids_are_asked_for_until_seen() {
+    let mut cursor = Cursor::new(10);
+    cursor.saw(14);
+    assert_eq!(cursor.last, 14);
+    assert_eq!(missing(&mut cursor), vec![11, 12, 13]);
+    cursor.saw(12);
+    assert_eq!(cursor.last, 14);
+    assert_eq!(missing(&mut cursor), vec![11, 13]);
+  }
+
+  #[test]
+  fn gaps_are_given_up_on_after_a_while() {
+    let mut cursor = Cursor::new(10);
+    cursor.saw(13);
+    let long_ago = Instant::now() - Duration::from_secs(GAP_SECS + 1);
+    cursor.gaps.insert(11, long_ago);
+    assert_eq!(missing(&mut cursor), vec![12]);
+  }
+
+  #[test]
+  fn large_jumps_only_keep_the_latest_gaps() {
+    let mut cursor = Cursor::new(0);
+    cursor.saw(5 * MAX_GAPS);
+    let missing = missing(&mut cursor);
+    assert_eq!(missing.len(), MAX_GAPS as usize);
+    assert_eq!(missing[0], 4 * MAX_GAPS);
+  }
+}
@@ -0,0 +1,117 @@
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
+use readability::extractor;
+use std::env;
+use std::io::Cursor;
+use std::time::Duration;
+use tokio::timer::Timeout;
+use url::Url;
+
+use db::{self, set_item_content};
+use error::HermesError;
+use feed::fetch_feed;
+use models::{ContentRetry, Item};
+use sanitize;
+
+static FETCH_TIMEOUT_SECS: u64 = 15;
+// the first retry waits half an hour, every following one twice as long
+static RETRY_DELAY_SECS: i64 = 1800;
+static RETRY_BATCH: i64 = 20;
+static RETRY_LEASE_SECS: i64 = 600;
+
+lazy_static! {
+  // CONTENT_RETRIES=0 leaves failed items with the feed's content right away
+  static ref CONTENT_RETRIES: i32 = env::var("CONTENT_RETRIES")
+    .ok()
+    .and_then(|r| r.parse().ok())
+    .unwrap_or(5);
+}
+
+// Downloads the item's page and keeps the readable part of it as content.
+// When the page can't be fetched or read, another attempt is scheduled.
+pub fn fetch_full(item_id: i32, link: String) -> impl Future<Item = String, Error = ()> {
+  let url = match Url::parse(&link) {
+    Ok(url) => url,
+    Err(_) => return Either::A(future::err(())),
+  };
+  let page = Timeout::new(fetch_feed(link), Duration::from_secs(FETCH_TIMEOUT_SECS)).map_err(|e| {
+    e.into_inner()
+      .map(|e| e.to_string())
+      .unwrap_or_else(|| "timed out".to_owned())
+  });
+  Either::B(
+    page
+      .and_then(move |body| {
+        extractor::extract(&mut Cursor::new(body), &url)
+          .map(|product| sanitize::clean(&product.content, Some(&url)))
+          .map_err(|e| format!("could not extract: {:?}", e))
+      }).then(move |res| match res {
+        Ok(html) => match set_item_content(item_id, &html) {
+          Ok(_) => {
+            if let Err(e) = db::clear_content_retry(item_id) {
+              error!("could not clear content retry of item '{}': {}", item_id, e);
+            }
+            Ok(html)
+          }
+          Err(e) => {
+            error!("could not store content of item '{}': {}", item_id, e);
+            Err(())
+          }
+        },
+        Err(reason) => {
+          error!("could not fetch full content of item '{}': {}", item_id, reason);
+          retry_later(item_id, &reason);
+          Err(())
+        }
+      }),
+  )
+}
+
+fn retry_later(item_id: i32, reason: &str) {
+  match db::record_content_failure(item_id, reason, *CONTENT_RETRIES, RETRY_DELAY_SECS) {
+    Ok(ContentRetry {
+      next_attempt_at: Some(at),
+      ..
+    }) => info!("will fetch content of item '{}' again at {}", item_id, at),
+    Ok(_) => info!("giving up on the content of item '{}'", item_id),
+    Err(e) => error!("could not schedule content retry of item '{}': {}", item_id, e),
+  }
+}
+
+// Fetches the items whose retry is due, one after the other.
+pub fn retry_due() -> impl Future<Item = (), Error = ()> {
+  let due = db::claim_content_retries(RETRY_BATCH, RETRY_LEASE_SECS).unwrap_or_else(|e| {
+    error!("could not claim content retries: {}", e);
+    vec![]
+  });
+  if !due.is_empty() {
+    debug!("retrying content of {} items", due.len());
+  }
+  stream::iter_ok(due).for_each(|(item_id, link)| fetch_full(item_id, link).then(|_| Ok(())))
+}
+
+// For feeds with `fetch_full_content`, replaces the content of freshly
+// inserted items one page at a time; items that fail keep the feed's version.
+pub fn fill_content(
+  feed_id: i32,
+  items: Vec<Item>,
+) -> impl Future<Item = Vec<Item>, Error = HermesError> {
+  let enabled = match db::get_feed(feed_id) {
+    Ok(feed) => feed.fetch_full_content,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  if !enabled || items.is_empty() {
+    return Either::A(future::ok(items));
+  }
+  Either::B(
+    stream::iter_ok(items)
+      .and_then(|mut item| {
+        fetch_full(item.id, item.link.clone()).then(move |res| {
+          if let Ok(html) = res {
+            item.content = Some(html);
+          }
+          Ok(item)
+        })
+      }).collect(),
+  )
+}
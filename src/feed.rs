@@ -1,159 +1,882 @@
 use atom_syndication;
-use futures::future::IntoFuture;
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::future::{self, Either, IntoFuture, Loop};
+use futures::stream;
 use hyper::rt::{self, Future, Stream};
-use hyper::{Body, Client};
+use hyper::header::{CONTENT_LENGTH, LOCATION};
+use hyper::{Body, Client, Response, StatusCode, Uri};
 use hyper_tls::HttpsConnector;
 use quick_xml::events::Event;
 use quick_xml::Reader;
 use rss;
+use serde_json;
+use std::collections::{HashMap, HashSet};
 use std::io::BufReader;
 use std::option::Option;
 use std::str;
 use std::time::{Duration, Instant};
-use tokio::timer::Interval;
+use tokio::timer::{Delay, Interval, Timeout};
+use url::Url;
 
 use db::{
   self, find_duplicates, get_channel_urls_and_subscribers, insert_channel, insert_items,
   insert_subscribed_items, update_item,
 };
-use models::{CompositeItem, Item, NewFeed, NewItem, OutgoingWebsocketMessage};
+use deliveries;
+use discovery;
+use error::HermesError;
+use events;
+use extract;
+use filters;
+use guid_cache;
+use icons;
+use media;
+use metrics;
+use mutes;
+use pages;
+use paging;
+use push;
+use quotas::{self, Resource};
+use retention;
+use streaming;
+use topics;
+use webhooks;
+use websub;
+use windows;
+use models::{
+  AddFeedOutcome, AddFeedResult, CompositeItem, Item, NewEnclosure, NewFeed, NewItem,
+  OutgoingWebsocketMessage, Page, ParseWarning, ParseWarningKind,
+};
 use web::{types::SubscribeParams, types::UserWebsocketState, ws::ws_send_message};
 
-enum FeedType {
+// a response after redirects, whatever its status
+pub struct Fetched {
+  pub status: StatusCode,
+  pub body: Vec<u8>,
+  // where the resource has moved permanently, if it has
+  pub moved_to: Option<String>,
+}
+impl Fetched {
+  pub fn check_status(&self) -> Result<(), HermesError> {
+    match self.status.is_success() {
+      true => Ok(()),
+      false => Err(HermesError::Fetch(format!("HTTP {}", self.status))),
+    }
+  }
+}
+
+pub enum FeedType {
   RSS(rss::Channel),
   Atom(atom_syndication::Feed),
+  // a page without a feed, watched for changes
+  Page(pages::Page),
 }
 enum ItemType {
   Item(Vec<rss::Item>),
   Entry(Vec<atom_syndication::Entry>),
 }
 
+pub static UPDATE_SECS: u64 = 300;
+// a failing feed waits at most 2^MAX_BACKOFF_SHIFT intervals between fetches
+static MAX_BACKOFF_SHIFT: i32 = 4;
+// a bit shorter than the interval, so the lease has expired by the next round
+static LEASE_SECS: i64 = 270;
+static ADD_TIMEOUT_SECS: u64 = 20;
+static MAX_REDIRECTS: usize = 5;
+static REFRESH_TIMEOUT_SECS: u64 = 30;
+static CLEANUP_SECS: u64 = 3600;
+// job leases end a minute before the job's next run, like LEASE_SECS
+static JOB_LEASE_MARGIN_SECS: u64 = 60;
+static CONTENT_RETRY_SECS: u64 = 300;
+static RECONCILE_DAYS: i64 = 30;
+static ACTIVITY_RETENTION_DAYS: i64 = 90;
+static NOTIFICATION_RETENTION_DAYS: i64 = 30;
+static ITEM_STATE_LOG_RETENTION_DAYS: i64 = 30;
+
+// Each feed is fetched at a fixed offset into the interval instead of all at
+// once. The offset only depends on the feed id, so every worker (and the
+// schedule route) agrees on it.
+pub fn fetch_offset(feed_id: i32) -> Duration {
+  let spread = (feed_id as u32).wrapping_mul(2_654_435_761) as u64 % (UPDATE_SECS * 1000);
+  Duration::from_millis(spread)
+}
+
+// Each failure in a row doubles the intervals between a feed's fetches, so a
+// broken server isn't asked every five minutes until its feed is dead.
+pub fn backoff_cycles(failures: i32) -> u64 {
+  1 << failures.max(0).min(MAX_BACKOFF_SHIFT)
+}
+
+// Cycles are counted from the epoch, so every worker agrees on them; the
+// feed id staggers feeds that started failing together.
+pub fn is_due(feed_id: i32, failures: i32, cycle: u64) -> bool {
+  (cycle + feed_id as u64) % backoff_cycles(failures) == 0
+}
+
 ////////////////////////
 /// Future sequences ///
 ////////////////////////
 
-pub fn start_interval_loops(global_user_state: UserWebsocketState) {
-  let update_subscriptions = Interval::new(Instant::now(), Duration::from_secs(300))
+// whether this worker runs the job this time, see `db::lease_job`
+fn lease_job(job: &str, every_secs: u64) -> bool {
+  match db::lease_job(job, (every_secs - JOB_LEASE_MARGIN_SECS) as i64) {
+    Ok(true) => true,
+    Ok(false) => {
+      debug!("job '{}' is leased by another worker", job);
+      false
+    }
+    Err(e) => {
+      error!("could not lease job '{}': {}", job, e);
+      false
+    }
+  }
+}
+
+pub fn start_interval_loops() {
+  let update_subscriptions = Interval::new(Instant::now(), Duration::from_secs(UPDATE_SECS))
     .for_each(move |_| {
-      get_channel_urls_and_subscribers().into_iter().for_each(
+      metrics::record_cycle();
+      let feeds = match get_channel_urls_and_subscribers() {
+        Ok(feeds) => feeds,
+        Err(e) => {
+          error!("could not load the feeds to update: {}", e);
+          return Ok(());
+        }
+      };
+      guid_cache::retain(&feeds.iter().map(|f| f.0).collect());
+      let mut schedule = windows::load();
+      let pushed_feeds = db::get_pushed_feeds().unwrap_or_else(|e| {
+        error!("could not load the pushed feeds: {}", e);
+        HashSet::new()
+      });
+      let failing_feeds = db::get_failing_feeds().unwrap_or_else(|e| {
+        error!("could not load the failing feeds: {}", e);
+        HashMap::new()
+      });
+      let cycle = Utc::now().timestamp() as u64 / UPDATE_SECS;
+      metrics::record_schedule(
+        feeds
+          .iter()
+          .map(|&(feed_id, _, _)| {
+            let failures = failing_feeds.get(&feed_id).cloned().unwrap_or(0);
+            (feed_id, fetch_offset(feed_id), UPDATE_SECS * backoff_cycles(failures))
+          }).collect(),
+      );
+      feeds.into_iter().for_each(
         |(feed_id, feed_url, subscriber_ids)| {
-          let local_state = global_user_state.clone();
+          let failures = failing_feeds.get(&feed_id).cloned().unwrap_or(0);
+          if !is_due(feed_id, failures, cycle) {
+            debug!("feed '{}' is backing off after {} failures", feed_id, failures);
+            return;
+          }
           let sid = subscriber_ids.clone();
-          let work = update_feed(feed_id, feed_url, subscriber_ids).and_then(move |new_items| {
-            match new_items {
-              Some(items) => {
-                debug!("found {} new items for {}", items.len(), &feed_id);
-                send_items(feed_id, items, &sid, &local_state);
+          let window = schedule.remove(&feed_id);
+          let pushed = pushed_feeds.contains(&feed_id);
+          let work = Delay::new(Instant::now() + fetch_offset(feed_id))
+            .map_err(|e| error!("fetch delay errored; err={:?}", e))
+            .and_then(move |_| {
+              if !windows::is_open(&window, Utc::now()) {
+                debug!("feed '{}' is outside of its refresh windows", feed_id);
+                return Err(());
               }
-              None => (),
-            };
-            Ok(())
-          });
+              // feeds a hub pushes are only polled in case pushes go missing
+              let lease = match pushed {
+                true => *websub::POLL_SECS,
+                false => LEASE_SECS,
+              };
+              match db::lease_feed(feed_id, lease) {
+                Ok(true) => Ok(()),
+                Ok(false) => {
+                  debug!("feed '{}' is leased by another worker", feed_id);
+                  Err(())
+                }
+                Err(e) => {
+                  error!("could not lease feed '{}': {}", feed_id, e);
+                  Err(())
+                }
+              }
+            }).and_then(move |_| {
+              update_feed(feed_id, feed_url, subscriber_ids).map_err(move |e| {
+                metrics::record_fetch(false);
+                error!("could not update feed '{}': {}", feed_id, e)
+              })
+            }).and_then(move |new_items| {
+              metrics::record_fetch(true);
+              match new_items {
+                Some(items) => {
+                  debug!("found {} new items for {}", items.len(), &feed_id);
+                  metrics::record_items(items.len());
+                  events::publish_new_items(feed_id, &items, &sid);
+                }
+                None => (),
+              };
+              Ok(())
+            });
           rt::spawn(work);
         },
       );
+      events::prune_events();
       Ok(())
     }).map_err(|e| panic!("delay errored; err={:?}", e));
   rt::spawn(update_subscriptions);
+
+  let cleanup = Interval::new(Instant::now(), Duration::from_secs(CLEANUP_SECS))
+    .for_each(|_| {
+      if !lease_job("cleanup", CLEANUP_SECS) {
+        return Ok(());
+      }
+      match db::delete_orphan_feeds() {
+        Ok(0) => (),
+        Ok(n) => info!("deleted {} feeds without subscribers", n),
+        Err(e) => error!("could not delete orphaned feeds: {}", e),
+      };
+      let now = Utc::now();
+      let ago = |days: i64| now - ChronoDuration::days(days);
+      let pruned = vec![
+        ("activity entries", db::prune_activity(ago(ACTIVITY_RETENTION_DAYS))),
+        ("deleted item entries", db::prune_deleted_items(ago(ACTIVITY_RETENTION_DAYS))),
+        ("notifications", db::prune_notifications(ago(NOTIFICATION_RETENTION_DAYS))),
+        ("item state changes", db::prune_item_state_log(ago(ITEM_STATE_LOG_RETENTION_DAYS))),
+        ("failed deliveries", db::prune_failed_deliveries(ago(deliveries::RETENTION_DAYS))),
+        ("webhook deliveries", db::prune_webhook_deliveries(ago(webhooks::LOG_RETENTION_DAYS))),
+        ("kiosk tokens", db::prune_kiosk_tokens()),
+      ];
+      for (what, result) in pruned {
+        match result {
+          Ok(n) => debug!("pruned {} {}", n, what),
+          Err(e) => error!("could not prune {}: {}", what, e),
+        };
+      }
+      websub::renew_due();
+      match db::auto_read_items() {
+        Ok(0) => (),
+        Ok(n) => info!("marked {} old items as read", n),
+        Err(e) => error!("could not mark old items as read: {}", e),
+      };
+      retention::prune();
+      if *media::ENABLED {
+        match db::prune_media(Utc::now() - ChronoDuration::days(media::RETENTION_DAYS)) {
+          Ok(0) => (),
+          Ok(n) => info!("deleted {} unused media blobs", n),
+          Err(e) => error!("could not prune media: {}", e),
+        };
+      }
+      Ok(())
+    }).map_err(|e| panic!("cleanup errored; err={:?}", e));
+  rt::spawn(cleanup);
+
+  let content_retries = Interval::new(Instant::now(), Duration::from_secs(CONTENT_RETRY_SECS))
+    .for_each(|_| {
+      rt::spawn(extract::retry_due());
+      Ok(())
+    }).map_err(|e| panic!("content retry interval errored; err={:?}", e));
+  rt::spawn(content_retries);
+
+  // nightly, a few minutes past midnight UTC
+  let now = Utc::now();
+  let midnight = (now.date() + ChronoDuration::days(1)).and_hms(0, 5, 0);
+  let wait = (midnight - now).to_std().unwrap_or_default();
+  let reconcile = Interval::new(Instant::now() + wait, Duration::from_secs(86400))
+    .for_each(|_| {
+      if !lease_job("reconcile", 86400) {
+        return Ok(());
+      }
+      let since = (Utc::now() - ChronoDuration::days(RECONCILE_DAYS)).naive_utc().date();
+      match db::reconcile_feed_stats(since) {
+        Ok(n) => debug!("recounted {} days of feed stats", n),
+        Err(e) => error!("could not reconcile feed stats: {}", e),
+      };
+      // today has only just started, yesterday is the last whole day
+      let yesterday = (Utc::now() - ChronoDuration::days(1)).naive_utc().date();
+      match db::update_item_rates(yesterday) {
+        Ok(n) => debug!("updated the item rate of {} feeds", n),
+        Err(e) => error!("could not update item rates: {}", e),
+      };
+      Ok(())
+    }).map_err(|e| panic!("reconcile errored; err={:?}", e));
+  rt::spawn(reconcile);
 }
 
 pub fn subscribe_feed(url: SubscribeParams, user_id: i32, state: UserWebsocketState) {
   let url = url.feed_url;
   debug!("subscribing: '{}' by '{}'", url, user_id);
+  if let Err(e) = quotas::check(user_id, Resource::Feeds, 1) {
+    error!("user {} could not subscribe: {}", user_id, e);
+    return;
+  }
   let work = db::get_feed_id(&url)
     .into_future()
-    .and_then(|feed_id| {
-      debug!("in db: '{}'", feed_id);
-      Ok((feed_id, db::get_item_ids(&feed_id)))
-    }).or_else(|_| {
-      debug!("not in db: '{}'", url);
-      add_feed(url)
-    }).and_then(move |(feed_id, item_ids)| {
-      db::subscribe_feed(&user_id, &feed_id);
-      Ok((feed_id, item_ids))
+    .then(move |known| match known {
+      Ok(feed_id) => {
+        debug!("in db: '{}'", feed_id);
+        Either::A(db::get_item_ids(&feed_id).map(|ids| (feed_id, Some(ids))).into_future())
+      }
+      Err(_) => {
+        debug!("not in db: '{}'", url);
+        Either::B(add_feed(url))
+      }
     }).and_then(move |(feed_id, item_ids)| {
-      match item_ids {
-        Some(item_ids) => subscribe_new_items(&item_ids, &vec![user_id]),
-        None => (),
-      };
-      Ok((feed_id, state))
-    }).and_then(move |(feed_id, state)| {
-      send_subscribeditems(feed_id, user_id, &state);
+      subscribe_user(feed_id, item_ids, user_id, &state);
       Ok(())
-    });
+    }).map_err(move |e| error!("user {} could not subscribe: {}", user_id, e));
   rt::spawn(work);
 }
 
-pub fn add_feed(url: String) -> impl Future<Item = (i32, Option<Vec<i32>>), Error = ()> {
-  fetch_feed(url.to_string())
-    .and_then(|data| parse_fetched_data(&data))
-    .and_then(move |data| handle_feed_types(data, &url))
-    .and_then(|(new_feed, new_items)| {
-      let new_ch = insert_channel(new_feed);
-      Ok((new_items, new_ch.id))
-    }).and_then(|(items, feed_id)| Ok((feed_id, handle_item_types(items, &feed_id))))
-    .and_then(|(feed_id, items)| {
-      let items = insert_items(&items).unwrap();
-      let item_ids: Vec<_> = items.into_iter().map(|i| i.id).collect();
-      Ok((feed_id, Some(item_ids)))
+// Hosts are worked through concurrently, but the feeds of a single host one
+// after the other, so a pasted blogroll doesn't hammer one site.
+pub fn add_feeds(
+  urls: Vec<String>,
+  user_id: i32,
+  state: UserWebsocketState,
+) -> impl Future<Item = Vec<AddFeedResult>, Error = ()> {
+  let mut invalid = vec![];
+  let mut by_host: HashMap<String, Vec<String>> = HashMap::new();
+  for raw in urls {
+    let raw = raw.trim().to_string();
+    match Url::parse(&raw) {
+      Ok(ref u) if (u.scheme() == "http" || u.scheme() == "https") && u.host_str().is_some() => {
+        by_host
+          .entry(u.host_str().unwrap().to_string())
+          .or_insert_with(Vec::new)
+          .push(raw.clone());
+      }
+      _ => invalid.push(AddFeedResult::new(&raw, AddFeedOutcome::Invalid, None)),
+    }
+  }
+
+  let hosts: Vec<_> = by_host
+    .into_iter()
+    .map(move |(_, urls)| {
+      let state = state.clone();
+      stream::iter_ok(urls)
+        .and_then(move |url| add_feed_for_user(url, user_id, state.clone()))
+        .collect()
+    }).collect();
+  future::join_all(hosts).map(move |results| {
+    results.into_iter().fold(invalid, |mut acc, r| {
+      acc.extend(r);
+      acc
+    })
+  })
+}
+
+fn add_feed_for_user(
+  url: String,
+  user_id: i32,
+  state: UserWebsocketState,
+) -> impl Future<Item = AddFeedResult, Error = ()> {
+  let known = db::get_feed_id(&url).ok();
+  if let Some(feed_id) = known {
+    match db::is_subscribed(&user_id, &feed_id) {
+      Ok(true) => {
+        let mut res = AddFeedResult::new(&url, AddFeedOutcome::Duplicate, Some(feed_id));
+        res.title = db::get_feed(feed_id).ok().map(|f| f.title);
+        return Either::A(future::ok(res));
+      }
+      Ok(false) => (),
+      Err(e) => {
+        error!("could not look up the subscriptions of user {}: {}", user_id, e);
+        let res = AddFeedResult::new(&url, AddFeedOutcome::Failed, None);
+        return Either::A(future::ok(res));
+      }
+    }
+  }
+
+  let lookup = match known {
+    Some(feed_id) => Either::A(future::result(
+      db::get_item_ids(&feed_id)
+        .map(|ids| (feed_id, Some(ids)))
+        .map_err(|e| {
+          error!("could not load the items of feed '{}': {}", feed_id, e);
+          AddFeedOutcome::Failed
+        }),
+    )),
+    None => Either::B(
+      Timeout::new(add_feed(url.clone()), Duration::from_secs(ADD_TIMEOUT_SECS)).map_err(|e| {
+        if e.is_elapsed() {
+          return AddFeedOutcome::Timeout;
+        }
+        match e.into_inner() {
+          Some(HermesError::Fetch(_)) => AddFeedOutcome::Unreachable,
+          Some(HermesError::Parse(_)) => AddFeedOutcome::NotAFeed,
+          _ => AddFeedOutcome::Failed,
+        }
+      }),
+    ),
+  };
+  let local = url.clone();
+  Either::B(
+    lookup
+      .and_then(move |(feed_id, item_ids)| {
+        // a site's page can lead to a feed the user already has
+        let feed = db::get_feed(feed_id).ok();
+        match db::is_subscribed(&user_id, &feed_id) {
+          Ok(true) => {
+            let mut res = AddFeedResult::new(&local, AddFeedOutcome::Duplicate, Some(feed_id));
+            res.title = feed.map(|f| f.title);
+            return Ok(res);
+          }
+          Ok(false) => (),
+          Err(e) => {
+            error!("could not look up the subscriptions of user {}: {}", user_id, e);
+            return Err(AddFeedOutcome::Failed);
+          }
+        }
+        if quotas::check(user_id, Resource::Feeds, 1).is_err() {
+          return Ok(AddFeedResult::new(&local, AddFeedOutcome::OverQuota, None));
+        }
+        subscribe_user(feed_id, item_ids, user_id, &state);
+        let mut res = AddFeedResult::new(&local, AddFeedOutcome::Added, Some(feed_id));
+        if let Some(feed) = feed {
+          res.warnings = feed.warnings();
+          res.title = Some(feed.title);
+        }
+        Ok(res)
+      }).or_else(move |outcome| Ok(AddFeedResult::new(&url, outcome, None))),
+  )
+}
+
+fn subscribe_user(
+  feed_id: i32,
+  item_ids: Option<Vec<i32>>,
+  user_id: i32,
+  state: &UserWebsocketState,
+) {
+  if let Err(e) = db::subscribe_feed(&user_id, &feed_id) {
+    error!("subscribe failure: '{}' by '{}': {}", feed_id, user_id, e);
+    return;
+  }
+  // the items of a feed `insert_feed` just added only reach their first
+  // subscriber here, so that's when the user's mutes and filters see them
+  match item_ids {
+    Some(item_ids) => {
+      subscribe_new_items(&item_ids, &vec![user_id]);
+      match db::get_items_by_ids(&item_ids) {
+        Ok(items) => {
+          mutes::apply_new(&items, &[user_id]);
+          filters::apply_new(&items, &[user_id]);
+        }
+        Err(e) => error!("could not load the items of feed '{}': {}", feed_id, e),
+      };
+    }
+    None => (),
+  };
+  send_subscribeditems(feed_id, user_id, state);
+}
+
+// `url` can also be a page that links to its feed, see `discovery`
+pub fn add_feed(url: String) -> impl Future<Item = (i32, Option<Vec<i32>>), Error = HermesError> {
+  fetch_following(url.to_string())
+    .and_then(move |fetched| {
+      if let Err(e) = fetched.check_status() {
+        return Either::A(future::err(e));
+      }
+      let url = fetched.moved_to.unwrap_or(url);
+      match parse_fetched_data(&fetched.body) {
+        Ok(parsed) => Either::A(future::ok((url, parsed))),
+        Err(e) => Either::B(fetch_discovered(url, &fetched.body, e)),
+      }
+    }).and_then(|(url, parsed)| match db::get_feed_id(&url) {
+      // a page pointing at a feed we already have
+      Ok(feed_id) => db::get_item_ids(&feed_id).map(|ids| (feed_id, Some(ids))),
+      Err(_) => insert_feed(url, parsed),
     })
 }
 
+fn fetch_discovered(
+  url: String,
+  page: &[u8],
+  not_feed: HermesError,
+) -> impl Future<Item = (String, FeedType), Error = HermesError> {
+  let found = Url::parse(&url)
+    .ok()
+    .and_then(|base| discovery::find_feed_links(page, &base).into_iter().next());
+  match found {
+    Some(candidate) => {
+      info!("'{}' is not a feed, trying the one it links to: '{}'", url, candidate.url);
+      Either::A(
+        fetch_feed(candidate.url.clone())
+          .and_then(move |data| Ok((candidate.url, parse_fetched_data(&data)?))),
+      )
+    }
+    None => {
+      let page = pages::extract(page, &url).map_err(|_| not_feed);
+      if page.is_ok() {
+        info!("'{}' is not a feed and links to none, watching the page", url);
+      }
+      Either::B(future::result(page.map(|page| (url, FeedType::Page(page)))))
+    }
+  }
+}
+
+fn insert_feed(url: String, parsed: FeedType) -> Result<(i32, Option<Vec<i32>>), HermesError> {
+  let parsed = match parsed {
+    FeedType::Page(page) => return pages::insert(url, page),
+    parsed => parsed,
+  };
+  let archive = paging::link(&parsed, &url, paging::PREV_ARCHIVE);
+  let (new_feed, items) = handle_feed_types(parsed, &url)?;
+  let base = Url::parse(&new_feed.site_link).ok();
+  let site_link = new_feed.site_link.clone();
+  let feed_id = insert_channel(new_feed)?.id;
+  rt::spawn(icons::discover(feed_id, site_link));
+  let enclosures = parse_enclosures(&items);
+  let items = insert_items(&handle_item_types(items, &feed_id, base.as_ref()))?;
+  store_enclosures(&items, enclosures);
+  topics::match_items(feed_id, &items);
+  if let Some(archive) = archive {
+    follow_pages(feed_id, archive, paging::PREV_ARCHIVE, *paging::ARCHIVE_PAGES);
+  }
+  let item_ids: Vec<_> = items.into_iter().map(|i| i.id).collect();
+  Ok((feed_id, Some(item_ids)))
+}
+
+// A fetch on request, outside of the schedule. It takes the feed's lease like
+// a scheduled one, so the result is None while a worker is fetching it.
+pub fn refresh_feed(feed_id: i32) -> impl Future<Item = Option<usize>, Error = HermesError> {
+  let feed = match db::get_feed(feed_id) {
+    Ok(feed) => feed,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  match db::lease_feed(feed_id, LEASE_SECS) {
+    Ok(true) => (),
+    Ok(false) => {
+      debug!("feed '{}' is leased by another worker", feed_id);
+      return Either::A(future::ok(None));
+    }
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let subscriber_ids = match db::get_feed_subscribers(feed_id) {
+    Ok(ids) => ids,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let sid = subscriber_ids.clone();
+  let update = update_feed(feed_id, feed.feed_link, subscriber_ids);
+  Either::B(
+    Timeout::new(update, Duration::from_secs(REFRESH_TIMEOUT_SECS))
+      .map_err(move |e| {
+        e.into_inner()
+          .unwrap_or_else(|| HermesError::Fetch(format!("feed '{}' timed out", feed_id)))
+      }).then(move |updated| {
+        metrics::record_fetch(updated.is_ok());
+        let items = updated?.unwrap_or_default();
+        if !items.is_empty() {
+          metrics::record_items(items.len());
+          events::publish_new_items(feed_id, &items, &sid);
+        }
+        Ok::<_, HermesError>(Some(items.len()))
+      }),
+  )
+}
+
 pub fn update_feed(
   feed_id: i32,
   channel_url: String,
   subscriber_ids: Vec<i32>,
-) -> impl Future<Item = Option<Vec<Item>>, Error = ()> {
+) -> impl Future<Item = Option<Vec<Item>>, Error = HermesError> {
+  match db::is_watched_page(feed_id) {
+    Ok(true) => return Either::A(Either::B(update_page(feed_id, channel_url, subscriber_ids))),
+    Ok(false) => (),
+    Err(e) => return Either::A(Either::A(future::err(e))),
+  };
   let local = channel_url.clone();
-  fetch_feed(channel_url)
-    .and_then(|data| parse_fetched_data(&data))
-    .and_then(move |data| handle_feed_types(data, &local))
-    .and_then(move |(_, items)| Ok(handle_item_types(items, &feed_id)))
-    .and_then(|items| Ok(process_duplicates(items)))
-    .and_then(move |new_items| match new_items {
+  let update = fetch_following(channel_url)
+    .then(move |fetched| {
+      count_fetch(feed_id, &fetched);
+      fetched
+    }).and_then(move |fetched| {
+      if follow_move(feed_id, &fetched.moved_to) {
+        return Ok(None);
+      }
+      let parsed = fetched
+        .check_status()
+        .and_then(|_| parse_fetched_data(&fetched.body));
+      let error = parsed.as_ref().err().map(|e| e.to_string());
+      record_fetch_status(feed_id, Some(fetched.status.as_u16() as i32), error);
+      parsed.map(Some)
+    })
+    .and_then(move |data| match data {
+      Some(data) => {
+        websub::discover(feed_id, &data, &local);
+        Either::A(store_fetched(feed_id, local, data, subscriber_ids))
+      }
+      None => Either::B(future::ok(None)),
+    });
+  Either::B(update)
+}
+
+// Stores the new items of a feed document, fetched or pushed by a hub, and
+// hands them to the subscribers.
+pub fn store_fetched(
+  feed_id: i32,
+  url: String,
+  data: FeedType,
+  subscriber_ids: Vec<i32>,
+) -> impl Future<Item = Option<Vec<Item>>, Error = HermesError> {
+  let next = paging::link(&data, &url, paging::NEXT);
+  future::result(handle_feed_types(data, &url).map(|parsed| (parsed, next)))
+    .and_then(move |((new_feed, items), next)| {
+      let base = Url::parse(&new_feed.site_link).ok();
+      let enclosures = parse_enclosures(&items);
+      Ok((handle_item_types(items, &feed_id, base.as_ref()), enclosures, next))
+    })
+    .and_then(move |(items, enclosures, next)| {
+      let fetched = items.len();
+      let (items, snapshot) = guid_cache::skip_known(feed_id, drop_purged(feed_id, items)?);
+      Ok::<_, HermesError>((items, enclosures, snapshot, fetched, next))
+    }).and_then(move |(items, enclosures, snapshot, fetched, next)| {
+      process_duplicates(feed_id, items)
+        .map(|new_items| (new_items, enclosures, snapshot, fetched, next))
+    }).and_then(move |(new_items, enclosures, snapshot, fetched, next)| match new_items {
       Some(items) => {
-        let items = insert_items(&items).unwrap();
-        let item_ids = items.iter().map(|i| i.id).collect();
-        subscribe_new_items(&item_ids, &subscriber_ids);
-        Ok(Some(items))
+        let items = match insert_items(&items) {
+          Ok(items) => items,
+          Err(e) => return Either::B(future::err(e)),
+        };
+        guid_cache::remember(feed_id, snapshot);
+        // only edits
+        if items.is_empty() {
+          return Either::B(future::ok(None));
+        }
+        store_enclosures(&items, enclosures);
+        // with nothing known on the page, the rest may be on the next one
+        if let Some(next) = next.filter(|_| items.len() == fetched) {
+          follow_pages(feed_id, next, paging::NEXT, *paging::NEXT_PAGES);
+        }
+        Either::A(extract::fill_content(feed_id, items).map(move |items| {
+          topics::match_items(feed_id, &items);
+          let item_ids = items.iter().map(|i| i.id).collect();
+          subscribe_new_items(&item_ids, &subscriber_ids);
+          mutes::apply_new(&items, &subscriber_ids);
+          filters::apply_new(&items, &subscriber_ids);
+          push::notify_new(feed_id, &items);
+          webhooks::notify_new(feed_id, &items, &subscriber_ids);
+          Some(items)
+        }))
+      }
+      None => {
+        guid_cache::remember(feed_id, snapshot);
+        Either::B(future::ok(None))
       }
-      None => Ok(None),
     })
 }
 
-fn send_items(
+// a new version of the feed from its hub, see `websub`
+pub fn receive_pushed(feed_id: i32, body: &[u8]) -> impl Future<Item = usize, Error = HermesError> {
+  let url = match db::get_feed(feed_id) {
+    Ok(feed) => feed.feed_link,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let data = match parse_fetched_data(body) {
+    Ok(data) => data,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let subscriber_ids = match db::get_feed_subscribers(feed_id) {
+    Ok(ids) => ids,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let sid = subscriber_ids.clone();
+  Either::B(
+    store_fetched(feed_id, url, data, subscriber_ids).map(move |items| {
+      let items = items.unwrap_or_default();
+      if !items.is_empty() {
+        metrics::record_items(items.len());
+        events::publish_new_items(feed_id, &items, &sid);
+      }
+      items.len()
+    }),
+  )
+}
+
+// reads the pages linked with `rel` in the background, see `paging`
+fn follow_pages(feed_id: i32, url: String, rel: &'static str, pages: usize) {
+  if pages == 0 {
+    return;
+  }
+  debug!("following '{}' pages of feed '{}' from '{}'", rel, feed_id, url);
+  rt::spawn(
+    walk_pages(feed_id, url, rel, pages)
+      .map(move |added| info!("added {} items from '{}' pages of feed '{}'", added, rel, feed_id))
+      .map_err(move |e| error!("could not follow '{}' pages of feed '{}': {}", rel, feed_id, e)),
+  );
+}
+
+// up to `pages` documents, each linking to the next with `rel`, stopping at
+// the first without new items or one already read
+fn walk_pages(
+  feed_id: i32,
+  url: String,
+  rel: &'static str,
+  pages: usize,
+) -> impl Future<Item = usize, Error = HermesError> {
+  future::loop_fn(
+    (url, pages, 0, HashSet::new()),
+    move |(url, left, added, mut seen): (String, usize, usize, HashSet<String>)| {
+      seen.insert(url.clone());
+      fetch_following(url.clone()).and_then(move |fetched| {
+        let page = fetched
+          .check_status()
+          .and_then(|_| store_page(feed_id, &url, rel, &fetched.body));
+        page.map(move |(new_items, next)| {
+          let next = next.filter(|n| new_items > 0 && left > 1 && !seen.contains(n));
+          match next {
+            Some(next) => Loop::Continue((next, left - 1, added + new_items, seen)),
+            None => Loop::Break(added + new_items),
+          }
+        })
+      })
+    },
+  )
+}
+
+// the number of new items on a page of the feed, and the page it links to
+fn store_page(
+  feed_id: i32,
+  url: &str,
+  rel: &str,
+  body: &[u8],
+) -> Result<(usize, Option<String>), HermesError> {
+  let parsed = parse_fetched_data(body)?;
+  let next = paging::link(&parsed, url, rel);
+  let (new_feed, items) = handle_feed_types(parsed, url)?;
+  let base = Url::parse(&new_feed.site_link).ok();
+  let enclosures = parse_enclosures(&items);
+  // unlike `handle_item_types`, keeps the warnings about the feed itself
+  let items = match items {
+    ItemType::Item(items) => process_items(items, &feed_id, base.as_ref()),
+    ItemType::Entry(entries) => process_entries(entries, &feed_id, base.as_ref()),
+  };
+  let subscriber_ids = db::get_feed_subscribers(feed_id)?;
+  let items = match process_duplicates(feed_id, drop_purged(feed_id, items)?)? {
+    Some(items) => insert_items(&items)?,
+    None => return Ok((0, next)),
+  };
+  store_enclosures(&items, enclosures);
+  topics::match_items(feed_id, &items);
+  let item_ids = items.iter().map(|i| i.id).collect();
+  subscribe_new_items(&item_ids, &subscriber_ids);
+  mutes::apply_new(&items, &subscriber_ids);
+  filters::apply_new(&items, &subscriber_ids);
+  push::notify_new(feed_id, &items);
+  webhooks::notify_new(feed_id, &items, &subscriber_ids);
+  Ok((items.len(), next))
+}
+
+// see `pages`
+fn update_page(
+  feed_id: i32,
+  page_url: String,
+  subscriber_ids: Vec<i32>,
+) -> impl Future<Item = Option<Vec<Item>>, Error = HermesError> {
+  fetch_following(page_url.clone())
+    .then(move |fetched| {
+      count_fetch(feed_id, &fetched);
+      fetched
+    }).and_then(move |fetched| {
+      if follow_move(feed_id, &fetched.moved_to) {
+        return Ok(None);
+      }
+      let url = fetched.moved_to.clone().unwrap_or(page_url);
+      let changed = fetched
+        .check_status()
+        .and_then(|_| pages::check(feed_id, &url, &fetched.body));
+      let error = changed.as_ref().err().map(|e| e.to_string());
+      record_fetch_status(feed_id, Some(fetched.status.as_u16() as i32), error);
+      changed.map(|changed| {
+        changed.map(|item| {
+          let items = vec![item];
+          topics::match_items(feed_id, &items);
+          subscribe_new_items(&vec![items[0].id], &subscriber_ids);
+          mutes::apply_new(&items, &subscriber_ids);
+          filters::apply_new(&items, &subscriber_ids);
+          push::notify_new(feed_id, &items);
+          webhooks::notify_new(feed_id, &items, &subscriber_ids);
+          items
+        })
+      })
+    })
+}
+
+fn count_fetch(feed_id: i32, fetched: &Result<Fetched, HermesError>) {
+  let counted = match *fetched {
+    Ok(ref f) => db::count_feed_fetch(feed_id, f.body.len(), !f.status.is_success()),
+    Err(ref e) => {
+      record_fetch_status(feed_id, None, Some(e.to_string()));
+      db::count_feed_fetch(feed_id, 0, true)
+    }
+  };
+  if let Err(e) = counted {
+    error!("could not count fetch of feed '{}': {}", feed_id, e);
+  }
+}
+
+fn record_fetch_status(feed_id: i32, status: Option<i32>, error: Option<String>) {
+  if let Err(e) = db::record_fetch_status(feed_id, status, error) {
+    error!("could not record the fetch status of feed '{}': {}", feed_id, e);
+  }
+}
+
+pub fn send_items(
   feed_id: i32,
   new_items: Vec<Item>,
   subscriber_ids: &Vec<i32>,
   state: &UserWebsocketState,
 ) {
-  let composites: Vec<_> = new_items
-    .into_iter()
-    .map(|item| CompositeItem::from_item(&item))
-    .collect();
+  let composites = with_enclosures(
+    new_items
+      .into_iter()
+      .map(|item| CompositeItem::from_item(&item))
+      .collect(),
+  );
   for uid in subscriber_ids.iter() {
     send_ws(feed_id, *uid, &composites, state);
   }
 }
 
 fn send_subscribeditems(feed_id: i32, user_id: i32, state: &UserWebsocketState) {
-  let items = db::get_subscribed_items(feed_id, user_id, None);
-  let composites: Vec<_> = items
-    .unwrap()
-    .into_iter()
-    .map(|item| CompositeItem::from_subscribed(&item))
-    .collect();
+  let items = match db::get_subscribed_items(feed_id, user_id, None, Page::default()) {
+    Ok(items) => items,
+    Err(e) => {
+      error!("could not load the items of feed '{}' for user {}: {}", feed_id, user_id, e);
+      return;
+    }
+  };
+  let composites = with_enclosures(
+    items
+      .into_iter()
+      .map(|item| CompositeItem::from_subscribed(&item))
+      .collect(),
+  );
   send_ws(feed_id, user_id, &composites, state);
 }
 
+pub fn with_enclosures(mut composites: Vec<CompositeItem>) -> Vec<CompositeItem> {
+  let ids = composites.iter().map(|c| c.id).collect();
+  let mut enclosures = db::get_enclosures(&ids).unwrap_or_else(|e| {
+    error!("could not load enclosures: {}", e);
+    HashMap::new()
+  });
+  for composite in composites.iter_mut() {
+    composite.enclosures = enclosures.remove(&composite.id).unwrap_or_default();
+  }
+  composites
+}
+
 fn send_ws(
   feed_id: i32,
   user_id: i32,
   composites: &Vec<CompositeItem>,
   state: &UserWebsocketState,
 ) {
-  let feed = db::get_subscribed_feed(&user_id, &feed_id);
-  let msg = OutgoingWebsocketMessage::new_feed(feed.unwrap());
+  let feed = match db::get_subscribed_feed(&user_id, &feed_id) {
+    Ok(feed) => feed,
+    Err(HermesError::NotFound) => return,
+    Err(e) => {
+      error!("could not load feed '{}' for user {}: {}", feed_id, user_id, e);
+      return;
+    }
+  };
+  let msg = OutgoingWebsocketMessage::new_feed(feed);
   ws_send_message(&user_id, msg.to_message(), &state);
   let msg = OutgoingWebsocketMessage::new_items(feed_id, composites.to_vec());
   ws_send_message(&user_id, msg.to_message(), &state);
@@ -163,58 +886,190 @@ fn send_ws(
 /// Future components ///
 /////////////////////////
 
-pub fn fetch_feed(url: String) -> impl Future<Item = Vec<u8>, Error = ()> {
-  let https = HttpsConnector::new(2).expect("TLS initialization failed");
+// the body of a successful response
+pub fn fetch_feed(url: String) -> impl Future<Item = Vec<u8>, Error = HermesError> {
+  fetch_following(url.clone()).and_then(move |fetched| {
+    fetched
+      .check_status()
+      .map_err(|e| HermesError::Fetch(format!("'{}': {}", url, e)))?;
+    Ok(fetched.body)
+  })
+}
+
+// Follows up to MAX_REDIRECTS redirects. The URL reached through permanent
+// ones (301 and 308) only, if any, is where the resource lives now.
+pub fn fetch_following(url: String) -> impl Future<Item = Fetched, Error = HermesError> {
+  let https = match HttpsConnector::new(2) {
+    Ok(https) => https,
+    Err(e) => return Either::A(future::err(HermesError::Fetch(format!("TLS: {}", e)))),
+  };
   let client = Client::builder().build::<_, Body>(https);
-  let local = url.to_owned();
-  client
-    .get(url.parse().unwrap())
-    .map_err(move |err| error!("could not fetch: '{}': {}", url, err))
-    .and_then(move |res| {
-      debug!("fetching: '{}'", local);
-      res
-        .into_body()
-        .concat2()
-        .map_err(|_err| ())
-        .and_then(move |body| {
-          debug!("collected body: {}", local);
-          Ok(body.to_vec())
-        })
-    })
+  Either::B(future::loop_fn(
+    (url, 0, None, true),
+    move |(url, hops, moved_to, permanent): (String, usize, Option<String>, bool)| {
+      let uri = match url.parse::<Uri>() {
+        Ok(uri) => uri,
+        Err(e) => return Either::A(future::err(HermesError::Fetch(format!("'{}': {}", url, e)))),
+      };
+      let local = url.to_owned();
+      Either::B(
+        client
+          .get(uri)
+          .map_err(move |err| {
+            error!("could not fetch: '{}': {}", url, err);
+            HermesError::Fetch(format!("'{}': {}", url, err))
+          }).and_then(move |res| {
+            debug!("fetching: '{}'", local);
+            let location = res
+              .headers()
+              .get(LOCATION)
+              .and_then(|l| l.to_str().ok())
+              .and_then(|l| Url::parse(&local).ok()?.join(l).ok());
+            let status = res.status();
+            match (status.is_redirection(), location) {
+              (true, Some(next)) => {
+                if hops == MAX_REDIRECTS {
+                  let e = format!("'{}': more than {} redirects", local, MAX_REDIRECTS);
+                  return Either::A(future::err(HermesError::Fetch(e)));
+                }
+                let next = next.to_string();
+                debug!("'{}' redirects to '{}'", local, next);
+                let permanent = permanent
+                  && (status == StatusCode::MOVED_PERMANENTLY
+                    || status == StatusCode::PERMANENT_REDIRECT);
+                let moved_to = match permanent {
+                  true => Some(next.clone()),
+                  false => moved_to,
+                };
+                Either::A(future::ok(Loop::Continue((next, hops + 1, moved_to, permanent))))
+              }
+              _ => Either::B(
+                collect_body(local.clone(), res)
+                  .map(move |body| {
+                    debug!("collected body: {}", local);
+                    Loop::Break(Fetched {
+                      status: status,
+                      body: body,
+                      moved_to: moved_to,
+                    })
+                  }),
+              ),
+            }
+          }),
+      )
+    },
+  ))
+}
+
+// stops as soon as the body is over MAX_FEED_BYTES, or says it will be
+fn collect_body(
+  url: String,
+  res: Response<Body>,
+) -> impl Future<Item = Vec<u8>, Error = HermesError> {
+  let max = *streaming::MAX_FEED_BYTES;
+  let too_big = move |url: &str| {
+    warn!("'{}' is larger than {} bytes", url, max);
+    HermesError::Fetch(format!("'{}' is larger than {} bytes", url, max))
+  };
+  let length = res
+    .headers()
+    .get(CONTENT_LENGTH)
+    .and_then(|l| l.to_str().ok())
+    .and_then(|l| l.parse::<usize>().ok());
+  if length.map_or(false, |l| l > max) {
+    return Either::A(future::err(too_big(&url)));
+  }
+  Either::B(res.into_body().map_err(HermesError::from).fold(
+    Vec::with_capacity(length.unwrap_or(0)),
+    move |mut body, chunk| {
+      if body.len() + chunk.len() > max {
+        return Err(too_big(&url));
+      }
+      body.extend_from_slice(&chunk);
+      Ok(body)
+    },
+  ))
+}
+
+// Points the feed at its new URL, or merges it into the feed already there.
+// True when it was merged, so there is nothing left to update.
+fn follow_move(feed_id: i32, moved_to: &Option<String>) -> bool {
+  let url = match *moved_to {
+    Some(ref url) => url,
+    None => return false,
+  };
+  match db::find_feed_by_url(url) {
+    Ok(ref other) if other.id == feed_id => return false,
+    Ok(other) => {
+      return match db::merge_feeds(feed_id, other.id) {
+        Ok(n) => {
+          info!("feed '{}' moved to '{}', moved {} items to feed '{}'", feed_id, url, n, other.id);
+          true
+        }
+        Err(e) => {
+          error!("could not merge feed '{}' into feed '{}': {}", feed_id, other.id, e);
+          false
+        }
+      };
+    }
+    Err(HermesError::NotFound) => (),
+    Err(e) => {
+      error!("could not look up the new link of feed '{}': {}", feed_id, e);
+      return false;
+    }
+  };
+  match db::update_feed_link(feed_id, url) {
+    Ok(_) => info!("feed '{}' moved permanently to '{}'", feed_id, url),
+    Err(e) => error!("could not update the link of feed '{}': {}", feed_id, e),
+  };
+  false
 }
 
 ///////////////////
 /// Synchronous ///
 ///////////////////
 
-fn parse_fetched_data(string: &[u8]) -> Result<FeedType, ()> {
+// looks for the first <rss> or <feed> element; anything else isn't a feed
+pub fn parse_fetched_data(string: &[u8]) -> Result<FeedType, HermesError> {
+  let text = str::from_utf8(string).map_err(|e| HermesError::Parse(e.to_string()))?;
   let mut buf = Vec::new();
-  let mut reader = Reader::from_str(str::from_utf8(string).unwrap());
+  let mut reader = Reader::from_str(text);
   loop {
     match reader.read_event(&mut buf) {
       Ok(Event::Start(ref e)) => match e.name() {
+        b"rss" if streaming::wanted(string) => {
+          debug!("found rss, reading it an item at a time");
+          return streaming::parse_rss(text);
+        }
+        b"feed" if streaming::wanted(string) => {
+          debug!("found atom, reading it an entry at a time");
+          return streaming::parse_atom(text);
+        }
         b"rss" => {
           debug!("found rss");
           match rss::Channel::read_from(BufReader::new(string)) {
             Ok(channel) => return Ok(FeedType::RSS(channel)),
-            Err(_) => return Err(()),
+            Err(e) => return Err(HermesError::Parse(e.to_string())),
           }
         }
         b"feed" => {
           debug!("found atom");
           match atom_syndication::Feed::read_from(BufReader::new(string)) {
             Ok(feed) => return Ok(FeedType::Atom(feed)),
-            Err(_) => return Err(()),
+            Err(e) => return Err(HermesError::Parse(e.to_string())),
           }
         }
         _ => (),
       },
+      Ok(Event::Eof) => return Err(HermesError::Parse("no rss or feed element".to_owned())),
+      Err(e) => return Err(HermesError::Parse(e.to_string())),
       _ => (),
     }
+    buf.clear();
   }
 }
 
-fn handle_feed_types(parsed: FeedType, url: &str) -> Result<(NewFeed, ItemType), ()> {
+fn handle_feed_types(parsed: FeedType, url: &str) -> Result<(NewFeed, ItemType), HermesError> {
   match parsed {
     FeedType::RSS(feed) => {
       let new_feed = NewFeed::from_rss(&feed, &url);
@@ -222,17 +1077,54 @@ fn handle_feed_types(parsed: FeedType, url: &str) -> Result<(NewFeed, ItemType),
       Ok((new_feed, new_items))
     }
     FeedType::Atom(feed) => {
-      let new_feed = NewFeed::from_atom(&feed, &url);
+      let new_feed = NewFeed::from_atom(&feed, &url)?;
       let new_items = ItemType::Entry(feed.entries().to_vec());
       Ok((new_feed, new_items))
     }
+    FeedType::Page(_) => Err(HermesError::Parse("a page, not a feed".to_owned())),
   }
 }
 
-fn handle_item_types(parsed: ItemType, feed_id: &i32) -> Vec<NewItem> {
-  match parsed {
-    ItemType::Item(i) => process_items(i, feed_id),
-    ItemType::Entry(i) => process_entries(i, feed_id),
+fn handle_item_types(parsed: ItemType, feed_id: &i32, base: Option<&Url>) -> Vec<NewItem> {
+  let items = match parsed {
+    ItemType::Item(i) => process_items(i, feed_id, base),
+    ItemType::Entry(i) => process_entries(i, feed_id, base),
+  };
+  let warnings = check_items(&items);
+  if !warnings.is_empty() {
+    debug!("feed '{}' parsed with warnings: {:?}", feed_id, warnings);
+  }
+  if let Err(e) = db::set_feed_warnings(*feed_id, &serde_json::to_string(&warnings).unwrap()) {
+    error!("could not store warnings for feed '{}': {}", feed_id, e);
+  }
+  items
+}
+
+fn check_items(items: &Vec<NewItem>) -> Vec<ParseWarning> {
+  let mut warnings = vec![];
+  let mut guids = HashSet::new();
+  for item in items.iter() {
+    if item.published_at.is_none() {
+      add_warning(&mut warnings, ParseWarningKind::MissingDate, &item.title);
+    }
+    if !guids.insert(item.guid.as_str()) {
+      add_warning(&mut warnings, ParseWarningKind::DuplicateGuid, &item.guid);
+    }
+    if Url::parse(&item.link).is_err() {
+      add_warning(&mut warnings, ParseWarningKind::InvalidLink, &item.link);
+    }
+  }
+  warnings
+}
+
+fn add_warning(warnings: &mut Vec<ParseWarning>, kind: ParseWarningKind, example: &str) {
+  match warnings.iter_mut().find(|w| w.kind == kind) {
+    Some(w) => w.count += 1,
+    None => warnings.push(ParseWarning {
+      kind: kind,
+      count: 1,
+      example: Some(example.to_owned()),
+    }),
   }
 }
 
@@ -245,55 +1137,201 @@ fn subscribe_new_items(inserted_items: &Vec<i32>, subscribers: &Vec<i32>) {
         .map(move |i| (s, i, false))
         .collect::<Vec<(&i32, &i32, bool)>>()
     }).collect::<Vec<(&i32, &i32, bool)>>();
-  insert_subscribed_items(insertables);
+  if let Err(e) = insert_subscribed_items(insertables) {
+    error!("could not add new items for their subscribers: {}", e);
+  }
 }
 
-fn process_items<'a>(feed_items: Vec<rss::Item>, channel_id: &'a i32) -> Vec<NewItem> {
+fn process_items<'a>(
+  feed_items: Vec<rss::Item>,
+  channel_id: &'a i32,
+  base: Option<&Url>,
+) -> Vec<NewItem> {
   let items: Vec<NewItem> = feed_items
     .iter()
-    .map(|item| NewItem::from_item(item, *channel_id))
+    .filter_map(|item| skip_invalid(*channel_id, NewItem::from_item(item, *channel_id, base)))
     .collect();
   items
 }
 fn process_entries<'a>(
   feed_items: Vec<atom_syndication::Entry>,
   channel_id: &'a i32,
+  base: Option<&Url>,
 ) -> Vec<NewItem> {
   let items: Vec<NewItem> = feed_items
     .iter()
-    .map(|entry| NewItem::from_entry(entry, *channel_id))
+    .filter_map(|entry| skip_invalid(*channel_id, NewItem::from_entry(entry, *channel_id, base)))
     .collect();
   items
 }
 
-fn process_duplicates(items: Vec<NewItem>) -> Option<Vec<NewItem>> {
-  let new_items = match find_duplicates(items.iter().map(|x| x.guid.as_str()).collect()) {
-    Some(dupes) => {
-      let guids: Vec<&str> = dupes.iter().map(|x| x.1.as_str()).collect();
-      let (new_items, mut duplicated_items): (Vec<NewItem>, Vec<NewItem>) = items
-        .into_iter()
-        .partition(|x| !guids.contains(&x.guid.as_str()));
-
-      let updated_items: Vec<(i32, NewItem)> = duplicated_items
-        .into_iter()
-        .filter_map(|d| {
-          let idx = dupes.iter().find(|(_, y, _)| y == &d.guid).unwrap();
-          if d.published_at != idx.2 {
-            Some((idx.0, d))
-          } else {
-            None
-          }
-        }).collect();
-      debug!("found {} updated items", updated_items.len());
-      updated_items
-        .into_iter()
-        .for_each(|(id, item)| update_item(id, item));
-      new_items
+fn skip_invalid(feed_id: i32, item: Result<NewItem, HermesError>) -> Option<NewItem> {
+  item
+    .map_err(|e| warn!("skipping an item of feed '{}': {}", feed_id, e))
+    .ok()
+}
+
+// keyed by guid, as the items have no ids yet
+fn parse_enclosures(parsed: &ItemType) -> Vec<(String, NewEnclosure)> {
+  match *parsed {
+    ItemType::Item(ref items) => items
+      .iter()
+      .filter_map(|i| match (i.guid(), i.enclosure()) {
+        (Some(guid), Some(e)) => Some((guid.value().to_owned(), NewEnclosure::from_rss(e))),
+        _ => None,
+      }).collect(),
+    ItemType::Entry(ref entries) => entries
+      .iter()
+      .flat_map(|entry| {
+        entry
+          .links()
+          .iter()
+          .filter(|l| l.rel() == "enclosure")
+          .map(move |l| (entry.id().to_owned(), NewEnclosure::from_atom(l)))
+      }).collect(),
+  }
+}
+
+fn store_enclosures(items: &Vec<Item>, enclosures: Vec<(String, NewEnclosure)>) {
+  if enclosures.is_empty() {
+    return;
+  }
+  let ids: HashMap<&str, i32> = items.iter().map(|i| (i.guid.as_str(), i.id)).collect();
+  let enclosures: Vec<NewEnclosure> = enclosures
+    .into_iter()
+    .filter_map(|(guid, mut enclosure)| {
+      ids.get(guid.as_str()).map(|id| {
+        enclosure.item_id = *id;
+        enclosure
+      })
+    }).collect();
+  if let Err(e) = db::insert_enclosures(&enclosures) {
+    error!("could not insert enclosures: {}", e);
+  }
+}
+
+fn drop_purged(feed_id: i32, items: Vec<NewItem>) -> Result<Vec<NewItem>, HermesError> {
+  let purged = db::get_purged_guids(feed_id)?;
+  Ok(match purged.is_empty() {
+    true => items,
+    false => items.into_iter().filter(|i| !purged.contains(&i.guid)).collect(),
+  })
+}
+
+// Some feeds regenerate guids, so an item is also the same as a stored one
+// with its link, or with its title and date. Links that several fetched items
+// share, like a site's front page, don't count. A match updates the stored
+// row, taking on the new guid, instead of adding a copy. Items kept under
+// their guid but with new dates or content were edited; they are returned
+// along with the new ones, for `insert_items` to refresh. An edit the feed
+// didn't date is dated now. Items stored before content was hashed only
+// count as edited when their dates change.
+fn process_duplicates(
+  feed_id: i32,
+  items: Vec<NewItem>,
+) -> Result<Option<Vec<NewItem>>, HermesError> {
+  let mut link_uses: HashMap<String, usize> = HashMap::new();
+  for item in items.iter() {
+    *link_uses.entry(item.link.clone()).or_insert(0) += 1;
+  }
+  let known = find_duplicates(feed_id, &items)?;
+  let mut new_items: Vec<NewItem> = vec![];
+  let mut updated = 0;
+  let mut edited = 0;
+  for item in items {
+    let unique_link = !item.link.is_empty() && link_uses.get(&item.link) == Some(&1);
+    let found = known
+      .iter()
+      .find(|k| k.guid == item.guid)
+      .or_else(|| match unique_link {
+        true => known.iter().find(|k| k.link == item.link),
+        false => None,
+      }).or_else(|| match item.fingerprint {
+        Some(ref f) => known.iter().find(|k| k.fingerprint.as_ref() == Some(f)),
+        None => None,
+      });
+    match found {
+      Some(k) if k.guid != item.guid => {
+        update_item(k.id, item)?;
+        updated += 1;
+      }
+      Some(k) if k.published_at != item.published_at || k.updated_at != item.updated_at => {
+        new_items.push(item);
+        edited += 1;
+      }
+      Some(k) if k.content_hash.is_some() && k.content_hash != item.content_hash => {
+        new_items.push(NewItem {
+          updated_at: Some(Utc::now()),
+          ..item
+        });
+        edited += 1;
+      }
+      Some(_) => (),
+      None => {
+        // the same item twice in one fetch is only kept once
+        let repeated = item.fingerprint.is_some()
+          && new_items.iter().any(|n| n.fingerprint == item.fingerprint);
+        if !repeated {
+          new_items.push(item);
+        }
+      }
     }
-    None => items,
-  };
-  match new_items.is_empty() {
+  }
+  debug!("found {} updated and {} edited items", updated, edited);
+  Ok(match new_items.is_empty() {
     false => Some(new_items),
     true => None,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{backoff_cycles, fetch_offset, is_due, UPDATE_SECS};
+  use std::time::Duration;
+
+  #[test]
+  fn offsets_fall_within_the_interval() {
+    for feed_id in (1..10_000).chain(vec![i32::max_value()]) {
+      assert!(fetch_offset(feed_id) < Duration::from_secs(UPDATE_SECS));
+    }
+  }
+
+  #[test]
+  fn offsets_only_depend_on_the_feed() {
+    assert_eq!(fetch_offset(42), fetch_offset(42));
+    assert!(fetch_offset(1) != fetch_offset(2));
+  }
+
+  #[test]
+  fn offsets_spread_neighbouring_feeds() {
+    // ten consecutive ids land in more than half of ten equal slices
+    let slice = UPDATE_SECS * 1000 / 10;
+    let mut slices: Vec<u64> = (1..11)
+      .map(|feed_id| {
+        let offset = fetch_offset(feed_id);
+        (offset.as_secs() * 1000 + offset.subsec_millis() as u64) / slice
+      }).collect();
+    slices.sort();
+    slices.dedup();
+    assert!(slices.len() > 5);
+  }
+
+  #[test]
+  fn backoff_doubles_up_to_its_cap() {
+    assert_eq!(backoff_cycles(-1), 1);
+    assert_eq!(backoff_cycles(0), 1);
+    assert_eq!(backoff_cycles(1), 2);
+    assert_eq!(backoff_cycles(3), 8);
+    assert_eq!(backoff_cycles(4), 16);
+    assert_eq!(backoff_cycles(99), 16);
+  }
+
+  #[test]
+  fn failing_feeds_are_due_once_per_backoff() {
+    for failures in 0..6 {
+      let due = (1000..1000 + 64).filter(|&cycle| is_due(7, failures, cycle)).count() as u64;
+      assert_eq!(due, 64 / backoff_cycles(failures));
+    }
+    assert!(is_due(7, 0, 1234));
   }
 }
@@ -0,0 +1,248 @@
+use regex::{self, Regex, RegexBuilder};
+use std::fmt;
+
+use db::{self, get_recent_subscribed_items, get_user_filter};
+use error::HermesError;
+use models::{Filter, FilterPreview, Item, SubscribedItem};
+
+static PREVIEW_MAX_ITEMS: i64 = 1000;
+static APPLY_MAX_ITEMS: i64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchField {
+  Title,
+  Content,
+  Any,
+}
+impl MatchField {
+  pub fn parse(s: &str) -> Option<MatchField> {
+    match s {
+      "title" => Some(MatchField::Title),
+      "content" => Some(MatchField::Content),
+      "any" => Some(MatchField::Any),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterAction {
+  MarkRead,
+  Star,
+  Drop,
+}
+impl FilterAction {
+  pub fn parse(s: &str) -> Option<FilterAction> {
+    match s {
+      "mark_read" => Some(FilterAction::MarkRead),
+      "star" => Some(FilterAction::Star),
+      "drop" => Some(FilterAction::Drop),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum RuleError {
+  Pattern(String),
+  Field(String),
+  Action(String),
+}
+impl RuleError {
+  // doubles as the message id in src/locales
+  pub fn code(&self) -> &'static str {
+    match *self {
+      RuleError::Pattern(_) => "invalid_pattern",
+      RuleError::Field(_) => "invalid_field",
+      RuleError::Action(_) => "invalid_action",
+    }
+  }
+
+  pub fn detail(&self) -> &str {
+    match *self {
+      RuleError::Pattern(ref d) | RuleError::Field(ref d) | RuleError::Action(ref d) => d,
+    }
+  }
+}
+impl fmt::Display for RuleError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}: {}", self.code(), self.detail())
+  }
+}
+
+// A filter as stored, checked and ready to run against items.
+pub struct Rule {
+  pub regex: Regex,
+  pub field: MatchField,
+  pub action: FilterAction,
+  pub feed_id: Option<i32>,
+}
+impl Rule {
+  pub fn compile(pattern: &str, field: &str, action: &str) -> Result<Rule, RuleError> {
+    let regex = RegexBuilder::new(pattern)
+      .case_insensitive(true)
+      .size_limit(1 << 20)
+      .build()
+      .map_err(|e| RuleError::Pattern(e.to_string()))?;
+    let field = MatchField::parse(field).ok_or_else(|| RuleError::Field(field.to_owned()))?;
+    let action = FilterAction::parse(action).ok_or_else(|| RuleError::Action(action.to_owned()))?;
+    Ok(Rule {
+      regex: regex,
+      field: field,
+      action: action,
+      feed_id: None,
+    })
+  }
+
+  pub fn from_filter(filter: &Filter) -> Result<Rule, RuleError> {
+    let mut rule = Rule::compile(&filter.pattern, &filter.match_field, &filter.action)?;
+    rule.feed_id = filter.feed_id;
+    Ok(rule)
+  }
+
+  pub fn matches(
+    &self,
+    feed_id: i32,
+    title: &str,
+    summary: Option<&str>,
+    content: Option<&str>,
+  ) -> bool {
+    if self.feed_id.map(|f| f != feed_id).unwrap_or(false) {
+      return false;
+    }
+    let in_title = || self.regex.is_match(title);
+    let in_content = || {
+      summary.map(|s| self.regex.is_match(s)).unwrap_or(false)
+        || content.map(|c| self.regex.is_match(c)).unwrap_or(false)
+    };
+    match self.field {
+      MatchField::Title => in_title(),
+      MatchField::Content => in_content(),
+      MatchField::Any => in_title() || in_content(),
+    }
+  }
+}
+
+// A filter is created with either a regex `pattern` or a plain `keyword`,
+// which is matched as written, case aside.
+pub fn pattern_of(pattern: &str, keyword: Option<&str>) -> Result<String, RuleError> {
+  let pattern = match keyword {
+    Some(keyword) => regex::escape(keyword.trim()),
+    None => pattern.to_owned(),
+  };
+  match pattern.is_empty() {
+    true => Err(RuleError::Pattern("empty pattern".to_owned())),
+    false => Ok(pattern),
+  }
+}
+
+// Takes the filter's action on items, returns how many it changed.
+fn act(action: FilterAction, user_id: i32, item_ids: &[i32]) -> usize {
+  let mut changed = 0;
+  for &item_id in item_ids {
+    let result = match action {
+      FilterAction::MarkRead => db::set_item_seen(item_id, user_id, true),
+      FilterAction::Star => db::set_item_starred(item_id, user_id, true),
+      FilterAction::Drop => db::hide_item(item_id, user_id).map(|_| 1),
+    };
+    match result {
+      Ok(n) => changed += n,
+      Err(e) => error!("could not filter item {} for user {}: {}", item_id, user_id, e),
+    }
+  }
+  changed
+}
+
+// Runs the subscribers' enabled filters on items just added to one of their feeds.
+pub fn apply_new(items: &[Item], subscribers: &[i32]) {
+  if items.is_empty() || subscribers.is_empty() {
+    return;
+  }
+  let enabled = match db::get_enabled_filters(subscribers) {
+    Ok(enabled) => enabled,
+    Err(e) => {
+      error!("could not load filters: {}", e);
+      return;
+    }
+  };
+  for filter in enabled {
+    let rule = match Rule::from_filter(&filter) {
+      Ok(rule) => rule,
+      Err(e) => {
+        warn!("skipping filter {}: {}", filter.id, e);
+        continue;
+      }
+    };
+    let matched = items
+      .iter()
+      .filter(|i| {
+        rule.matches(
+          i.feed_id,
+          &i.title,
+          i.summary.as_ref().map(|s| s.as_str()),
+          i.content.as_ref().map(|s| s.as_str()),
+        )
+      }).map(|i| i.id)
+      .collect::<Vec<i32>>();
+    if !matched.is_empty() {
+      let changed = act(rule.action, filter.user_id, &matched);
+      debug!("filter {} changed {} new items", filter.id, changed);
+    }
+  }
+}
+
+fn recent_matches(
+  filter: &Filter,
+  rule: &Rule,
+  user_id: i32,
+  max: i64,
+) -> Result<(usize, Vec<SubscribedItem>), HermesError> {
+  let items = get_recent_subscribed_items(user_id, filter.feed_id, max)?;
+  let scanned = items.len();
+  let matched = items
+    .into_iter()
+    .filter(|i| {
+      rule.matches(
+        i.feed_id,
+        &i.title,
+        i.summary.as_ref().map(|s| s.as_str()),
+        i.content.as_ref().map(|s| s.as_str()),
+      )
+    }).collect();
+  Ok((scanned, matched))
+}
+
+// Runs a filter over the user's recent items without touching them, enabled or not.
+pub fn preview(
+  filter_id: i32,
+  user_id: i32,
+) -> Result<Result<FilterPreview, RuleError>, HermesError> {
+  let filter = get_user_filter(filter_id, user_id)?;
+  let rule = match Rule::from_filter(&filter) {
+    Ok(rule) => rule,
+    Err(e) => return Ok(Err(e)),
+  };
+  let (scanned, matched) = recent_matches(&filter, &rule, user_id, PREVIEW_MAX_ITEMS)?;
+  Ok(Ok(FilterPreview {
+    scanned: scanned,
+    matched: matched,
+  }))
+}
+
+// Takes a filter's action on the user's existing items, enabled or not, and
+// returns the items it matched.
+pub fn apply(
+  filter_id: i32,
+  user_id: i32,
+) -> Result<Result<Vec<SubscribedItem>, RuleError>, HermesError> {
+  let filter = get_user_filter(filter_id, user_id)?;
+  let rule = match Rule::from_filter(&filter) {
+    Ok(rule) => rule,
+    Err(e) => return Ok(Err(e)),
+  };
+  let (_, matched) = recent_matches(&filter, &rule, user_id, APPLY_MAX_ITEMS)?;
+  let item_ids = matched.iter().map(|i| i.id).collect::<Vec<i32>>();
+  let changed = act(rule.action, user_id, &item_ids);
+  info!("filter {} matched {} items and changed {}", filter_id, matched.len(), changed);
+  Ok(Ok(matched))
+}
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use models::{GuidCacheStats, NewItem};
+
+// The guids (and dates) each feed had on its last poll. Items that show up
+// again unchanged are dropped before the database is asked about them; the
+// cache is per process, so a fresh worker checks everything once. An item
+// whose dates or content hash changed was edited, and is looked up again.
+
+pub type Version = (Option<DateTime<Utc>>, Option<DateTime<Utc>>, Option<String>);
+
+lazy_static! {
+  static ref KNOWN: Mutex<HashMap<i32, HashMap<String, Version>>> = Mutex::new(HashMap::new());
+}
+
+static CHECKED: AtomicUsize = AtomicUsize::new(0);
+static SKIPPED: AtomicUsize = AtomicUsize::new(0);
+
+pub type Snapshot = Vec<(String, Version)>;
+
+// returns the items that need a database lookup, and what to remember once
+// they have been stored
+pub fn skip_known(feed_id: i32, items: Vec<NewItem>) -> (Vec<NewItem>, Snapshot) {
+  let snapshot: Snapshot = items
+    .iter()
+    .map(|i| (i.guid.clone(), version(i)))
+    .collect();
+  let total = items.len();
+  let unknown: Vec<NewItem> = {
+    let known = KNOWN.lock().unwrap();
+    match known.get(&feed_id) {
+      Some(guids) => items
+        .into_iter()
+        .filter(|i| guids.get(&i.guid) != Some(&version(i)))
+        .collect(),
+      None => items,
+    }
+  };
+  let skipped = total - unknown.len();
+  CHECKED.fetch_add(total, Ordering::Relaxed);
+  SKIPPED.fetch_add(skipped, Ordering::Relaxed);
+  if skipped > 0 {
+    debug!("feed '{}': skipped {} of {} known items", feed_id, skipped, total);
+  }
+  (unknown, snapshot)
+}
+
+fn version(item: &NewItem) -> Version {
+  (item.published_at, item.updated_at, item.content_hash.clone())
+}
+
+pub fn remember(feed_id: i32, snapshot: Snapshot) {
+  KNOWN
+    .lock()
+    .unwrap()
+    .insert(feed_id, snapshot.into_iter().collect());
+}
+
+// drops feeds that are not polled anymore
+pub fn retain(feed_ids: &Vec<i32>) {
+  KNOWN
+    .lock()
+    .unwrap()
+    .retain(|feed_id, _| feed_ids.contains(feed_id));
+}
+
+pub fn stats() -> GuidCacheStats {
+  GuidCacheStats {
+    feeds: KNOWN.lock().unwrap().len(),
+    checked_items: CHECKED.load(Ordering::Relaxed),
+    skipped_items: SKIPPED.load(Ordering::Relaxed),
+  }
+}
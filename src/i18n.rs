@@ -0,0 +1,76 @@
+use fluent::context::MessageContext;
+use fluent::types::FluentValue;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// Text the server writes for people, as opposed to codes meant for clients.
+// Messages live in src/locales/<locale>.ftl and are compiled into the binary.
+
+pub static DEFAULT_LOCALE: &'static str = "en-US";
+
+static LOCALES: &'static [(&'static str, &'static str)] = &[
+  ("en-US", include_str!("locales/en-US.ftl")),
+  ("de", include_str!("locales/de.ftl")),
+];
+
+thread_local! {
+  static CONTEXTS: RefCell<HashMap<&'static str, MessageContext<'static>>> =
+    RefCell::new(HashMap::new());
+}
+
+// exact match first, then on the language alone, so `de-AT` gets `de`
+pub fn supported(locale: &str) -> Option<&'static str> {
+  let locale = locale.trim();
+  let language = locale.split('-').next().unwrap_or("");
+  LOCALES
+    .iter()
+    .find(|(l, _)| l.eq_ignore_ascii_case(locale))
+    .or_else(|| {
+      LOCALES
+        .iter()
+        .find(|(l, _)| l.split('-').next().unwrap().eq_ignore_ascii_case(language))
+    }).map(|(l, _)| *l)
+}
+
+// picks from an Accept-Language header, ignoring weights beyond their order
+pub fn negotiate(accept_language: Option<&str>) -> &'static str {
+  accept_language
+    .and_then(|header| {
+      header
+        .split(',')
+        .filter_map(|lang| lang.split(';').next())
+        .filter_map(supported)
+        .next()
+    }).unwrap_or(DEFAULT_LOCALE)
+}
+
+pub fn tr(locale: &str, id: &str) -> String {
+  tr_args(locale, id, &[])
+}
+
+// falls back to English, then to the message id itself
+pub fn tr_args(locale: &str, id: &str, args: &[(&str, &str)]) -> String {
+  let args: HashMap<&str, FluentValue> = args
+    .iter()
+    .map(|&(name, value)| (name, FluentValue::from(value)))
+    .collect();
+  let locale = supported(locale).unwrap_or(DEFAULT_LOCALE);
+  format(locale, id, &args)
+    .or_else(|| format(DEFAULT_LOCALE, id, &args))
+    .unwrap_or_else(|| id.to_owned())
+}
+
+fn format(locale: &'static str, id: &str, args: &HashMap<&str, FluentValue>) -> Option<String> {
+  CONTEXTS.with(|contexts| {
+    let mut contexts = contexts.borrow_mut();
+    let ctx = contexts.entry(locale).or_insert_with(|| {
+      let mut ctx = MessageContext::new(&[locale]);
+      let source = LOCALES.iter().find(|(l, _)| *l == locale).unwrap().1;
+      ctx.add_messages(source);
+      ctx
+    });
+    ctx
+      .get_message(id)
+      .and_then(|message| ctx.format(message, Some(args)))
+  })
+}
@@ -0,0 +1,70 @@
+use futures::future::{self, Either};
+use futures::{stream, Future, Stream};
+use regex::Regex;
+use std::time::Duration;
+use tokio::timer::Timeout;
+use url::Url;
+
+use db::set_feed_icon;
+use feed::fetch_feed;
+use media::sniff_image;
+
+static ICON_TIMEOUT_SECS: u64 = 20;
+static MAX_ICON_BYTES: usize = 100 * 1024;
+
+lazy_static! {
+  static ref LINK_TAG: Regex = Regex::new(r"(?i)<link\s[^>]*>").unwrap();
+  static ref REL_ICON: Regex =
+    Regex::new(r#"(?i)\brel\s*=\s*["']?(?:shortcut\s+)?icon["'\s>/]"#).unwrap();
+  static ref HREF: Regex = Regex::new(r#"(?i)\bhref\s*=\s*["']([^"']+)["']"#).unwrap();
+}
+
+// Looks for the icon the site's front page declares, then for /favicon.ico,
+// and stores the first one that turns out to be an image.
+pub fn discover(feed_id: i32, site_link: String) -> impl Future<Item = (), Error = ()> {
+  let site = match Url::parse(&site_link) {
+    Ok(site) => site,
+    Err(_) => return Either::A(future::ok(())),
+  };
+  let fallback = site.join("/favicon.ico").ok();
+  let found = fetch_feed(site_link).then(move |page| {
+    let declared = page.ok().and_then(|p| find_icon_link(&p, &site));
+    let candidates: Vec<Url> = declared.into_iter().chain(fallback).collect();
+    stream::iter_ok(candidates)
+      .and_then(|url| fetch_feed(url.to_string()).then(|res| Ok::<_, ()>(res.ok())))
+      .filter_map(|body| body.and_then(|b| sniff(&b).map(|mime| (mime, b))))
+      .into_future()
+      .map(|(first, _)| first)
+      .map_err(|_| ())
+  });
+  Either::B(
+    Timeout::new(found, Duration::from_secs(ICON_TIMEOUT_SECS))
+      .map_err(move |_| debug!("icon discovery for feed '{}' failed", feed_id))
+      .map(move |icon| match icon {
+        Some((mime, data)) => {
+          if let Err(e) = set_feed_icon(feed_id, mime, &data) {
+            error!("could not store icon of feed '{}': {}", feed_id, e);
+          }
+        }
+        None => debug!("no icon found for feed '{}'", feed_id),
+      }),
+  )
+}
+
+fn find_icon_link(page: &[u8], base: &Url) -> Option<Url> {
+  let page = String::from_utf8_lossy(page);
+  LINK_TAG
+    .find_iter(&page)
+    .map(|tag| tag.as_str())
+    .filter(|tag| REL_ICON.is_match(tag))
+    .filter_map(|tag| HREF.captures(tag))
+    .filter_map(|href| base.join(&href[1]).ok())
+    .next()
+}
+
+fn sniff(data: &[u8]) -> Option<&'static str> {
+  if data.len() > MAX_ICON_BYTES {
+    return None;
+  }
+  sniff_image(data)
+}
@@ -0,0 +1,18 @@
+# Registration
+
+registration_closed = Die Registrierung ist auf dieser Instanz geschlossen.
+invalid_username = Benutzernamen bestehen aus höchstens 32 Buchstaben, Ziffern, Punkten, Binde- oder Unterstrichen.
+weak_password = Passwörter müssen mindestens 8 Zeichen lang sein.
+username_taken = Dieser Benutzername ist bereits vergeben.
+invalid_invite = Dieser Einladungscode ist ungültig oder wurde bereits verwendet.
+
+# Filters
+
+invalid_pattern = Das Muster ist kein gültiger regulärer Ausdruck: { $detail }
+invalid_field = Unbekanntes Feld „{ $detail }“, erwartet wird title, content oder any.
+invalid_action = Unbekannte Aktion „{ $detail }“, erwartet wird mark_read, star oder drop.
+
+# Setup
+
+invalid_registration = Unbekannter Registrierungsmodus, erwartet wird closed, open, invite oder approval.
+weak_secret = Das JWT-Geheimnis muss mindestens 32 Zeichen lang sein.
@@ -0,0 +1,18 @@
+# Registration
+
+registration_closed = Registration is closed on this instance.
+invalid_username = Usernames are up to 32 letters, digits, dots, dashes or underscores.
+weak_password = Passwords need at least 8 characters.
+username_taken = This username is already taken.
+invalid_invite = This invite code is invalid or has already been used.
+
+# Filters
+
+invalid_pattern = The pattern is not a valid regular expression: { $detail }
+invalid_field = Unknown field "{ $detail }", expected title, content or any.
+invalid_action = Unknown action "{ $detail }", expected mark_read, star or drop.
+
+# Setup
+
+invalid_registration = Unknown registration mode, expected closed, open, invite or approval.
+weak_secret = The JWT secret needs at least 32 characters.
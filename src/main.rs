@@ -1,24 +1,36 @@
 #![allow(unused)]
+extern crate ammonia;
 extern crate atom_syndication;
+extern crate backtrace;
 extern crate base64;
+extern crate bytes;
 extern crate chrono;
 #[macro_use]
 extern crate diesel;
 extern crate dotenv;
+extern crate fluent;
 #[macro_use]
 extern crate log;
+#[macro_use]
 extern crate futures;
 extern crate hyper;
 extern crate hyper_tls;
+#[cfg(feature = "embed-ui")]
+#[macro_use]
+extern crate include_dir;
 extern crate jsonwebtoken;
 #[macro_use]
 extern crate lazy_static;
+extern crate md5;
 extern crate pretty_env_logger;
 extern crate quick_xml;
 extern crate r2d2;
 extern crate r2d2_diesel;
+extern crate readability;
 extern crate regex;
+extern crate ring;
 extern crate rss;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 #[macro_use]
@@ -34,32 +46,105 @@ use dotenv::dotenv;
 use hyper::rt;
 use std::collections::HashMap;
 use std::env;
+use std::process;
 use std::sync::{Arc, Mutex};
 
+pub mod bench;
+pub mod config;
+pub mod crypto;
 pub mod db;
+pub mod deliveries;
+pub mod discovery;
+pub mod error;
+pub mod events;
+pub mod extract;
 pub mod feed;
+pub mod filters;
+pub mod guid_cache;
+pub mod i18n;
+pub mod icons;
+pub mod media;
+pub mod metrics;
 pub mod models;
+pub mod mutes;
+pub mod pages;
+pub mod paging;
+pub mod push;
+pub mod quotas;
+pub mod retention;
+pub mod sanitize;
 pub mod schema;
+pub mod streaming;
+pub mod topics;
 pub mod views;
 pub mod web;
+pub mod webhooks;
+pub mod websub;
+pub mod windows;
 
-use db::create_admin_user;
+use db::{create_admin_user, reseal_secrets};
+use events::start_event_listener;
 use feed::start_interval_loops;
-use web::{start_web, types::UserWebsocketState};
+use web::{start_metrics, start_web, types::UserWebsocketState};
 
+// `hermes` runs everything in one process, `hermes web` only serves HTTP and
+// websockets, `hermes worker` only fetches feeds. Web nodes and workers
+// coordinate through the database, see `events` and `db::lease_feed`.
+// `hermes bench` puts a running instance under load, see `bench`.
 fn main() {
   dotenv().ok();
   env::set_var("RUST_LOG", "hermes=info");
   pretty_env_logger::init();
 
-  create_admin_user();
+  let mode = env::args().nth(1).unwrap_or_else(|| "all".to_string());
+  if mode == "bench" {
+    bench::run();
+  }
+  let (web, worker) = match mode.as_ref() {
+    "all" => (true, true),
+    "web" => (true, false),
+    "worker" => (false, true),
+    m => {
+      error!("unknown mode '{}', expected one of: all, web, worker, bench", m);
+      process::exit(1);
+    }
+  };
+  info!("starting in '{}' mode", mode);
+
+  if web {
+    create_admin_user();
+  }
+  if crypto::enabled() {
+    match reseal_secrets() {
+      Ok(0) => (),
+      Ok(n) => info!("sealed {} stored secrets with the current key", n),
+      Err(e) => error!("could not seal stored secrets: {}", e),
+    };
+  } else {
+    warn!("SECRETS_KEY is not set, stored credentials are not encrypted");
+  }
+  if worker {
+    match sanitize::backfill() {
+      Ok(0) => (),
+      Ok(n) => info!("sanitized the HTML of {} items", n),
+      Err(e) => error!("could not sanitize stored items: {}", e),
+    };
+  }
 
-  rt::run(rt::lazy(|| {
-    let state = Arc::new(Mutex::new(HashMap::new()));
-    let global_user_state = UserWebsocketState { state: state };
+  rt::run(rt::lazy(move || {
+    if worker {
+      start_interval_loops();
+      if !web {
+        start_metrics();
+      }
+    }
+    if web {
+      let state = Arc::new(Mutex::new(HashMap::new()));
+      let global_user_state = UserWebsocketState { state: state };
 
-    start_interval_loops(global_user_state.clone());
-    start_web(global_user_state.clone());
+      start_event_listener(global_user_state.clone());
+      start_web(global_user_state.clone());
+    }
     Ok(())
   }));
 }
@@ -0,0 +1,79 @@
+use futures::future::{self, Either};
+use futures::Future;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::time::Duration;
+use tokio::timer::Timeout;
+use url::Url;
+
+use db;
+use error::HermesError;
+use feed::fetch_feed;
+use models::MediaBlob;
+
+// Images fetched for clients through `GET /api/media?url=`, when MEDIA_CACHE=true.
+// Blobs are stored once per SHA-256 of their bytes and count the URLs that
+// point at them. Beyond MEDIA_CACHE_MAX_BYTES the least recently served ones
+// are evicted; URLs not served for a while are forgotten by the cleanup.
+
+static FETCH_TIMEOUT_SECS: u64 = 20;
+static MAX_MEDIA_BYTES: usize = 10 * 1024 * 1024;
+pub static RETENTION_DAYS: i64 = 30;
+
+lazy_static! {
+  pub static ref ENABLED: bool = env::var("MEDIA_CACHE")
+    .map(|v| v == "true")
+    .unwrap_or(false);
+  static ref MAX_CACHE_BYTES: i64 = env::var("MEDIA_CACHE_MAX_BYTES")
+    .ok()
+    .and_then(|b| b.parse().ok())
+    .unwrap_or(256 * 1024 * 1024);
+}
+
+pub fn get(url: String) -> impl Future<Item = MediaBlob, Error = HermesError> {
+  match db::get_media(&url) {
+    Ok(blob) => return Either::A(future::ok(blob)),
+    Err(HermesError::NotFound) => (),
+    Err(e) => return Either::A(future::err(e)),
+  }
+  match Url::parse(&url) {
+    Ok(ref u) if u.scheme() == "http" || u.scheme() == "https" => (),
+    _ => return Either::A(future::err(HermesError::Fetch(format!("'{}' is not a web URL", url)))),
+  };
+  let fetched = Timeout::new(fetch_feed(url.clone()), Duration::from_secs(FETCH_TIMEOUT_SECS))
+    .map_err(|e| {
+      e.into_inner()
+        .unwrap_or_else(|| HermesError::Fetch("timed out".to_owned()))
+    });
+  Either::B(fetched.and_then(move |data| {
+    if data.len() > MAX_MEDIA_BYTES {
+      return Err(HermesError::Fetch(format!("'{}' is too large", url)));
+    }
+    let mime = sniff_image(&data)
+      .ok_or_else(|| HermesError::Parse(format!("'{}' is not an image", url)))?;
+    let blob = db::store_media(&url, &format!("{:x}", Sha256::digest(&data)), mime, &data)?;
+    match db::evict_media(*MAX_CACHE_BYTES) {
+      Ok(0) => (),
+      Ok(n) => debug!("evicted {} media blobs", n),
+      Err(e) => error!("could not evict media: {}", e),
+    };
+    Ok(blob)
+  }))
+}
+
+// the type comes from the bytes, not the server; SVG is left out as it can carry scripts
+pub fn sniff_image(data: &[u8]) -> Option<&'static str> {
+  if data.starts_with(b"\x89PNG") {
+    Some("image/png")
+  } else if data.starts_with(b"\0\0\x01\0") {
+    Some("image/x-icon")
+  } else if data.starts_with(b"GIF8") {
+    Some("image/gif")
+  } else if data.starts_with(b"\xFF\xD8\xFF") {
+    Some("image/jpeg")
+  } else if data.len() > 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+    Some("image/webp")
+  } else {
+    None
+  }
+}
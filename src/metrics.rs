@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use db::pool_state;
+
+// Counters for `GET /metrics`, in Prometheus' text format. They are kept in
+// the process, so every web node and worker is scraped on its own.
+
+static FETCH_SUCCESSES: AtomicUsize = AtomicUsize::new(0);
+static FETCH_FAILURES: AtomicUsize = AtomicUsize::new(0);
+static ITEMS_INSERTED: AtomicUsize = AtomicUsize::new(0);
+static REFRESH_CYCLES: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+  // (method, route, status) -> (requests, total seconds)
+  static ref REQUESTS: Mutex<BTreeMap<(String, String, u16), (u64, f64)>> =
+    Mutex::new(BTreeMap::new());
+  // feed id -> (offset into the interval, seconds between fetches), as of
+  // the last refresh cycle of this worker
+  static ref SCHEDULE: Mutex<BTreeMap<i32, (Duration, u64)>> = Mutex::new(BTreeMap::new());
+}
+
+// ids in paths are replaced, so there is one series per route, not per item
+fn route(path: &str) -> String {
+  path
+    .split('/')
+    .map(|segment| match !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+      true => ":id",
+      false => segment,
+    }).collect::<Vec<_>>()
+    .join("/")
+}
+
+pub fn record_request(method: &str, path: &str, status: u16, elapsed: Option<Duration>) {
+  let seconds = elapsed
+    .map(|e| e.as_secs() as f64 + e.subsec_nanos() as f64 / 1e9)
+    .unwrap_or(0.0);
+  let mut requests = REQUESTS.lock().unwrap();
+  let entry = requests
+    .entry((method.to_owned(), route(path), status))
+    .or_insert((0, 0.0));
+  entry.0 += 1;
+  entry.1 += seconds;
+}
+
+pub fn record_fetch(success: bool) {
+  match success {
+    true => FETCH_SUCCESSES.fetch_add(1, Ordering::Relaxed),
+    false => FETCH_FAILURES.fetch_add(1, Ordering::Relaxed),
+  };
+}
+
+pub fn record_items(inserted: usize) {
+  ITEMS_INSERTED.fetch_add(inserted, Ordering::Relaxed);
+}
+
+pub fn record_cycle() {
+  REFRESH_CYCLES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_schedule(feeds: Vec<(i32, Duration, u64)>) {
+  *SCHEDULE.lock().unwrap() = feeds
+    .into_iter()
+    .map(|(feed_id, offset, interval)| (feed_id, (offset, interval)))
+    .collect();
+}
+
+// websockets are only known to web nodes
+pub fn render(websockets: Option<usize>) -> String {
+  let mut out = String::new();
+
+  let requests: Vec<(String, u64, f64)> = REQUESTS
+    .lock()
+    .unwrap()
+    .iter()
+    .map(|(&(ref method, ref route, status), &(count, seconds))| {
+      let labels = format!(
+        "method=\"{}\",route=\"{}\",status=\"{}\"",
+        method,
+        escape(route),
+        status
+      );
+      (labels, count, seconds)
+    }).collect();
+  let _ = writeln!(out, "# HELP hermes_http_requests_total Requests handled, by route and status.");
+  let _ = writeln!(out, "# TYPE hermes_http_requests_total counter");
+  for &(ref labels, count, _) in requests.iter() {
+    let _ = writeln!(out, "hermes_http_requests_total{{{}}} {}", labels, count);
+  }
+  let _ = writeln!(
+    out,
+    "# HELP hermes_http_request_duration_seconds Time spent handling requests."
+  );
+  let _ = writeln!(out, "# TYPE hermes_http_request_duration_seconds summary");
+  for &(ref labels, count, seconds) in requests.iter() {
+    let _ = writeln!(out, "hermes_http_request_duration_seconds_count{{{}}} {}", labels, count);
+    let _ = writeln!(out, "hermes_http_request_duration_seconds_sum{{{}}} {}", labels, seconds);
+  }
+
+  let _ = writeln!(out, "# HELP hermes_feed_fetches_total Feed refreshes, by result.");
+  let _ = writeln!(out, "# TYPE hermes_feed_fetches_total counter");
+  let _ = writeln!(
+    out,
+    "hermes_feed_fetches_total{{result=\"success\"}} {}",
+    FETCH_SUCCESSES.load(Ordering::Relaxed)
+  );
+  let _ = writeln!(
+    out,
+    "hermes_feed_fetches_total{{result=\"failure\"}} {}",
+    FETCH_FAILURES.load(Ordering::Relaxed)
+  );
+
+  let _ = writeln!(out, "# HELP hermes_items_inserted_total New items stored by refreshes.");
+  let _ = writeln!(out, "# TYPE hermes_items_inserted_total counter");
+  let _ = writeln!(
+    out,
+    "hermes_items_inserted_total {}",
+    ITEMS_INSERTED.load(Ordering::Relaxed)
+  );
+  let _ = writeln!(out, "# HELP hermes_refresh_cycles_total Refresh intervals started.");
+  let _ = writeln!(out, "# TYPE hermes_refresh_cycles_total counter");
+  let _ = writeln!(
+    out,
+    "hermes_refresh_cycles_total {}",
+    REFRESH_CYCLES.load(Ordering::Relaxed)
+  );
+
+  let schedule = SCHEDULE.lock().unwrap();
+  if !schedule.is_empty() {
+    let _ = writeln!(
+      out,
+      "# HELP hermes_feed_fetch_offset_seconds When in the interval a feed is fetched."
+    );
+    let _ = writeln!(out, "# TYPE hermes_feed_fetch_offset_seconds gauge");
+    for (feed_id, &(offset, _)) in schedule.iter() {
+      let seconds = offset.as_secs() as f64 + offset.subsec_millis() as f64 / 1000.0;
+      let _ = writeln!(out, "hermes_feed_fetch_offset_seconds{{feed=\"{}\"}} {}", feed_id, seconds);
+    }
+    let _ = writeln!(
+      out,
+      "# HELP hermes_feed_fetch_interval_seconds Time between a feed's fetches, with backoff."
+    );
+    let _ = writeln!(out, "# TYPE hermes_feed_fetch_interval_seconds gauge");
+    for (feed_id, &(_, interval)) in schedule.iter() {
+      let _ = writeln!(
+        out,
+        "hermes_feed_fetch_interval_seconds{{feed=\"{}\"}} {}",
+        feed_id, interval
+      );
+    }
+  }
+
+  if let Some(websockets) = websockets {
+    let _ = writeln!(out, "# HELP hermes_websocket_connections Open websockets.");
+    let _ = writeln!(out, "# TYPE hermes_websocket_connections gauge");
+    let _ = writeln!(out, "hermes_websocket_connections {}", websockets);
+  }
+
+  let (connections, idle, max) = pool_state();
+  let _ = writeln!(out, "# HELP hermes_db_pool_connections Database connections, by state.");
+  let _ = writeln!(out, "# TYPE hermes_db_pool_connections gauge");
+  let _ = writeln!(
+    out,
+    "hermes_db_pool_connections{{state=\"busy\"}} {}",
+    connections - idle
+  );
+  let _ = writeln!(out, "hermes_db_pool_connections{{state=\"idle\"}} {}", idle);
+  let _ = writeln!(out, "# HELP hermes_db_pool_max_connections Size of the database pool.");
+  let _ = writeln!(out, "# TYPE hermes_db_pool_max_connections gauge");
+  let _ = writeln!(out, "hermes_db_pool_max_connections {}", max);
+  out
+}
+
+fn escape(label: &str) -> String {
+  label.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -1,14 +1,26 @@
 use atom_syndication;
 use base64::{decode, encode};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use diesel::sql_types::{BigInt, Bool, Date, Double, Integer, Nullable, Text};
+use serde::{Serialize, Serializer};
+use md5;
+use ring::constant_time;
 use rss;
 use sha2::{Digest, Sha256};
+use serde_json;
+use std::collections::HashMap;
 use std::str;
+use url::Url;
 use warp::ws::Message;
 
 use db::get_user;
+use error::HermesError;
+use sanitize;
 use schema::*;
+use views::*;
+use web::handlers::random_token;
 use web::types::IncomingMessageType;
+use web::version;
 
 //////////
 // Feed //
@@ -21,7 +33,207 @@ pub struct Feed {
   pub description: Option<String>,
   pub site_link: String,
   pub feed_link: String,
+  #[serde(serialize_with = "timestamp")]
   pub updated_at: DateTime<Utc>,
+  #[serde(skip_serializing)]
+  pub leased_until: Option<DateTime<Utc>>,
+  #[serde(skip_serializing)]
+  pub parse_warnings: Option<String>,
+  pub fetch_full_content: bool,
+  // HTTP status of the last fetch, None when there was no response
+  pub last_fetch_status: Option<i32>,
+  pub consecutive_failure_count: i32,
+  pub last_error: Option<String>,
+}
+impl Feed {
+  // feeds failing this many times in a row are not fetched anymore
+  pub const DEAD_AFTER_FAILURES: i32 = 100;
+
+  pub fn dead(&self) -> bool {
+    self.consecutive_failure_count >= Feed::DEAD_AFTER_FAILURES
+  }
+
+  pub fn warnings(&self) -> Vec<ParseWarning> {
+    self
+      .parse_warnings
+      .as_ref()
+      .and_then(|w| serde_json::from_str(w).ok())
+      .unwrap_or_else(|| vec![])
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedInfo {
+  #[serde(flatten)]
+  pub feed: Feed,
+  pub warnings: Vec<ParseWarning>,
+  pub items_per_day: Option<f64>,
+  pub dead: bool,
+}
+
+// how the last fetches of a feed went
+#[derive(Debug, Queryable, Serialize)]
+pub struct FetchStatus {
+  #[serde(skip_serializing)]
+  pub feed_id: i32,
+  pub last_fetch_status: Option<i32>,
+  pub consecutive_failure_count: i32,
+  pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SubscribedFeedStatus {
+  #[serde(flatten)]
+  pub feed: SubscribedFeed,
+  #[serde(flatten)]
+  pub fetch: Option<FetchStatus>,
+  pub dead: bool,
+}
+impl SubscribedFeedStatus {
+  pub fn new(feed: SubscribedFeed, fetch: Option<FetchStatus>) -> Self {
+    SubscribedFeedStatus {
+      dead: fetch
+        .as_ref()
+        .map(|f| f.consecutive_failure_count >= Feed::DEAD_AFTER_FAILURES)
+        .unwrap_or(false),
+      feed: feed,
+      fetch: fetch,
+    }
+  }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseWarningKind {
+  MissingDate,
+  DuplicateGuid,
+  InvalidLink,
+}
+
+// one entry per kind, with an example item so users can go and look
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ParseWarning {
+  pub kind: ParseWarningKind,
+  pub count: usize,
+  pub example: Option<String>,
+}
+
+// a feed from the instance catalog, as seen by one user
+#[derive(Debug, Serialize)]
+pub struct CatalogFeed {
+  #[serde(flatten)]
+  pub feed: Feed,
+  pub subscribed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScheduledFeed {
+  pub feed_id: i32,
+  pub feed_link: String,
+  pub offset_secs: f64,
+  pub interval_secs: u64,
+}
+
+// a feed's counters for one UTC day; items count on the day they were published
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct FeedDay {
+  #[serde(skip_serializing)]
+  pub feed_id: i32,
+  pub day: NaiveDate,
+  pub items: i32,
+  pub bytes: i64,
+  pub fetches: i32,
+  pub failures: i32,
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct FeedTotal {
+  #[sql_type = "Integer"]
+  pub feed_id: i32,
+  #[sql_type = "BigInt"]
+  pub items: i64,
+  #[sql_type = "Nullable<Date>"]
+  pub last_post: Option<NaiveDate>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FeedStats {
+  pub feed_id: i32,
+  pub posts_per_week: f64,
+  pub unread_ratio: f64,
+  pub bytes_per_day: f64,
+  pub failure_rate: f64,
+  pub last_post: Option<NaiveDate>,
+  pub items_per_day: Option<f64>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub days: Vec<FeedDay>,
+}
+impl FeedStats {
+  pub const WINDOW_DAYS: i64 = 28;
+
+  // `days` are the last WINDOW_DAYS, `total` covers the feed's whole history
+  pub fn new(
+    feed: &SubscribedFeed,
+    days: Vec<FeedDay>,
+    total: Option<&FeedTotal>,
+    rate: Option<f64>,
+  ) -> FeedStats {
+    let window = FeedStats::WINDOW_DAYS as f64;
+    let items: i64 = days.iter().map(|d| d.items as i64).sum();
+    let bytes: i64 = days.iter().map(|d| d.bytes).sum();
+    let fetches: i64 = days.iter().map(|d| d.fetches as i64).sum();
+    let failures: i64 = days.iter().map(|d| d.failures as i64).sum();
+    FeedStats {
+      feed_id: feed.id,
+      posts_per_week: items as f64 * 7.0 / window,
+      unread_ratio: ratio(feed.unseen_count as i64, total.map(|t| t.items).unwrap_or(0)),
+      bytes_per_day: bytes as f64 / window,
+      failure_rate: ratio(failures, fetches),
+      last_post: total.and_then(|t| t.last_post),
+      items_per_day: rate,
+      days: days,
+    }
+  }
+}
+
+fn ratio(part: i64, whole: i64) -> f64 {
+  match whole {
+    0 => 0.0,
+    w => (part as f64 / w as f64).min(1.0),
+  }
+}
+
+// one subscription in `GET /api/feeds/hygiene`
+#[derive(Debug, Serialize)]
+pub struct FeedHygiene {
+  pub title: String,
+  #[serde(flatten)]
+  pub stats: FeedStats,
+  pub stale: bool,
+  pub failing: bool,
+  pub noisy: bool,
+  // "mute" or "digest" for noisy feeds
+  pub suggestion: Option<&'static str>,
+}
+
+// items per day, smoothed so that one busy day doesn't make a feed noisy
+#[derive(Debug, Queryable, Insertable)]
+#[table_name = "feed_rates"]
+pub struct FeedRate {
+  pub feed_id: i32,
+  pub items_per_day: f64,
+  // the last day folded in
+  pub day: NaiveDate,
+}
+impl FeedRate {
+  // an exponential moving average over about two weeks
+  pub const SPAN_DAYS: f64 = 14.0;
+
+  pub fn fold(&mut self, day: NaiveDate, items: i32) {
+    let alpha = 2.0 / (FeedRate::SPAN_DAYS + 1.0);
+    self.items_per_day = alpha * items as f64 + (1.0 - alpha) * self.items_per_day;
+    self.day = day;
+  }
 }
 
 #[derive(Insertable)]
@@ -44,17 +256,40 @@ impl NewFeed {
     }
   }
 
-  pub fn from_atom(feed: &atom_syndication::Feed, url: &str) -> NewFeed {
-    NewFeed {
+  pub fn from_atom(feed: &atom_syndication::Feed, url: &str) -> Result<NewFeed, HermesError> {
+    let link = feed
+      .links()
+      .first()
+      .ok_or_else(|| HermesError::Parse("feed without a link".to_owned()))?;
+    Ok(NewFeed {
       title: feed.title().to_string(),
-      site_link: feed.links()[0].href().to_string(),
+      site_link: link.href().to_string(),
       feed_link: url.to_string(),
       description: feed.subtitle().and_then(|s| Some(s.to_owned())),
       updated_at: Utc::now(),
-    }
+    })
   }
 }
 
+#[derive(Debug, Queryable)]
+pub struct FeedIcon {
+  pub feed_id: i32,
+  pub content_type: String,
+  pub data: Vec<u8>,
+  pub fetched_at: DateTime<Utc>,
+}
+
+// a cached media file, stored once however many URLs serve it
+#[derive(Debug, Queryable)]
+pub struct MediaBlob {
+  pub hash: String,
+  pub content_type: String,
+  pub data: Vec<u8>,
+  pub size: i64,
+  pub refs: i32,
+  pub last_used_at: DateTime<Utc>,
+}
+
 //////////
 // Item //
 //////////
@@ -71,10 +306,64 @@ pub struct Item {
   pub summary: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub content: Option<String>,
+  #[serde(serialize_with = "optional_timestamp")]
   pub published_at: Option<DateTime<Utc>>,
+  #[serde(serialize_with = "optional_timestamp")]
   pub updated_at: Option<DateTime<Utc>>,
   #[serde(skip_serializing)]
   pub feed_id: i32,
+  // stable across feed URL changes, unlike `id` it's safe for clients to keep
+  pub uuid: String,
+  #[serde(skip_serializing)]
+  pub fingerprint: Option<String>,
+  #[serde(skip_serializing)]
+  pub content_hash: Option<String>,
+  #[serde(rename = "updated", serialize_with = "is_set")]
+  pub edited_at: Option<DateTime<Utc>>,
+}
+
+// `/api/v1` writes timestamps the same way every time, see `web::version`
+pub fn timestamp<S: Serializer>(at: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+  match version::canonical() {
+    true => s.serialize_str(&at.to_rfc3339_opts(SecondsFormat::Micros, true)),
+    false => at.serialize(s),
+  }
+}
+
+pub fn optional_timestamp<S: Serializer>(
+  at: &Option<DateTime<Utc>>,
+  s: S,
+) -> Result<S::Ok, S::Error> {
+  match *at {
+    Some(ref at) => timestamp(at, s),
+    None => s.serialize_none(),
+  }
+}
+
+// for timestamps in replies put together with `json!`
+#[derive(Debug)]
+pub struct Timestamp(pub DateTime<Utc>);
+impl Serialize for Timestamp {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    timestamp(&self.0, s)
+  }
+}
+
+// items and subscribed items tell whether they were edited, not when
+fn is_set<S: Serializer>(edited_at: &Option<DateTime<Utc>>, s: S) -> Result<S::Ok, S::Error> {
+  s.serialize_bool(edited_at.is_some())
+}
+
+// what a fetched item is matched against to tell whether it is already stored
+#[derive(Debug, Queryable)]
+pub struct ItemKey {
+  pub id: i32,
+  pub guid: String,
+  pub link: String,
+  pub fingerprint: Option<String>,
+  pub published_at: Option<DateTime<Utc>>,
+  pub updated_at: Option<DateTime<Utc>>,
+  pub content_hash: Option<String>,
 }
 
 #[derive(Insertable, AsChangeset, Debug)]
@@ -88,33 +377,79 @@ pub struct NewItem {
   pub published_at: Option<DateTime<Utc>>,
   pub updated_at: Option<DateTime<Utc>>,
   pub feed_id: i32,
+  pub fingerprint: Option<String>,
+  pub content_hash: Option<String>,
 }
 impl NewItem {
-  pub fn from_item(item: &rss::Item, feed_id: i32) -> NewItem {
-    NewItem {
-      guid: item.guid().unwrap().value().to_owned(),
-      title: item.title().expect("no title!").to_owned(),
-      link: item.link().expect("no link!").to_owned(),
-      summary: item.description().and_then(|s| Some(s.to_owned())),
-      content: item.content().and_then(|s| Some(s.to_owned())),
-      published_at: item.pub_date().and_then(|d| parse_date(d)),
-      updated_at: item.pub_date().and_then(|d| parse_date(d)),
+  pub fn from_item(
+    item: &rss::Item,
+    feed_id: i32,
+    base: Option<&Url>,
+  ) -> Result<NewItem, HermesError> {
+    let missing = |what: &str| HermesError::Parse(format!("item without a {}", what));
+    let title = item.title().ok_or_else(|| missing("title"))?;
+    let guid = item.guid().ok_or_else(|| missing("guid"))?;
+    let link = item.link().ok_or_else(|| missing("link"))?;
+    let published_at = item.pub_date().and_then(|d| parse_date(d));
+    let summary = item.description().map(|s| sanitize::clean(s, base));
+    let content = item.content().map(|s| sanitize::clean(s, base));
+    Ok(NewItem {
+      guid: guid.value().to_owned(),
+      title: title.to_owned(),
+      link: link.to_owned(),
+      content_hash: Some(NewItem::content_hash(title, &summary, &content)),
+      summary: summary,
+      content: content,
+      published_at: published_at,
+      updated_at: published_at,
       feed_id: feed_id,
-    }
+      fingerprint: NewItem::fingerprint(title, published_at),
+    })
   }
-  pub fn from_entry(item: &atom_syndication::Entry, feed_id: i32) -> NewItem {
-    NewItem {
+  pub fn from_entry(
+    item: &atom_syndication::Entry,
+    feed_id: i32,
+    base: Option<&Url>,
+  ) -> Result<NewItem, HermesError> {
+    let link = item
+      .links()
+      .first()
+      .ok_or_else(|| HermesError::Parse("entry without a link".to_owned()))?;
+    let published_at = item.published().and_then(|d| parse_date(d));
+    let summary = item.summary().map(|s| sanitize::clean(s, base));
+    let content = item
+      .content()
+      .and_then(|o| o.value().map(|s| sanitize::clean(s, base)));
+    Ok(NewItem {
       guid: item.id().to_owned(),
       title: item.title().to_owned(),
-      link: item.links()[0].href().to_owned(),
-      summary: item.summary().and_then(|s| Some(s.to_owned())),
-      content: item
-        .content()
-        .and_then(|o| o.value().and_then(|s| Some(s.to_owned()))),
-      published_at: item.published().and_then(|d| parse_date(d)),
+      link: link.href().to_owned(),
+      content_hash: Some(NewItem::content_hash(item.title(), &summary, &content)),
+      summary: summary,
+      content: content,
+      published_at: published_at,
       updated_at: parse_date(item.updated()),
       feed_id: feed_id,
-    }
+      fingerprint: NewItem::fingerprint(item.title(), published_at),
+    })
+  }
+
+  // Identifies an item by its title and date when its guid can't be trusted.
+  // Undated items have none, as titles alone repeat too often. The migration
+  // adding the column computes the same thing in SQL.
+  pub fn fingerprint(title: &str, published_at: Option<DateTime<Utc>>) -> Option<String> {
+    published_at.map(|date| {
+      let title = title.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+      format!("{:x}", md5::compute(format!("{} {}", title, date.timestamp())))
+    })
+  }
+
+  // Tells an edited item from the same one fetched again. The migration adding
+  // the column computes the same thing in SQL.
+  pub fn content_hash(title: &str, summary: &Option<String>, content: &Option<String>) -> String {
+    let summary = summary.as_ref().map(|s| s.as_str()).unwrap_or("");
+    let content = content.as_ref().map(|c| c.as_str()).unwrap_or("");
+    format!("{:x}", md5::compute(format!("{}\n{}\n{}", title, summary, content)))
   }
 }
 
@@ -122,7 +457,8 @@ impl NewItem {
 // Subscription //
 //////////////////
 
-#[derive(Debug, Queryable, Serialize)]
+#[derive(Debug, Queryable, QueryableByName, Serialize)]
+#[table_name = "subscribed_items_view"]
 pub struct SubscribedItem {
   pub id: i32,
   #[serde(skip_serializing)]
@@ -131,12 +467,29 @@ pub struct SubscribedItem {
   pub title: String,
   pub summary: Option<String>,
   pub content: Option<String>,
+  #[serde(serialize_with = "optional_timestamp")]
   pub published_at: Option<DateTime<Utc>>,
+  #[serde(serialize_with = "optional_timestamp")]
   pub updated_at: Option<DateTime<Utc>>,
   pub feed_id: i32,
+  pub uuid: String,
   pub subscribed_item_id: i32,
   pub user_id: i32,
   pub seen: bool,
+  pub starred: bool,
+  #[serde(rename = "updated", serialize_with = "is_set")]
+  pub edited_at: Option<DateTime<Utc>>,
+}
+
+// an item's state as sent to syncing clients
+#[derive(Debug, Queryable, Serialize)]
+pub struct ItemState {
+  pub item_id: i32,
+  pub uuid: String,
+  pub seen: bool,
+  pub starred: bool,
+  #[serde(serialize_with = "optional_timestamp")]
+  pub updated_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Queryable, Serialize, Associations)]
@@ -147,9 +500,300 @@ pub struct SubscribedFeed {
   pub description: Option<String>,
   pub site_link: String,
   pub feed_link: String,
+  #[serde(serialize_with = "timestamp")]
   pub updated_at: DateTime<Utc>,
   pub user_id: i32,
   pub unseen_count: i32,
+  pub pinned: bool,
+  pub sort_order: Option<i32>,
+}
+
+// subscriptions whose sites share a host, `www.` aside
+#[derive(Debug, Serialize)]
+pub struct DomainGroup {
+  pub domain: String,
+  pub unseen_count: i64,
+  pub feeds: Vec<SubscribedFeed>,
+}
+impl DomainGroup {
+  pub fn group(feeds: Vec<SubscribedFeed>) -> Vec<DomainGroup> {
+    let mut groups: Vec<DomainGroup> = vec![];
+    for feed in feeds {
+      let domain = feed_domain(&feed);
+      match groups.iter().position(|g| g.domain == domain) {
+        Some(i) => {
+          groups[i].unseen_count += feed.unseen_count as i64;
+          groups[i].feeds.push(feed);
+        }
+        None => groups.push(DomainGroup {
+          domain: domain,
+          unseen_count: feed.unseen_count as i64,
+          feeds: vec![feed],
+        }),
+      }
+    }
+    groups.sort_by(|a, b| a.domain.cmp(&b.domain));
+    groups
+  }
+}
+
+// the site's host, or the feed's when the site link is missing or relative
+fn feed_domain(feed: &SubscribedFeed) -> String {
+  let host = Url::parse(&feed.site_link)
+    .ok()
+    .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+    .or_else(|| {
+      Url::parse(&feed.feed_link)
+        .ok()
+        .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+    }).unwrap_or_default();
+  match host.starts_with("www.") {
+    true => host[4..].to_owned(),
+    false => host,
+  }
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AddFeedOutcome {
+  Added,
+  Duplicate,
+  Invalid,
+  Timeout,
+  // the request failed or the server answered with an error
+  Unreachable,
+  // neither a feed nor a page that links to one
+  NotAFeed,
+  Failed,
+  // the user has as many feeds as they are allowed
+  OverQuota,
+}
+
+// a feed a page links to
+#[derive(Debug, Serialize)]
+pub struct FeedCandidate {
+  pub url: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  // "rss" or "atom"
+  pub kind: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AddFeedResult {
+  pub url: String,
+  pub result: AddFeedOutcome,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub feed_id: Option<i32>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub title: Option<String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub warnings: Vec<ParseWarning>,
+}
+impl AddFeedResult {
+  pub fn new(url: &str, result: AddFeedOutcome, feed_id: Option<i32>) -> Self {
+    AddFeedResult {
+      url: url.to_string(),
+      result: result,
+      feed_id: feed_id,
+      title: None,
+      warnings: vec![],
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+  pub limit: i64,
+  pub offset: i64,
+}
+impl Page {
+  pub const DEFAULT_LIMIT: i64 = 50;
+  pub const MAX_LIMIT: i64 = 500;
+}
+impl Default for Page {
+  fn default() -> Self {
+    Page {
+      limit: Page::DEFAULT_LIMIT,
+      offset: 0,
+    }
+  }
+}
+
+// a user as the admin's list shows them
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct UserSummary {
+  #[sql_type = "Integer"]
+  pub id: i32,
+  #[sql_type = "Text"]
+  pub username: String,
+  #[sql_type = "Bool"]
+  pub approved: bool,
+  #[sql_type = "Bool"]
+  pub is_admin: bool,
+  #[sql_type = "Bool"]
+  pub disabled: bool,
+  #[sql_type = "BigInt"]
+  pub subscriptions: i64,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct TagCount {
+  #[sql_type = "Text"]
+  pub tag: String,
+  #[sql_type = "BigInt"]
+  pub unseen_count: i64,
+}
+
+///////////
+// Stats //
+///////////
+
+#[derive(Debug, Serialize)]
+pub struct DbStats {
+  pub tables: Vec<TableStats>,
+  pub indexes: Vec<IndexStats>,
+  pub cache: Option<CacheStats>,
+  // None when pg_stat_statements is not installed
+  pub slow_queries: Option<Vec<SlowQuery>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminStats {
+  #[serde(flatten)]
+  pub database: DbStats,
+  // of the process answering, workers keep their own
+  pub guid_cache: GuidCacheStats,
+}
+
+// what a user had unseen at some past time, for `GET /debug/items`
+#[derive(Debug, Serialize)]
+pub struct UnreadSnapshot {
+  pub user_id: i32,
+  #[serde(serialize_with = "timestamp")]
+  pub as_of: DateTime<Utc>,
+  // None while nothing has been logged yet
+  #[serde(serialize_with = "optional_timestamp")]
+  pub log_starts_at: Option<DateTime<Utc>>,
+  pub count: usize,
+  pub unread: Vec<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GuidCacheStats {
+  pub feeds: usize,
+  pub checked_items: usize,
+  pub skipped_items: usize,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct TableStats {
+  #[sql_type = "Text"]
+  pub table_name: String,
+  #[sql_type = "BigInt"]
+  pub row_count: i64,
+  #[sql_type = "BigInt"]
+  pub total_bytes: i64,
+  #[sql_type = "BigInt"]
+  pub index_bytes: i64,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct IndexStats {
+  #[sql_type = "Text"]
+  pub index_name: String,
+  #[sql_type = "Text"]
+  pub table_name: String,
+  #[sql_type = "BigInt"]
+  pub bytes: i64,
+  #[sql_type = "BigInt"]
+  pub scans: i64,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct CacheStats {
+  #[sql_type = "Nullable<Double>"]
+  pub heap_hit_ratio: Option<f64>,
+  #[sql_type = "Nullable<Double>"]
+  pub index_hit_ratio: Option<f64>,
+}
+
+#[derive(Debug, QueryableByName, Serialize)]
+pub struct SlowQuery {
+  #[sql_type = "Text"]
+  pub query: String,
+  #[sql_type = "BigInt"]
+  pub calls: i64,
+  #[sql_type = "Double"]
+  pub total_ms: f64,
+  #[sql_type = "Double"]
+  pub mean_ms: f64,
+}
+
+///////////////
+// Enclosure //
+///////////////
+
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct Enclosure {
+  #[serde(skip_serializing)]
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub item_id: i32,
+  pub url: String,
+  pub mime_type: Option<String>,
+  pub length: Option<i64>,
+}
+
+// a failed content extraction; `next_attempt_at` is unset once retries ran out
+#[derive(Debug, Queryable, Serialize, Clone)]
+pub struct ContentRetry {
+  pub item_id: i32,
+  pub attempts: i32,
+  pub last_error: String,
+  #[serde(serialize_with = "optional_timestamp")]
+  pub next_attempt_at: Option<DateTime<Utc>>,
+  #[serde(serialize_with = "timestamp")]
+  pub updated_at: DateTime<Utc>,
+}
+
+// the item id is filled in once the item is stored
+#[derive(Insertable, Debug)]
+#[table_name = "enclosures"]
+pub struct NewEnclosure {
+  pub item_id: i32,
+  pub url: String,
+  pub mime_type: Option<String>,
+  pub length: Option<i64>,
+}
+impl NewEnclosure {
+  pub fn from_rss(enclosure: &rss::Enclosure) -> NewEnclosure {
+    NewEnclosure {
+      item_id: 0,
+      url: enclosure.url().to_owned(),
+      mime_type: Some(enclosure.mime_type().to_owned()).filter(|m| !m.is_empty()),
+      length: enclosure.length().parse().ok(),
+    }
+  }
+
+  pub fn from_atom(link: &atom_syndication::Link) -> NewEnclosure {
+    NewEnclosure {
+      item_id: 0,
+      url: link.href().to_owned(),
+      mime_type: link.mime_type().map(|m| m.to_owned()),
+      length: link.length().and_then(|l| l.parse().ok()),
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemWithEnclosures {
+  #[serde(flatten)]
+  pub item: SubscribedItem,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub enclosures: Vec<Enclosure>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub content_retry: Option<ContentRetry>,
 }
 
 ///////////////
@@ -159,20 +803,29 @@ pub struct SubscribedFeed {
 #[derive(Debug, Serialize, Clone)]
 pub struct CompositeItem {
   pub id: i32,
+  pub uuid: String,
   pub title: String,
   pub link: String,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub summary: Option<String>,
   #[serde(skip_serializing_if = "Option::is_none")]
   pub content: Option<String>,
+  #[serde(serialize_with = "optional_timestamp")]
   pub published_at: Option<DateTime<Utc>>,
+  #[serde(serialize_with = "optional_timestamp")]
   pub updated_at: Option<DateTime<Utc>>,
   pub seen: bool,
+  pub starred: bool,
+  // edited since it was first stored
+  pub updated: bool,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub enclosures: Vec<Enclosure>,
 }
 impl CompositeItem {
   pub fn from_item(item: &Item) -> Self {
     CompositeItem {
       id: item.id,
+      uuid: item.uuid.clone(),
       title: item.title.clone(),
       link: item.link.clone(),
       summary: item.summary.clone(),
@@ -180,11 +833,15 @@ impl CompositeItem {
       published_at: item.published_at,
       updated_at: item.updated_at,
       seen: false,
+      starred: false,
+      updated: item.edited_at.is_some(),
+      enclosures: vec![],
     }
   }
   pub fn from_subscribed(item: &SubscribedItem) -> Self {
     CompositeItem {
       id: item.id,
+      uuid: item.uuid.clone(),
       title: item.title.clone(),
       link: item.link.clone(),
       summary: item.summary.clone(),
@@ -192,6 +849,15 @@ impl CompositeItem {
       published_at: item.published_at,
       updated_at: item.updated_at,
       seen: item.seen,
+      starred: item.starred,
+      updated: item.edited_at.is_some(),
+      enclosures: vec![],
+    }
+  }
+  pub fn without_content(self) -> Self {
+    CompositeItem {
+      content: None,
+      ..self
     }
   }
 }
@@ -200,20 +866,40 @@ impl CompositeItem {
 // User //
 //////////
 
-#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[derive(Debug, Clone, Queryable, Associations, Identifiable, Serialize)]
 pub struct User {
   pub id: i32,
   pub username: String,
   pub password_hash: Vec<u8>,
+  pub approved: bool,
+  #[serde(skip_serializing)]
+  pub fever_key_hash: Option<String>,
+  pub locale: String,
+  // items per day above which a subscription is flagged as noisy
+  pub rate_warning: f64,
+  // age in days after which unstarred items are marked as read
+  pub auto_read_days: Option<i32>,
+  pub is_admin: bool,
+  // by an admin, who can enable the account again
+  pub disabled: bool,
 }
 impl User {
-  pub fn check_user(username: &str, pass: &str) -> Option<User> {
+  pub const DEFAULT_RATE_WARNING: f64 = 10.0;
+
+  // whether the user can log in
+  pub fn active(&self) -> bool {
+    self.approved && !self.disabled
+  }
+
+  // Ok(None) for unknown users and wrong passwords alike
+  pub fn check_user(username: &str, pass: &str) -> Result<Option<User>, HermesError> {
     match get_user(username) {
-      Some(user) => match user.verifies(pass) {
-        true => Some(user),
-        false => None,
+      Ok(user) => match user.active() && user.verifies(pass) {
+        true => Ok(Some(user)),
+        false => Ok(None),
       },
-      None => None,
+      Err(HermesError::NotFound) => Ok(None),
+      Err(e) => Err(e),
     }
   }
 
@@ -226,13 +912,546 @@ impl User {
     e
   }
 
+  // A hash that doesn't decode matches no password rather than failing the login
   fn verifies(&self, pass: &str) -> bool {
-    let orig_hash = decode(&self.password_hash).unwrap();
+    let orig_hash = match decode(&self.password_hash) {
+      Ok(orig_hash) => orig_hash,
+      Err(_) => return false,
+    };
     let mut hasher = Sha256::default();
     hasher.input(pass.as_bytes());
     let output = hasher.result();
     let hashed_pw = &output[..];
-    orig_hash == hashed_pw
+    constant_time::verify_slices_are_equal(&orig_hash, hashed_pw).is_ok()
+  }
+}
+
+///////////
+// Event //
+///////////
+
+#[derive(Debug, Queryable, Associations, Identifiable)]
+#[belongs_to(Feed)]
+pub struct Event {
+  pub id: i32,
+  pub feed_id: i32,
+  pub item_ids: Vec<i32>,
+  pub user_ids: Vec<i32>,
+  pub created_at: DateTime<Utc>,
+  pub topic_id: Option<i32>,
+}
+
+///////////
+// Topic //
+///////////
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[belongs_to(User)]
+pub struct Topic {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub name: String,
+  pub keywords: Vec<String>,
+  pub notify: bool,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+impl Topic {
+  pub fn matches(&self, item: &Item) -> bool {
+    let text = format!(
+      "{} {} {}",
+      item.title,
+      item.summary.as_ref().map(|s| s.as_str()).unwrap_or(""),
+      item.content.as_ref().map(|s| s.as_str()).unwrap_or("")
+    ).to_lowercase();
+    self
+      .keywords
+      .iter()
+      .any(|k| !k.is_empty() && text.contains(&k.to_lowercase()))
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TimelineDay {
+  pub day: String,
+  pub items: Vec<Item>,
+}
+
+//////////////
+// Activity //
+//////////////
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+  Login,
+  LoginFailed,
+  Logout,
+  TokenRefresh,
+  Unsubscribe,
+  MarkAllRead,
+  DeleteTopic,
+  SetFeverKey,
+  HideItem,
+  PurgeItem,
+  SetLocale,
+  SessionRevoked,
+  SetRateWarning,
+  SetAutoRead,
+  TransferSubscriptions,
+  AccountDisabled,
+  AccountEnabled,
+  PasswordReset,
+  PasswordChanged,
+}
+impl Action {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      Action::Login => "login",
+      Action::LoginFailed => "login_failed",
+      Action::Logout => "logout",
+      Action::TokenRefresh => "token_refresh",
+      Action::Unsubscribe => "unsubscribe",
+      Action::MarkAllRead => "mark_all_read",
+      Action::DeleteTopic => "delete_topic",
+      Action::SetFeverKey => "set_fever_key",
+      Action::HideItem => "hide_item",
+      Action::PurgeItem => "purge_item",
+      Action::SetLocale => "set_locale",
+      Action::SessionRevoked => "session_revoked",
+      Action::SetRateWarning => "set_rate_warning",
+      Action::SetAutoRead => "set_auto_read",
+      Action::TransferSubscriptions => "transfer_subscriptions",
+      Action::AccountDisabled => "account_disabled",
+      Action::AccountEnabled => "account_enabled",
+      Action::PasswordReset => "password_reset",
+      Action::PasswordChanged => "password_changed",
+    }
+  }
+}
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[belongs_to(User)]
+#[table_name = "activity_log"]
+pub struct Activity {
+  #[serde(skip_serializing)]
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub action: String,
+  pub detail: Option<String>,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+// What the inbox holds besides feed items. Producers add to it through
+// `db::notify`, web nodes push new entries to open websockets.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NotificationKind {
+  System,
+  Digest,
+  WebhookFailure,
+  Share,
+}
+impl NotificationKind {
+  pub fn as_str(&self) -> &'static str {
+    match *self {
+      NotificationKind::System => "system",
+      NotificationKind::Digest => "digest",
+      NotificationKind::WebhookFailure => "webhook_failure",
+      NotificationKind::Share => "share",
+    }
+  }
+}
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize, Clone)]
+#[belongs_to(User)]
+pub struct Notification {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub kind: String,
+  pub message: String,
+  pub detail: Option<String>,
+  pub seen: bool,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+// what sync clients need to drop an item they already have
+#[derive(Debug, Queryable, Serialize)]
+pub struct DeletedItem {
+  #[serde(skip_serializing)]
+  pub id: i32,
+  pub item_id: i32,
+  pub feed_id: i32,
+  #[serde(skip_serializing)]
+  pub guid: String,
+  #[serde(skip_serializing)]
+  pub user_id: Option<i32>,
+  #[serde(serialize_with = "timestamp")]
+  pub deleted_at: DateTime<Utc>,
+}
+
+// one logged in device, followed across token refreshes
+#[derive(Debug, Queryable, Associations, Identifiable)]
+#[belongs_to(User)]
+pub struct Session {
+  pub id: i32,
+  pub user_id: i32,
+  pub jti: String,
+  pub created_at: DateTime<Utc>,
+  pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AccountActivity {
+  pub websocket_connected: bool,
+  pub logins: Vec<Activity>,
+  pub tokens: Vec<Activity>,
+  pub actions: Vec<Activity>,
+}
+impl AccountActivity {
+  pub fn new(websocket_connected: bool, log: Vec<Activity>) -> Self {
+    let (logins, rest): (Vec<_>, Vec<_>) = log.into_iter().partition(|a| {
+      a.action == Action::Login.as_str() || a.action == Action::LoginFailed.as_str()
+    });
+    let (tokens, actions): (Vec<_>, Vec<_>) = rest.into_iter().partition(|a| {
+      a.action == Action::TokenRefresh.as_str()
+        || a.action == Action::Logout.as_str()
+        || a.action == Action::SessionRevoked.as_str()
+    });
+    AccountActivity {
+      websocket_connected: websocket_connected,
+      logins: logins,
+      tokens: tokens,
+      actions: actions,
+    }
+  }
+}
+
+////////////
+// Filter //
+////////////
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[belongs_to(User)]
+pub struct Filter {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub feed_id: Option<i32>,
+  pub pattern: String,
+  pub match_field: String,
+  pub action: String,
+  pub enabled: bool,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FilterPreview {
+  pub scanned: usize,
+  pub matched: Vec<SubscribedItem>,
+}
+
+//////////
+// Mute //
+//////////
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[belongs_to(User)]
+pub struct Mute {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub feed_id: Option<i32>,
+  pub phrase: String,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Queryable, Serialize)]
+pub struct MutedItem {
+  pub id: i32,
+  pub feed_id: i32,
+  pub link: String,
+  pub title: String,
+  #[serde(serialize_with = "optional_timestamp")]
+  pub published_at: Option<DateTime<Utc>>,
+}
+
+//////////
+// Push //
+//////////
+
+#[derive(Debug, Queryable, QueryableByName, Associations, Identifiable)]
+#[belongs_to(User)]
+#[table_name = "push_subscriptions"]
+pub struct PushSubscription {
+  pub id: i32,
+  pub user_id: i32,
+  pub endpoint: String,
+  pub p256dh: String,
+  pub auth: String,
+  pub created_at: DateTime<Utc>,
+}
+
+////////////
+// WebSub //
+////////////
+
+#[derive(Debug, Queryable, Associations, Identifiable)]
+#[belongs_to(Feed)]
+#[primary_key(feed_id)]
+pub struct WebsubSubscription {
+  pub feed_id: i32,
+  pub hub: String,
+  pub topic: String,
+  pub token: String,
+  // sealed, see `db::open_websub_secret`
+  pub secret: String,
+  pub requested_at: DateTime<Utc>,
+  pub expires_at: Option<DateTime<Utc>>,
+}
+
+////////////
+// Invite //
+////////////
+
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+pub struct Invite {
+  pub id: i32,
+  pub code: String,
+  pub created_by: i32,
+  pub used_by: Option<i32>,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+///////////
+// Kiosk //
+///////////
+
+// read-only access to one tag, one feed or all of a user's subscriptions
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+pub struct KioskToken {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  #[serde(skip_serializing)]
+  pub token_hash: String,
+  pub tag: Option<String>,
+  pub feed_id: Option<i32>,
+  pub item_limit: i32,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+  #[serde(serialize_with = "timestamp")]
+  pub expires_at: DateTime<Utc>,
+}
+impl KioskToken {
+  // only the hash is stored, the token itself is shown once
+  pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssuedKioskToken {
+  pub token: String,
+  #[serde(flatten)]
+  pub kiosk: KioskToken,
+}
+
+// what scripts and other clients log in with instead of a password
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+pub struct ApiToken {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub name: String,
+  #[serde(skip_serializing)]
+  pub token_hash: String,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+  #[serde(serialize_with = "optional_timestamp")]
+  pub last_used_at: Option<DateTime<Utc>>,
+}
+impl ApiToken {
+  // only the hash is stored, the token itself is shown once
+  pub fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IssuedApiToken {
+  pub token: String,
+  #[serde(flatten)]
+  pub api_token: ApiToken,
+}
+
+/////////////
+// Webhook //
+/////////////
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[belongs_to(User)]
+pub struct Webhook {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub url: String,
+  pub feed_id: Option<i32>,
+  // sealed, see `db::open_webhook_secret`
+  #[serde(skip_serializing)]
+  pub secret: String,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+// the secret is only shown when the webhook is created
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+  pub secret: String,
+  #[serde(flatten)]
+  pub webhook: Webhook,
+}
+
+#[derive(Debug, Queryable, Associations, Identifiable, Serialize)]
+#[belongs_to(Webhook)]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub webhook_id: i32,
+  pub item_id: i32,
+  pub attempts: i32,
+  pub error: Option<String>,
+  #[serde(serialize_with = "timestamp")]
+  pub finished_at: DateTime<Utc>,
+}
+
+// an item as a kiosk shows it, without the owner's read state
+#[derive(Debug, Serialize)]
+pub struct KioskItem {
+  pub uuid: String,
+  pub title: String,
+  pub link: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub summary: Option<String>,
+  #[serde(serialize_with = "optional_timestamp")]
+  pub published_at: Option<DateTime<Utc>>,
+}
+impl KioskItem {
+  pub fn from_subscribed(item: SubscribedItem) -> Self {
+    KioskItem {
+      uuid: item.uuid,
+      title: item.title,
+      link: item.link,
+      summary: item.summary,
+      published_at: item.published_at,
+    }
+  }
+}
+
+// a public link to one item, served at `/share/:token`
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+pub struct ItemShare {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub item_id: i32,
+  pub token: String,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+// items of a tag or feed that item retention leaves alone for a user
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+pub struct RetentionExemption {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub tag: Option<String>,
+  pub feed_id: Option<i32>,
+  #[serde(serialize_with = "timestamp")]
+  pub created_at: DateTime<Utc>,
+}
+
+// a webhook or push message that failed every attempt, see `deliveries`
+#[derive(Debug, Queryable, Identifiable, Serialize)]
+pub struct FailedDelivery {
+  pub id: i32,
+  #[serde(skip_serializing)]
+  pub user_id: i32,
+  pub kind: String,
+  pub target: String,
+  // may hold credentials of the endpoint
+  #[serde(skip_serializing)]
+  pub headers: String,
+  pub payload: String,
+  pub error: String,
+  pub attempts: i32,
+  #[serde(serialize_with = "timestamp")]
+  pub failed_at: DateTime<Utc>,
+}
+
+// what `GET /api/counts` answers, feeds without unread items are left out
+#[derive(Debug, Serialize)]
+pub struct UnreadCounts {
+  pub total: i64,
+  pub feeds: HashMap<i32, i32>,
+}
+
+impl UnreadCounts {
+  pub fn new(counts: Vec<(i32, i32)>) -> Self {
+    UnreadCounts {
+      total: counts.iter().map(|&(_, n)| n as i64).sum(),
+      feeds: counts.into_iter().collect(),
+    }
+  }
+}
+
+// what `GET /api/companion` answers, kept short for tray apps
+#[derive(Debug, Serialize)]
+pub struct CompanionUpdate {
+  #[serde(rename = "c")]
+  pub cursor: String,
+  #[serde(rename = "n", skip_serializing_if = "Option::is_none")]
+  pub unseen_total: Option<i64>,
+  #[serde(rename = "u", skip_serializing_if = "HashMap::is_empty")]
+  pub unseen_counts: HashMap<i32, i32>,
+  #[serde(rename = "t", skip_serializing_if = "Vec::is_empty")]
+  pub titles: Vec<CompanionItem>,
+}
+impl CompanionUpdate {
+  pub fn heartbeat(cursor: String) -> Self {
+    CompanionUpdate {
+      cursor: cursor,
+      unseen_total: None,
+      unseen_counts: HashMap::new(),
+      titles: vec![],
+    }
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompanionItem {
+  #[serde(rename = "i")]
+  pub id: i32,
+  #[serde(rename = "f")]
+  pub feed_id: i32,
+  #[serde(rename = "s")]
+  pub title: String,
+}
+impl CompanionItem {
+  pub fn from_item(item: Item) -> Self {
+    CompanionItem {
+      id: item.id,
+      feed_id: item.feed_id,
+      title: item.title,
+    }
   }
 }
 
@@ -244,6 +1463,24 @@ impl User {
 pub struct Claims {
   pub name: String,
   pub id: i32,
+  pub iat: i64,
+  pub exp: i64,
+  pub jti: String,
+}
+impl Claims {
+  pub fn for_user(user: &User, ttl_secs: i64) -> Option<Claims> {
+    let now = Utc::now().timestamp();
+    let jti = random_token(16)
+      .map_err(|e| error!("could not read random bytes: {}", e))
+      .ok()?;
+    Some(Claims {
+      name: user.username.to_string(),
+      id: user.id,
+      iat: now,
+      exp: now + ttl_secs,
+      jti: jti,
+    })
+  }
 }
 
 fn parse_date(date: &str) -> Option<DateTime<Utc>> {
@@ -262,12 +1499,20 @@ pub enum OutgoingWebsocketMessageType {
   NewFeed,
   NewItems,
   ActionResult,
+  UnseenCount,
+  TopicMatch,
+  SessionRevoked,
+  Notification,
 }
 #[derive(Debug, Serialize)]
 pub enum OutgoingWebsocketMessageData {
   NewFeed(FeedMessage),
   NewItems(ItemsMessage),
   ActionResult(ResultMessage),
+  UnseenCount(UnseenCountMessage),
+  TopicMatch(TopicMatchMessage),
+  SessionRevoked(SessionRevokedMessage),
+  Notification(Notification),
 }
 #[derive(Debug, Serialize)]
 pub struct OutgoingWebsocketMessage {
@@ -295,16 +1540,75 @@ impl OutgoingWebsocketMessage {
       data: OutgoingWebsocketMessageData::NewItems(p),
     }
   }
-  pub fn action_result(action: IncomingMessageType, result: bool) -> Self {
-    let p = ResultMessage {
-      id: action,
-      result: result,
+  // `items` or `content` for commands that load some, `Err` with what went wrong
+  pub fn action_result(
+    action: IncomingMessageType,
+    request_id: Option<String>,
+    result: Result<Option<ActionOutput>, String>,
+  ) -> Self {
+    let p = match result {
+      Ok(output) => {
+        let (items, content) = match output {
+          Some(ActionOutput::Items(items)) => (Some(items), None),
+          Some(ActionOutput::Content(content)) => (None, Some(content)),
+          None => (None, None),
+        };
+        ResultMessage {
+          id: action,
+          result: true,
+          request_id: request_id,
+          error: None,
+          items: items,
+          content: content,
+        }
+      }
+      Err(e) => ResultMessage {
+        id: action,
+        result: false,
+        request_id: request_id,
+        error: Some(e),
+        items: None,
+        content: None,
+      },
     };
     OutgoingWebsocketMessage {
       id: OutgoingWebsocketMessageType::ActionResult,
       data: OutgoingWebsocketMessageData::ActionResult(p),
     }
   }
+  pub fn unseen_count(feed_id: i32, unseen_count: i32) -> Self {
+    let p = UnseenCountMessage {
+      feed_id: feed_id,
+      unseen_count: unseen_count,
+    };
+    OutgoingWebsocketMessage {
+      id: OutgoingWebsocketMessageType::UnseenCount,
+      data: OutgoingWebsocketMessageData::UnseenCount(p),
+    }
+  }
+  pub fn topic_match(topic_id: i32, items: Vec<CompositeItem>) -> Self {
+    let p = TopicMatchMessage {
+      topic_id: topic_id,
+      items: items,
+    };
+    OutgoingWebsocketMessage {
+      id: OutgoingWebsocketMessageType::TopicMatch,
+      data: OutgoingWebsocketMessageData::TopicMatch(p),
+    }
+  }
+  pub fn session_revoked(reason: &'static str) -> Self {
+    let p = SessionRevokedMessage { reason: reason };
+    OutgoingWebsocketMessage {
+      id: OutgoingWebsocketMessageType::SessionRevoked,
+      data: OutgoingWebsocketMessageData::SessionRevoked(p),
+    }
+  }
+  pub fn notification(notification: Notification) -> Self {
+    OutgoingWebsocketMessage {
+      id: OutgoingWebsocketMessageType::Notification,
+      data: OutgoingWebsocketMessageData::Notification(notification),
+    }
+  }
   pub fn to_message(&self) -> Message {
     let msg = json!(self);
     Message::text(msg.to_string())
@@ -325,4 +1629,68 @@ pub struct ItemsMessage {
 pub struct ResultMessage {
   pub id: IncomingMessageType,
   pub result: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub request_id: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub error: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub items: Option<Vec<CompositeItem>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub content: Option<String>,
+}
+// what a command loaded, see `action_result`
+#[derive(Debug)]
+pub enum ActionOutput {
+  Items(Vec<CompositeItem>),
+  Content(String),
+}
+#[derive(Serialize, Debug)]
+pub struct UnseenCountMessage {
+  pub feed_id: i32,
+  pub unseen_count: i32,
+}
+#[derive(Serialize, Debug)]
+pub struct TopicMatchMessage {
+  pub topic_id: i32,
+  pub items: Vec<CompositeItem>,
+}
+#[derive(Serialize, Debug)]
+pub struct SessionRevokedMessage {
+  pub reason: &'static str,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::User;
+
+  fn user(password_hash: &[u8]) -> User {
+    User {
+      id: 1,
+      username: "user".to_owned(),
+      password_hash: password_hash.to_vec(),
+      approved: true,
+      fever_key_hash: None,
+      locale: "en".to_owned(),
+      rate_warning: User::DEFAULT_RATE_WARNING,
+      auto_read_days: None,
+      is_admin: false,
+      disabled: false,
+    }
+  }
+
+  #[test]
+  fn verifies_the_password_it_was_hashed_from() {
+    let user = user(User::hash_pw("correct horse").as_bytes());
+    assert!(user.verifies("correct horse"));
+    assert!(!user.verifies("correct horse "));
+    assert!(!user.verifies(""));
+  }
+
+  #[test]
+  fn malformed_hashes_match_no_password() {
+    assert!(!user(b"not base64!").verifies("not base64!"));
+    assert!(!user(b"").verifies(""));
+    // decodes, but is too short for a SHA-256
+    assert!(!user(b"AAAA").verifies(""));
+  }
 }
@@ -0,0 +1,55 @@
+use db;
+use models::{Item, Mute};
+
+// A mute hides new items mentioning a phrase, in one feed or in all of them.
+// The items stay subscribed but suppressed: they are left out of the item
+// lists and the unread counts, and `GET /api/mutes/items` still shows them.
+// Unlike filters, mutes only look at items fetched after they were added.
+
+fn mentions(text: &str, phrase: &str) -> bool {
+  text.to_lowercase().contains(phrase)
+}
+
+fn is_muted(mute: &Mute, item: &Item) -> bool {
+  if mute.feed_id.map(|f| f != item.feed_id).unwrap_or(false) {
+    return false;
+  }
+  let phrase = mute.phrase.to_lowercase();
+  if phrase.is_empty() {
+    return false;
+  }
+  mentions(&item.title, &phrase)
+    || item.summary.as_ref().map(|s| mentions(s, &phrase)).unwrap_or(false)
+    || item.content.as_ref().map(|c| mentions(c, &phrase)).unwrap_or(false)
+}
+
+// Suppresses the new items muted by any of their subscribers.
+pub fn apply_new(items: &[Item], subscribers: &[i32]) {
+  if items.is_empty() || subscribers.is_empty() {
+    return;
+  }
+  let mutes = match db::get_mutes(subscribers) {
+    Ok(mutes) => mutes,
+    Err(e) => {
+      error!("could not load mutes: {}", e);
+      return;
+    }
+  };
+  for &user_id in subscribers {
+    let muted = items
+      .iter()
+      .filter(|i| {
+        mutes
+          .iter()
+          .any(|m| m.user_id == user_id && is_muted(m, i))
+      }).map(|i| i.id)
+      .collect::<Vec<i32>>();
+    if muted.is_empty() {
+      continue;
+    }
+    match db::suppress_items(user_id, &muted) {
+      Ok(n) => debug!("muted {} new items for user {}", n, user_id),
+      Err(e) => error!("could not mute items for user {}: {}", user_id, e),
+    }
+  }
+}
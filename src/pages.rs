@@ -0,0 +1,136 @@
+use chrono::Utc;
+use hyper::rt;
+use readability::extractor;
+use regex::Regex;
+use std::collections::HashMap;
+use std::env;
+use std::io::Cursor;
+use url::Url;
+
+use db;
+use error::HermesError;
+use icons;
+use models::{Item, NewFeed, NewItem};
+use sanitize;
+use topics;
+
+// Sites without a feed can be followed too: subscribing to a page that is not
+// a feed and links to none watches the page itself. Its readable part, as
+// `extract` would find it for an item, is kept as a snapshot, and each fetch
+// compares the new one with it word by word. A new item is only added, and
+// the snapshot replaced, when they are less alike than
+//
+// PAGE_SIMILARITY  share of words two versions must have in common to count
+//                  as the same, 0.9 if unset; 1 reports every change
+//
+// so a page changing nothing but a date or a visitor counter adds no items.
+
+lazy_static! {
+  static ref SIMILARITY: f64 = env::var("PAGE_SIMILARITY")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(0.9);
+  static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+  static ref WORD: Regex = Regex::new(r"\w+").unwrap();
+}
+
+pub struct Page {
+  pub title: String,
+  pub content: String,
+}
+
+// the readable part of a page, an error when there is none
+pub fn extract(body: &[u8], url: &str) -> Result<Page, HermesError> {
+  let base = Url::parse(url).map_err(|e| HermesError::Parse(e.to_string()))?;
+  let product = extractor::extract(&mut Cursor::new(body), &base)
+    .map_err(|e| HermesError::Parse(format!("could not extract: {:?}", e)))?;
+  let content = sanitize::clean(&product.content, Some(&base));
+  if words(&content).is_empty() {
+    return Err(HermesError::Parse("no readable content".to_owned()));
+  }
+  let title = match product.title.trim() {
+    "" => url.to_owned(),
+    title => title.to_owned(),
+  };
+  Ok(Page {
+    title: title,
+    content: content,
+  })
+}
+
+// a new feed for the page, with its current version as the first item
+pub fn insert(url: String, page: Page) -> Result<(i32, Option<Vec<i32>>), HermesError> {
+  let feed = db::insert_channel(NewFeed {
+    title: page.title.clone(),
+    description: None,
+    site_link: url.clone(),
+    feed_link: url.clone(),
+    updated_at: Utc::now(),
+  })?;
+  rt::spawn(icons::discover(feed.id, url.clone()));
+  let item = db::record_page_change(&new_item(feed.id, &url, page))?;
+  let item_ids = vec![item.id];
+  topics::match_items(feed.id, &vec![item]);
+  Ok((feed.id, Some(item_ids)))
+}
+
+// the item for a change since the snapshot, None when there was none worth one
+pub fn check(feed_id: i32, url: &str, body: &[u8]) -> Result<Option<Item>, HermesError> {
+  let page = extract(body, url)?;
+  let snapshot = db::get_page_snapshot(feed_id)?;
+  let alike = similarity(&snapshot, &page.content);
+  if alike >= *SIMILARITY {
+    debug!("page '{}' is {:.2} alike, not changed", feed_id, alike);
+    return Ok(None);
+  }
+  debug!("page '{}' is {:.2} alike, changed", feed_id, alike);
+  db::record_page_change(&new_item(feed_id, url, page)).map(Some)
+}
+
+fn new_item(feed_id: i32, url: &str, page: Page) -> NewItem {
+  let now = Utc::now();
+  let content = Some(page.content);
+  NewItem {
+    guid: format!("{}#{}", url, now.timestamp()),
+    link: url.to_owned(),
+    content_hash: Some(NewItem::content_hash(&page.title, &None, &content)),
+    title: page.title,
+    summary: None,
+    content: content,
+    published_at: Some(now),
+    updated_at: Some(now),
+    feed_id: feed_id,
+    fingerprint: None,
+  }
+}
+
+// the share of words both texts have, from 0 to 1; order is left out, so
+// moving a paragraph around isn't a change
+fn similarity(old: &str, new: &str) -> f64 {
+  let old = words(old);
+  let new = words(new);
+  if old.is_empty() && new.is_empty() {
+    return 1.0;
+  }
+  let mut counts: HashMap<&str, usize> = HashMap::new();
+  for word in old.iter() {
+    *counts.entry(word.as_str()).or_insert(0) += 1;
+  }
+  let mut shared = 0;
+  for word in new.iter() {
+    if let Some(count) = counts.get_mut(word.as_str()) {
+      if *count > 0 {
+        *count -= 1;
+        shared += 1;
+      }
+    }
+  }
+  2.0 * shared as f64 / (old.len() + new.len()) as f64
+}
+
+fn words(html: &str) -> Vec<String> {
+  WORD
+    .find_iter(&TAG.replace_all(html, " "))
+    .map(|w| w.as_str().to_lowercase())
+    .collect()
+}
@@ -0,0 +1,53 @@
+use std::env;
+use url::Url;
+
+use feed::FeedType;
+
+// Feed paging and archives (RFC 5005). A feed added with a `prev-archive`
+// link has its archive read backwards, a page at a time, for up to
+//
+// ARCHIVE_PAGES  archive documents read when a feed is added, 10 if unset;
+//                0 reads none
+//
+// and a fetch whose items are all new follows the feed's `next` link, in
+// case more were posted than fit on one page, for up to
+//
+// NEXT_PAGES     pages followed after such a fetch, 5 if unset; 0 follows none
+//
+// Both stop at a page without new items. The links are read from Atom feeds
+// and from `atom:link` elements in RSS ones.
+
+pub static PREV_ARCHIVE: &'static str = "prev-archive";
+pub static NEXT: &'static str = "next";
+
+lazy_static! {
+  pub static ref ARCHIVE_PAGES: usize = env::var("ARCHIVE_PAGES")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(10);
+  pub static ref NEXT_PAGES: usize = env::var("NEXT_PAGES")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(5);
+}
+
+// the feed's link with `rel`, resolved against `base`
+pub fn link(parsed: &FeedType, base: &str, rel: &str) -> Option<String> {
+  let href = match *parsed {
+    FeedType::Atom(ref feed) => feed
+      .links()
+      .iter()
+      .find(|l| l.rel() == rel)
+      .map(|l| l.href().to_owned()),
+    // any namespace prefix will do, they are the document's choice
+    FeedType::RSS(ref channel) => channel
+      .extensions()
+      .values()
+      .filter_map(|elements| elements.get("link"))
+      .flat_map(|links| links.iter())
+      .find(|l| l.attrs().get("rel").map(|r| r.as_str()) == Some(rel))
+      .and_then(|l| l.attrs().get("href").cloned()),
+    FeedType::Page(_) => None,
+  }?;
+  Url::parse(base).ok()?.join(&href).ok().map(|u| u.to_string())
+}
@@ -0,0 +1,229 @@
+use base64;
+use chrono::{Duration as ChronoDuration, Utc};
+use hyper::rt::{self, Future};
+use jsonwebtoken::{encode, Algorithm, Header};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use std::env;
+use std::sync::RwLock;
+use url::Url;
+
+use db;
+use deliveries::{self, Delivery};
+use error::HermesError;
+use models::Item;
+
+// Web Push, for the UI's service worker to show notifications when feeds the
+// user asked for (`POST /api/feed/:id/push`) get new items. The pushes carry
+// no payload, as the message encryption of RFC 8291 isn't implemented; the
+// worker has to ask the API for what's new when one arrives. They are sent
+// through `deliveries`.
+//
+// Push services want to know who is sending, which is done with VAPID: each
+// push has a JWT signed with the instance's P-256 key, whose public half the
+// UI passes to `PushManager.subscribe()`.
+//
+// VAPID_PRIVATE_KEY  base64 of the PKCS#8 key; one is made and stored in the
+//                    settings when unset
+// VAPID_SUBJECT      how push services can reach the admin, a `mailto:` or
+//                    `https:` URL
+
+// pushes the service can't deliver within a day are dropped
+static TTL_SECS: i64 = 24 * 3600;
+static JWT_TTL_HOURS: i64 = 12;
+// an uncompressed P-256 point
+static PUBLIC_KEY_LEN: usize = 65;
+
+lazy_static! {
+  static ref SUBJECT: Option<String> = env::var("VAPID_SUBJECT").ok();
+  // the PKCS#8 key and its public half
+  static ref KEYS: RwLock<Option<(Vec<u8>, Vec<u8>)>> = RwLock::new(None);
+}
+
+#[derive(Serialize)]
+struct VapidClaims<'a> {
+  aud: String,
+  exp: i64,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  sub: Option<&'a str>,
+}
+
+// Reads the DER element at the start of `der`: its tag, its contents and what
+// follows it. Lengths of up to two bytes are enough for a key.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+  let tag = *der.get(0)?;
+  let (len, start) = match *der.get(1)? {
+    len if len < 0x80 => (len as usize, 2),
+    0x81 => (*der.get(2)? as usize, 3),
+    0x82 => ((*der.get(2)? as usize) << 8 | *der.get(3)? as usize, 4),
+    _ => return None,
+  };
+  if der.len() < start + len {
+    return None;
+  }
+  Some((tag, &der[start..start + len], &der[start + len..]))
+}
+
+// The public key is the `publicKey` field of the ECPrivateKey (RFC 5915) in
+// the PKCS#8 document, which ring wants for signing anyway.
+fn public_from_pkcs8(pkcs8: &[u8]) -> Option<Vec<u8>> {
+  let (tag, info, _) = der_element(pkcs8)?;
+  if tag != 0x30 {
+    return None;
+  }
+  // past the version and the algorithm to the wrapped key
+  let (_, _, rest) = der_element(info)?;
+  let (_, _, rest) = der_element(rest)?;
+  let (tag, wrapped, _) = der_element(rest)?;
+  if tag != 0x04 {
+    return None;
+  }
+  let (tag, ec_key, _) = der_element(wrapped)?;
+  if tag != 0x30 {
+    return None;
+  }
+  // past the version, then the private key and the optional parameters
+  let (_, _, mut fields) = der_element(ec_key)?;
+  while !fields.is_empty() {
+    let (tag, contents, rest) = der_element(fields)?;
+    if tag == 0xa1 {
+      // a BIT STRING, whose first byte counts the unused bits
+      let (tag, bits, _) = der_element(contents)?;
+      return match tag == 0x03 && bits.len() == PUBLIC_KEY_LEN + 1 && bits[..2] == [0, 4] {
+        true => Some(bits[1..].to_vec()),
+        false => None,
+      };
+    }
+    fields = rest;
+  }
+  None
+}
+
+// the PKCS#8 key, from the environment, the settings or made now, and its
+// public half
+fn keys() -> Result<(Vec<u8>, Vec<u8>), HermesError> {
+  if let Some(ref keys) = *KEYS.read().unwrap() {
+    return Ok(keys.clone());
+  }
+  let encoded = match env::var("VAPID_PRIVATE_KEY").ok() {
+    Some(key) => key,
+    None => match db::get_setting(db::VAPID_KEY_SETTING)? {
+      Some(key) => key,
+      None => {
+        let rng = SystemRandom::new();
+        let made = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng)
+          .map_err(|_| HermesError::Auth("could not make a VAPID key".into()))?;
+        info!("made a VAPID key for Web Push");
+        db::add_setting(db::VAPID_KEY_SETTING, &base64::encode(made.as_ref()))?
+      }
+    },
+  };
+  let key = base64::decode(encoded.trim())
+    .map_err(|e| HermesError::Parse(format!("VAPID key: {}", e)))?;
+  let public = public_from_pkcs8(&key)
+    .ok_or_else(|| HermesError::Parse("VAPID key is not a P-256 key with its public key".into()))?;
+  *KEYS.write().unwrap() = Some((key.clone(), public.clone()));
+  Ok((key, public))
+}
+
+// what the UI subscribes with, base64url as `PushManager.subscribe()` takes it
+pub fn public_key() -> Result<String, HermesError> {
+  let (_, public) = keys()?;
+  Ok(base64::encode_config(&public, base64::URL_SAFE_NO_PAD))
+}
+
+fn delivery(endpoint: &str) -> Result<Delivery, HermesError> {
+  let audience = Url::parse(endpoint)
+    .map_err(|e| HermesError::Parse(format!("push endpoint '{}': {}", endpoint, e)))?
+    .origin()
+    .ascii_serialization();
+  let claims = VapidClaims {
+    aud: audience,
+    exp: (Utc::now() + ChronoDuration::hours(JWT_TTL_HOURS)).timestamp(),
+    sub: SUBJECT.as_ref().map(|s| s.as_str()),
+  };
+  let (private, _) = keys()?;
+  let jwt = encode(&Header::new(Algorithm::ES256), &claims, &private)
+    .map_err(|e| HermesError::Auth(format!("could not sign VAPID token: {}", e)))?;
+  Ok(Delivery {
+    kind: "push",
+    target: endpoint.to_owned(),
+    headers: vec![
+      (
+        "authorization".to_owned(),
+        format!("vapid t={}, k={}", jwt, public_key()?),
+      ),
+      ("ttl".to_owned(), TTL_SECS.to_string()),
+      ("urgency".to_owned(), "high".to_owned()),
+    ],
+    payload: String::new(),
+  })
+}
+
+// Pushes to the browsers of the subscribers who want to hear about the feed.
+pub fn notify_new(feed_id: i32, items: &[Item]) {
+  if items.is_empty() {
+    return;
+  }
+  let item_ids = items.iter().map(|i| i.id).collect::<Vec<i32>>();
+  let targets = match db::get_push_targets(feed_id, &item_ids) {
+    Ok(targets) => targets,
+    Err(e) => {
+      error!("could not find who to push feed {} to: {}", feed_id, e);
+      return;
+    }
+  };
+  for target in targets {
+    match delivery(&target.endpoint) {
+      Ok(delivery) => rt::spawn(deliveries::deliver(target.user_id, delivery).map(|_| ())),
+      Err(e) => error!("could not push to user {}: {}", target.user_id, e),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::public_from_pkcs8;
+  use base64;
+
+  // a throwaway key, as `openssl pkcs8 -topk8 -nocrypt` writes it
+  static PKCS8: &'static str = "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg9rXpMDdyDZHoDRKoG\
+    nvGtGOqu/Q5hGu01aKOwhw8wK+hRANCAARHuOoITXUWZduwRWLig+xoqpLBJjGE5nsUNgXEBVBme2j4PEydZrCRReatiVj5\
+    C7WkTZvPW4t94OrpYKTz4vi0";
+  // the same with an empty set of attributes after the key
+  static WITH_ATTRIBUTES: &'static str = "MIGJAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg9rXpMDd\
+    yDZHoDRKoGnvGtGOqu/Q5hGu01aKOwhw8wK+hRANCAARHuOoITXUWZduwRWLig+xoqpLBJjGE5nsUNgXEBVBme2j4PEyd\
+    ZrCRReatiVj5C7WkTZvPW4t94OrpYKTz4vi0oAA=";
+  // the same without the public key
+  static WITHOUT_PUBLIC: &'static str = "MEECAQAwEwYHKoZIzj0CAQYIKoZIzj0DAQcEJzAlAgEBBCD2tekwN3INk\
+    egNEqgae8a0Y6q79DmEa7TVoo7CHDzArw==";
+  static PUBLIC: &'static str = "BEe46ghNdRZl27BFYuKD7GiqksEmMYTmexQ2BcQFUGZ7aPg8TJ1msJFF5q2JWPkL\
+    taRNm89bi33g6ulgpPPi+LQ=";
+
+  fn public(pkcs8: &str) -> Option<String> {
+    public_from_pkcs8(&base64::decode(pkcs8).unwrap()).map(|p| base64::encode(&p))
+  }
+
+  #[test]
+  fn reads_the_public_key() {
+    assert_eq!(public(PKCS8), Some(PUBLIC.to_owned()));
+  }
+
+  #[test]
+  fn reads_the_public_key_before_other_fields() {
+    assert_eq!(public(WITH_ATTRIBUTES), Some(PUBLIC.to_owned()));
+  }
+
+  #[test]
+  fn needs_the_public_key() {
+    assert_eq!(public(WITHOUT_PUBLIC), None);
+  }
+
+  #[test]
+  fn rejects_what_is_not_a_key() {
+    assert_eq!(public_from_pkcs8(&[]), None);
+    assert_eq!(public_from_pkcs8(&[0x30, 0x05, 0x02, 0x01]), None);
+    let truncated = base64::decode(PKCS8).unwrap();
+    assert_eq!(public_from_pkcs8(&truncated[..truncated.len() - 1]), None);
+  }
+}
@@ -0,0 +1,88 @@
+use db;
+use error::HermesError;
+
+// Limits on what each user can have, kept in `quotas`: a row without a user
+// is everyone's default, and one with a user overrides it for them. Without
+// either, or with a NULL limit, there is none, which is how a new instance
+// starts out. Going over a limit answers 403 `quota_exceeded`.
+//
+// Archives are counted by the feature storing them; until that exists their
+// usage is 0.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+  Feeds,
+  Filters,
+  Webhooks,
+  ApiTokens,
+  ArchiveBytes,
+}
+impl Resource {
+  pub fn all() -> [Resource; 5] {
+    [
+      Resource::Feeds,
+      Resource::Filters,
+      Resource::Webhooks,
+      Resource::ApiTokens,
+      Resource::ArchiveBytes,
+    ]
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      Resource::Feeds => "feeds",
+      Resource::Filters => "filters",
+      Resource::Webhooks => "webhooks",
+      Resource::ApiTokens => "api_tokens",
+      Resource::ArchiveBytes => "archive_bytes",
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Resource> {
+    Resource::all().iter().cloned().find(|r| r.name() == name)
+  }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Usage {
+  pub name: &'static str,
+  pub used: i64,
+  pub limit: Option<i64>,
+}
+
+fn used(uid: i32, resource: Resource) -> Result<i64, HermesError> {
+  match resource {
+    Resource::Feeds => db::count_subscriptions(uid),
+    Resource::Filters => db::count_filters(uid),
+    Resource::ApiTokens => db::count_api_tokens(uid),
+    Resource::Webhooks => db::count_webhooks(uid),
+    Resource::ArchiveBytes => Ok(0),
+  }
+}
+
+// Err(QuotaExceeded) when `adding` more would take the user over the limit
+pub fn check(uid: i32, resource: Resource, adding: i64) -> Result<(), HermesError> {
+  let max = match db::get_quota(uid, resource.name())? {
+    Some(max) => max,
+    None => return Ok(()),
+  };
+  match used(uid, resource)? + adding > max {
+    true => {
+      debug!("user {} is at their {} quota of {}", uid, resource.name(), max);
+      Err(HermesError::QuotaExceeded(resource.name(), max))
+    }
+    false => Ok(()),
+  }
+}
+
+pub fn usage(uid: i32) -> Result<Vec<Usage>, HermesError> {
+  Resource::all()
+    .iter()
+    .map(|&resource| {
+      Ok(Usage {
+        name: resource.name(),
+        used: used(uid, resource)?,
+        limit: db::get_quota(uid, resource.name())?,
+      })
+    }).collect()
+}
@@ -0,0 +1,46 @@
+use chrono::{Duration, Utc};
+use std::env;
+
+use db;
+
+// Old items are deleted by the cleanup when either is set:
+//
+// ITEM_RETENTION_COUNT  the newest this many items of each feed are kept
+// ITEM_RETENTION_DAYS   items published in the last this many days are kept
+//
+// With both, an item goes once it is neither among the newest nor recent.
+// Starred and shared items are always kept, as are the items of feeds a
+// subscriber exempted, by feed or by one of their tags (folders are tags).
+// Items are shared between users, so one exemption keeps them for everyone.
+// Undated items are only counted against ITEM_RETENTION_COUNT. Pruned items
+// are remembered like purged ones, so a feed still listing them does not
+// bring them back.
+
+static BATCH_SIZE: usize = 500;
+
+lazy_static! {
+  static ref KEEP_COUNT: Option<i64> = env::var("ITEM_RETENTION_COUNT")
+    .ok()
+    .and_then(|n| n.parse().ok())
+    .filter(|n| *n > 0);
+  static ref KEEP_DAYS: Option<i64> = env::var("ITEM_RETENTION_DAYS")
+    .ok()
+    .and_then(|d| d.parse().ok())
+    .filter(|d| *d > 0);
+}
+
+pub fn enabled() -> bool {
+  KEEP_COUNT.is_some() || KEEP_DAYS.is_some()
+}
+
+pub fn prune() {
+  if !enabled() {
+    return;
+  }
+  let older_than = KEEP_DAYS.map(|d| Utc::now() - Duration::days(d));
+  match db::prune_items(*KEEP_COUNT, older_than, BATCH_SIZE) {
+    Ok(0) => (),
+    Ok(n) => info!("pruned {} old items", n),
+    Err(e) => error!("could not prune items: {}", e),
+  };
+}
@@ -0,0 +1,79 @@
+use ammonia::{Builder, UrlRelative};
+use std::collections::HashSet;
+use std::env;
+use url::Url;
+
+use db;
+use error::HermesError;
+
+static BACKFILL_BATCH: i64 = 500;
+
+lazy_static! {
+  // SANITIZE_TAGS=p,a,img,... replaces ammonia's default tag allowlist
+  static ref ALLOWED_TAGS: Option<Vec<String>> = from_env("SANITIZE_TAGS");
+  // SANITIZE_ATTRIBUTES=title,lang,... replaces the attributes allowed on any tag
+  static ref ALLOWED_ATTRIBUTES: Option<Vec<String>> = from_env("SANITIZE_ATTRIBUTES");
+}
+
+fn from_env(name: &str) -> Option<Vec<String>> {
+  env::var(name).ok().map(|v| {
+    v.split(',')
+      .map(|s| s.trim().to_lowercase())
+      .filter(|s| !s.is_empty())
+      .collect()
+  })
+}
+
+// Item HTML comes from whoever runs the feed, so it is cleaned before it is
+// stored. Relative links and images are resolved against the feed's site.
+pub fn clean(html: &str, base: Option<&Url>) -> String {
+  let mut builder = Builder::default();
+  if let Some(ref tags) = *ALLOWED_TAGS {
+    builder.tags(tags.iter().map(|t| t.as_str()).collect::<HashSet<_>>());
+  }
+  if let Some(ref attributes) = *ALLOWED_ATTRIBUTES {
+    builder.generic_attributes(attributes.iter().map(|a| a.as_str()).collect::<HashSet<_>>());
+  }
+  match base {
+    Some(base) => builder.url_relative(UrlRelative::RewriteWithBase(base.clone())),
+    None => builder.url_relative(UrlRelative::Deny),
+  };
+  builder.clean(html).to_string()
+}
+
+// Items stored before `clean` was applied are cleaned at startup, from the
+// newest down. The migration leaves the bound to go below in the settings;
+// it is lowered after each batch, so a restart picks up where it stopped.
+pub fn backfill() -> Result<usize, HermesError> {
+  let bound = db::get_setting(db::UNSANITIZED_ITEMS_SETTING)?.and_then(|b| b.parse().ok());
+  let mut below = match bound {
+    Some(below) => below,
+    None => return Ok(0),
+  };
+  info!("sanitizing the HTML of items stored before it was");
+  let mut cleaned = 0;
+  loop {
+    let batch = db::get_item_html_before(below, BACKFILL_BATCH)?;
+    let lowest = match batch.last() {
+      Some(&(id, _, _, _)) => id,
+      None => break,
+    };
+    for (id, summary, content, site_link) in batch {
+      let base = Url::parse(&site_link).ok();
+      let clean_summary = summary.as_ref().map(|s| clean(s, base.as_ref()));
+      let clean_content = content.as_ref().map(|c| clean(c, base.as_ref()));
+      if clean_summary != summary || clean_content != content {
+        cleaned += db::set_item_html(
+          id,
+          clean_summary.as_ref().map(|s| s.as_str()),
+          clean_content.as_ref().map(|c| c.as_str()),
+        )?;
+      }
+    }
+    below = lowest;
+    db::set_unsanitized_items_below(Some(below))?;
+    debug!("sanitized items down to {}", below);
+  }
+  db::set_unsanitized_items_below(None)?;
+  Ok(cleaned)
+}
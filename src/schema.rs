@@ -1,3 +1,108 @@
+table! {
+    api_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        token_hash -> Varchar,
+        created_at -> Timestamptz,
+        last_used_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    activity_log (id) {
+        id -> Int4,
+        user_id -> Int4,
+        action -> Varchar,
+        detail -> Nullable<Text>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    content_retries (item_id) {
+        item_id -> Int4,
+        attempts -> Int4,
+        last_error -> Text,
+        next_attempt_at -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+    }
+}
+
+table! {
+    deleted_items (id) {
+        id -> Int4,
+        item_id -> Int4,
+        feed_id -> Int4,
+        guid -> Varchar,
+        user_id -> Nullable<Int4>,
+        deleted_at -> Timestamptz,
+    }
+}
+
+table! {
+    enclosures (id) {
+        id -> Int4,
+        item_id -> Int4,
+        url -> Varchar,
+        mime_type -> Nullable<Varchar>,
+        length -> Nullable<Int8>,
+    }
+}
+
+table! {
+    events (id) {
+        id -> Int4,
+        feed_id -> Int4,
+        item_ids -> Array<Int4>,
+        user_ids -> Array<Int4>,
+        created_at -> Timestamptz,
+        topic_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    failed_deliveries (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> Varchar,
+        target -> Varchar,
+        headers -> Text,
+        payload -> Text,
+        error -> Text,
+        attempts -> Int4,
+        failed_at -> Timestamptz,
+    }
+}
+
+table! {
+    feed_icons (feed_id) {
+        feed_id -> Int4,
+        content_type -> Varchar,
+        data -> Bytea,
+        fetched_at -> Timestamptz,
+    }
+}
+
+table! {
+    feed_rates (feed_id) {
+        feed_id -> Int4,
+        items_per_day -> Float8,
+        day -> Date,
+    }
+}
+
+table! {
+    feed_stats (feed_id, day) {
+        feed_id -> Int4,
+        day -> Date,
+        items -> Int4,
+        bytes -> Int8,
+        fetches -> Int4,
+        failures -> Int4,
+    }
+}
+
 table! {
     feeds (id) {
         id -> Int4,
@@ -6,6 +111,59 @@ table! {
         site_link -> Varchar,
         feed_link -> Varchar,
         updated_at -> Timestamptz,
+        leased_until -> Nullable<Timestamptz>,
+        parse_warnings -> Nullable<Text>,
+        fetch_full_content -> Bool,
+        last_fetch_status -> Nullable<Int4>,
+        consecutive_failure_count -> Int4,
+        last_error -> Nullable<Text>,
+    }
+}
+
+table! {
+    filters (id) {
+        id -> Int4,
+        user_id -> Int4,
+        feed_id -> Nullable<Int4>,
+        pattern -> Varchar,
+        match_field -> Varchar,
+        action -> Varchar,
+        enabled -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    invites (id) {
+        id -> Int4,
+        code -> Varchar,
+        created_by -> Int4,
+        used_by -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    item_shares (id) {
+        id -> Int4,
+        user_id -> Int4,
+        item_id -> Int4,
+        token -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    item_state_log (id) {
+        id -> Int8,
+        user_id -> Int4,
+        item_id -> Int4,
+        change -> Varchar,
+        seen -> Nullable<Bool>,
+        starred -> Nullable<Bool>,
+        was_seen -> Nullable<Bool>,
+        was_starred -> Nullable<Bool>,
+        changed_at -> Timestamptz,
     }
 }
 
@@ -20,6 +178,134 @@ table! {
         published_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
         feed_id -> Int4,
+        uuid -> Varchar,
+        fingerprint -> Nullable<Varchar>,
+        content_hash -> Nullable<Varchar>,
+        edited_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    job_leases (name) {
+        name -> Varchar,
+        leased_until -> Timestamptz,
+    }
+}
+
+table! {
+    kiosk_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        tag -> Nullable<Varchar>,
+        feed_id -> Nullable<Int4>,
+        item_limit -> Int4,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    media (url) {
+        url -> Varchar,
+        hash -> Varchar,
+        last_used_at -> Timestamptz,
+    }
+}
+
+table! {
+    media_blobs (hash) {
+        hash -> Varchar,
+        content_type -> Varchar,
+        data -> Bytea,
+        size -> Int8,
+        refs -> Int4,
+        last_used_at -> Timestamptz,
+    }
+}
+
+table! {
+    mutes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        feed_id -> Nullable<Int4>,
+        phrase -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    notifications (id) {
+        id -> Int4,
+        user_id -> Int4,
+        kind -> Varchar,
+        message -> Text,
+        detail -> Nullable<Text>,
+        seen -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    page_snapshots (feed_id) {
+        feed_id -> Int4,
+        content -> Text,
+        changed_at -> Timestamptz,
+    }
+}
+
+table! {
+    push_subscriptions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        endpoint -> Varchar,
+        p256dh -> Varchar,
+        auth -> Varchar,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    quotas (id) {
+        id -> Int4,
+        user_id -> Nullable<Int4>,
+        name -> Varchar,
+        max_value -> Nullable<Int8>,
+    }
+}
+
+table! {
+    retention_exemptions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        tag -> Nullable<Varchar>,
+        feed_id -> Nullable<Int4>,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    revoked_tokens (id) {
+        id -> Int4,
+        jti -> Varchar,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    sessions (id) {
+        id -> Int4,
+        user_id -> Int4,
+        jti -> Varchar,
+        created_at -> Timestamptz,
+        expires_at -> Timestamptz,
+    }
+}
+
+table! {
+    settings (name) {
+        name -> Varchar,
+        value -> Varchar,
     }
 }
 
@@ -28,6 +314,11 @@ table! {
         id -> Int4,
         user_id -> Int4,
         feed_id -> Int4,
+        refresh_window -> Nullable<Varchar>,
+        unread_on_edit -> Bool,
+        pinned -> Bool,
+        sort_order -> Nullable<Int4>,
+        push_notify -> Bool,
     }
 }
 
@@ -37,6 +328,45 @@ table! {
         user_id -> Int4,
         item_id -> Int4,
         seen -> Bool,
+        starred -> Bool,
+        state_changed_at -> Nullable<Timestamptz>,
+        suppressed -> Bool,
+    }
+}
+
+table! {
+    subscription_tags (id) {
+        id -> Int4,
+        subscribed_feed_id -> Int4,
+        tag -> Varchar,
+    }
+}
+
+table! {
+    topic_items (id) {
+        id -> Int4,
+        topic_id -> Int4,
+        item_id -> Int4,
+        matched_at -> Timestamptz,
+    }
+}
+
+table! {
+    topics (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        keywords -> Array<Text>,
+        notify -> Bool,
+        created_at -> Timestamptz,
+    }
+}
+
+table! {
+    unread_counts (user_id, feed_id) {
+        user_id -> Int4,
+        feed_id -> Int4,
+        unseen_count -> Int4,
     }
 }
 
@@ -45,19 +375,129 @@ table! {
         id -> Int4,
         username -> Varchar,
         password_hash -> Bytea,
+        approved -> Bool,
+        fever_key_hash -> Nullable<Varchar>,
+        locale -> Varchar,
+        rate_warning -> Float8,
+        auto_read_days -> Nullable<Int4>,
+        is_admin -> Bool,
+        disabled -> Bool,
+    }
+}
+
+table! {
+    webhook_deliveries (id) {
+        id -> Int4,
+        webhook_id -> Int4,
+        item_id -> Int4,
+        attempts -> Int4,
+        error -> Nullable<Text>,
+        finished_at -> Timestamptz,
+    }
+}
+
+table! {
+    websub_subscriptions (feed_id) {
+        feed_id -> Int4,
+        hub -> Varchar,
+        topic -> Varchar,
+        token -> Varchar,
+        secret -> Varchar,
+        requested_at -> Timestamptz,
+        expires_at -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    webhooks (id) {
+        id -> Int4,
+        user_id -> Int4,
+        url -> Varchar,
+        feed_id -> Nullable<Int4>,
+        secret -> Varchar,
+        created_at -> Timestamptz,
     }
 }
 
+joinable!(activity_log -> users (user_id));
+joinable!(api_tokens -> users (user_id));
+joinable!(content_retries -> items (item_id));
+joinable!(deleted_items -> feeds (feed_id));
+joinable!(deleted_items -> users (user_id));
+joinable!(enclosures -> items (item_id));
+joinable!(events -> feeds (feed_id));
+joinable!(failed_deliveries -> users (user_id));
+joinable!(feed_icons -> feeds (feed_id));
+joinable!(feed_rates -> feeds (feed_id));
+joinable!(feed_stats -> feeds (feed_id));
+joinable!(filters -> feeds (feed_id));
+joinable!(filters -> users (user_id));
+joinable!(item_shares -> items (item_id));
+joinable!(item_shares -> users (user_id));
 joinable!(items -> feeds (feed_id));
+joinable!(kiosk_tokens -> feeds (feed_id));
+joinable!(kiosk_tokens -> users (user_id));
+joinable!(media -> media_blobs (hash));
+joinable!(mutes -> feeds (feed_id));
+joinable!(mutes -> users (user_id));
+joinable!(notifications -> users (user_id));
+joinable!(page_snapshots -> feeds (feed_id));
+joinable!(push_subscriptions -> users (user_id));
+joinable!(quotas -> users (user_id));
+joinable!(retention_exemptions -> feeds (feed_id));
+joinable!(retention_exemptions -> users (user_id));
+joinable!(sessions -> users (user_id));
 joinable!(subscribed_feeds -> feeds (feed_id));
 joinable!(subscribed_feeds -> users (user_id));
 joinable!(subscribed_items -> items (item_id));
 joinable!(subscribed_items -> users (user_id));
+joinable!(subscription_tags -> subscribed_feeds (subscribed_feed_id));
+joinable!(topic_items -> items (item_id));
+joinable!(topic_items -> topics (topic_id));
+joinable!(topics -> users (user_id));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+joinable!(webhooks -> feeds (feed_id));
+joinable!(webhooks -> users (user_id));
+joinable!(websub_subscriptions -> feeds (feed_id));
 
 allow_tables_to_appear_in_same_query!(
+    activity_log,
+    api_tokens,
+    content_retries,
+    deleted_items,
+    enclosures,
+    events,
+    failed_deliveries,
+    feed_icons,
+    feed_rates,
+    feed_stats,
     feeds,
+    filters,
+    invites,
+    item_shares,
+    item_state_log,
     items,
+    job_leases,
+    kiosk_tokens,
+    media,
+    media_blobs,
+    mutes,
+    notifications,
+    page_snapshots,
+    push_subscriptions,
+    quotas,
+    retention_exemptions,
+    revoked_tokens,
+    sessions,
+    settings,
     subscribed_feeds,
     subscribed_items,
+    subscription_tags,
+    topic_items,
+    topics,
+    unread_counts,
     users,
+    webhook_deliveries,
+    webhooks,
+    websub_subscriptions,
 );
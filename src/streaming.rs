@@ -0,0 +1,144 @@
+use atom_syndication;
+use quick_xml::events::Event;
+use quick_xml::Reader;
+use rss;
+use std::env;
+use std::io::BufReader;
+
+use error::HermesError;
+use feed::FeedType;
+
+// Big feeds are not handed to the parsers whole. A pass over the XML finds
+// where each item is, then every item is read on its own, wrapped in the
+// channel's elements from before the first one. This does not save memory:
+// the body is buffered in full and the parsed items are all collected before
+// they are stored, so MAX_FEED_BYTES is what bounds a fetch.
+//
+// STREAM_PARSE_BYTES  documents this big or bigger are read an item at a
+//                     time, 1 MB if unset
+// MAX_FEED_BYTES      fetches of bigger documents stop and fail, 10 MB if
+//                     unset; the error shows up with the feed like any other
+
+lazy_static! {
+  static ref STREAM_PARSE_BYTES: usize = env::var("STREAM_PARSE_BYTES")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(1024 * 1024);
+  pub static ref MAX_FEED_BYTES: usize = env::var("MAX_FEED_BYTES")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(10 * 1024 * 1024);
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+  Rss,
+  Atom,
+}
+impl Format {
+  fn item_depth(&self) -> usize {
+    match *self {
+      // <rss><channel><item>
+      Format::Rss => 3,
+      // <feed><entry>
+      Format::Atom => 2,
+    }
+  }
+
+  fn is_item(&self, name: &[u8]) -> bool {
+    match *self {
+      Format::Rss => name == b"item",
+      Format::Atom => name == b"entry",
+    }
+  }
+
+  fn closing(&self) -> &'static str {
+    match *self {
+      Format::Rss => "</channel></rss>",
+      Format::Atom => "</feed>",
+    }
+  }
+}
+
+pub fn wanted(document: &[u8]) -> bool {
+  document.len() >= *STREAM_PARSE_BYTES
+}
+
+pub fn parse_rss(text: &str) -> Result<FeedType, HermesError> {
+  let (header, items) = split(text, Format::Rss)?;
+  let mut channel = parse_channel(&format!("{}{}", header, Format::Rss.closing()))?;
+  let mut parsed = Vec::with_capacity(items.len());
+  for (start, end) in items {
+    let document = format!("{}{}{}", header, &text[start..end], Format::Rss.closing());
+    parsed.extend(parse_channel(&document)?.items().first().cloned());
+  }
+  channel.set_items(parsed);
+  Ok(FeedType::RSS(channel))
+}
+
+pub fn parse_atom(text: &str) -> Result<FeedType, HermesError> {
+  let (header, entries) = split(text, Format::Atom)?;
+  let mut feed = parse_feed(&format!("{}{}", header, Format::Atom.closing()))?;
+  let mut parsed = Vec::with_capacity(entries.len());
+  for (start, end) in entries {
+    let document = format!("{}{}{}", header, &text[start..end], Format::Atom.closing());
+    parsed.extend(parse_feed(&document)?.entries().first().cloned());
+  }
+  feed.set_entries(parsed);
+  Ok(FeedType::Atom(feed))
+}
+
+fn parse_channel(document: &str) -> Result<rss::Channel, HermesError> {
+  rss::Channel::read_from(BufReader::new(document.as_bytes()))
+    .map_err(|e| HermesError::Parse(e.to_string()))
+}
+
+fn parse_feed(document: &str) -> Result<atom_syndication::Feed, HermesError> {
+  atom_syndication::Feed::read_from(BufReader::new(document.as_bytes()))
+    .map_err(|e| HermesError::Parse(e.to_string()))
+}
+
+// everything before the first item, and where each item starts and ends
+fn split(text: &str, format: Format) -> Result<(String, Vec<(usize, usize)>), HermesError> {
+  let mut reader = Reader::from_str(text);
+  let mut buf = Vec::new();
+  let mut depth = 0;
+  let mut first_item = None;
+  let mut item_start = None;
+  let mut items = vec![];
+  loop {
+    let before = reader.buffer_position();
+    match reader.read_event(&mut buf) {
+      Ok(Event::Start(ref e)) => {
+        depth += 1;
+        if depth == format.item_depth() && format.is_item(e.name()) {
+          first_item = first_item.or(Some(before));
+          item_start = Some(before);
+        }
+      }
+      Ok(Event::End(_)) => {
+        if depth == format.item_depth() {
+          if let Some(start) = item_start.take() {
+            items.push((start, reader.buffer_position()));
+          }
+        }
+        depth -= 1;
+      }
+      Ok(Event::Empty(ref e)) => {
+        if depth + 1 == format.item_depth() && format.is_item(e.name()) {
+          first_item = first_item.or(Some(before));
+          items.push((before, reader.buffer_position()));
+        }
+      }
+      Ok(Event::Eof) => break,
+      Err(e) => return Err(HermesError::Parse(e.to_string())),
+      _ => (),
+    }
+    buf.clear();
+  }
+  let header = match first_item {
+    Some(start) => text[..start].to_owned(),
+    None => return Err(HermesError::Parse("no items".to_owned())),
+  };
+  Ok((header, items))
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+
+use db::{get_all_topics, get_topic_items, insert_topic_items};
+use error::HermesError;
+use events::publish_topic_match;
+use models::{Item, TimelineDay, Topic};
+
+static TIMELINE_MAX_ITEMS: i64 = 500;
+
+// Called with freshly inserted items from any feed on the instance, not only
+// the ones the topic owner is subscribed to.
+pub fn match_items(feed_id: i32, items: &Vec<Item>) {
+  let topics = match get_all_topics() {
+    Ok(topics) => topics,
+    Err(e) => {
+      error!("could not load topics: {}", e);
+      return;
+    }
+  };
+  if topics.is_empty() || items.is_empty() {
+    return;
+  }
+  let mut matches = vec![];
+  for topic in topics.iter() {
+    let item_ids: Vec<i32> = items
+      .iter()
+      .filter(|i| topic.matches(i))
+      .map(|i| i.id)
+      .collect();
+    if item_ids.is_empty() {
+      continue;
+    }
+    debug!("topic '{}' matched {} items", topic.id, item_ids.len());
+    if topic.notify {
+      publish_topic_match(topic, feed_id, &item_ids);
+    }
+    matches.extend(item_ids.into_iter().map(|iid| (topic.id, iid)));
+  }
+  if !matches.is_empty() {
+    match insert_topic_items(&matches) {
+      Ok(n) => debug!("matched {} topic items", n),
+      Err(e) => error!("could not save topic matches: {}", e),
+    };
+  }
+}
+
+// newest day first, items without a date are placed on the day they matched
+pub fn timeline(topic: &Topic) -> Result<Vec<TimelineDay>, HermesError> {
+  let mut entries: Vec<(DateTime<Utc>, Item)> = get_topic_items(topic.id, TIMELINE_MAX_ITEMS)?
+    .into_iter()
+    .map(|(item, matched_at)| (item.published_at.unwrap_or(matched_at), item))
+    .collect();
+  entries.sort_by(|a, b| b.0.cmp(&a.0));
+
+  let mut days: Vec<TimelineDay> = vec![];
+  for (date, item) in entries {
+    let day = date.format("%Y-%m-%d").to_string();
+    let same_day = match days.last() {
+      Some(d) => d.day == day,
+      None => false,
+    };
+    match same_day {
+      true => days.last_mut().unwrap().items.push(item),
+      false => days.push(TimelineDay {
+        day: day,
+        items: vec![item],
+      }),
+    }
+  }
+  Ok(days)
+}
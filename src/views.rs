@@ -8,6 +8,8 @@ table! {
         updated_at -> Timestamptz,
         user_id -> Int4,
         unseen_count -> Int4,
+        pinned -> Bool,
+        sort_order -> Nullable<Int4>,
     }
 }
 
@@ -22,8 +24,11 @@ table! {
         published_at -> Nullable<Timestamptz>,
         updated_at -> Nullable<Timestamptz>,
         feed_id -> Int4,
+        uuid -> Varchar,
         subscribed_item_id -> Int4,
         user_id -> Int4,
         seen -> Bool,
+        starred -> Bool,
+        edited_at -> Nullable<Timestamptz>,
     }
 }
@@ -0,0 +1,112 @@
+use warp::{self, Filter, Rejection};
+
+use super::handlers::random_token;
+use super::types::ApiTokenParams;
+use super::{request_log, warmup};
+use db;
+use error::HermesError;
+use models::{ApiToken, Claims, IssuedApiToken};
+use quotas::{self, Resource};
+
+// Scripts and other readers log in with a token of their own instead of the
+// user's password, sent as `Authorization: Bearer hermes_...`. Tokens don't
+// expire, they stop working when deleted or when their user is disabled.
+// Changing the password leaves them be. They can't refresh or log out, as
+// they aren't sessions.
+
+static TOKEN_PREFIX: &'static str = "hermes_";
+static TOKEN_BYTES: usize = 32;
+static MAX_NAME_LEN: usize = 100;
+// made per request, like those of proxy users
+static CLAIMS_TTL_SECS: i64 = 60;
+
+// other bearer tokens are JWTs, for `verify_token`
+fn bearer_token(header: &str) -> Option<String> {
+  match header.trim().splitn(2, ' ').nth(1) {
+    Some(token) if header.starts_with("Bearer ") && token.starts_with(TOKEN_PREFIX) => {
+      Some(token.trim().to_owned())
+    }
+    _ => None,
+  }
+}
+
+pub fn auth() -> impl Filter<Extract = (Claims,), Error = Rejection> + Copy {
+  warp::header::<String>("authorization").and_then(|header: String| {
+    let token = match bearer_token(&header) {
+      Some(token) => token,
+      None => return Err(warp::reject()),
+    };
+    let user = db::find_user_by_api_token(&ApiToken::hash(&token)).map_err(|e| match e {
+      HermesError::NotFound => Rejection::from(HermesError::Auth("unknown API token".into())),
+      e => Rejection::from(e),
+    })?;
+    request_log::set_user(user.id);
+    warmup::user_active(user.id);
+    Claims::for_user(&user, CLAIMS_TTL_SECS).ok_or_else(|| warp::reject::server_error())
+  })
+}
+
+pub fn create(claims: Claims, params: ApiTokenParams) -> Result<impl warp::Reply, Rejection> {
+  let name = params.name.trim();
+  if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+    return Err(warp::reject::bad_request());
+  }
+  quotas::check(claims.id, Resource::ApiTokens, 1)?;
+  let token = random_token(TOKEN_BYTES).map_err(|e| {
+    error!("could not read random bytes: {}", e);
+    warp::reject::server_error()
+  })?;
+  let token = format!("{}{}", TOKEN_PREFIX, token);
+  let api_token = db::insert_api_token(claims.id, name, &ApiToken::hash(&token))?;
+  info!("user {} created API token {}", claims.id, api_token.id);
+  Ok(warp::reply::json(&IssuedApiToken {
+    token: token,
+    api_token: api_token,
+  }))
+}
+
+pub fn list(claims: Claims) -> Result<impl warp::Reply, Rejection> {
+  let tokens = db::get_api_tokens(claims.id)?;
+  Ok(warp::reply::json(&tokens))
+}
+
+pub fn revoke(claims: Claims, token_id: i32) -> Result<impl warp::Reply, Rejection> {
+  match db::delete_api_token(token_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete API token {}: {}", token_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::bearer_token;
+  use models::ApiToken;
+
+  #[test]
+  fn takes_api_tokens_from_bearer_headers() {
+    assert_eq!(bearer_token("Bearer hermes_abc"), Some("hermes_abc".to_owned()));
+    assert_eq!(bearer_token("Bearer hermes_abc "), Some("hermes_abc".to_owned()));
+  }
+
+  #[test]
+  fn leaves_other_credentials_alone() {
+    assert_eq!(bearer_token("Bearer eyJhbGciOiJIUzI1NiJ9.e30.sig"), None);
+    assert_eq!(bearer_token("Basic aGVybWVzXzp4"), None);
+    assert_eq!(bearer_token("hermes_abc"), None);
+    assert_eq!(bearer_token("Bearer"), None);
+    assert_eq!(bearer_token(""), None);
+  }
+
+  #[test]
+  fn stores_tokens_hashed() {
+    let hash = ApiToken::hash("hermes_abc");
+    assert_eq!(hash.len(), 64);
+    assert!(!hash.contains("hermes_abc"));
+    assert_eq!(hash, ApiToken::hash("hermes_abc"));
+    assert!(hash != ApiToken::hash("hermes_abd"));
+  }
+}
@@ -0,0 +1,356 @@
+use chrono::{DateTime, Utc};
+use futures::{future, Future};
+use hyper::Body;
+#[cfg(feature = "embed-ui")]
+use include_dir::Dir;
+use md5;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tokio_fs;
+use tokio_io;
+use warp::http::response::Builder;
+use warp::http::{Response, StatusCode};
+use warp::{self, Filter, Rejection};
+
+use super::types::AssetFile;
+
+pub static ASSET_PATH: &'static str = "./ui/dist/static";
+static IMMUTABLE_CACHE: &'static str = "public, max-age=31536000, immutable";
+static REVALIDATE_CACHE: &'static str = "no-cache";
+static HTTP_DATE: &'static str = "%a, %d %b %Y %H:%M:%S GMT";
+
+// Only files found in ASSET_PATH at startup are served, so nothing outside
+// of it can be reached, whatever the request or a symlink says. Built with
+// the `embed-ui` feature, the files are compiled in and ASSET_PATH is unused.
+//
+// `main.1a2b3c4d.js` style names carry a content hash and are cached for good.
+// `x.js.br` and `x.js.gz` next to `x.js` are sent to clients accepting them.
+// The rest is revalidated through `ETag` and `Last-Modified`, and a single
+// byte range can be asked for as long as the file is sent uncompressed.
+
+#[cfg(feature = "embed-ui")]
+static EMBEDDED: Dir = include_dir!("ui/dist/static");
+
+lazy_static! {
+  static ref INDEX: HashMap<String, Asset> = build_index();
+  static ref HASHED_NAME: Regex = Regex::new(r"\.[0-9a-f]{8,}\.[A-Za-z0-9]+$").unwrap();
+}
+
+struct Asset {
+  content_type: &'static str,
+  hashed: bool,
+  identity: Variant,
+  brotli: Option<Variant>,
+  gzip: Option<Variant>,
+}
+
+#[derive(Clone)]
+enum Source {
+  File(PathBuf),
+  Embedded(&'static [u8]),
+}
+
+struct Variant {
+  source: Source,
+  etag: String,
+  last_modified: Option<DateTime<Utc>>,
+}
+
+impl Variant {
+  fn new(source: &Source, suffix: &str) -> Option<Variant> {
+    let (etag, last_modified) = match *source {
+      Source::File(ref path) => {
+        let meta = fs::metadata(path).ok()?;
+        let modified = meta.modified().ok().map(DateTime::<Utc>::from);
+        let stamp = modified.map(|m| m.timestamp()).unwrap_or(0);
+        (format!("\"{:x}-{:x}{}\"", meta.len(), stamp, suffix), modified)
+      }
+      // the build has no useful date, the content is all there is
+      Source::Embedded(data) => (format!("\"{:x}{}\"", md5::compute(data), suffix), None),
+    };
+    Some(Variant {
+      source: source.clone(),
+      etag: etag,
+      last_modified: last_modified,
+    })
+  }
+
+  fn read(&self) -> impl Future<Item = Vec<u8>, Error = io::Error> + Send {
+    match self.source {
+      Source::Embedded(data) => future::Either::A(future::ok(data.to_vec())),
+      Source::File(ref path) => future::Either::B(
+        tokio_fs::file::File::open(path.clone())
+          .and_then(|file| tokio_io::io::read_to_end(file, Vec::new()))
+          .map(|(_, data)| data),
+      ),
+    }
+  }
+}
+
+// the headers a request for an asset is answered by
+pub struct AssetRequest {
+  accept_encoding: Option<String>,
+  range: Option<String>,
+  if_none_match: Option<String>,
+  if_modified_since: Option<String>,
+  if_range: Option<String>,
+}
+
+fn optional_header(
+  name: &'static str,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Copy {
+  warp::header::<String>(name)
+    .map(Some)
+    .or(warp::any().map(|| None))
+    .unify()
+}
+
+pub fn asset_request() -> impl Filter<Extract = (AssetRequest,), Error = Rejection> + Copy {
+  optional_header("accept-encoding")
+    .and(optional_header("range"))
+    .and(optional_header("if-none-match"))
+    .and(optional_header("if-modified-since"))
+    .and(optional_header("if-range"))
+    .map(
+      |accept_encoding, range, if_none_match, if_modified_since, if_range| AssetRequest {
+        accept_encoding: accept_encoding,
+        range: range,
+        if_none_match: if_none_match,
+        if_modified_since: if_modified_since,
+        if_range: if_range,
+      },
+    )
+}
+
+#[cfg(not(feature = "embed-ui"))]
+fn list_files() -> HashMap<String, Source> {
+  let root = match fs::canonicalize(ASSET_PATH) {
+    Ok(root) => root,
+    Err(e) => {
+      error!("no assets at '{}': {}", ASSET_PATH, e);
+      return HashMap::new();
+    }
+  };
+  let entries = match fs::read_dir(&root) {
+    Ok(entries) => entries,
+    Err(e) => {
+      error!("could not list assets: {}", e);
+      return HashMap::new();
+    }
+  };
+  entries
+    .filter_map(|e| e.ok())
+    .filter_map(|e| {
+      let path = fs::canonicalize(e.path()).ok()?;
+      match path.starts_with(&root) && path.is_file() {
+        true => Some((e.file_name().into_string().ok()?, Source::File(path))),
+        false => None,
+      }
+    }).collect()
+}
+
+#[cfg(feature = "embed-ui")]
+fn list_files() -> HashMap<String, Source> {
+  EMBEDDED
+    .files()
+    .iter()
+    .filter_map(|f| {
+      let name = f.path().file_name()?.to_str()?.to_owned();
+      Some((name, Source::Embedded(f.contents())))
+    }).collect()
+}
+
+fn build_index() -> HashMap<String, Asset> {
+  let mut index = HashMap::new();
+  let files = list_files();
+  for (name, source) in files.iter() {
+    if name.ends_with(".br") || name.ends_with(".gz") {
+      continue;
+    }
+    let identity = match Variant::new(source, "") {
+      Some(identity) => identity,
+      None => continue,
+    };
+    let asset = Asset {
+      content_type: content_type(name),
+      hashed: HASHED_NAME.is_match(name),
+      identity: identity,
+      brotli: files
+        .get(&format!("{}.br", name))
+        .and_then(|p| Variant::new(p, "-br")),
+      gzip: files
+        .get(&format!("{}.gz", name))
+        .and_then(|p| Variant::new(p, "-gz")),
+    };
+    index.insert(name.clone(), asset);
+  }
+  info!("serving {} assets", index.len());
+  index
+}
+
+// by extension, the UI build only emits a handful of kinds
+fn content_type(name: &str) -> &'static str {
+  let extension = name.rsplit('.').next().unwrap_or("").to_lowercase();
+  match extension.as_str() {
+    "html" | "htm" => "text/html; charset=utf-8",
+    "js" | "mjs" => "application/javascript; charset=utf-8",
+    "css" => "text/css; charset=utf-8",
+    "json" | "map" => "application/json",
+    "webmanifest" => "application/manifest+json",
+    "txt" => "text/plain; charset=utf-8",
+    "xml" => "application/xml",
+    "svg" => "image/svg+xml",
+    "png" => "image/png",
+    "jpg" | "jpeg" => "image/jpeg",
+    "gif" => "image/gif",
+    "webp" => "image/webp",
+    "ico" => "image/x-icon",
+    "woff" => "font/woff",
+    "woff2" => "font/woff2",
+    "ttf" => "font/ttf",
+    "otf" => "font/otf",
+    "eot" => "application/vnd.ms-fontobject",
+    "wasm" => "application/wasm",
+    _ => "application/octet-stream",
+  }
+}
+
+fn accepts(accept_encoding: &Option<String>, encoding: &str) -> bool {
+  accept_encoding
+    .as_ref()
+    .map(|a| {
+      a.split(',')
+        .filter_map(|e| e.split(';').next())
+        .any(|e| e.trim() == encoding)
+    }).unwrap_or(false)
+}
+
+pub fn etag_matches(header: &str, etag: &str) -> bool {
+  header
+    .split(',')
+    .map(|t| t.trim().trim_left_matches("W/"))
+    .any(|t| t == "*" || t == etag)
+}
+
+fn not_modified_since(header: &str, last_modified: Option<DateTime<Utc>>) -> bool {
+  match (DateTime::parse_from_rfc2822(header), last_modified) {
+    (Ok(since), Some(modified)) => modified.timestamp() <= since.timestamp(),
+    _ => false,
+  }
+}
+
+fn is_fresh(request: &AssetRequest, entry: &Asset, etag: &str) -> bool {
+  match (&request.if_none_match, &request.if_modified_since) {
+    (&Some(ref tags), _) => etag_matches(tags, etag),
+    (&None, &Some(ref since)) => not_modified_since(since, entry.identity.last_modified),
+    _ => false,
+  }
+}
+
+// `bytes=a-b`, `bytes=a-` or `bytes=-n`; several ranges get the whole file
+fn parse_range(header: &str, len: u64) -> Option<Result<(u64, u64), ()>> {
+  let spec = header.trim();
+  if !spec.starts_with("bytes=") || spec.contains(',') {
+    return None;
+  }
+  let mut bounds = spec["bytes=".len()..].splitn(2, '-');
+  let (start, end) = (bounds.next()?.trim(), bounds.next()?.trim());
+  let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+    (Ok(start), Ok(end)) if start <= end => (start, end.min(len.saturating_sub(1))),
+    (Ok(start), Err(_)) if end.is_empty() => (start, len.saturating_sub(1)),
+    (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => {
+      (len.saturating_sub(suffix), len.saturating_sub(1))
+    }
+    _ => return None,
+  };
+  match range.0 < len {
+    true => Some(Ok(range)),
+    false => Some(Err(())),
+  }
+}
+
+pub fn serve_static(
+  asset: AssetFile,
+  request: AssetRequest,
+) -> impl Future<Item = Response<Body>, Error = Rejection> + Send {
+  let entry = match INDEX.get(&asset.0) {
+    Some(entry) => entry,
+    None => return future::Either::A(future::err(warp::reject::not_found())),
+  };
+  let (variant, encoding) = match (&entry.brotli, &entry.gzip) {
+    (&Some(ref brotli), _) if accepts(&request.accept_encoding, "br") => (brotli, Some("br")),
+    (_, &Some(ref gzip)) if accepts(&request.accept_encoding, "gzip") => (gzip, Some("gzip")),
+    _ => (&entry.identity, None),
+  };
+  let cache_control = match entry.hashed {
+    true => IMMUTABLE_CACHE,
+    false => REVALIDATE_CACHE,
+  };
+
+  let mut response = Response::builder();
+  response
+    .header("content-type", entry.content_type)
+    .header("cache-control", cache_control)
+    .header("vary", "accept-encoding")
+    .header("etag", variant.etag.as_str());
+  if let Some(modified) = entry.identity.last_modified {
+    response.header("last-modified", modified.format(HTTP_DATE).to_string());
+  }
+  if is_fresh(&request, entry, &variant.etag) {
+    let reply = response.status(StatusCode::NOT_MODIFIED).body(Body::empty());
+    return future::Either::A(future::ok(reply.unwrap()));
+  }
+  if let Some(encoding) = encoding {
+    response.header("content-encoding", encoding);
+  } else {
+    response.header("accept-ranges", "bytes");
+  }
+
+  // a range only applies to the version the client has, if `If-Range` names one
+  let same_version = request
+    .if_range
+    .as_ref()
+    .map(|tag| tag.trim() == variant.etag)
+    .unwrap_or(true);
+  let range = match encoding {
+    None if same_version => request.range,
+    _ => None,
+  };
+  future::Either::B(
+    variant
+      .read()
+      .map(move |b| respond(response, b, range.as_ref().map(|r| r.as_str())))
+      .or_else(|e| {
+        error!("file open error: {} ", e);
+        let err = match e.kind() {
+          io::ErrorKind::NotFound => warp::reject::not_found().with(e),
+          _ => warp::reject::server_error().with(e),
+        };
+        Err(err)
+      }),
+  )
+}
+
+fn respond(mut response: Builder, mut body: Vec<u8>, range: Option<&str>) -> Response<Body> {
+  let len = body.len() as u64;
+  match range.and_then(|r| parse_range(r, len)) {
+    Some(Ok((start, end))) => {
+      body.truncate(end as usize + 1);
+      body.drain(..start as usize);
+      response
+        .status(StatusCode::PARTIAL_CONTENT)
+        .header("content-range", format!("bytes {}-{}/{}", start, end, len));
+    }
+    Some(Err(())) => {
+      body.clear();
+      response
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .header("content-range", format!("bytes */{}", len));
+    }
+    None => (),
+  };
+  response.body(Body::from(body)).unwrap()
+}
@@ -0,0 +1,79 @@
+use futures::{future, stream};
+use hyper::Body;
+use serde::Serialize;
+use serde_json;
+use warp::http::Response;
+
+use super::version;
+use error::HermesError;
+
+// A JSON array written out a batch at a time while the client reads it, so
+// only one batch is held in memory however long the list is. `next` returns
+// the following batch, and an empty one once there are no more.
+//
+// The reply is written after the handler returned, so the API version it
+// was asked for is taken along. An error after the first batch can only
+// cut the reply short, so whatever can fail early should be done before.
+
+struct Batches<F> {
+  next: F,
+  canonical: bool,
+  written: usize,
+  done: bool,
+}
+
+impl<F> Batches<F> {
+  fn next_chunk<T>(&mut self) -> Result<Vec<u8>, HermesError>
+  where
+    T: Serialize,
+    F: FnMut() -> Result<Vec<T>, HermesError>,
+  {
+    let batch = (self.next)()?;
+    let mut chunk = Vec::new();
+    if self.written == 0 {
+      chunk.push(b'[');
+    }
+    for value in batch.iter() {
+      if self.written > 0 {
+        chunk.push(b',');
+      }
+      version::scoped(self.canonical, || serde_json::to_writer(&mut chunk, value))
+        .map_err(|e| HermesError::Parse(e.to_string()))?;
+      self.written += 1;
+    }
+    if batch.is_empty() {
+      chunk.push(b']');
+      self.done = true;
+    }
+    Ok(chunk)
+  }
+}
+
+pub fn json<T, F>(next: F) -> Response<Body>
+where
+  T: Serialize,
+  F: FnMut() -> Result<Vec<T>, HermesError> + Send + 'static,
+{
+  let batches = Batches {
+    next: next,
+    canonical: version::canonical(),
+    written: 0,
+    done: false,
+  };
+  let chunks = stream::unfold(batches, |mut batches| {
+    if batches.done {
+      return None;
+    }
+    Some(match batches.next_chunk() {
+      Ok(chunk) => future::ok((chunk, batches)),
+      Err(e) => {
+        error!("could not write reply: {}", e);
+        future::err(e)
+      }
+    })
+  });
+  Response::builder()
+    .header("content-type", "application/json")
+    .body(Body::wrap_stream(chunks))
+    .unwrap()
+}
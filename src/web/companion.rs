@@ -0,0 +1,164 @@
+use futures::future::{self, Either, Loop};
+use futures::Future;
+use std::cmp;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::timer::Delay;
+use warp::{self, Rejection};
+
+use db;
+use error::HermesError;
+use models::{Claims, CompanionItem, CompanionUpdate};
+
+// Tray apps and other small daemons only want to know how many items are
+// unread and when something important arrives, so instead of the SPA's
+// websocket they long-poll `GET /api/companion?cursor=&wait=`. A reply is one
+// JSON object with one-letter keys:
+//
+//   {"c": "812.4410", "n": 17, "u": {"12": 3, "40": 0}, "t": [{"i": 9912, "f": 12, "s": "Title"}]}
+//
+// c  the cursor to send with the next request
+// n  unread items over all subscriptions
+// u  unread counts by feed id, of the feeds that changed
+// t  new items matching one of the user's topics, newest first
+//
+// Without a cursor the reply comes at once, with `u` holding every feed that
+// has unread items. With one, the request is held until something changes
+// or `wait` seconds pass; the reply is then only `{"c": ...}`, the
+// heartbeat. A daemon that hears nothing for `wait` seconds and some slack
+// should reconnect. Topic matches are kept for an hour, a daemon that was
+// away for longer starts over without a cursor.
+
+static POLL_SECS: u64 = 2;
+static DEFAULT_WAIT_SECS: u64 = 25;
+pub static MAX_WAIT_SECS: u64 = 55;
+static MAX_TITLES: usize = 20;
+
+// the last event and item state change a daemon has heard of, as `event.change`
+#[derive(Clone, Copy)]
+struct Cursor {
+  event: i32,
+  change: i64,
+}
+
+impl Cursor {
+  fn latest() -> Result<Cursor, HermesError> {
+    Ok(Cursor {
+      event: db::get_latest_event_id()?,
+      change: db::get_latest_item_state_id()?,
+    })
+  }
+
+  fn parse(cursor: &str) -> Option<Cursor> {
+    let mut parts = cursor.splitn(2, '.');
+    match (
+      parts.next().and_then(|e| e.parse().ok()),
+      parts.next().and_then(|c| c.parse().ok()),
+    ) {
+      (Some(event), Some(change)) => Some(Cursor {
+        event: event,
+        change: change,
+      }),
+      _ => None,
+    }
+  }
+
+  fn to_string(&self) -> String {
+    format!("{}.{}", self.event, self.change)
+  }
+}
+
+pub fn poll(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let user_id = claims.id;
+  let wait = match query.get("wait").map(|w| w.parse::<u64>()) {
+    Some(Ok(w)) if w <= MAX_WAIT_SECS => w,
+    Some(_) => return Either::A(future::err(warp::reject::bad_request())),
+    None => DEFAULT_WAIT_SECS,
+  };
+  let since = match query.get("cursor").map(|c| Cursor::parse(c)) {
+    Some(Some(cursor)) => cursor,
+    Some(None) => return Either::A(future::err(warp::reject::bad_request())),
+    None => return Either::A(future::result(snapshot(user_id).map(|s| warp::reply::json(&s)))),
+  };
+
+  let deadline = Instant::now() + Duration::from_secs(wait);
+  let update = future::loop_fn((), move |_| {
+    match changes(user_id, since) {
+      Ok(Some(update)) => return Either::A(future::ok(Loop::Break(update))),
+      Ok(None) => (),
+      Err(e) => return Either::A(future::err(e)),
+    };
+    if Instant::now() >= deadline {
+      let heartbeat = CompanionUpdate::heartbeat(since.to_string());
+      return Either::A(future::ok(Loop::Break(heartbeat)));
+    }
+    let next = cmp::min(deadline, Instant::now() + Duration::from_secs(POLL_SECS));
+    Either::B(Delay::new(next).map(|_| Loop::Continue(())).map_err(|e| {
+      error!("companion poll timer errored: {}", e);
+      warp::reject::server_error()
+    }))
+  });
+  Either::B(update.map(|update| warp::reply::json(&update)))
+}
+
+fn snapshot(user_id: i32) -> Result<CompanionUpdate, Rejection> {
+  let cursor = Cursor::latest()?;
+  let feeds = db::get_all_subscribed_feeds(&user_id)?;
+  Ok(CompanionUpdate {
+    cursor: cursor.to_string(),
+    unseen_total: Some(feeds.iter().map(|f| f.unseen_count as i64).sum()),
+    unseen_counts: feeds
+      .iter()
+      .filter(|f| f.unseen_count > 0)
+      .map(|f| (f.id, f.unseen_count))
+      .collect(),
+    titles: vec![],
+  })
+}
+
+// None while nothing changed for the user since the cursor
+fn changes(user_id: i32, since: Cursor) -> Result<Option<CompanionUpdate>, Rejection> {
+  let cursor = Cursor::latest()?;
+  let changed = db::get_changed_feed_ids(user_id, since.change)?;
+  let titles = topic_titles(user_id, since.event, cursor.event)?;
+  if changed.is_empty() && titles.is_empty() {
+    return Ok(None);
+  }
+  // the counts view drops feeds without unseen items, so those report 0
+  let feeds = db::get_all_subscribed_feeds(&user_id)?;
+  let unseen: HashMap<i32, i32> = feeds.iter().map(|f| (f.id, f.unseen_count)).collect();
+  Ok(Some(CompanionUpdate {
+    cursor: cursor.to_string(),
+    unseen_total: Some(unseen.values().map(|n| *n as i64).sum()),
+    unseen_counts: changed
+      .into_iter()
+      .map(|feed_id| (feed_id, unseen.get(&feed_id).cloned().unwrap_or(0)))
+      .collect(),
+    titles: titles,
+  }))
+}
+
+fn topic_titles(user_id: i32, after: i32, until: i32) -> Result<Vec<CompanionItem>, HermesError> {
+  let mut item_ids: Vec<i32> = db::get_topic_events_since(user_id, after)?
+    .into_iter()
+    .filter(|e| e.id <= until)
+    .flat_map(|e| e.item_ids)
+    .collect();
+  if item_ids.is_empty() {
+    return Ok(vec![]);
+  }
+  item_ids.sort();
+  item_ids.dedup();
+  let mut items = db::get_items_by_ids(&item_ids)?;
+  items.sort_by(|a, b| b.id.cmp(&a.id));
+  Ok(
+    items
+      .into_iter()
+      .take(MAX_TITLES)
+      .map(CompanionItem::from_item)
+      .collect(),
+  )
+}
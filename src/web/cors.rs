@@ -0,0 +1,151 @@
+use warp::{self, Filter, Rejection, Reply};
+
+use config;
+
+// Requests carrying an `Origin` go through when it is the instance's own or
+// one of CORS_ORIGINS (see `config`), and are answered 403 otherwise. Replies
+// to allowed ones name that origin, so that browsers let the page read them
+// and send its cookie along; with `*` any page can read them, but without
+// the user's cookie. Preflight requests are answered here, before any route.
+
+static METHODS: &'static str = "GET, POST, PATCH, DELETE";
+static HEADERS: &'static str = "authorization, content-type, if-none-match";
+// paging, caching and rate limit headers, which pages can't read otherwise
+static EXPOSED: &'static str =
+  "x-total-count, etag, retry-after, ratelimit-limit, ratelimit-remaining, ratelimit-reset";
+static MAX_AGE_SECS: u32 = 600;
+
+fn optional_header(
+  name: &'static str,
+) -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Copy {
+  warp::header::<String>(name)
+    .map(Some)
+    .or(warp::any().map(|| None))
+    .unify()
+}
+
+// the request's origin, when it has one that is allowed
+pub fn allowed_origin() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+  optional_header("origin").and(optional_header("host")).and_then(
+    |origin: Option<String>, host: Option<String>| match origin {
+      Some(origin) => match config::WEB.origin_allowed(&origin, host.as_ref().map(|h| h.as_str())) {
+        true => Ok(origin),
+        false => {
+          debug!("CORS: rejected origin {}", origin);
+          Err(warp::reject::forbidden())
+        }
+      },
+      None => Err(warp::reject()),
+    },
+  )
+}
+
+// an allowed origin, when replies name it and let the cookie along
+pub fn named_origin() -> impl Filter<Extract = (String,), Error = Rejection> + Copy {
+  allowed_origin().and_then(|origin: String| match config::WEB.any_origin() {
+    true => Err(warp::reject()),
+    false => Ok(origin),
+  })
+}
+
+// any origin, when CORS_ORIGINS has `*`
+pub fn any_origin() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  allowed_origin()
+    .and_then(|_origin: String| match config::WEB.any_origin() {
+      true => Ok(()),
+      false => Err(warp::reject()),
+    }).untuple_one()
+}
+
+// other programs, and browsers loading a page
+pub fn no_origin() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  optional_header("origin")
+    .and_then(|origin: Option<String>| match origin {
+      Some(_) => Err(warp::reject()),
+      None => Ok(()),
+    }).untuple_one()
+}
+
+pub fn preflight() -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Copy {
+  let request = warp::options().and(warp::header::<String>("access-control-request-method"));
+  request
+    .and(named_origin())
+    .map(|_method: String, origin: String| preflight_headers(with_headers(origin, warp::reply())))
+    .or(
+      request
+        .and(any_origin())
+        .map(|_method: String| preflight_headers(with_any_headers(warp::reply()))),
+    )
+}
+
+fn preflight_headers<T: Reply>(reply: T) -> impl Reply {
+  let reply = warp::reply::with_header(reply, "access-control-allow-methods", METHODS);
+  let reply = warp::reply::with_header(reply, "access-control-allow-headers", HEADERS);
+  warp::reply::with_header(reply, "access-control-max-age", MAX_AGE_SECS.to_string())
+}
+
+pub fn with_headers<T: Reply>(origin: String, reply: T) -> impl Reply {
+  let reply = warp::reply::with_header(reply, "access-control-allow-origin", origin);
+  let reply = warp::reply::with_header(reply, "access-control-allow-credentials", "true");
+  exposed_headers(reply)
+}
+
+// browsers refuse credentials along with `*`, so the header is left out
+pub fn with_any_headers<T: Reply>(reply: T) -> impl Reply {
+  exposed_headers(warp::reply::with_header(reply, "access-control-allow-origin", "*"))
+}
+
+fn exposed_headers<T: Reply>(reply: T) -> impl Reply {
+  let reply = warp::reply::with_header(reply, "access-control-expose-headers", EXPOSED);
+  warp::reply::with_header(reply, "vary", "origin")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::allowed_origin;
+  use std::env;
+  use warp;
+
+  // `config::WEB` is read once, so every test sets the same origins
+  fn origin(origin: Option<&str>, host: &str) -> Option<String> {
+    env::set_var("CORS_ORIGINS", "https://reader.example.com/");
+    let request = warp::test::request().header("host", host);
+    let request = match origin {
+      Some(origin) => request.header("origin", origin),
+      None => request,
+    };
+    request.filter(&allowed_origin()).ok()
+  }
+
+  #[test]
+  fn allows_the_instance_itself() {
+    assert_eq!(
+      origin(Some("https://hermes.example.com"), "hermes.example.com"),
+      Some("https://hermes.example.com".to_owned())
+    );
+    assert_eq!(
+      origin(Some("http://localhost:3030"), "localhost:3030"),
+      Some("http://localhost:3030".to_owned())
+    );
+  }
+
+  #[test]
+  fn allows_configured_origins() {
+    assert_eq!(
+      origin(Some("https://reader.example.com"), "hermes.example.com"),
+      Some("https://reader.example.com".to_owned())
+    );
+  }
+
+  #[test]
+  fn rejects_other_origins() {
+    assert_eq!(origin(Some("https://evil.example.com"), "hermes.example.com"), None);
+    assert_eq!(origin(Some("http://localhost:8080"), "localhost:3030"), None);
+    assert_eq!(origin(Some("null"), "hermes.example.com"), None);
+  }
+
+  #[test]
+  fn leaves_requests_without_origin_to_other_filters() {
+    assert_eq!(origin(None, "hermes.example.com"), None);
+  }
+}
@@ -0,0 +1,49 @@
+use hyper::Body;
+use md5;
+use serde::Serialize;
+use serde_json;
+use warp::http::{Response, StatusCode};
+use warp::{self, Filter, Rejection};
+
+use super::assets::etag_matches;
+
+// Replies clients poll for carry an `ETag`, the hash of their JSON, and a
+// request sending it back in `If-None-Match` is answered 304 without a body
+// while nothing in the reply changed. The reply is still loaded and written
+// to hash it, only sending it is saved. The two API versions write
+// timestamps differently, so their tags differ as well.
+
+static CACHE_CONTROL: &'static str = "private, no-cache";
+
+pub fn if_none_match() -> impl Filter<Extract = (Option<String>,), Error = Rejection> + Copy {
+  warp::header::<String>("if-none-match")
+    .map(Some)
+    .or(warp::any().map(|| None))
+    .unify()
+}
+
+pub fn json<T: Serialize>(value: &T, if_none_match: Option<String>) -> Response<Body> {
+  let body = match serde_json::to_vec(value) {
+    Ok(body) => body,
+    Err(e) => {
+      error!("could not serialize reply: {}", e);
+      let mut response = Response::new(Body::empty());
+      *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+      return response;
+    }
+  };
+  let etag = format!("\"{:x}\"", md5::compute(&body));
+  let mut response = Response::builder();
+  response
+    .header("cache-control", CACHE_CONTROL)
+    .header("etag", etag.as_str());
+  let reply = match if_none_match {
+    Some(ref tags) if etag_matches(tags, &etag) => {
+      response.status(StatusCode::NOT_MODIFIED).body(Body::empty())
+    }
+    _ => response
+      .header("content-type", "application/json")
+      .body(Body::from(body)),
+  };
+  reply.unwrap()
+}
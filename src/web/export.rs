@@ -0,0 +1,182 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use futures::future;
+use futures::stream;
+use hyper::Body;
+use serde_json;
+use std::collections::HashMap;
+use warp::http::Response;
+use warp::{self, Rejection};
+
+use db;
+use error::HermesError;
+use models::{Claims, Item};
+
+// `GET /api/feed/:id/export?format=json|csv&range=&content=` writes out every
+// stored item of a feed. Items are loaded BATCH_SIZE at a time as the client
+// reads the response, so large archives are never held in memory at once.
+//
+// range    `2018-01-01..2018-06-30`, either end can be left out; both days
+//          are included and items are dated by publication, else update
+// content  `true` to include the summary and content of the items
+
+static BATCH_SIZE: i64 = 500;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+  Json,
+  Csv,
+}
+
+struct Export {
+  feed_id: i32,
+  format: Format,
+  from: Option<DateTime<Utc>>,
+  until: Option<DateTime<Utc>>,
+  content: bool,
+  after: i32,
+  written: usize,
+  done: bool,
+}
+
+impl Export {
+  fn next_chunk(&mut self) -> Result<Vec<u8>, HermesError> {
+    let items =
+      db::get_feed_items_after(self.feed_id, self.after, self.from, self.until, BATCH_SIZE)?;
+    let mut chunk = String::new();
+    if self.written == 0 {
+      chunk.push_str(match self.format {
+        Format::Json => "[",
+        Format::Csv => self.csv_header(),
+      });
+    }
+    if (items.len() as i64) < BATCH_SIZE {
+      self.done = true;
+    }
+    for mut item in items {
+      self.after = item.id;
+      if !self.content {
+        item.summary = None;
+        item.content = None;
+      }
+      match self.format {
+        Format::Json => {
+          if self.written > 0 {
+            chunk.push(',');
+          }
+          let json = serde_json::to_string(&item).map_err(|e| HermesError::Parse(e.to_string()))?;
+          chunk.push_str(&json);
+        }
+        Format::Csv => chunk.push_str(&self.csv_row(&item)),
+      };
+      self.written += 1;
+    }
+    if self.done && self.format == Format::Json {
+      chunk.push(']');
+    }
+    Ok(chunk.into_bytes())
+  }
+
+  fn csv_header(&self) -> &'static str {
+    match self.content {
+      true => "id,uuid,title,link,published_at,updated_at,summary,content\r\n",
+      false => "id,uuid,title,link,published_at,updated_at\r\n",
+    }
+  }
+
+  fn csv_row(&self, item: &Item) -> String {
+    let date = |d: Option<DateTime<Utc>>| d.map(|d| d.to_rfc3339()).unwrap_or_default();
+    let mut fields = vec![
+      item.id.to_string(),
+      csv_field(&item.uuid),
+      csv_field(&item.title),
+      csv_field(&item.link),
+      date(item.published_at),
+      date(item.updated_at),
+    ];
+    if self.content {
+      fields.push(csv_field(item.summary.as_ref().map(|s| s.as_str()).unwrap_or("")));
+      fields.push(csv_field(item.content.as_ref().map(|c| c.as_str()).unwrap_or("")));
+    }
+    let mut row = fields.join(",");
+    row.push_str("\r\n");
+    row
+  }
+}
+
+pub fn feed_items(
+  claims: Claims,
+  feed_id: i32,
+  query: HashMap<String, String>,
+) -> Result<Response<Body>, Rejection> {
+  if !db::is_subscribed(&claims.id, &feed_id)? {
+    return Err(warp::reject::not_found());
+  }
+  let format = match query.get("format").map(|f| f.as_str()) {
+    None | Some("json") => Format::Json,
+    Some("csv") => Format::Csv,
+    Some(_) => return Err(warp::reject::bad_request()),
+  };
+  let (from, until) = match query.get("range") {
+    Some(range) => parse_range(range).ok_or_else(|| warp::reject::bad_request())?,
+    None => (None, None),
+  };
+  let export = Export {
+    feed_id: feed_id,
+    format: format,
+    from: from,
+    until: until,
+    content: query.get("content").map(|c| c == "true").unwrap_or(false),
+    after: 0,
+    written: 0,
+    done: false,
+  };
+  let chunks = stream::unfold(export, |mut export| {
+    if export.done {
+      return None;
+    }
+    Some(match export.next_chunk() {
+      Ok(chunk) => future::ok((chunk, export)),
+      Err(e) => {
+        error!("could not export items of feed {}: {}", export.feed_id, e);
+        future::err(e)
+      }
+    })
+  });
+  let (content_type, extension) = match format {
+    Format::Json => ("application/json", "json"),
+    Format::Csv => ("text/csv; charset=utf-8", "csv"),
+  };
+  Ok(
+    Response::builder()
+      .header("content-type", content_type)
+      .header(
+        "content-disposition",
+        format!("attachment; filename=\"feed-{}.{}\"", feed_id, extension).as_str(),
+      ).body(Body::wrap_stream(chunks))
+      .unwrap(),
+  )
+}
+
+// `from..until` as days, `until` included
+fn parse_range(range: &str) -> Option<(Option<DateTime<Utc>>, Option<DateTime<Utc>>)> {
+  let mut ends = range.splitn(2, "..");
+  let day = |d: &str| -> Result<Option<NaiveDate>, ()> {
+    match d.trim() {
+      "" => Ok(None),
+      d => NaiveDate::parse_from_str(d, "%Y-%m-%d").map(Some).map_err(|_| ()),
+    }
+  };
+  let from = day(ends.next()?).ok()?;
+  let until = day(ends.next()?).ok()?;
+  Some((
+    from.map(|d| Utc.from_utc_date(&d).and_hms(0, 0, 0)),
+    until.map(|d| Utc.from_utc_date(&d).and_hms(0, 0, 0) + Duration::days(1)),
+  ))
+}
+
+fn csv_field(value: &str) -> String {
+  match value.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+    true => format!("\"{}\"", value.replace('"', "\"\"")),
+    false => value.to_owned(),
+  }
+}
@@ -0,0 +1,268 @@
+use chrono::{TimeZone, Utc};
+use md5;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use warp;
+
+use db::{
+  count_user_items, find_user_by_fever_key, get_item_ids_where, get_items_by_id_range, get_tag_rows,
+  get_user_feeds, log_activity, mark_feeds_read_before, set_fever_key, set_item_seen,
+  set_item_starred,
+};
+use error::HermesError;
+use models::{Action, Claims, SubscribedItem, User};
+use super::request_log;
+use super::types::FeverPasswordParams;
+
+// Fever clients (Reeder, Unread, ...) POST `api_key=md5("username:password")`
+// to `/fever/?api&<what>`. Tags are exposed as groups, starred items as saved.
+
+static API_VERSION: i32 = 3;
+static MAX_ITEMS: i64 = 50;
+
+pub fn fever_key(username: &str, password: &str) -> String {
+  format!("{:x}", md5::compute(format!("{}:{}", username, password)))
+}
+
+pub fn set_password(
+  claims: Claims,
+  params: FeverPasswordParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match User::check_user(&claims.name, &params.password) {
+    Ok(Some(_)) => (),
+    Ok(None) => return Err(warp::reject::forbidden()),
+    Err(e) => {
+      error!("could not check the password of user {}: {}", claims.id, e);
+      return Err(e.into());
+    }
+  }
+  let key = fever_key(&claims.name, &params.password);
+  match set_fever_key(claims.id, &User::hash_pw(&key)) {
+    Ok(_) => {
+      log_activity(claims.id, Action::SetFeverKey, None);
+      Ok(warp::reply::json(&json!({ "enabled": true })))
+    }
+    Err(e) => {
+      error!("could not set fever key for user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// Parameters may arrive in the query string or the form body, clients differ.
+pub fn handle(
+  query: HashMap<String, String>,
+  form: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let mut params = query;
+  params.extend(form);
+
+  let mut response = BTreeMap::new();
+  response.insert("api_version", json!(API_VERSION));
+  let user = match params.get("api_key") {
+    Some(key) => find_user_by_fever_key(&User::hash_pw(&key.to_lowercase())),
+    None => Err(HermesError::NotFound),
+  };
+  let user = match user {
+    Ok(user) => user,
+    Err(HermesError::NotFound) => {
+      response.insert("auth", json!(0));
+      return Ok(warp::reply::json(&response));
+    }
+    Err(e) => return Err(e.into()),
+  };
+  response.insert("auth", json!(1));
+  request_log::set_user(user.id);
+
+  if params.contains_key("mark") {
+    mark(user.id, &params);
+  }
+
+  let feeds = get_user_feeds(user.id)?;
+  let last_refreshed = feeds.iter().map(|f| f.updated_at.timestamp()).max();
+  response.insert("last_refreshed_on_time", json!(last_refreshed.unwrap_or(0)));
+
+  if params.contains_key("groups") || params.contains_key("feeds") {
+    let (groups, feeds_groups) = groups(user.id)?;
+    if params.contains_key("groups") {
+      response.insert("groups", groups);
+    }
+    response.insert("feeds_groups", feeds_groups);
+  }
+  if params.contains_key("feeds") {
+    let feeds: Vec<Value> = feeds
+      .iter()
+      .map(|f| {
+        json!({
+          "id": f.id,
+          "favicon_id": 0,
+          "title": f.title,
+          "url": f.feed_link,
+          "site_url": f.site_link,
+          "is_spark": 0,
+          "last_updated_on_time": f.updated_at.timestamp(),
+        })
+      }).collect();
+    response.insert("feeds", json!(feeds));
+  }
+  if params.contains_key("favicons") {
+    response.insert("favicons", json!([]));
+  }
+  if params.contains_key("links") {
+    response.insert("links", json!([]));
+  }
+  if params.contains_key("items") {
+    let with_ids = params.get("with_ids").map(|ids| parse_ids(ids));
+    let items = get_items_by_id_range(
+      user.id,
+      parse_id(&params, "since_id"),
+      parse_id(&params, "max_id"),
+      with_ids,
+      MAX_ITEMS,
+    )?;
+    let items: Vec<Value> = items.iter().map(item).collect();
+    response.insert("items", json!(items));
+    response.insert("total_items", json!(count_user_items(user.id)?));
+  }
+  if params.contains_key("unread_item_ids") {
+    let ids = join_ids(&get_item_ids_where(user.id, true, false)?);
+    response.insert("unread_item_ids", json!(ids));
+  }
+  if params.contains_key("saved_item_ids") {
+    let ids = join_ids(&get_item_ids_where(user.id, false, true)?);
+    response.insert("saved_item_ids", json!(ids));
+  }
+  Ok(warp::reply::json(&response))
+}
+
+// mark=item&as=read|unread|saved|unsaved&id=
+// mark=feed|group&as=read&id=&before=, group 0 is every feed
+fn mark(user_id: i32, params: &HashMap<String, String>) {
+  let id = match parse_id(params, "id") {
+    Some(id) => id,
+    None => return,
+  };
+  let action = params.get("as").map(|a| a.as_str()).unwrap_or("");
+  let result = match params.get("mark").map(|m| m.as_str()) {
+    Some("item") => match action {
+      "read" => set_item_seen(id, user_id, true),
+      "unread" => set_item_seen(id, user_id, false),
+      "saved" => set_item_starred(id, user_id, true),
+      "unsaved" => set_item_starred(id, user_id, false),
+      _ => return,
+    },
+    Some(kind @ "feed") | Some(kind @ "group") if action == "read" => {
+      let before = params
+        .get("before")
+        .and_then(|b| b.parse::<i64>().ok())
+        .map(|b| Utc.timestamp(b, 0))
+        .unwrap_or_else(Utc::now);
+      let feed_ids = match kind {
+        "feed" => Ok(vec![id]),
+        _ if id == 0 => get_user_feeds(user_id).map(|feeds| feeds.iter().map(|f| f.id).collect()),
+        _ => group_feed_ids(user_id, id),
+      };
+      feed_ids.and_then(|feed_ids| mark_feeds_read_before(&feed_ids, user_id, before))
+    }
+    _ => return,
+  };
+  if let Err(e) = result {
+    error!("fever: could not mark {:?} for user {}: {}", params.get("mark"), user_id, e);
+  }
+}
+
+// a tag's group id is the lowest row id it was stored under
+fn tag_groups(user_id: i32) -> Result<BTreeMap<String, (i32, Vec<i32>)>, HermesError> {
+  let mut groups: BTreeMap<String, (i32, Vec<i32>)> = BTreeMap::new();
+  for (row_id, tag, feed_id) in get_tag_rows(user_id)? {
+    let group = groups.entry(tag).or_insert((row_id, vec![]));
+    group.1.push(feed_id);
+  }
+  Ok(groups)
+}
+
+fn group_feed_ids(user_id: i32, group_id: i32) -> Result<Vec<i32>, HermesError> {
+  Ok(
+    tag_groups(user_id)?
+      .into_iter()
+      .find(|(_, (id, _))| *id == group_id)
+      .map(|(_, (_, feed_ids))| feed_ids)
+      .unwrap_or_default(),
+  )
+}
+
+fn groups(user_id: i32) -> Result<(Value, Value), HermesError> {
+  let tags = tag_groups(user_id)?;
+  let groups: Vec<Value> = tags
+    .iter()
+    .map(|(tag, (id, _))| json!({ "id": id, "title": tag }))
+    .collect();
+  let feeds_groups: Vec<Value> = tags
+    .values()
+    .map(|(id, feed_ids)| json!({ "group_id": id, "feed_ids": join_ids(feed_ids) }))
+    .collect();
+  Ok((json!(groups), json!(feeds_groups)))
+}
+
+fn item(item: &SubscribedItem) -> Value {
+  let html = item
+    .content
+    .as_ref()
+    .or(item.summary.as_ref())
+    .cloned()
+    .unwrap_or_default();
+  let created = item.published_at.or(item.updated_at).map(|d| d.timestamp());
+  json!({
+    "id": item.id,
+    "uuid": item.uuid,
+    "feed_id": item.feed_id,
+    "title": item.title,
+    "author": "",
+    "html": html,
+    "url": item.link,
+    "is_saved": item.starred as i32,
+    "is_read": item.seen as i32,
+    "created_on_time": created.unwrap_or(0),
+  })
+}
+
+fn parse_id(params: &HashMap<String, String>, name: &str) -> Option<i32> {
+  params.get(name).and_then(|v| v.parse().ok())
+}
+
+fn parse_ids(ids: &str) -> Vec<i32> {
+  ids.split(',').filter_map(|id| id.trim().parse().ok()).collect()
+}
+
+fn join_ids(ids: &Vec<i32>) -> String {
+  ids
+    .iter()
+    .map(|id| id.to_string())
+    .collect::<Vec<_>>()
+    .join(",")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{join_ids, parse_ids};
+
+  #[test]
+  fn parses_comma_separated_ids() {
+    assert_eq!(parse_ids("1,2,3"), vec![1, 2, 3]);
+    assert_eq!(parse_ids(" 4, 5 ,6 "), vec![4, 5, 6]);
+    assert_eq!(parse_ids("7"), vec![7]);
+  }
+
+  #[test]
+  fn skips_what_is_not_an_id() {
+    assert_eq!(parse_ids(""), Vec::<i32>::new());
+    assert_eq!(parse_ids("1,,x,2,99999999999,-"), vec![1, 2]);
+  }
+
+  #[test]
+  fn joins_ids_back() {
+    assert_eq!(join_ids(&vec![1, 2, 3]), "1,2,3");
+    assert_eq!(join_ids(&vec![]), "");
+    assert_eq!(parse_ids(&join_ids(&vec![8, 9])), vec![8, 9]);
+  }
+}
@@ -0,0 +1,128 @@
+use backtrace::Backtrace;
+use futures::future;
+use futures::{Future, IntoFuture};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::time::Duration;
+use tokio::timer::Timeout;
+use warp::http::StatusCode;
+use warp::{self, Rejection};
+
+use super::version::{self, Versioned};
+
+// Route handlers run through `run`, so that one which panics answers 500
+// instead of taking the connection down, and one which takes too long
+// answers 504 instead of holding it forever. The hook installed by
+// `log_panics` logs panics with a backtrace.
+//
+// ROUTE_TIMEOUT_SECS  how long a handler may take, 60 if unset; routes that
+//                     hold requests on purpose pass their own to `run_for`
+//
+// Only handlers waiting on futures can be cut off. One blocked on the
+// database is answered 504 once it returns, its work is not undone.
+
+lazy_static! {
+  static ref TIMEOUT: Duration = Duration::from_secs(
+    env::var("ROUTE_TIMEOUT_SECS")
+      .ok()
+      .and_then(|v| v.parse().ok())
+      .unwrap_or(60)
+  );
+}
+
+#[derive(Debug)]
+pub enum Failure {
+  Panicked,
+  TimedOut,
+}
+impl fmt::Display for Failure {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.description())
+  }
+}
+impl Error for Failure {
+  fn description(&self) -> &str {
+    match *self {
+      Failure::Panicked => "handler panicked",
+      Failure::TimedOut => "handler timed out",
+    }
+  }
+}
+
+pub fn log_panics() {
+  panic::set_hook(Box::new(|info| {
+    let message = match info.payload().downcast_ref::<&str>() {
+      Some(s) => *s,
+      None => match info.payload().downcast_ref::<String>() {
+        Some(s) => s.as_str(),
+        None => "Box<Any>",
+      },
+    };
+    let location = info
+      .location()
+      .map(|l| format!("{}:{}", l.file(), l.line()))
+      .unwrap_or_default();
+    error!(
+      "thread '{}' panicked at '{}', {}\n{:?}",
+      thread::current().name().unwrap_or("<unnamed>"),
+      message,
+      location,
+      Backtrace::new()
+    );
+  }));
+}
+
+// boxed, as the handler may borrow from its route and the reply must not
+pub fn run<F, R>(handler: F) -> Box<Future<Item = R::Item, Error = Rejection> + Send>
+where
+  F: FnOnce() -> R,
+  R: IntoFuture<Error = Rejection>,
+  R::Future: Send + 'static,
+  R::Item: Send + 'static,
+{
+  run_for(*TIMEOUT, handler)
+}
+
+pub fn run_for<F, R>(
+  timeout: Duration,
+  handler: F,
+) -> Box<Future<Item = R::Item, Error = Rejection> + Send>
+where
+  F: FnOnce() -> R,
+  R: IntoFuture<Error = Rejection>,
+  R::Future: Send + 'static,
+  R::Item: Send + 'static,
+{
+  // most handlers do all their work right here, before returning a future
+  let v1 = version::is_v1();
+  let started = panic::catch_unwind(AssertUnwindSafe(|| {
+    version::scoped(v1, || handler().into_future())
+  }));
+  let started = match started {
+    Ok(started) => started,
+    Err(_) => return Box::new(future::err(warp::reject::custom(Failure::Panicked))),
+  };
+  let guarded = AssertUnwindSafe(Versioned::new(v1, started))
+    .catch_unwind()
+    .then(|finished| match finished {
+      Ok(reply) => reply,
+      Err(_) => Err(warp::reject::custom(Failure::Panicked)),
+    });
+  Box::new(
+    Timeout::new(guarded, timeout)
+      .map_err(|e| e.into_inner().unwrap_or_else(|| warp::reject::custom(Failure::TimedOut))),
+  )
+}
+
+pub fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, Rejection> {
+  let (code, status) = match err.find_cause::<Failure>() {
+    Some(&Failure::Panicked) => ("internal_error", StatusCode::INTERNAL_SERVER_ERROR),
+    Some(&Failure::TimedOut) => ("timeout", StatusCode::GATEWAY_TIMEOUT),
+    None => return Err(err),
+  };
+  let reply = warp::reply::json(&json!({ "error": code }));
+  Ok(warp::reply::with_status(reply, status))
+}
@@ -1,8 +1,152 @@
-use super::types::{LoginParams, SettingsData};
-use db::{create_user, get_user};
-use models::{Claims, User};
+use base64;
+use std::env;
+use std::fs::File;
+use std::io::{self, Read};
 use std::str;
 
+use super::types::{LoginParams, RegisterParams, SettingsData};
+use db::{self, create_user, get_user, RegisterFailure};
+use error::HermesError;
+use models::{Claims, Invite, User};
+
+#[derive(Debug, PartialEq)]
+pub enum RegistrationMode {
+  Closed,
+  Open,
+  Invite,
+  Approval,
+}
+impl RegistrationMode {
+  // REGISTRATION=closed|open|invite|approval, else what was chosen at setup,
+  // closed if neither is set
+  pub fn current() -> Self {
+    let mode = match env::var("REGISTRATION") {
+      Ok(mode) => Some(mode),
+      Err(_) => db::get_setting("registration").unwrap_or_else(|e| {
+        error!("could not load the registration mode: {}", e);
+        None
+      }),
+    };
+    mode
+      .and_then(|mode| RegistrationMode::parse(&mode))
+      .unwrap_or(RegistrationMode::Closed)
+  }
+
+  pub fn name(&self) -> &'static str {
+    match *self {
+      RegistrationMode::Closed => "closed",
+      RegistrationMode::Open => "open",
+      RegistrationMode::Invite => "invite",
+      RegistrationMode::Approval => "approval",
+    }
+  }
+
+  pub fn parse(mode: &str) -> Option<Self> {
+    match mode {
+      "closed" => Some(RegistrationMode::Closed),
+      "open" => Some(RegistrationMode::Open),
+      "invite" => Some(RegistrationMode::Invite),
+      "approval" => Some(RegistrationMode::Approval),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum RegisterError {
+  Closed,
+  InvalidUsername,
+  WeakPassword,
+  UsernameTaken,
+  InvalidInvite,
+  Failed,
+}
+
+pub fn register(params: &RegisterParams) -> Result<User, RegisterError> {
+  let mode = RegistrationMode::current();
+  if mode == RegistrationMode::Closed {
+    return Err(RegisterError::Closed);
+  }
+  if !valid_username(&params.username) {
+    return Err(RegisterError::InvalidUsername);
+  }
+  if !strong_password(&params.password) {
+    return Err(RegisterError::WeakPassword);
+  }
+  match get_user(&params.username) {
+    Ok(_) => return Err(RegisterError::UsernameTaken),
+    Err(HermesError::NotFound) => (),
+    Err(e) => {
+      error!("could not look up '{}': {}", params.username, e);
+      return Err(RegisterError::Failed);
+    }
+  }
+  let invite = match mode {
+    RegistrationMode::Invite => match params.invite_code {
+      Some(ref c) => Some(c.as_str()),
+      None => return Err(RegisterError::InvalidInvite),
+    },
+    _ => None,
+  };
+
+  let pwh = User::hash_pw(&params.password);
+  let approved = mode != RegistrationMode::Approval;
+  match db::register_user(&params.username, &pwh, approved, invite) {
+    Ok(user) => Ok(user),
+    Err(RegisterFailure::InvalidInvite) => Err(RegisterError::InvalidInvite),
+    Err(RegisterFailure::Database(e)) => {
+      error!("could not register '{}': {}", params.username, e);
+      Err(RegisterError::Failed)
+    }
+  }
+}
+
+pub fn valid_username(username: &str) -> bool {
+  username.len() <= 32
+    && !username.is_empty()
+    && username
+      .chars()
+      .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+pub fn strong_password(password: &str) -> bool {
+  password.chars().count() >= 8
+}
+
+pub fn create_invite(claims: &Claims) -> Result<Invite, ()> {
+  let code = random_token(12).map_err(|e| error!("could not read random bytes: {}", e))?;
+  db::create_invite(&code, claims.id).map_err(|e| error!("could not create invite: {}", e))
+}
+
+// creates an account for a user authenticated by the reverse proxy, with a
+// random password so it can't be logged into directly
+pub fn provision_user(username: &str) -> Option<User> {
+  let pass = random_token(24)
+    .map_err(|e| error!("could not read random bytes: {}", e))
+    .ok()?;
+  match db::register_user(username, &User::hash_pw(&pass), true, None) {
+    Ok(user) => {
+      info!("provisioned proxy user '{}'", username);
+      Some(user)
+    }
+    Err(e) => {
+      error!("could not provision proxy user '{}': {:?}", username, e);
+      None
+    }
+  }
+}
+
+pub fn random_token(bytes: usize) -> io::Result<String> {
+  let mut buf = vec![0u8; bytes];
+  File::open("/dev/urandom")?.read_exact(&mut buf)?;
+  Ok(base64::encode_config(&buf, base64::URL_SAFE_NO_PAD))
+}
+
+// false for an unknown user
+pub fn approve_user(user_id: i32) -> Result<bool, HermesError> {
+  db::approve_user(user_id).map(|n| n == 1)
+}
+
 // pub fn change_settings(settings: &SettingsData, claims: &Claims) -> Result<(), ()> {
 
 //   match settings.name.as_ref() {
@@ -25,19 +169,16 @@ use std::str;
 //   }
 // }
 
-pub fn add_user(login: &LoginParams, claims: &Claims) -> Result<(), ()> {
-  if claims.id != 1 {
-    return Err(());
-  };
+pub fn add_user(login: &LoginParams) -> Result<(), ()> {
   match get_user(&login.username) {
-    None => {
+    Err(HermesError::NotFound) => {
       let pwh = User::hash_pw(&login.password);
-      match create_user(&login.password, &pwh) {
+      match create_user(&login.username, &pwh) {
         Ok(_) => Ok(()),
         Err(_e) => Err(()),
       }
     }
-    Some(_) => Err(()),
+    _ => Err(()),
   }
 }
 
@@ -1,66 +1,233 @@
+use chrono::{TimeZone, Utc};
 use jsonwebtoken::errors::ErrorKind;
 use jsonwebtoken::{decode, encode, Header, Validation};
 use std::env;
+use std::sync::RwLock;
 use warp;
-use warp::http::StatusCode;
 
-use super::types::LoginParams;
-use models::{Claims, User};
+use super::handlers::strong_password;
+use super::types::{ChangePasswordParams, LoginParams, UserWebsocketState};
+use super::ws::ws_close_session;
+use config;
+use db::{
+  end_session, get_setting, get_user, is_token_revoked, log_activity, renew_session, revoke_token,
+  set_password, start_session, trim_sessions, JWT_SECRET_SETTING,
+};
+use error::HermesError;
+use models::{Action, Claims, User};
 
-pub fn authenticate(params: LoginParams) -> Result<impl warp::Reply, warp::Rejection> {
+lazy_static! {
+  static ref JWT_TTL_SECS: i64 = env::var("JWT_TTL_SECS")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(7 * 24 * 3600);
+  // how many devices a user can be logged in on at once, 0 for no limit
+  static ref MAX_SESSIONS: i64 = env::var("MAX_SESSIONS")
+    .ok()
+    .and_then(|s| s.parse().ok())
+    .unwrap_or(0);
+  static ref SECRET: RwLock<Option<String>> = RwLock::new(None);
+}
+
+// JWT_SECRET, else the one stored at setup; remembered once found
+fn secret() -> Result<Option<String>, HermesError> {
+  if let Some(ref secret) = *SECRET.read().unwrap() {
+    return Ok(Some(secret.clone()));
+  }
+  let found = match env::var("JWT_SECRET") {
+    Ok(secret) => Some(secret),
+    Err(_) => get_setting(JWT_SECRET_SETTING)?,
+  };
+  if found.is_some() {
+    *SECRET.write().unwrap() = found.clone();
+  }
+  Ok(found)
+}
+
+pub fn authenticate(
+  params: LoginParams,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
   match User::check_user(&params.username, &params.password) {
-    Some(user) => {
-      let jwt = generate_jwt(&user).unwrap();
+    Ok(Some(user)) => {
+      let jwt = generate_jwt(&user).ok_or_else(|| warp::reject::server_error())?;
+      log_activity(user.id, Action::Login, None);
+      enforce_session_limit(user.id, &state);
       let json_body = json!({ "token": jwt, });
-      Ok(warp::reply::json(&json_body))
+      Ok(with_session(warp::reply::json(&json_body), &jwt))
+    }
+    Ok(None) => {
+      if let Ok(user) = get_user(&params.username) {
+        log_activity(user.id, Action::LoginFailed, None);
+      }
+      Err(warp::reject::bad_request())
+    }
+    Err(e) => {
+      error!("could not check the credentials of '{}': {}", params.username, e);
+      Err(e.into())
     }
-    _ => Err(warp::reject::bad_request()),
   }
 }
 
-pub fn decode_jwt(token: String) -> Result<Claims, StatusCode> {
-  let secret = env::var("JWT_SECRET").unwrap();
+pub fn decode_jwt(token: String) -> Result<Claims, HermesError> {
+  let secret = secret()?.ok_or_else(|| HermesError::Auth("no JWT secret".into()))?;
   let t = token;
 
-  let validation = Validation {
-    validate_exp: false,
-    ..Default::default()
-  };
+  let validation = Validation::default();
   let token = decode::<Claims>(&t, secret.as_ref(), &validation);
   match token {
     Ok(jwt) => {
+      if is_token_revoked(&jwt.claims.jti)? {
+        error!("revoked: {}", jwt.claims.jti);
+        return Err(HermesError::Auth("token revoked".into()));
+      }
       debug!("decoded: {:?}", jwt);
       Ok(jwt.claims)
     }
     Err(e) => {
       error!("failed to decode: {:?}", e);
-      match *e.kind() {
-        ErrorKind::ExpiredSignature => error!("expired: {:?}", e),
-        ErrorKind::InvalidToken => error!("invalid: {:?}", e),
-        _ => panic!(),
-      }
-      Err(StatusCode::UNAUTHORIZED)
+      let reason = match *e.kind() {
+        ErrorKind::ExpiredSignature => "token expired",
+        ErrorKind::InvalidToken => "invalid token",
+        _ => "token rejected",
+      };
+      Err(HermesError::Auth(reason.into()))
     }
   }
 }
 
-pub fn generate_jwt(user: &User) -> Option<String> {
-  let claims = Claims {
-    name: user.username.to_string(),
-    id: user.id,
+// the old token is revoked, so each token can be exchanged only once
+pub fn refresh(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  let user = match get_user(&claims.name) {
+    Ok(ref user) if user.id == claims.id && user.active() => user.clone(),
+    Ok(_) | Err(HermesError::NotFound) => return Err(warp::reject::forbidden()),
+    Err(e) => {
+      error!("could not load user {}: {}", claims.id, e);
+      return Err(e.into());
+    }
+  };
+  revoke(&claims)?;
+  log_activity(user.id, Action::TokenRefresh, None);
+  let (new_claims, jwt) = sign(&user).ok_or_else(|| warp::reject::server_error())?;
+  let expires = Utc.timestamp(new_claims.exp, 0);
+  if let Err(e) = renew_session(user.id, &claims.jti, &new_claims.jti, expires) {
+    error!("could not renew session of user {}: {}", user.id, e);
+    return Err(e.into());
+  }
+  Ok(with_session(warp::reply::json(&json!({ "token": jwt })), &jwt))
+}
+
+pub fn logout(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  revoke(&claims)?;
+  if let Err(e) = end_session(&claims.jti) {
+    error!("could not end session of user {}: {}", claims.id, e);
+  }
+  log_activity(claims.id, Action::Logout, None);
+  debug!("user {} logged out", claims.id);
+  let reply = warp::reply::json(&json!({ "logged_out": true }));
+  Ok(warp::reply::with_header(reply, "set-cookie", config::WEB.expired_cookie()))
+}
+
+// all sessions end, including this one, which gets a new token instead
+pub fn change_password(
+  claims: Claims,
+  params: ChangePasswordParams,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let user = match User::check_user(&claims.name, &params.old_pass) {
+    Ok(Some(ref user)) if user.id == claims.id => user.clone(),
+    Ok(_) => return Err(warp::reject::forbidden()),
+    Err(e) => {
+      error!("could not check the password of user {}: {}", claims.id, e);
+      return Err(e.into());
+    }
   };
+  if !strong_password(&params.new_pass) {
+    return Err(warp::reject::bad_request());
+  }
+  if let Err(e) = set_password(user.id, &User::hash_pw(&params.new_pass)) {
+    error!("could not change the password of user {}: {}", user.id, e);
+    return Err(e.into());
+  }
+  revoke(&claims)?;
+  end_sessions(user.id, "password_changed", &state);
+  log_activity(user.id, Action::PasswordChanged, None);
+  let jwt = generate_jwt(&user).ok_or_else(|| warp::reject::server_error())?;
+  Ok(with_session(warp::reply::json(&json!({ "token": jwt })), &jwt))
+}
+
+// browsers get the token as a cookie as well, other clients use the body
+fn with_session(reply: impl warp::Reply, jwt: &str) -> impl warp::Reply {
+  let cookie = config::WEB.session_cookie(jwt, *JWT_TTL_SECS);
+  warp::reply::with_header(reply, "set-cookie", cookie)
+}
+
+fn revoke(claims: &Claims) -> Result<(), warp::Rejection> {
+  match revoke_token(&claims.jti, Utc.timestamp(claims.exp, 0)) {
+    Ok(_) => Ok(()),
+    Err(e) => {
+      error!("could not revoke token {}: {}", claims.jti, e);
+      Err(e.into())
+    }
+  }
+}
+
+// over the limit, the oldest sessions are revoked and their sockets told so
+fn enforce_session_limit(user_id: i32, state: &UserWebsocketState) {
+  if *MAX_SESSIONS <= 0 {
+    return;
+  }
+  match trim_sessions(user_id, *MAX_SESSIONS) {
+    Ok(revoked) => for session in revoked.iter() {
+      info!("revoked session {} of user {}", session.id, user_id);
+      log_activity(user_id, Action::SessionRevoked, None);
+      ws_close_session(&user_id, &session.id, "session_limit", state);
+    },
+    Err(e) => error!("could not limit sessions of user {}: {}", user_id, e),
+  }
+}
+
+// revokes all of the user's tokens, e.g. once their account was disabled
+pub fn end_sessions(user_id: i32, reason: &'static str, state: &UserWebsocketState) {
+  match trim_sessions(user_id, 0) {
+    Ok(revoked) => for session in revoked.iter() {
+      info!("revoked session {} of user {}", session.id, user_id);
+      ws_close_session(&user_id, &session.id, reason, state);
+    },
+    Err(e) => error!("could not end sessions of user {}: {}", user_id, e),
+  }
+}
 
-  match env::var("JWT_SECRET") {
-    Ok(val) => {
+// starts a new session, the token of a refresh goes through `sign` instead
+pub fn generate_jwt(user: &User) -> Option<String> {
+  let (claims, jwt) = sign(user)?;
+  match start_session(user.id, &claims.jti, Utc.timestamp(claims.exp, 0)) {
+    Ok(_) => Some(jwt),
+    Err(e) => {
+      error!("could not start session of user {}: {}", user.id, e);
+      None
+    }
+  }
+}
+
+fn sign(user: &User) -> Option<(Claims, String)> {
+  let claims = Claims::for_user(user, *JWT_TTL_SECS)?;
+
+  match secret() {
+    Ok(Some(val)) => {
       let token = encode(&Header::default(), &claims, &val.as_ref());
       match token {
         Ok(jwt) => {
           debug!("generated jwt: {:?}", jwt);
-          Some(jwt)
+          Some((claims, jwt))
         }
         Err(_) => None,
       }
     }
-    Err(_) => None,
+    Ok(None) => None,
+    Err(e) => {
+      error!("could not load the JWT secret: {}", e);
+      None
+    }
   }
 }
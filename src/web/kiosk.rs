@@ -0,0 +1,88 @@
+use chrono::{Duration, Utc};
+use warp::{self, Rejection};
+
+use super::handlers::random_token;
+use super::types::KioskParams;
+use db;
+use error::reject;
+use models::{Claims, IssuedKioskToken, KioskItem, KioskToken, Page};
+
+// Lobby screens and dashboards get a token instead of an account. It only
+// reads the newest items of one tag, one feed or all of its owner's
+// subscriptions, through `GET /kiosk/:token/items`, until it expires.
+
+static TOKEN_BYTES: usize = 24;
+static DEFAULT_ITEMS: i32 = 20;
+static MAX_ITEMS: i32 = 100;
+static DEFAULT_DAYS: i64 = 30;
+static MAX_DAYS: i64 = 365;
+
+pub fn create(claims: Claims, params: KioskParams) -> Result<impl warp::Reply, Rejection> {
+  let tag = params
+    .tag
+    .map(|t| t.trim().to_lowercase())
+    .filter(|t| !t.is_empty());
+  if tag.is_some() && params.feed_id.is_some() {
+    return Err(warp::reject::bad_request());
+  }
+  if let Some(feed_id) = params.feed_id {
+    db::get_subscribed_feed(&claims.id, &feed_id).map_err(reject)?;
+  }
+  let limit = params.limit.unwrap_or(DEFAULT_ITEMS);
+  let days = params.ttl_days.unwrap_or(DEFAULT_DAYS);
+  if limit < 1 || limit > MAX_ITEMS || days < 1 || days > MAX_DAYS {
+    return Err(warp::reject::bad_request());
+  }
+
+  let token = random_token(TOKEN_BYTES).map_err(|e| {
+    error!("could not read random bytes: {}", e);
+    warp::reject::server_error()
+  })?;
+  let kiosk = db::insert_kiosk_token(
+    claims.id,
+    &KioskToken::hash(&token),
+    tag.as_ref().map(|t| t.as_str()),
+    params.feed_id,
+    limit,
+    Utc::now() + Duration::days(days),
+  )?;
+  Ok(warp::reply::json(&IssuedKioskToken {
+    token: token,
+    kiosk: kiosk,
+  }))
+}
+
+pub fn list(claims: Claims) -> Result<impl warp::Reply, Rejection> {
+  let tokens = db::get_kiosk_tokens(claims.id)?;
+  Ok(warp::reply::json(&tokens))
+}
+
+pub fn revoke(claims: Claims, kiosk_id: i32) -> Result<impl warp::Reply, Rejection> {
+  match db::delete_kiosk_token(kiosk_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete kiosk token {}: {}", kiosk_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_items(token: String) -> Result<impl warp::Reply, Rejection> {
+  let kiosk = db::find_kiosk_token(&KioskToken::hash(&token)).map_err(reject)?;
+  let feed_ids = match (&kiosk.tag, kiosk.feed_id) {
+    (&Some(ref tag), _) => db::get_tagged_feed_ids(kiosk.user_id, tag)?,
+    (&None, Some(feed_id)) => vec![feed_id],
+    (&None, None) => db::get_all_subscribed_feeds(&kiosk.user_id)?
+      .iter()
+      .map(|f| f.id)
+      .collect(),
+  };
+  let page = Page {
+    limit: kiosk.item_limit as i64,
+    offset: 0,
+  };
+  let (items, _) = db::get_items_for_feeds(kiosk.user_id, &feed_ids, page)?;
+  let items: Vec<KioskItem> = items.into_iter().map(KioskItem::from_subscribed).collect();
+  Ok(warp::reply::json(&items))
+}
@@ -0,0 +1,246 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Instant;
+use warp::http::StatusCode;
+use warp::{self, Filter, Rejection, Reply};
+
+use super::TRUSTED_PROXIES;
+use models::Claims;
+
+// Token buckets against password guessing and runaway clients: logins are
+// limited per address, authenticated requests per user. A bucket holds as
+// many requests as are allowed in a minute and refills evenly, so bursts are
+// fine while the average stays below the limit.
+//
+// LOGIN_RATE_LIMIT  logins per minute and address, 10 if unset
+// API_RATE_LIMIT    requests per minute and user, 600 if unset
+//
+// Requests over the limit answer 429. Those and the ones let through carry
+// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`, in seconds
+// until the bucket is full again. The address of requests from
+// TRUSTED_PROXIES is the last one in their `X-Forwarded-For`.
+//
+// Buckets are kept by each web node for itself.
+
+// full buckets are dropped past this many
+static MAX_BUCKETS: usize = 10_000;
+
+lazy_static! {
+  static ref LOGINS: Limiter<Option<IpAddr>> = Limiter::new(
+    env::var("LOGIN_RATE_LIMIT")
+      .ok()
+      .and_then(|v| v.parse().ok())
+      .unwrap_or(10)
+  );
+  static ref REQUESTS: Limiter<i32> = Limiter::new(
+    env::var("API_RATE_LIMIT")
+      .ok()
+      .and_then(|v| v.parse().ok())
+      .unwrap_or(600)
+  );
+}
+
+task_local! {
+  static QUOTA: Cell<Option<Quota>> = Cell::new(None)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+  limit: u32,
+  remaining: u32,
+  reset_secs: u64,
+  // until the next request is let through, when this one was not
+  retry_secs: u64,
+}
+
+struct Bucket {
+  tokens: f64,
+  updated: Instant,
+}
+impl Bucket {
+  fn level(&self, now: Instant, per_minute: f64) -> f64 {
+    let elapsed = now.duration_since(self.updated);
+    let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+    (self.tokens + elapsed * per_minute / 60.0).min(per_minute)
+  }
+}
+
+struct Limiter<K> {
+  per_minute: u32,
+  buckets: Mutex<HashMap<K, Bucket>>,
+}
+impl<K: Hash + Eq> Limiter<K> {
+  fn new(per_minute: u32) -> Self {
+    Limiter {
+      per_minute: per_minute.max(1),
+      buckets: Mutex::new(HashMap::new()),
+    }
+  }
+
+  // Err when the bucket is empty
+  fn take(&self, key: K) -> Result<Quota, Quota> {
+    let per_minute = self.per_minute as f64;
+    let now = Instant::now();
+    let mut buckets = self.buckets.lock().unwrap();
+    if buckets.len() >= MAX_BUCKETS {
+      buckets.retain(|_, b| b.level(now, per_minute) < per_minute);
+    }
+    let bucket = buckets.entry(key).or_insert(Bucket {
+      tokens: per_minute,
+      updated: now,
+    });
+    bucket.tokens = bucket.level(now, per_minute);
+    bucket.updated = now;
+    let taken = bucket.tokens >= 1.0;
+    if taken {
+      bucket.tokens -= 1.0;
+    }
+    let secs_for = |tokens: f64| (tokens.max(0.0) * 60.0 / per_minute).ceil() as u64;
+    let quota = Quota {
+      limit: self.per_minute,
+      remaining: bucket.tokens.floor() as u32,
+      reset_secs: secs_for(per_minute - bucket.tokens),
+      retry_secs: secs_for(1.0 - bucket.tokens),
+    };
+    match taken {
+      true => Ok(quota),
+      false => Err(quota),
+    }
+  }
+}
+
+#[derive(Debug)]
+pub struct Limited(Quota);
+impl fmt::Display for Limited {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.description())
+  }
+}
+impl Error for Limited {
+  fn description(&self) -> &str {
+    "rate limited"
+  }
+}
+
+fn admit(taken: Result<Quota, Quota>) -> Result<(), Rejection> {
+  match taken {
+    Ok(quota) => {
+      QUOTA.with(|q| q.set(Some(quota)));
+      Ok(())
+    }
+    Err(quota) => Err(warp::reject::custom(Limited(quota))),
+  }
+}
+
+// goes in front of the login route, after its path
+pub fn per_address() -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::addr::remote()
+    .and(
+      warp::header::<String>("x-forwarded-for")
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify(),
+    ).and_then(|addr: Option<SocketAddr>, forwarded: Option<String>| {
+      let ip = client_ip(addr, forwarded);
+      admit(LOGINS.take(ip)).map_err(|e| {
+        debug!("too many logins from {:?}", ip);
+        e
+      })
+    }).untuple_one()
+}
+
+// goes after authentication
+pub fn per_user(claims: Claims) -> Result<Claims, Rejection> {
+  admit(REQUESTS.take(claims.id))?;
+  Ok(claims)
+}
+
+fn client_ip(addr: Option<SocketAddr>, forwarded: Option<String>) -> Option<IpAddr> {
+  let ip = addr.map(|a| a.ip());
+  match (ip, forwarded) {
+    (Some(ip), Some(forwarded)) if TRUSTED_PROXIES.contains(&ip) => forwarded
+      .rsplit(',')
+      .next()
+      .and_then(|f| f.trim().parse().ok())
+      .or(Some(ip)),
+    (ip, _) => ip,
+  }
+}
+
+// around the routes going through `per_address` or `per_user`
+pub fn with_headers<T: Reply>(reply: T) -> impl Reply {
+  let quota = QUOTA.with(|q| q.replace(None)).unwrap_or(Quota {
+    limit: REQUESTS.per_minute,
+    remaining: REQUESTS.per_minute,
+    reset_secs: 0,
+    retry_secs: 0,
+  });
+  let reply = warp::reply::with_header(reply, "ratelimit-limit", quota.limit.to_string());
+  let reply = warp::reply::with_header(reply, "ratelimit-remaining", quota.remaining.to_string());
+  warp::reply::with_header(reply, "ratelimit-reset", quota.reset_secs.to_string())
+}
+
+pub fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, Rejection> {
+  let quota = match err.find_cause::<Limited>() {
+    Some(&Limited(quota)) => quota,
+    None => return Err(err),
+  };
+  let reply = warp::reply::json(&json!({ "error": "rate_limited" }));
+  let reply = warp::reply::with_status(reply, StatusCode::TOO_MANY_REQUESTS);
+  let reply = warp::reply::with_header(reply, "retry-after", quota.retry_secs.to_string());
+  let reply = warp::reply::with_header(reply, "ratelimit-limit", quota.limit.to_string());
+  let reply = warp::reply::with_header(reply, "ratelimit-remaining", "0");
+  Ok(warp::reply::with_header(
+    reply,
+    "ratelimit-reset",
+    quota.reset_secs.to_string(),
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Limiter;
+
+  #[test]
+  fn lets_a_minute_of_requests_through_at_once() {
+    let limiter = Limiter::new(3);
+    let remaining: Vec<u32> = (0..3)
+      .map(|_| limiter.take("a").map(|q| q.remaining).unwrap_or(99))
+      .collect();
+    assert_eq!(remaining, vec![2, 1, 0]);
+  }
+
+  #[test]
+  fn limits_past_an_empty_bucket() {
+    let limiter = Limiter::new(3);
+    for _ in 0..3 {
+      assert!(limiter.take("a").is_ok());
+    }
+    let quota = limiter.take("a").unwrap_err();
+    assert_eq!(quota.limit, 3);
+    assert_eq!(quota.remaining, 0);
+    assert_eq!(quota.retry_secs, 20);
+    assert_eq!(quota.reset_secs, 60);
+  }
+
+  #[test]
+  fn keeps_a_bucket_per_key() {
+    let limiter = Limiter::new(1);
+    assert!(limiter.take("a").is_ok());
+    assert!(limiter.take("a").is_err());
+    assert!(limiter.take("b").is_ok());
+  }
+
+  #[test]
+  fn allows_at_least_one_request() {
+    let limiter = Limiter::new(0);
+    assert_eq!(limiter.per_minute, 1);
+    assert!(limiter.take(()).is_ok());
+  }
+}
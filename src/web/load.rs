@@ -0,0 +1,87 @@
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use warp::http::StatusCode;
+use warp::{self, Filter, Rejection};
+
+use db::pool_saturation;
+
+// Under load, expensive routes (search, stats, previews, ...) answer 503 so
+// that logins and plain reads keep going through.
+//
+// SHED_MAX_IN_FLIGHT     requests being handled at once, 256 if unset
+// SHED_POOL_SATURATION   share of busy database connections, 0.9 if unset
+
+static RETRY_AFTER_SECS: u64 = 5;
+
+lazy_static! {
+  static ref MAX_IN_FLIGHT: usize = env::var("SHED_MAX_IN_FLIGHT")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(256);
+  static ref MAX_POOL_SATURATION: f64 = env::var("SHED_POOL_SATURATION")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(0.9);
+}
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+// counts a request from when routing starts until its reply is built
+pub struct InFlight;
+impl InFlight {
+  pub fn enter() -> InFlight {
+    IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+    InFlight
+  }
+}
+impl Drop for InFlight {
+  fn drop(&mut self) {
+    IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+  }
+}
+
+#[derive(Debug)]
+pub struct Overloaded;
+impl fmt::Display for Overloaded {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(self.description())
+  }
+}
+impl Error for Overloaded {
+  fn description(&self) -> &str {
+    "server overloaded"
+  }
+}
+
+fn overloaded() -> bool {
+  IN_FLIGHT.load(Ordering::SeqCst) > *MAX_IN_FLIGHT || pool_saturation() >= *MAX_POOL_SATURATION
+}
+
+// goes in front of low priority routes, after their path
+pub fn shed() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  warp::any()
+    .and_then(|| match overloaded() {
+      true => {
+        debug!("shedding request, {} in flight", IN_FLIGHT.load(Ordering::SeqCst));
+        Err(warp::reject::custom(Overloaded))
+      }
+      false => Ok(()),
+    }).untuple_one()
+}
+
+pub fn handle_rejection(err: Rejection) -> Result<impl warp::Reply, Rejection> {
+  match err.find_cause::<Overloaded>() {
+    Some(_) => {
+      let reply = warp::reply::json(&json!({ "error": "overloaded" }));
+      let reply = warp::reply::with_status(reply, StatusCode::SERVICE_UNAVAILABLE);
+      Ok(warp::reply::with_header(
+        reply,
+        "retry-after",
+        RETRY_AFTER_SECS.to_string(),
+      ))
+    }
+    None => Err(err),
+  }
+}
@@ -1,19 +1,101 @@
+use hyper::{rt, Body};
 use std::collections::HashMap;
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use warp::http::{Response, StatusCode};
+use warp::path::FullPath;
 use warp::ws::Ws2;
 use warp::{self, Filter, Rejection};
 
-mod handlers;
-mod jwt;
+mod api_tokens;
+mod assets;
+mod companion;
+mod chunked;
+mod cors;
+mod etag;
+mod export;
+mod fever;
+mod guard;
+pub mod handlers;
+pub mod jwt;
+mod kiosk;
+mod limit;
+mod load;
+mod params;
+mod request_log;
 mod rest;
+mod setup;
+mod share;
+mod sse;
+pub mod version;
 pub mod types;
+mod warmup;
+mod websub;
 pub mod ws;
 
-use self::jwt::{authenticate, decode_jwt};
-use self::rest::{serve_static, show_feeds, show_item, show_items, ASSET_PATH};
-use self::types::{AccessToken, AssetFile, LoginParams, UserWebsocketState};
-use self::ws::ws_created;
+use self::assets::{asset_request, serve_static};
+use self::jwt::{authenticate, change_password, decode_jwt, logout, refresh};
+use self::load::{handle_rejection, shed, InFlight};
+use self::rest::{
+  add_feeds, add_retention_exemption, add_user, apply_filter, approve_user, create_filter,
+  create_invite, create_mute, create_notice, create_topic, create_webhook, delete_account,
+  delete_filter, delete_item, delete_mute, delete_quota, delete_retention_exemption, delete_topic,
+  delete_webhook, discover_feeds, dismiss_delivery, enable_filter, export_json, fetch_full,
+  import_json, lookup_item, mark_all_read, mark_feed_read, preview_filter, push_subscribe,
+  push_unsubscribe, read_notifications, refresh_all, refresh_feed, register, replay_delivery,
+  reset_password, retry_feed, search_feeds, search_items, set_auto_read, set_feed_order,
+  set_full_content, set_locale, set_push_notify, set_quota, set_rate_warning, set_refresh_window,
+  set_tags, set_unread_on_edit, set_user_disabled, show_activity, show_adjacent_unread,
+  show_client_config, show_content_retries, show_counts, show_deleted, show_failed_deliveries,
+  show_feed, show_feed_stats, show_feeds, show_filters, show_hygiene, show_icon, show_item,
+  show_items, show_media, show_muted_items, show_mutes, show_notifications, show_push_key,
+  show_quota, show_refresh_window, show_retention_exemptions, show_river, show_schedule,
+  show_starred, show_stats, show_tagged_items, show_tags, show_timeline, show_topics,
+  show_unread_as_of, show_users, show_webhook_deliveries, show_webhooks, star_item, subscribe,
+  sync_items, transfer_subscriptions, unsubscribe,
+};
+use self::types::{
+  AccessToken, AddFeedsParams, ApiTokenParams, AssetFile, AutoReadParams, ChangePasswordParams,
+  FeedOrderEntry, FeverPasswordParams, FilterParams, KioskParams, LocaleParams, LoginParams,
+  MuteParams, NoticeParams, PasswordParams, PushSubscribeParams, PushUnsubscribeParams, QuotaParams,
+  RateWarningParams, RefreshWindowParams, RegisterParams, RetentionExemptionParams, SetupParams,
+  SubscribeParams, SubscriptionEntry, SyncParams, TagsParams, TopicParams, TransferParams,
+  UnsubscribeParams, UserWebsocketState, WebhookParams,
+};
+use self::ws::ws_connected;
 
+use config::{self, SESSION_COOKIE};
+use db::{get_user, is_admin};
+use error;
+use metrics;
 use models::Claims;
+use streaming;
+
+// proxy claims are made per request and never handed out as tokens
+static PROXY_CLAIMS_TTL_SECS: i64 = 60;
+// for routes that fetch many feeds while the client waits
+static LONG_TIMEOUT_SECS: u64 = 300;
+
+lazy_static! {
+  // TRUSTED_PROXIES=10.0.0.2,10.0.0.3 enables header authentication from those addresses
+  static ref TRUSTED_PROXIES: Vec<IpAddr> = env::var("TRUSTED_PROXIES")
+    .unwrap_or_default()
+    .split(',')
+    .filter(|s| !s.trim().is_empty())
+    .filter_map(|s| match s.trim().parse() {
+      Ok(ip) => Some(ip),
+      Err(_) => {
+        error!("ignoring invalid trusted proxy '{}'", s);
+        None
+      }
+    }).collect();
+  static ref PROXY_AUTO_PROVISION: bool = env::var("PROXY_AUTO_PROVISION")
+    .map(|v| v == "true")
+    .unwrap_or(false);
+  // METRICS_TOKEN=... makes `/metrics` require `Authorization: Bearer ...`
+  static ref METRICS_TOKEN: Option<String> = env::var("METRICS_TOKEN").ok();
+}
 
 pub fn verify_token() -> impl warp::Filter<Extract = (Claims,), Error = Rejection> + Clone {
   warp::path::index()
@@ -22,59 +104,1472 @@ pub fn verify_token() -> impl warp::Filter<Extract = (Claims,), Error = Rejectio
 }
 
 pub fn make_claim(token: String) -> Result<Claims, Rejection> {
-  match decode_jwt(token) {
-    Ok(claim) => Ok(claim),
-    Err(_) => Err(warp::reject()),
+  let claims = decode_jwt(token)?;
+  request_log::set_user(claims.id);
+  warmup::user_active(claims.id);
+  Ok(claims)
+}
+
+// `Remote-User` / `X-Auth-Request-User`, only honored from TRUSTED_PROXIES
+pub fn proxy_auth() -> impl warp::Filter<Extract = (Claims,), Error = Rejection> + Clone {
+  warp::addr::remote()
+    .and(
+      warp::header::<String>("remote-user")
+        .or(warp::header::<String>("x-auth-request-user"))
+        .unify(),
+    ).and_then(|addr: Option<SocketAddr>, username: String| proxy_claim(addr, username))
+}
+
+pub fn proxy_claim(addr: Option<SocketAddr>, username: String) -> Result<Claims, Rejection> {
+  match addr {
+    Some(a) if TRUSTED_PROXIES.contains(&a.ip()) => (),
+    _ => return Err(warp::reject()),
+  };
+  let user = match get_user(&username) {
+    Ok(user) => Some(user),
+    Err(error::HermesError::NotFound) if *PROXY_AUTO_PROVISION => {
+      handlers::provision_user(&username)
+    }
+    Err(error::HermesError::NotFound) => None,
+    Err(e) => return Err(e.into()),
+  };
+  match user {
+    Some(ref user) if user.active() => {
+      request_log::set_user(user.id);
+      warmup::user_active(user.id);
+      Claims::for_user(user, PROXY_CLAIMS_TTL_SECS).ok_or_else(|| warp::reject::server_error())
+    }
+    _ => Err(warp::reject::forbidden()),
   }
 }
 
+// with cookie sessions a foreign page could open a socket as the user
+pub fn ws_origin() -> impl warp::Filter<Extract = (), Error = Rejection> + Clone {
+  let origin = warp::header::<String>("origin")
+    .map(Some)
+    .or(warp::any().map(|| None))
+    .unify();
+  let host = warp::header::<String>("host")
+    .map(Some)
+    .or(warp::any().map(|| None))
+    .unify();
+  origin
+    .and(host)
+    .and_then(|origin: Option<String>, host: Option<String>| match origin {
+      Some(ref o) if !config::WEB.origin_allowed(o, host.as_ref().map(|h| h.as_str())) => {
+        error!("WS: rejected origin {}", o);
+        Err(warp::reject::forbidden())
+      }
+      _ => Ok(()),
+    }).untuple_one()
+}
+
+// passes requests asking for `?purge=true` only when that is `wanted`
+fn purge(wanted: bool) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+  warp::query::<HashMap<String, String>>()
+    .or(warp::any().map(HashMap::new))
+    .unify()
+    .and_then(move |query: HashMap<String, String>| {
+      match query.get("purge").map(|p| p == "true").unwrap_or(false) == wanted {
+        true => Ok(()),
+        false => Err(warp::reject::not_found()),
+      }
+    }).untuple_one()
+}
+
+// GET /metrics, websockets are only counted when `state` is given
+fn metrics_route(
+  state: Option<UserWebsocketState>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+  warp::get2()
+    .and(warp::path("metrics"))
+    .and(warp::path::index())
+    .and(
+      warp::header::<String>("authorization")
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify(),
+    ).and_then(move |authorization: Option<String>| {
+      if let Some(ref token) = *METRICS_TOKEN {
+        if authorization != Some(format!("Bearer {}", token)) {
+          return Err(warp::reject::forbidden());
+        }
+      }
+      let body = metrics::render(state.as_ref().map(|s| s.connections()));
+      Ok(warp::reply::with_header(
+        body,
+        "content-type",
+        "text/plain; version=0.0.4",
+      ))
+    })
+}
+
+// `hermes worker` has no other routes, but can still be scraped
+pub fn start_metrics() {
+  let routes = metrics_route(None);
+  rt::spawn(warp::serve(routes).bind(([0, 0, 0, 0], config::WEB.port)));
+}
+
+// with TLS, HTTP_REDIRECT_PORT answers every request with the same URL over HTTPS
+fn https_redirect() -> impl Filter<Extract = (Response<Body>,), Error = Rejection> + Clone {
+  warp::header::<String>("host")
+    .and(warp::path::full())
+    .and(
+      warp::query::raw()
+        .map(|q: String| format!("?{}", q))
+        .or(warp::any().map(String::new))
+        .unify(),
+    ).and_then(|host: String, path: FullPath, query: String| {
+      let location = config::WEB
+        .https_url(&host, &format!("{}{}", path.as_str(), query))
+        .ok_or_else(|| warp::reject::bad_request())?;
+      Ok(
+        Response::builder()
+          .status(StatusCode::PERMANENT_REDIRECT)
+          .header("location", location)
+          .body(Body::empty())
+          .unwrap(),
+      )
+    })
+}
+
 pub fn start_web(state: UserWebsocketState) {
+  guard::log_panics();
   let state2 = state.clone();
   let jwt_auth =
     warp::query::<AccessToken>().and_then(|token: AccessToken| make_claim(token.access_token));
+  let cookie_auth = warp::cookie(SESSION_COOKIE).and_then(|token: String| make_claim(token));
+  let token_auth = jwt_auth.or(cookie_auth).unify();
+  let auth = proxy_auth()
+    .or(api_tokens::auth())
+    .or(token_auth.clone())
+    .unify()
+    .and_then(limit::per_user);
+  // for the admin's routes, answers other users 403
+  let admin = auth.clone().and_then(|claims: Claims| match is_admin(claims.id) {
+    Ok(true) => Ok(claims),
+    Ok(false) => Err(warp::reject::forbidden()),
+    Err(e) => Err(Rejection::from(e)),
+  });
 
+  let login_state = state.clone();
   let authenticate = warp::post2()
     .and(warp::path("authenticate"))
     .and(warp::path::index())
+    .and(limit::per_address())
+    .and(warp::body::json())
+    .and_then(move |payload: LoginParams| {
+      guard::run(|| authenticate(payload, login_state.clone()))
+    }).map(limit::with_headers);
+
+  let refresh = warp::post2()
+    .and(warp::path("refresh"))
+    .and(warp::path::index())
+    .and(token_auth.clone())
+    .and_then(|claims| guard::run(|| refresh(claims)));
+
+  let logout = warp::post2()
+    .and(warp::path("logout"))
+    .and(warp::path::index())
+    .and(token_auth.clone())
+    .and_then(|claims| guard::run(|| logout(claims)));
+
+  let accept_language = warp::header::<String>("accept-language")
+    .map(Some)
+    .or(warp::any().map(|| None))
+    .unify();
+  let register = warp::post2()
+    .and(warp::path("register"))
+    .and(warp::path::index())
+    .and(warp::body::json())
+    .and(accept_language.clone())
+    .and_then(|payload: RegisterParams, lang| guard::run(|| register(payload, lang)));
+
+  // GET|POST /setup, until the first user exists
+  let setup_page = warp::get2()
+    .and(warp::path("setup"))
+    .and(warp::path::index())
+    .and_then(|| guard::run(setup::show_page));
+  let setup = warp::post2()
+    .and(warp::path("setup"))
+    .and(warp::path::index())
     .and(warp::body::json())
-    .and_then(|payload: LoginParams| authenticate(payload));
+    .and(accept_language)
+    .and_then(|payload: SetupParams, lang| guard::run(|| setup::run(payload, lang)));
+
+  // GET /config.json, for the UI
+  let client_config = warp::get2()
+    .and(warp::path("config.json"))
+    .and(warp::path::index())
+    .and_then(|| guard::run(show_client_config));
+
+  // POST /fever/?api&...
+  let fever = warp::post2()
+    .and(warp::path("fever"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(warp::body::form())
+    .and_then(|query, form| guard::run(|| fever::handle(query, form)));
+
+  // GET /websub/callback/:token, a hub confirming a subscription
+  let websub_verify = warp::get2()
+    .and(warp::path("websub"))
+    .and(warp::path("callback"))
+    .and(warp::path::param::<String>())
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(|token, query| guard::run(|| websub::verify(token, query)));
+
+  // POST /websub/callback/:token, a hub pushing a new version of a feed
+  let websub_receive = warp::post2()
+    .and(warp::path("websub"))
+    .and(warp::path("callback"))
+    .and(warp::path::param::<String>())
+    .and(warp::path::index())
+    .and(
+      warp::header::<String>("x-hub-signature")
+        .map(Some)
+        .or(warp::any().map(|| None))
+        .unify(),
+    ).and(warp::body::content_length_limit(*streaming::MAX_FEED_BYTES as u64))
+    .and(warp::body::concat())
+    .and_then(|token, signature, body| guard::run(|| websub::receive(token, signature, body)));
+
+  // GET /kiosk/:token/items, with no other authentication
+  let kiosk_items = warp::get2()
+    .and(warp::path("kiosk"))
+    .and(warp::path::param::<String>())
+    .and(warp::path("items"))
+    .and(warp::path::index())
+    .and_then(|token| guard::run(|| kiosk::show_items(token)));
+
+  // GET /share/:token, a public page for one item
+  let shared_item = warp::get2()
+    .and(warp::path("share"))
+    .and(warp::path::param::<String>())
+    .and(warp::path::index())
+    .and_then(|token| guard::run(|| share::show_page(token)));
 
   let assets = warp::get2()
     .and(warp::path::param::<AssetFile>())
-    .and_then(|a: AssetFile| serve_static(a));
+    .and(warp::path::index())
+    .and(asset_request())
+    .and_then(|a: AssetFile, request| serve_static(a, request));
 
+  // the UI routes everything else itself, but an API path no route took, e.g.
+  // one whose id isn't a number, is answered with the API's rejection
   let star = warp::get2()
-    .and(warp::any())
-    .and(warp::fs::file(format!("{}/index.html", ASSET_PATH)));
+    .and(warp::path::full())
+    .and_then(|path: FullPath| match path.as_str() == "/api" || path.as_str().starts_with("/api/") {
+      true => Err(warp::reject::not_found()),
+      false => Ok(()),
+    }).untuple_one()
+    .and(asset_request())
+    .and_then(|request| serve_static(AssetFile("index.html".to_owned()), request));
 
   // /api/feeds
-  let api_feeds = warp::path("api")
+  let api_feeds = version::api()
+    .and(warp::path("feeds"))
+    .and(warp::query::<HashMap<String, String>>())
+    .and(etag::if_none_match())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, tags, claims| {
+      guard::run(|| show_feeds(claims, query, tags))
+    });
+  // POST /api/add_feeds
+  let add_state = state.clone();
+  let api_add_feeds = warp::post2()
+    .and(version::api())
+    .and(warp::path("add_feeds"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(move |claims, params: AddFeedsParams| {
+      guard::run_for(Duration::from_secs(LONG_TIMEOUT_SECS), || {
+        add_feeds(claims, params, add_state.clone())
+      })
+    });
+  // GET /api/export/json
+  let api_export_json = warp::get2()
+    .and(version::api())
+    .and(warp::path("export"))
+    .and(warp::path("json"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| export_json(claims)));
+  // POST /api/import/json
+  let import_state = state.clone();
+  let api_import_json = warp::post2()
+    .and(version::api())
+    .and(warp::path("import"))
+    .and(warp::path("json"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(move |claims, entries: Vec<SubscriptionEntry>| {
+      guard::run_for(Duration::from_secs(LONG_TIMEOUT_SECS), || {
+        import_json(claims, entries, import_state.clone())
+      })
+    });
+  // /api/feeds/hygiene
+  let api_hygiene = warp::get2()
+    .and(version::api())
+    .and(warp::path("feeds"))
+    .and(warp::path("hygiene"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_hygiene(claims)));
+  // PATCH /api/feeds/order
+  let api_feed_order = warp::patch()
+    .and(version::api())
+    .and(warp::path("feeds"))
+    .and(warp::path("order"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, entries: Vec<FeedOrderEntry>| {
+      guard::run(|| set_feed_order(claims, entries))
+    });
+  // /api/feeds/search?q=
+  let api_feeds_search = warp::get2()
+    .and(version::api())
+    .and(warp::path("feeds"))
+    .and(warp::path("search"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, claims| guard::run(|| search_feeds(claims, query)));
+  // GET /api/feeds/discover?url=
+  let api_discover = warp::get2()
+    .and(version::api())
     .and(warp::path("feeds"))
-    .and(jwt_auth)
-    .and_then(|claims| show_feeds(claims));
+    .and(warp::path("discover"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(|_claims, query| guard::run(|| discover_feeds(query)));
+  // POST /api/subscribe
+  let subscribe_state = state.clone();
+  let api_subscribe = warp::post2()
+    .and(version::api())
+    .and(warp::path("subscribe"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(move |claims, params: SubscribeParams| {
+      guard::run_for(Duration::from_secs(LONG_TIMEOUT_SECS), || {
+        subscribe(claims, params, subscribe_state.clone())
+      })
+    });
+  // POST /api/unsubscribe
+  let api_unsubscribe = warp::post2()
+    .and(version::api())
+    .and(warp::path("unsubscribe"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: UnsubscribeParams| guard::run(|| unsubscribe(claims, params)));
+  // /api/account/activity
+  let activity_state = state.clone();
+  let api_activity = warp::get2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("activity"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(move |claims| guard::run(|| show_activity(claims, activity_state.clone())));
+  // POST /api/account/locale
+  let api_locale = warp::post2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("locale"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: LocaleParams| guard::run(|| set_locale(claims, params)));
+  // POST /api/account/rate_warning
+  let api_rate_warning = warp::post2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("rate_warning"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: RateWarningParams| guard::run(|| set_rate_warning(claims, params)));
+  // POST /api/account/auto_read
+  let api_auto_read = warp::post2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("auto_read"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: AutoReadParams| guard::run(|| set_auto_read(claims, params)));
+  // GET /api/account/retention
+  let api_retention = warp::get2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("retention"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_retention_exemptions(claims)));
+  // POST /api/account/retention
+  let api_retention_add = warp::post2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("retention"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: RetentionExemptionParams| {
+      guard::run(|| add_retention_exemption(claims, params))
+    });
+  // DELETE /api/account/retention/:id
+  let api_retention_delete = warp::delete2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("retention"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|exemption_id, claims| {
+      guard::run(|| delete_retention_exemption(claims, exemption_id))
+    });
+  // GET /api/account/deliveries/failed
+  let api_failed_deliveries = warp::get2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("deliveries"))
+    .and(warp::path("failed"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_failed_deliveries(claims)));
+  // POST /api/account/deliveries/failed/:id/replay
+  let api_replay_delivery = warp::post2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("deliveries"))
+    .and(warp::path("failed"))
+    .and(params::param::<i32>())
+    .and(warp::path("replay"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|delivery_id, claims| guard::run(|| replay_delivery(claims, delivery_id)));
+  // DELETE /api/account/deliveries/failed/:id
+  let api_dismiss_delivery = warp::delete2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("deliveries"))
+    .and(warp::path("failed"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|delivery_id, claims| guard::run(|| dismiss_delivery(claims, delivery_id)));
+  // POST /api/fever/password
+  let api_fever_password = warp::post2()
+    .and(version::api())
+    .and(warp::path("fever"))
+    .and(warp::path("password"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: FeverPasswordParams| {
+      guard::run(|| fever::set_password(claims, params))
+    });
+  // /api/schedule
+  let api_schedule = warp::get2()
+    .and(version::api())
+    .and(warp::path("schedule"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(admin.clone())
+    .and_then(|_claims| guard::run(show_schedule));
+  // /api/schedule/content
+  let api_content_retries = warp::get2()
+    .and(version::api())
+    .and(warp::path("schedule"))
+    .and(warp::path("content"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and_then(|_claims| guard::run(show_content_retries));
+  // /api/debug/items?as_of=&user=
+  let debug_items = warp::get2()
+    .and(version::api_or_root())
+    .and(warp::path("debug"))
+    .and(warp::path("items"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(|_claims, query| guard::run(|| show_unread_as_of(query)));
+  // /api/admin/stats
+  let api_stats = warp::get2()
+    .and(version::api())
+    .and(warp::path("admin"))
+    .and(warp::path("stats"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(admin.clone())
+    .and_then(|_claims| guard::run(show_stats));
+  // GET|POST /api/filters
+  let api_filters = warp::get2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_filters(claims)));
+  let api_create_filter = warp::post2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: FilterParams| guard::run(|| create_filter(claims, params)));
+  // DELETE /api/filters/:filter_id
+  let api_delete_filter = warp::delete2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|filter_id, claims| guard::run(|| delete_filter(claims, filter_id)));
+  // POST|DELETE /api/filters/:filter_id/enable
+  let api_enable_filter = warp::post2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(params::param::<i32>())
+    .and(warp::path("enable"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|filter_id, claims| guard::run(|| enable_filter(claims, filter_id, true)));
+  let api_disable_filter = warp::delete2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(params::param::<i32>())
+    .and(warp::path("enable"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|filter_id, claims| guard::run(|| enable_filter(claims, filter_id, false)));
+  // POST /api/filters/:filter_id/preview
+  let api_preview_filter = warp::post2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(params::param::<i32>())
+    .and(warp::path("preview"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(auth.clone())
+    .and_then(|filter_id, claims| guard::run(|| preview_filter(claims, filter_id)));
+  // POST /api/filters/:filter_id/apply
+  let apply_state = state.clone();
+  let api_apply_filter = warp::post2()
+    .and(version::api())
+    .and(warp::path("filters"))
+    .and(params::param::<i32>())
+    .and(warp::path("apply"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(auth.clone())
+    .and_then(move |filter_id, claims| {
+      guard::run_for(Duration::from_secs(LONG_TIMEOUT_SECS), || {
+        apply_filter(claims, filter_id, apply_state.clone())
+      })
+    });
+  // GET|POST /api/webhooks
+  let api_webhooks = warp::get2()
+    .and(version::api())
+    .and(warp::path("webhooks"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_webhooks(claims)));
+  let api_create_webhook = warp::post2()
+    .and(version::api())
+    .and(warp::path("webhooks"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: WebhookParams| guard::run(|| create_webhook(claims, params)));
+  // DELETE /api/webhooks/:webhook_id
+  let api_delete_webhook = warp::delete2()
+    .and(version::api())
+    .and(warp::path("webhooks"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|webhook_id, claims| guard::run(|| delete_webhook(claims, webhook_id)));
+  // GET /api/webhooks/:webhook_id/deliveries
+  let api_webhook_deliveries = warp::get2()
+    .and(version::api())
+    .and(warp::path("webhooks"))
+    .and(params::param::<i32>())
+    .and(warp::path("deliveries"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|webhook_id, claims| {
+      guard::run(|| show_webhook_deliveries(claims, webhook_id))
+    });
+  // GET|POST /api/mutes
+  let api_mutes = warp::get2()
+    .and(version::api())
+    .and(warp::path("mutes"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_mutes(claims)));
+  let api_create_mute = warp::post2()
+    .and(version::api())
+    .and(warp::path("mutes"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: MuteParams| guard::run(|| create_mute(claims, params)));
+  // DELETE /api/mutes/:mute_id
+  let api_delete_mute = warp::delete2()
+    .and(version::api())
+    .and(warp::path("mutes"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|mute_id, claims| guard::run(|| delete_mute(claims, mute_id)));
+  // GET /api/mutes/items
+  let api_muted_items = warp::get2()
+    .and(version::api())
+    .and(warp::path("mutes"))
+    .and(warp::path("items"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_muted_items(claims)));
+  // GET /api/counts
+  let api_counts = warp::get2()
+    .and(version::api())
+    .and(warp::path("counts"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_counts(claims)));
+  // GET /api/companion?cursor=&wait=, long-polled by tray apps
+  let api_companion = warp::get2()
+    .and(version::api())
+    .and(warp::path("companion"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(|claims, query| {
+      guard::run_for(Duration::from_secs(companion::MAX_WAIT_SECS + 5), || {
+        companion::poll(claims, query)
+      })
+    });
+  // GET|POST /api/tokens
+  let api_list_tokens = warp::get2()
+    .and(version::api())
+    .and(warp::path("tokens"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| api_tokens::list(claims)));
+  let api_create_token = warp::post2()
+    .and(version::api())
+    .and(warp::path("tokens"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: ApiTokenParams| guard::run(|| api_tokens::create(claims, params)));
+  // DELETE /api/tokens/:token_id
+  let api_revoke_token = warp::delete2()
+    .and(version::api())
+    .and(warp::path("tokens"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|token_id, claims| guard::run(|| api_tokens::revoke(claims, token_id)));
+  // GET|POST /api/kiosk
+  let api_kiosks = warp::get2()
+    .and(version::api())
+    .and(warp::path("kiosk"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| kiosk::list(claims)));
+  let api_create_kiosk = warp::post2()
+    .and(version::api())
+    .and(warp::path("kiosk"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: KioskParams| guard::run(|| kiosk::create(claims, params)));
+  // DELETE /api/kiosk/:kiosk_id
+  let api_revoke_kiosk = warp::delete2()
+    .and(version::api())
+    .and(warp::path("kiosk"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|kiosk_id, claims| guard::run(|| kiosk::revoke(claims, kiosk_id)));
+  // POST /api/invites
+  let api_invites = warp::post2()
+    .and(version::api())
+    .and(warp::path("invites"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and_then(|claims| guard::run(|| create_invite(claims)));
+  // POST /api/users/:user_id/approve
+  let api_approve = warp::post2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("approve"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and_then(|user_id, _claims| guard::run(|| approve_user(user_id)));
+  // GET|POST /api/users
+  let api_users = warp::get2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(|_claims, query| guard::run(|| show_users(query)));
+  let api_add_user = warp::post2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::body::json())
+    .and_then(|_claims, login: LoginParams| guard::run(|| add_user(login)));
+  // POST /api/users/:user_id/disable
+  let disable_state = state.clone();
+  let api_disable_user = warp::post2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("disable"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and_then(move |user_id, claims| {
+      guard::run(|| set_user_disabled(claims, user_id, true, disable_state.clone()))
+    });
+  // POST /api/users/:user_id/enable
+  let enable_state = state.clone();
+  let api_enable_user = warp::post2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("enable"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and_then(move |user_id, claims| {
+      guard::run(|| set_user_disabled(claims, user_id, false, enable_state.clone()))
+    });
+  // POST /api/users/:user_id/password
+  let password_state = state.clone();
+  let api_reset_password = warp::post2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("password"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::body::json())
+    .and_then(move |user_id, _claims, params: PasswordParams| {
+      guard::run(|| reset_password(user_id, params, password_state.clone()))
+    });
+  // POST /admin/users/:user_id/transfer
+  let admin_transfer = warp::post2()
+    .and(warp::path("admin"))
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("transfer"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::body::json())
+    .and_then(|user_id, _claims, params: TransferParams| {
+      guard::run(|| transfer_subscriptions(user_id, params))
+    });
+  // GET /api/account/quota
+  let api_quota = warp::get2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("quota"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_quota(claims)));
+  // POST /api/account/password
+  let password_change_state = state.clone();
+  let api_change_password = warp::post2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path("password"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(move |claims, params: ChangePasswordParams| {
+      guard::run(|| change_password(claims, params, password_change_state.clone()))
+    });
+  // DELETE /api/account
+  let delete_account_state = state.clone();
+  let api_delete_account = warp::delete2()
+    .and(version::api())
+    .and(warp::path("account"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(move |claims, params: PasswordParams| {
+      guard::run(|| delete_account(claims, params, delete_account_state.clone()))
+    });
+  // POST /api/quotas, the defaults
+  let api_default_quota = warp::post2()
+    .and(version::api())
+    .and(warp::path("quotas"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::body::json())
+    .and_then(|_claims, params: QuotaParams| guard::run(|| set_quota(None, params)));
+  // POST /api/users/:user_id/quota, DELETE /api/users/:user_id/quota/:name
+  let api_user_quota = warp::post2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("quota"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::body::json())
+    .and_then(|user_id, _claims, params: QuotaParams| {
+      guard::run(|| set_quota(Some(user_id), params))
+    });
+  let api_delete_user_quota = warp::delete2()
+    .and(version::api())
+    .and(warp::path("users"))
+    .and(params::param::<i32>())
+    .and(warp::path("quota"))
+    .and(warp::path::param::<String>())
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and_then(|user_id, name, _claims| guard::run(|| delete_quota(user_id, name)));
+  // GET|POST /api/topics
+  let api_topics = warp::get2()
+    .and(version::api())
+    .and(warp::path("topics"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_topics(claims)));
+  let api_create_topic = warp::post2()
+    .and(version::api())
+    .and(warp::path("topics"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: TopicParams| guard::run(|| create_topic(claims, params)));
+  // DELETE /api/topics/:topic_id
+  let api_delete_topic = warp::delete2()
+    .and(version::api())
+    .and(warp::path("topics"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|topic_id, claims| guard::run(|| delete_topic(claims, topic_id)));
+  // /api/topics/:topic_id/timeline
+  let api_timeline = warp::get2()
+    .and(version::api())
+    .and(warp::path("topics"))
+    .and(params::param::<i32>())
+    .and(warp::path("timeline"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(auth.clone())
+    .and_then(|topic_id, claims| guard::run(|| show_timeline(claims, topic_id)));
+  // /api/feed/:feed_id
+  let api_feed = warp::get2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(etag::if_none_match())
+    .and(auth.clone())
+    .and_then(|feed_id, tags, claims| guard::run(|| show_feed(claims, feed_id, tags)));
+  // /api/feed/:feed_id/stats
+  let api_feed_stats = warp::get2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("stats"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| show_feed_stats(claims, feed_id)));
+  // /api/search?q=
+  let api_search = warp::get2()
+    .and(version::api())
+    .and(warp::path("search"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, claims| guard::run(|| search_items(claims, query)));
+  // POST /api/feed/:feed_id/tags
+  let api_feed_tags = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("tags"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|feed_id, claims, params: TagsParams| {
+      guard::run(|| set_tags(claims, feed_id, params))
+    });
+  // POST|DELETE /api/feed/:feed_id/full_content
+  let api_full_content = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("full_content"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| set_full_content(claims, feed_id, true)));
+  let api_no_full_content = warp::delete2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("full_content"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| set_full_content(claims, feed_id, false)));
+  // POST|DELETE /api/feed/:feed_id/unread_on_edit
+  let api_unread_on_edit = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("unread_on_edit"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| set_unread_on_edit(claims, feed_id, true)));
+  let api_no_unread_on_edit = warp::delete2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("unread_on_edit"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| set_unread_on_edit(claims, feed_id, false)));
+  // POST|DELETE /api/feed/:feed_id/push
+  let api_push_feed = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("push"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| set_push_notify(claims, feed_id, true)));
+  let api_no_push_feed = warp::delete2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("push"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| set_push_notify(claims, feed_id, false)));
+  // GET /api/push/key
+  let api_push_key = warp::get2()
+    .and(version::api())
+    .and(warp::path("push"))
+    .and(warp::path("key"))
+    .and(warp::path::index())
+    .and_then(|| guard::run(show_push_key));
+  // POST|DELETE /api/push/subscribe
+  let api_push_subscribe = warp::post2()
+    .and(version::api())
+    .and(warp::path("push"))
+    .and(warp::path("subscribe"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: PushSubscribeParams| {
+      guard::run(|| push_subscribe(claims, params))
+    });
+  let api_push_unsubscribe = warp::delete2()
+    .and(version::api())
+    .and(warp::path("push"))
+    .and(warp::path("subscribe"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|claims, params: PushUnsubscribeParams| {
+      guard::run(|| push_unsubscribe(claims, params))
+    });
+  // GET|POST /api/feed/:feed_id/refresh_window
+  let api_refresh_window = warp::get2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("refresh_window"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| show_refresh_window(claims, feed_id)));
+  let api_set_refresh_window = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("refresh_window"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(|feed_id, claims, params: RefreshWindowParams| {
+      guard::run(|| set_refresh_window(claims, feed_id, params))
+    });
+  // /api/feed/:feed_id/export?format=&range=&content=
+  let api_export_items = warp::get2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("export"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::query::<HashMap<String, String>>())
+    .and_then(|feed_id, claims, query| guard::run(|| export::feed_items(claims, feed_id, query)));
+  // POST /api/feed/:feed_id/refresh
+  let api_refresh_feed = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("refresh"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| refresh_feed(claims, feed_id)));
+  // POST /api/refresh_all
+  let api_refresh_all = warp::post2()
+    .and(version::api())
+    .and(warp::path("refresh_all"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| {
+      guard::run_for(Duration::from_secs(LONG_TIMEOUT_SECS), || refresh_all(claims))
+    });
+  // POST /api/feed/:feed_id/retry
+  let api_retry_feed = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("retry"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, claims| guard::run(|| retry_feed(claims, feed_id)));
+  // /api/feed/:feed_id/icon
+  let api_icon = warp::get2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("icon"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|feed_id, _claims: Claims| guard::run(|| show_icon(feed_id)));
+  // /api/notifications
+  let notifications = warp::get2()
+    .and(version::api_or_root())
+    .and(warp::path("notifications"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, claims| {
+      guard::run(|| show_notifications(claims, query))
+    });
+  let create_notice = warp::post2()
+    .and(version::api_or_root())
+    .and(warp::path("notifications"))
+    .and(warp::path::index())
+    .and(admin.clone())
+    .and(warp::body::json())
+    .and_then(|_claims, params: NoticeParams| guard::run(|| create_notice(params)));
+  // POST /api/notifications/read, /api/notifications/:id/read
+  let read_all_notifications = warp::post2()
+    .and(version::api_or_root())
+    .and(warp::path("notifications"))
+    .and(warp::path("read"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| read_notifications(claims, None)));
+  let read_notification = warp::post2()
+    .and(version::api_or_root())
+    .and(warp::path("notifications"))
+    .and(params::param::<i32>())
+    .and(warp::path("read"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|notification_id, claims| {
+      guard::run(|| read_notifications(claims, Some(notification_id)))
+    });
+  // /api/media?url=
+  let media = warp::get2()
+    .and(version::api_or_root())
+    .and(warp::path("media"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query, _claims: Claims| guard::run(|| show_media(query)));
+  // /api/tags
+  let api_tags = warp::get2()
+    .and(version::api())
+    .and(warp::path("tags"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_tags(claims)));
+  // /api/items?tag=
+  let api_tagged_items = warp::get2()
+    .and(version::api())
+    .and(warp::path("items"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, claims| {
+      guard::run(|| show_tagged_items(claims, query))
+    });
+  // /api/timeline?unread=&starred=&tag=&before=&limit=&offset=
+  let api_river = warp::get2()
+    .and(version::api())
+    .and(warp::path("timeline"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, claims| guard::run(|| show_river(claims, query)));
+  // GET /api/item/next_unread?after_id=&feed_id=, and prev_unread
+  let api_next_unread = warp::get2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(warp::path("next_unread"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query, claims| guard::run(|| show_adjacent_unread(claims, query, true)));
+  let api_prev_unread = warp::get2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(warp::path("prev_unread"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query, claims| guard::run(|| show_adjacent_unread(claims, query, false)));
+  // GET /api/item/lookup?url=
+  let api_lookup_item = warp::get2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(warp::path("lookup"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query, claims| guard::run(|| lookup_item(claims, query)));
   // /api/item/:item_id
-  let api_item = warp::path("api")
+  let api_item = warp::get2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(etag::if_none_match())
+    .and(auth.clone())
+    .and_then(|item_id, tags, claims| guard::run(|| show_item(claims, item_id, tags)));
+  // POST /api/item/:item_id/share
+  let api_share_item = warp::post2()
+    .and(version::api())
     .and(warp::path("item"))
-    .and(warp::path::param::<i32>())
-    .and(jwt_auth)
-    .and_then(|item_id, claims| show_item(claims, item_id));
+    .and(params::param::<i32>())
+    .and(warp::path("share"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|item_id, claims| guard::run(|| share::create(claims, item_id)));
+  // GET /api/shares
+  let api_shares = warp::get2()
+    .and(version::api())
+    .and(warp::path("shares"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| share::list(claims)));
+  // DELETE /api/shares/:share_id
+  let api_revoke_share = warp::delete2()
+    .and(version::api())
+    .and(warp::path("shares"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|share_id, claims| guard::run(|| share::revoke(claims, share_id)));
+  // POST|DELETE /api/item/:item_id/star
+  let api_star = warp::post2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(params::param::<i32>())
+    .and(warp::path("star"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|item_id, claims| guard::run(|| star_item(claims, item_id, true)));
+  let api_unstar = warp::delete2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(params::param::<i32>())
+    .and(warp::path("star"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|item_id, claims| guard::run(|| star_item(claims, item_id, false)));
+  // POST /api/item/:item_id/fetch_full
+  let api_fetch_full = warp::post2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(params::param::<i32>())
+    .and(warp::path("fetch_full"))
+    .and(warp::path::index())
+    .and(shed())
+    .and(auth.clone())
+    .and_then(|item_id, claims| guard::run(|| fetch_full(claims, item_id)));
+  // DELETE /api/item/:item_id, the admin's with `?purge=true`
+  let delete_state = state.clone();
+  let api_delete_item = warp::delete2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(purge(false))
+    .and(auth.clone())
+    .and_then(move |item_id, claims| {
+      guard::run(|| delete_item(claims, item_id, false, delete_state.clone()))
+    });
+  let purge_state = state.clone();
+  let api_purge_item = warp::delete2()
+    .and(version::api())
+    .and(warp::path("item"))
+    .and(params::param::<i32>())
+    .and(warp::path::index())
+    .and(purge(true))
+    .and(admin.clone())
+    .and_then(move |item_id, claims| {
+      guard::run(|| delete_item(claims, item_id, true, purge_state.clone()))
+    });
+  // /api/items/deleted?since=
+  let api_deleted = warp::get2()
+    .and(version::api())
+    .and(warp::path("items"))
+    .and(warp::path("deleted"))
+    .and(warp::path::index())
+    .and(warp::query::<HashMap<String, String>>())
+    .and(auth.clone())
+    .and_then(|query: HashMap<String, String>, claims| guard::run(|| show_deleted(claims, query)));
+  // /api/starred
+  let api_starred = warp::get2()
+    .and(version::api())
+    .and(warp::path("starred"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(|claims| guard::run(|| show_starred(claims)));
   // /api/items/:feed_id
-  let api_items = warp::path("api")
+  let api_items = warp::get2()
+    .and(version::api())
     .and(warp::path("items"))
-    .and(warp::path::param::<i32>())
+    .and(params::param::<i32>())
+    .and(warp::path::index())
     .and(warp::query::<HashMap<String, String>>())
-    .and(jwt_auth)
-    .and_then(|feed_id, query: HashMap<String, String>, claims| show_items(claims, feed_id, query));
+    .and(auth.clone())
+    .and_then(|feed_id, query: HashMap<String, String>, claims| {
+      guard::run(|| show_items(claims, feed_id, query))
+    });
 
+  // POST /api/feed/:feed_id/read_all
+  let read_state = state.clone();
+  let api_feed_read_all = warp::post2()
+    .and(version::api())
+    .and(warp::path("feed"))
+    .and(params::param::<i32>())
+    .and(warp::path("read_all"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(move |feed_id, claims| {
+      guard::run(|| mark_feed_read(claims, feed_id, read_state.clone()))
+    });
+  // POST /api/read_all
+  let read_state = state.clone();
+  let api_read_all = warp::post2()
+    .and(version::api())
+    .and(warp::path("read_all"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(move |claims| guard::run(|| mark_all_read(claims, read_state.clone())));
+  // POST /api/sync
+  let sync_state = state.clone();
+  let api_sync = warp::post2()
+    .and(version::api())
+    .and(warp::path("sync"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and(warp::body::json())
+    .and_then(move |claims, params: SyncParams| {
+      guard::run(|| sync_items(claims, params, sync_state.clone()))
+    });
+
+  // GET /api/events
+  let events_state = state.clone();
+  let events = warp::get2()
+    .and(version::api_or_root())
+    .and(warp::path("events"))
+    .and(warp::path::index())
+    .and(auth.clone())
+    .and_then(move |claims| guard::run(|| sse::connect(claims, events_state.clone())));
+
+  // without a token or cookie, the socket's first message has to authenticate it
+  let ws_auth = auth.clone().map(Some).or(warp::any().map(|| None)).unify();
   let ws = warp::path("ws")
+    .and(ws_origin())
     .and(warp::ws2())
-    .and(jwt_auth)
-    .map(move |ws: Ws2, claims: Claims| {
+    .and(ws_auth)
+    .map(move |ws: Ws2, claims: Option<Claims>| {
       let state = state2.clone();
-      ws.on_upgrade(|websocket| ws_created(websocket, claims, state))
+      ws.on_upgrade(|websocket| ws_connected(websocket, claims, state))
     });
 
-  let api = api_feeds.or(api_items).or(api_item);
-  let routes = authenticate.or(api).or(assets).or(ws).or(star);
-  warp::serve(routes).run(([0, 0, 0, 0], 3030));
+  let api = api_star
+    .or(api_share_item)
+    .or(api_shares)
+    .or(api_revoke_share)
+    .or(api_unstar)
+    .or(api_fetch_full)
+    .or(api_delete_item)
+    .or(api_purge_item)
+    .or(api_deleted)
+    .or(api_starred)
+    .or(api_feeds_search)
+    .or(api_discover)
+    .or(api_hygiene)
+    .or(api_feed_order)
+    .or(api_search)
+    .or(api_feeds)
+    .or(api_feed)
+    .or(api_feed_stats)
+    .or(api_feed_tags)
+    .or(api_full_content)
+    .or(api_icon)
+    .or(api_no_full_content)
+    .or(api_unread_on_edit)
+    .or(api_no_unread_on_edit)
+    .or(api_push_feed)
+    .or(api_no_push_feed)
+    .or(api_push_key)
+    .or(api_push_subscribe)
+    .or(api_push_unsubscribe)
+    .or(api_refresh_window)
+    .or(api_set_refresh_window)
+    .or(api_retry_feed)
+    .or(api_refresh_feed)
+    .or(api_refresh_all)
+    .or(api_export_items)
+    .or(api_tags)
+    .or(api_tagged_items)
+    .or(api_river)
+    .or(api_items)
+    .or(api_lookup_item)
+    .or(api_item)
+    .or(api_feed_read_all)
+    .or(api_read_all)
+    .or(api_sync)
+    .or(api_add_feeds)
+    .or(api_export_json)
+    .or(api_import_json)
+    .or(api_subscribe)
+    .or(api_unsubscribe)
+    .or(api_activity)
+    .or(api_fever_password)
+    .or(api_locale)
+    .or(api_rate_warning)
+    .or(api_auto_read)
+    .or(api_retention)
+    .or(api_retention_add)
+    .or(api_retention_delete)
+    .or(api_failed_deliveries)
+    .or(api_replay_delivery)
+    .or(api_dismiss_delivery)
+    .or(api_schedule)
+    .or(api_content_retries)
+    .or(api_stats)
+    .or(debug_items)
+    .or(api_filters)
+    .or(api_create_filter)
+    .or(api_delete_filter)
+    .or(api_enable_filter)
+    .or(api_disable_filter)
+    .or(api_preview_filter)
+    .or(api_apply_filter)
+    .or(api_webhooks)
+    .or(api_create_webhook)
+    .or(api_delete_webhook)
+    .or(api_webhook_deliveries)
+    .or(api_mutes)
+    .or(api_create_mute)
+    .or(api_delete_mute)
+    .or(api_muted_items)
+    .or(api_counts)
+    .or(api_companion)
+    .or(api_kiosks)
+    .or(api_create_kiosk)
+    .or(api_revoke_kiosk)
+    .or(api_list_tokens)
+    .or(api_create_token)
+    .or(api_revoke_token)
+    .or(api_invites)
+    .or(api_approve)
+    .or(api_users)
+    .or(api_add_user)
+    .or(api_disable_user)
+    .or(api_enable_user)
+    .or(api_reset_password)
+    .or(admin_transfer)
+    .or(api_quota)
+    .or(api_change_password)
+    .or(api_delete_account)
+    .or(api_default_quota)
+    .or(api_user_quota)
+    .or(api_delete_user_quota)
+    .or(api_topics)
+    .or(api_create_topic)
+    .or(api_delete_topic)
+    .or(api_timeline)
+    .map(limit::with_headers);
+  let routes = authenticate
+    .or(refresh)
+    .or(logout)
+    .or(register)
+    .or(setup_page)
+    .or(setup)
+    .or(client_config)
+    .or(metrics_route(Some(state.clone())))
+    .or(fever)
+    .or(websub_verify)
+    .or(websub_receive)
+    .or(kiosk_items)
+    .or(shared_item)
+    .or(media)
+    .or(notifications)
+    .or(create_notice)
+    .or(read_all_notifications)
+    .or(read_notification)
+    .or(api).or(assets).or(ws).or(events).or(star);
+  // What applies to many routes is layered around them with warp's own
+  // combinators rather than put in handlers. A filter in front runs before
+  // them and can answer first by rejecting, like `shed` or
+  // `limit::per_address`; a `.map` or `.with` after them sees the reply, like
+  // `limit::with_headers` or `cors::with_headers`; a `.recover` turns
+  // rejections into replies. Per-request state goes with the connection's
+  // task, see `request_log`.
+  let routes = request_log::start()
+    .and(version::start())
+    .map(InFlight::enter)
+    .and(routes)
+    .map(|_in_flight: InFlight, reply| reply)
+    .recover(guard::handle_rejection)
+    .recover(limit::handle_rejection)
+    .recover(handle_rejection)
+    .recover(error::handle_rejection);
+  let routes = cors::preflight()
+    .or(cors::named_origin().and(routes.clone()).map(cors::with_headers))
+    .or(cors::any_origin().and(routes.clone()).map(cors::with_any_headers))
+    .or(cors::no_origin().and(routes))
+    .with(warp::log::custom(request_log::log_request));
+  let addr = ([0, 0, 0, 0], config::WEB.port);
+  match config::WEB.tls {
+    Some(ref tls) => {
+      if let Some(port) = config::WEB.http_redirect_port {
+        rt::spawn(warp::serve(https_redirect()).bind(([0, 0, 0, 0], port)));
+      }
+      info!("serving HTTPS on port {}", addr.1);
+      warp::serve(routes).tls(&tls.cert, &tls.key).run(addr);
+    }
+    None => warp::serve(routes).run(addr),
+  }
 }
@@ -0,0 +1,16 @@
+use std::str::FromStr;
+use warp::{self, Filter, Rejection};
+
+// Path segments parsed into the type a handler takes, e.g. `param::<i32>()`
+// for `/api/feed/:feed_id`. `warp::path::param` treats a segment that doesn't
+// parse as not found, leaving it to the other routes; this does the same for
+// those, but a request no route takes is answered 400 instead of 404, as the
+// path was right and only the value wasn't.
+
+pub fn param<T>() -> impl Filter<Extract = (T,), Error = Rejection> + Copy
+where
+  T: FromStr + Send + 'static,
+{
+  warp::path::param::<String>()
+    .and_then(|segment: String| segment.parse::<T>().map_err(|_| warp::reject::bad_request()))
+}
@@ -0,0 +1,81 @@
+use std::cell::Cell;
+use std::env;
+use std::time::Instant;
+use warp::{self, Filter, Rejection};
+
+use metrics;
+
+// One line per request once its reply is ready, e.g.
+//
+//   GET /api/feeds 200 12ms user=3
+//
+// REQUEST_LOG_FORMAT=json writes the same fields as a JSON object instead.
+// Requests no route accepted are not logged.
+//
+// The start time and the authenticated user are kept with the connection's
+// task, as warp's log filter only knows about the request and its status.
+
+lazy_static! {
+  static ref JSON_FORMAT: bool = env::var("REQUEST_LOG_FORMAT")
+    .map(|f| f == "json")
+    .unwrap_or(false);
+}
+
+task_local! {
+  static STARTED: Cell<Option<Instant>> = Cell::new(None)
+}
+
+task_local! {
+  static USER: Cell<Option<i32>> = Cell::new(None)
+}
+
+// goes in front of all routes
+pub fn start() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  warp::any()
+    .map(|| {
+      STARTED.with(|s| s.set(Some(Instant::now())));
+      USER.with(|u| u.set(None));
+    }).untuple_one()
+}
+
+// called once a request is authenticated
+pub fn set_user(user_id: i32) {
+  USER.with(|u| u.set(Some(user_id)));
+}
+
+pub fn log_request(info: warp::log::Info) {
+  let elapsed = STARTED.with(|s| s.get()).map(|s| s.elapsed());
+  let elapsed_ms = elapsed.map(|e| e.as_secs() * 1000 + e.subsec_millis() as u64);
+  let user = USER.with(|u| u.get());
+  metrics::record_request(
+    info.method().as_str(),
+    info.path(),
+    info.status().as_u16(),
+    elapsed,
+  );
+  if *JSON_FORMAT {
+    let line = json!({
+      "method": info.method().as_str(),
+      "path": info.path(),
+      "status": info.status().as_u16(),
+      "latency_ms": elapsed_ms,
+      "user_id": user,
+    });
+    info!("{}", line);
+  } else {
+    let latency = elapsed_ms
+      .map(|ms| format!("{}ms", ms))
+      .unwrap_or_else(|| "-".to_owned());
+    let user = user
+      .map(|u| u.to_string())
+      .unwrap_or_else(|| "-".to_owned());
+    info!(
+      "{} {} {} {} user={}",
+      info.method(),
+      info.path(),
+      info.status().as_u16(),
+      latency,
+      user
+    );
+  }
+}
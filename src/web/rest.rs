@@ -1,48 +1,679 @@
-use chrono::{DateTime, Utc};
-use futures::Future;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use futures::future::{self, Either};
+use futures::{stream, Future, IntoFuture, Stream};
 use std::collections::HashMap;
-use std::io;
-use std::{path, str};
-use tokio_fs;
-use tokio_io;
+use std::str;
+use url::Url;
 
 use hyper::Body;
-use warp::http::Response;
+use warp::http::{Response, StatusCode};
 use warp::{self, Rejection};
 
-use super::types::AssetFile;
-use db::{get_subscribed_feeds, get_subscribed_item, get_subscribed_items};
-use models::Claims;
+use super::chunked;
+use super::etag;
+use super::handlers::{self, RegisterError, RegistrationMode};
+use super::jwt::{end_sessions, generate_jwt};
+use super::types::{
+  AddFeedsParams, AutoReadParams, FeedOrderEntry, FilterParams, LocaleParams, LoginParams,
+  MuteParams, NoticeParams, PasswordParams, PushSubscribeParams, PushUnsubscribeParams, QuotaParams,
+  RateWarningParams, RefreshWindowParams, RegisterParams, RetentionExemptionParams, SubscribeParams,
+  SubscriptionEntry, SyncParams, TagsParams, TopicParams, TransferParams, UnsubscribeParams,
+  UserWebsocketState, WebhookParams,
+};
+use super::ws::ws_send_unseen_counts;
+use super::TRUSTED_PROXIES;
+use config;
+use db::{
+  self, count_subscribed_feeds, count_subscribed_items, get_starred_items, get_subscribed_feed_ids,
+  get_subscribed_feeds, get_subscribed_item, get_subscribed_items, mark_all_as_read,
+  mark_feed_as_read, set_item_starred,
+};
+use deliveries;
+use discovery;
+use error::{reject, HermesError};
+use extract;
+use feed;
+use guid_cache;
+use i18n;
+use media;
+use filters::{self, Rule};
+use push;
+use quotas::{self, Resource};
+use topics;
+use windows::Window;
+use models::{
+  AccountActivity, Action, AddFeedOutcome, AdminStats, CatalogFeed, Claims, CreatedWebhook,
+  DomainGroup, FeedHygiene, FeedInfo, FeedStats, ItemWithEnclosures, NotificationKind, Page,
+  ScheduledFeed, SubscribedFeedStatus, SubscribedItem, Timestamp, UnreadCounts, UnreadSnapshot,
+  User,
+};
 
-pub static ASSET_PATH: &'static str = "./ui/dist/static";
+static MAX_ADD_FEEDS: usize = 200;
+static MAX_MUTED_ITEMS: i64 = 500;
+static MAX_WEBHOOK_DELIVERIES: i64 = 200;
+static WEBHOOK_SECRET_BYTES: usize = 32;
+static REFRESH_CONCURRENCY: usize = 4;
+static MAX_SEARCH_RESULTS: i64 = 50;
+static MAX_ACTIVITY: i64 = 200;
+static MAX_TAGS: usize = 20;
+static MAX_TAG_LEN: usize = 32;
+static MAX_ORDERED_FEEDS: usize = 1000;
+static MAX_SYNC_IDS: usize = 10_000;
+// items of a feed are loaded and written this many at a time
+static ITEM_BATCH: i64 = 200;
+static MAX_SLOW_QUERIES: i64 = 20;
+// hygiene: no post for this long is stale, failing this often is failing
+static STALE_DAYS: i64 = 90;
+static FAILING_RATE: f64 = 0.5;
+// a noisy feed that is hardly read is better muted than gathered into a digest
+static MUTE_UNREAD_RATIO: f64 = 0.9;
 
 /// feeds ///
 
-pub fn show_feeds(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
-  match get_subscribed_feeds(&claims.id) {
-    Some(feeds) => Ok(warp::reply::json(&feeds)),
-    None => Err(warp::reject::not_found()),
+pub fn show_feeds(
+  claims: Claims,
+  query: HashMap<String, String>,
+  if_none_match: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let page = parse_page(&query)?;
+  match query.get("group_by").map(|g| g.as_str()) {
+    None => (),
+    Some("domain") => {
+      let (groups, total) = group_by_domain(claims.id, page)?;
+      return Ok(with_total(etag::json(&groups, if_none_match), total));
+    }
+    Some(_) => return Err(warp::reject::bad_request()),
+  };
+  let feeds = get_subscribed_feeds(&claims.id, page)?;
+  let total = count_subscribed_feeds(&claims.id)?;
+  let mut statuses = db::get_fetch_statuses(&feeds.iter().map(|f| f.id).collect())?;
+  let feeds: Vec<SubscribedFeedStatus> = feeds
+    .into_iter()
+    .map(|f| {
+      let status = statuses.remove(&f.id);
+      SubscribedFeedStatus::new(f, status)
+    }).collect();
+  Ok(with_total(etag::json(&feeds, if_none_match), total))
+}
+
+// ?group_by=domain pages through the groups, so every feed is loaded to build them
+fn group_by_domain(uid: i32, page: Page) -> Result<(Vec<DomainGroup>, i64), Rejection> {
+  let groups = DomainGroup::group(db::get_all_subscribed_feeds(&uid)?);
+  let total = groups.len() as i64;
+  let groups: Vec<_> = groups
+    .into_iter()
+    .skip(page.offset as usize)
+    .take(page.limit as usize)
+    .collect();
+  Ok((groups, total))
+}
+
+pub fn add_feeds(
+  claims: Claims,
+  params: AddFeedsParams,
+  state: UserWebsocketState,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let urls = params.urls;
+  let check = match urls.len() {
+    0 => Err(warp::reject::bad_request()),
+    n if n > MAX_ADD_FEEDS => Err(warp::reject::bad_request()),
+    _ => quotas::check(claims.id, Resource::Feeds, 1).map_err(Rejection::from),
+  };
+  check.into_future().and_then(move |_| {
+    debug!("user {} adding {} feeds", claims.id, urls.len());
+    feed::add_feeds(urls, claims.id, state)
+      .map(|results| warp::reply::json(&results))
+      .map_err(|_| warp::reject::server_error())
+  })
+}
+
+// a feed that isn't known yet is fetched and added first, as with `add_feeds`
+pub fn subscribe(
+  claims: Claims,
+  params: SubscribeParams,
+  state: UserWebsocketState,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let url = match (params.feed_id, params.feed_url) {
+    (Some(feed_id), None) => db::get_feed(feed_id).map(|f| f.feed_link).map_err(reject),
+    (None, Some(url)) => Ok(url),
+    _ => Err(warp::reject::bad_request()),
+  };
+  let uid = claims.id;
+  url.into_future().and_then(move |url| {
+    feed::add_feeds(vec![url], uid, state)
+      .map_err(|_| warp::reject::server_error())
+      .and_then(move |mut results| -> Result<_, Rejection> {
+        let result = results.pop().ok_or_else(|| warp::reject::server_error())?;
+        let status = match result.result {
+          AddFeedOutcome::Added => StatusCode::CREATED,
+          AddFeedOutcome::Duplicate => StatusCode::OK,
+          AddFeedOutcome::Invalid => return Err(warp::reject::bad_request()),
+          AddFeedOutcome::Unreachable | AddFeedOutcome::Timeout => {
+            return Err(HermesError::Fetch(result.url).into())
+          }
+          AddFeedOutcome::NotAFeed => return Err(HermesError::Parse(result.url).into()),
+          AddFeedOutcome::OverQuota => {
+            return Err(match quotas::check(uid, Resource::Feeds, 1) {
+              Err(e) => e.into(),
+              Ok(_) => warp::reject::forbidden(),
+            })
+          }
+          AddFeedOutcome::Failed => return Err(warp::reject::server_error()),
+        };
+        let feed_id = result.feed_id.ok_or_else(|| warp::reject::server_error())?;
+        let feed = db::get_subscribed_feed(&uid, &feed_id)?;
+        Ok(warp::reply::with_status(warp::reply::json(&feed), status))
+      })
+  })
+}
+
+/// import / export ///
+
+pub fn export_json(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  let mut folders: HashMap<i32, String> = HashMap::new();
+  for (_, tag, feed_id) in db::get_tag_rows(claims.id)? {
+    let folder = folders.entry(feed_id).or_insert_with(|| tag.clone());
+    if tag < *folder {
+      *folder = tag;
+    }
+  }
+  let entries: Vec<SubscriptionEntry> = db::get_user_feeds(claims.id)?
+    .into_iter()
+    .map(|feed| SubscriptionEntry {
+      folder: folders.remove(&feed.id),
+      url: feed.feed_link,
+      title: Some(feed.title),
+      state: Some("active".to_owned()),
+    }).collect();
+  Ok(warp::reply::json(&entries))
+}
+
+// `state` is ignored, everything imported is subscribed to
+pub fn import_json(
+  claims: Claims,
+  entries: Vec<SubscriptionEntry>,
+  state: UserWebsocketState,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let check = match entries.len() {
+    0 => Err(warp::reject::bad_request()),
+    n if n > MAX_ADD_FEEDS => Err(warp::reject::bad_request()),
+    _ => quotas::check(claims.id, Resource::Feeds, 1).map_err(Rejection::from),
+  };
+  let folders: HashMap<String, String> = entries
+    .iter()
+    .filter_map(|e| {
+      let folder = e.folder.as_ref()?.trim().to_lowercase();
+      match folder.is_empty() || folder.chars().count() > MAX_TAG_LEN {
+        true => None,
+        false => Some((e.url.clone(), folder)),
+      }
+    }).collect();
+  let urls: Vec<String> = entries.into_iter().map(|e| e.url).collect();
+  check.into_future().and_then(move |_| {
+    debug!("user {} importing {} feeds", claims.id, urls.len());
+    feed::add_feeds(urls, claims.id, state)
+      .map(move |results| {
+        for result in results.iter() {
+          if let (Some(feed_id), Some(folder)) = (result.feed_id, folders.get(&result.url)) {
+            if let Err(e) = db::add_feed_tag(claims.id, feed_id, folder) {
+              error!("could not tag imported feed {}: {}", feed_id, e);
+            }
+          }
+        }
+        warp::reply::json(&results)
+      }).map_err(|_| warp::reject::server_error())
+  })
+}
+
+pub fn search_feeds(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let q = match query.get("q").map(|q| q.trim()) {
+    Some(q) if q.chars().count() >= 2 => q.to_owned(),
+    _ => return Err(warp::reject::bad_request()),
+  };
+  let subscribed = get_subscribed_feed_ids(&claims.id)?;
+  let results: Vec<CatalogFeed> = db::search_feeds(&q, MAX_SEARCH_RESULTS)?
+    .into_iter()
+    .map(|feed| CatalogFeed {
+      subscribed: subscribed.contains(&feed.id),
+      feed: feed,
+    }).collect();
+  Ok(warp::reply::json(&results))
+}
+
+pub fn discover_feeds(
+  query: HashMap<String, String>,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let url = match query.get("url").map(|u| u.trim()).map(|u| (u, Url::parse(u))) {
+    Some((u, Ok(ref parsed))) if parsed.scheme() == "http" || parsed.scheme() == "https" => {
+      u.to_owned()
+    }
+    _ => return Either::A(future::err(warp::reject::bad_request())),
+  };
+  Either::B(
+    discovery::candidates(url)
+      .map(|found| warp::reply::json(&found))
+      .map_err(Rejection::from),
+  )
+}
+
+pub fn unsubscribe(
+  claims: Claims,
+  params: UnsubscribeParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::unsubscribe_feed(claims.id, params.feed_id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => {
+      debug!("user {} unsubscribed from {}", claims.id, params.feed_id);
+      let detail = format!("feed {}", params.feed_id);
+      db::log_activity(claims.id, Action::Unsubscribe, Some(&detail));
+      Ok(warp::reply::json(&json!({ "unsubscribed": params.feed_id })))
+    }
+    Err(e) => {
+      error!("could not unsubscribe from {}: {}", params.feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_feed(
+  claims: Claims,
+  feed_id: i32,
+  if_none_match: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let feed = db::get_feed(feed_id).map_err(reject)?;
+  let info = FeedInfo {
+    warnings: feed.warnings(),
+    items_per_day: db::get_item_rates(&vec![feed_id])?.remove(&feed_id),
+    dead: feed.dead(),
+    feed: feed,
+  };
+  Ok(etag::json(&info, if_none_match))
+}
+
+pub fn show_feed_stats(claims: Claims, feed_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  let feed = db::get_subscribed_feed(&claims.id, &feed_id).map_err(reject)?;
+  let ids = vec![feed_id];
+  let mut days = db::get_feed_days(&ids, stats_since())?;
+  let totals = db::get_feed_totals(&ids)?;
+  let mut rates = db::get_item_rates(&ids)?;
+  let stats = FeedStats::new(
+    &feed,
+    days.remove(&feed_id).unwrap_or_default(),
+    totals.get(&feed_id),
+    rates.remove(&feed_id),
+  );
+  Ok(warp::reply::json(&stats))
+}
+
+// subscriptions that went quiet, keep failing, post too much or pile up unread items
+pub fn show_hygiene(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  let feeds = db::get_all_subscribed_feeds(&claims.id)?;
+  let ids = feeds.iter().map(|f| f.id).collect();
+  let mut days = db::get_feed_days(&ids, stats_since())?;
+  let totals = db::get_feed_totals(&ids)?;
+  let mut rates = db::get_item_rates(&ids)?;
+  let rate_warning = db::get_user_rate_warning(claims.id)?;
+  let stale_before = (Utc::now() - Duration::days(STALE_DAYS)).naive_utc().date();
+  let report: Vec<FeedHygiene> = feeds
+    .iter()
+    .map(|feed| {
+      let mut stats = FeedStats::new(
+        feed,
+        days.remove(&feed.id).unwrap_or_default(),
+        totals.get(&feed.id),
+        rates.remove(&feed.id),
+      );
+      stats.days.clear();
+      let noisy = stats.items_per_day.map(|r| r > rate_warning).unwrap_or(false);
+      let suggestion = match (noisy, stats.unread_ratio >= MUTE_UNREAD_RATIO) {
+        (false, _) => None,
+        (true, true) => Some("mute"),
+        (true, false) => Some("digest"),
+      };
+      FeedHygiene {
+        title: feed.title.clone(),
+        stale: stats.last_post.map(|d| d < stale_before).unwrap_or(true),
+        failing: stats.failure_rate >= FAILING_RATE,
+        noisy: noisy,
+        suggestion: suggestion,
+        stats: stats,
+      }
+    }).collect();
+  Ok(warp::reply::json(&report))
+}
+
+fn stats_since() -> NaiveDate {
+  (Utc::now() - Duration::days(FeedStats::WINDOW_DAYS)).naive_utc().date()
+}
+
+pub fn set_tags(
+  claims: Claims,
+  feed_id: i32,
+  params: TagsParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let mut tags: Vec<String> = params
+    .tags
+    .iter()
+    .map(|t| t.trim().to_lowercase())
+    .filter(|t| !t.is_empty())
+    .collect();
+  tags.sort();
+  tags.dedup();
+  if tags.len() > MAX_TAGS || tags.iter().any(|t| t.chars().count() > MAX_TAG_LEN) {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_feed_tags(claims.id, feed_id, &tags) {
+    Ok(_) => Ok(warp::reply::json(&tags)),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not tag feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
   }
 }
 
+pub fn set_full_content(
+  claims: Claims,
+  feed_id: i32,
+  enabled: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if !db::is_subscribed(&claims.id, &feed_id)? {
+    return Err(warp::reject::not_found());
+  }
+  match db::set_fetch_full_content(feed_id, enabled) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "fetch_full_content": enabled }))),
+    Err(e) => {
+      error!("could not set full content for feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn set_feed_order(
+  claims: Claims,
+  entries: Vec<FeedOrderEntry>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if entries.len() > MAX_ORDERED_FEEDS {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_feed_order(claims.id, &entries) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "updated": entries.len() }))),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not order feeds for user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn set_push_notify(
+  claims: Claims,
+  feed_id: i32,
+  enabled: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::set_push_notify(claims.id, feed_id, enabled) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "push": enabled }))),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not set push notifications for feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn set_unread_on_edit(
+  claims: Claims,
+  feed_id: i32,
+  enabled: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::set_unread_on_edit(claims.id, feed_id, enabled) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "unread_on_edit": enabled }))),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not set unread on edit for feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_refresh_window(
+  claims: Claims,
+  feed_id: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::get_refresh_window(claims.id, feed_id) {
+    Ok(window) => Ok(warp::reply::json(&json!({ "window": window }))),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not load the refresh window of feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn set_refresh_window(
+  claims: Claims,
+  feed_id: i32,
+  params: RefreshWindowParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let window = match params.window {
+    Some(ref spec) => match Window::parse(spec) {
+      Ok((_, window)) => Some(window),
+      Err(_) => return Err(warp::reject::bad_request()),
+    },
+    None => None,
+  };
+  match db::set_refresh_window(claims.id, feed_id, window.as_ref().map(|w| w.as_str())) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "window": window }))),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not set the refresh window of feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// brings a dead feed back into the fetch schedule
+pub fn retry_feed(claims: Claims, feed_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  if !db::is_subscribed(&claims.id, &feed_id)? {
+    return Err(warp::reject::not_found());
+  }
+  match db::reset_fetch_failures(feed_id) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "dead": false }))),
+    Err(e) => {
+      error!("could not reset failures of feed {}: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_icon(feed_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  let icon = db::get_feed_icon(feed_id).map_err(reject)?;
+  Ok(
+    Response::builder()
+      .header("content-type", icon.content_type.as_str())
+      .header("cache-control", "private, max-age=86400")
+      .header("x-content-type-options", "nosniff")
+      .body(Body::from(icon.data))
+      .unwrap(),
+  )
+}
+
+// `?url=`, only while the media cache is enabled
+pub fn show_media(
+  query: HashMap<String, String>,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let url = match query.get("url") {
+    Some(url) if *media::ENABLED => url.to_owned(),
+    Some(_) => return Either::A(future::err(warp::reject::not_found())),
+    None => return Either::A(future::err(warp::reject::bad_request())),
+  };
+  Either::B(
+    media::get(url)
+      .map(|blob| {
+        Response::builder()
+          .header("content-type", blob.content_type.as_str())
+          .header("cache-control", "private, max-age=604800")
+          .header("etag", format!("\"{}\"", blob.hash))
+          .header("x-content-type-options", "nosniff")
+          .body(Body::from(blob.data))
+          .unwrap()
+      }).map_err(Rejection::from),
+  )
+}
+
+pub fn show_tags(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_tag_counts(claims.id)?))
+}
+
 /// items ///
 
-pub fn show_item(claims: Claims, item_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+pub fn show_item(
+  claims: Claims,
+  item_id: i32,
+  if_none_match: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
   let user_id = claims.id.clone();
   let got_item = get_subscribed_item(item_id, user_id);
   match got_item {
-    Some(mut data) => {
+    Ok(mut data) => {
       data.seen = true;
-      Ok(warp::reply::json(&data))
+      let item = attach_details(vec![data])?.pop();
+      Ok(etag::json(&item, if_none_match))
+    }
+    Err(HermesError::NotFound) => Err(warp::reject::bad_request()),
+    Err(e) => Err(e.into()),
+  }
+}
+
+// the same page can be linked with or without TLS and a trailing slash
+fn link_variants(link: &str) -> Vec<String> {
+  let mut variants = vec![link.to_owned()];
+  if link.starts_with("https://") {
+    variants.push(format!("http://{}", &link["https://".len()..]));
+  } else if link.starts_with("http://") {
+    variants.push(format!("https://{}", &link["http://".len()..]));
+  }
+  let slashes = variants
+    .iter()
+    .map(|v| match v.ends_with('/') {
+      true => v[..v.len() - 1].to_owned(),
+      false => format!("{}/", v),
+    }).collect::<Vec<String>>();
+  variants.extend(slashes);
+  variants
+}
+
+// `?url=`, for browser extensions and share targets opening a page in Hermes;
+// the item isn't marked as read
+pub fn lookup_item(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let link = match query.get("url").map(|u| u.trim()) {
+    Some(link) if !link.is_empty() => link,
+    _ => return Err(warp::reject::bad_request()),
+  };
+  let item = db::find_subscribed_item_by_link(claims.id, &link_variants(link)).map_err(reject)?;
+  Ok(warp::reply::json(&attach_details(vec![item])?.pop()))
+}
+
+// `?after_id=&feed_id=`, both optional; `null` when there are no more unread
+// items. The item isn't marked as read.
+pub fn show_adjacent_unread(
+  claims: Claims,
+  query: HashMap<String, String>,
+  forward: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let param = |name: &str| match query.get(name) {
+    Some(v) => v.parse::<i32>().map(Some).map_err(|_| warp::reject::bad_request()),
+    None => Ok(None),
+  };
+  let after_id = param("after_id")?;
+  let feed_id = param("feed_id")?;
+  match db::get_adjacent_unread(claims.id, after_id, feed_id, forward) {
+    Ok(item) => {
+      let item = match item {
+        Some(item) => attach_details(vec![item])?.pop(),
+        None => None,
+      };
+      Ok(warp::reply::json(&item))
+    }
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not find the next unread item: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn fetch_full(
+  claims: Claims,
+  item_id: i32,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  get_subscribed_item(item_id, claims.id)
+    .map_err(reject)
+    .into_future()
+    .and_then(move |mut item| {
+      extract::fetch_full(item.id, item.link.clone())
+        .map(move |html| {
+          item.content = Some(html);
+          warp::reply::json(&item)
+        }).map_err(|_| warp::reject::server_error())
+    })
+}
+
+// `purge` removes the item for everyone, on the admin's route
+pub fn delete_item(
+  claims: Claims,
+  item_id: i32,
+  purge: bool,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let result = match purge {
+    true => db::purge_item(item_id),
+    false => db::hide_item(item_id, claims.id).map(|feed_id| (feed_id, vec![claims.id])),
+  };
+  match result {
+    Ok((feed_id, user_ids)) => {
+      let action = if purge { Action::PurgeItem } else { Action::HideItem };
+      let detail = format!("item {}", item_id);
+      db::log_activity(claims.id, action, Some(&detail));
+      for user_id in user_ids.iter() {
+        ws_send_unseen_counts(user_id, &vec![feed_id], &state);
+      }
+      Ok(warp::reply::json(&json!({ "deleted": item_id, "purged": purge })))
+    }
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not delete item {}: {}", item_id, e);
+      Err(e.into())
     }
-    None => Err(warp::reject::bad_request()),
   }
 }
 
+// /api/items/deleted?since=, for clients keeping their own copy of items
+pub fn show_deleted(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let since = match query.get("since") {
+    Some(d) => match d.parse::<DateTime<Utc>>() {
+      Ok(t) => t,
+      Err(_) => return Err(warp::reject::bad_request()),
+    },
+    None => Utc.timestamp(0, 0),
+  };
+  Ok(warp::reply::json(&db::get_deleted_items(claims.id, since)?))
+}
+
+// `?limit=all` lists every item after `offset`, the reply is streamed either way
 pub fn show_items(
   claims: Claims,
   feed_id: i32,
-  query: HashMap<String, String>,
+  mut query: HashMap<String, String>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
   let updated = match query.get("updated") {
     Some(d) => match d.parse::<DateTime<Utc>>() {
@@ -52,29 +683,1089 @@ pub fn show_items(
     None => None,
   };
 
-  match get_subscribed_items(feed_id, claims.id, updated) {
-    Some(data) => Ok(warp::reply::json(&data)),
-    None => Err(warp::reject::not_found()),
+  let all = query.get("limit").map(|l| l == "all").unwrap_or(false);
+  if all {
+    query.remove("limit");
   }
+  let mut page = parse_page(&query)?;
+  if all {
+    page.limit = i64::max_value();
+  }
+
+  // the first batch is loaded here, so that a failing query still gets an error status
+  let uid = claims.id;
+  let first = Page {
+    limit: page.limit.min(ITEM_BATCH),
+    offset: page.offset,
+  };
+  let mut loaded = Some(get_subscribed_items(feed_id, uid, updated, first)?);
+  let total = count_subscribed_items(feed_id, uid)?;
+  let mut remaining = page.limit;
+  let mut offset = page.offset;
+  let batches = move || -> Result<Vec<ItemWithEnclosures>, HermesError> {
+    let limit = remaining.min(ITEM_BATCH);
+    let items = match loaded.take() {
+      Some(items) => items,
+      None if limit > 0 => {
+        let page = Page {
+          limit: limit,
+          offset: offset,
+        };
+        get_subscribed_items(feed_id, uid, updated, page)?
+      }
+      None => return Ok(Vec::new()),
+    };
+    if items.is_empty() {
+      return Ok(Vec::new());
+    }
+    // a short batch is the last one
+    remaining = match items.len() as i64 {
+      n if n < limit => 0,
+      n => remaining - n,
+    };
+    offset += items.len() as i64;
+    attach_details(items)
+  };
+  Ok(with_total(chunked::json(batches), total))
 }
 
-/// assets ///
+pub fn search_items(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let q = match query.get("q").map(|q| q.trim()) {
+    Some(q) if !q.is_empty() => q.to_owned(),
+    _ => return Err(warp::reject::bad_request()),
+  };
+  let page = parse_page(&query)?;
+
+  let (items, total) = db::search_subscribed_items(claims.id, &q, page)?;
+  Ok(with_total(warp::reply::json(&attach_details(items)?), total))
+}
 
-pub fn serve_static(
-  asset: AssetFile,
-) -> impl Future<Item = Response<Body>, Error = Rejection> + Send {
-  let asset_path = path::Path::new(&ASSET_PATH).join(asset.0);
-  tokio_fs::file::File::open(asset_path)
-    .and_then(move |file| {
-      let buf: Vec<u8> = Vec::new();
-      tokio_io::io::read_to_end(file, buf)
-        .and_then(|(_, b)| Ok(Response::builder().body(Body::from(b)).unwrap()))
-    }).or_else(|e| {
-      error!("file open error: {} ", e);
-      let err = match e.kind() {
-        io::ErrorKind::NotFound => warp::reject::not_found().with(e),
-        _ => warp::reject::server_error().with(e),
-      };
-      Err(err)
+pub fn show_tagged_items(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let tag = match query.get("tag") {
+    Some(t) => t.trim().to_lowercase(),
+    None => return Err(warp::reject::bad_request()),
+  };
+  let page = parse_page(&query)?;
+
+  let feed_ids = db::get_tagged_feed_ids(claims.id, &tag)?;
+  let (items, total) = db::get_items_for_feeds(claims.id, &feed_ids, page)?;
+  Ok(with_total(warp::reply::json(&attach_details(items)?), total))
+}
+
+// every subscription in one list, `?unread=true&starred=true&tag=&before=` narrow it
+pub fn show_river(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let flag = |name: &str| query.get(name).map(|v| v == "true").unwrap_or(false);
+  let before = match query.get("before") {
+    Some(d) => Some(d.parse::<DateTime<Utc>>().map_err(|_| warp::reject::bad_request())?),
+    None => None,
+  };
+  let feed_ids = match query.get("tag") {
+    Some(t) => Some(db::get_tagged_feed_ids(claims.id, &t.trim().to_lowercase())?),
+    None => None,
+  };
+  let page = parse_page(&query)?;
+
+  let (items, total) =
+    db::get_timeline_items(claims.id, flag("unread"), flag("starred"), feed_ids, before, page)?;
+  Ok(with_total(warp::reply::json(&attach_details(items)?), total))
+}
+
+// enclosures, and the retry state of items whose full content could not be fetched
+fn attach_details(items: Vec<SubscribedItem>) -> Result<Vec<ItemWithEnclosures>, HermesError> {
+  let ids = items.iter().map(|i| i.id).collect();
+  let mut enclosures = db::get_enclosures(&ids)?;
+  let mut retries = db::get_item_content_retries(&ids)?;
+  Ok(
+    items
+      .into_iter()
+      .map(|item| ItemWithEnclosures {
+        enclosures: enclosures.remove(&item.id).unwrap_or_default(),
+        content_retry: retries.remove(&item.id),
+        item: item,
+      }).collect(),
+  )
+}
+
+/// paging ///
+
+// `?limit=&offset=`, the total is sent in a header so the body stays a plain list
+fn parse_page(query: &HashMap<String, String>) -> Result<Page, Rejection> {
+  let mut page = Page::default();
+  if let Some(l) = query.get("limit") {
+    match l.parse::<i64>() {
+      Ok(l) if l > 0 => page.limit = l.min(Page::MAX_LIMIT),
+      _ => return Err(warp::reject::bad_request()),
+    }
+  }
+  if let Some(o) = query.get("offset") {
+    match o.parse::<i64>() {
+      Ok(o) if o >= 0 => page.offset = o,
+      _ => return Err(warp::reject::bad_request()),
+    }
+  }
+  Ok(page)
+}
+
+fn with_total(reply: impl warp::Reply, total: i64) -> impl warp::Reply {
+  warp::reply::with_header(reply, "x-total-count", total.to_string())
+}
+
+pub fn star_item(
+  claims: Claims,
+  item_id: i32,
+  starred: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match set_item_starred(item_id, claims.id, starred) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "starred": starred }))),
+    Err(e) => {
+      error!("could not star item {}: {}", item_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// Changes to items that changed on the server after the client's `updated_at`
+// are dropped, and those items listed as `conflicts`; their server state is
+// among the `changes`, which the client applies before syncing again with
+// `server_time` as `since`.
+pub fn sync_items(
+  claims: Claims,
+  params: SyncParams,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let count =
+    params.read.len() + params.unread.len() + params.starred.len() + params.unstarred.len();
+  if count > MAX_SYNC_IDS {
+    return Err(warp::reject::bad_request());
+  }
+  let server_time = Utc::now();
+  let changed_at = params.updated_at.unwrap_or(server_time).min(server_time);
+  match db::sync_item_states(claims.id, &params, changed_at) {
+    Ok((conflicts, feed_ids, changes)) => {
+      debug!(
+        "user {} synced {} items, {} conflicts, {} changes",
+        claims.id,
+        count,
+        conflicts.len(),
+        changes.len()
+      );
+      if !feed_ids.is_empty() {
+        ws_send_unseen_counts(&claims.id, &feed_ids, &state);
+      }
+      Ok(warp::reply::json(&json!({
+        "changes": changes,
+        "conflicts": conflicts,
+        "server_time": Timestamp(server_time),
+      })))
+    }
+    Err(e) => {
+      error!("could not sync the items of user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_starred(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  let data = get_starred_items(claims.id)?;
+  Ok(warp::reply::json(&attach_details(data)?))
+}
+
+// new items reach websockets like scheduled ones, through the events table
+pub fn refresh_feed(
+  claims: Claims,
+  feed_id: i32,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  match db::is_subscribed(&claims.id, &feed_id) {
+    Ok(true) => (),
+    Ok(false) => return Either::A(future::err(warp::reject::not_found())),
+    Err(e) => return Either::A(future::err(e.into())),
+  }
+  Either::B(
+    feed::refresh_feed(feed_id)
+      .map(|refreshed| {
+        warp::reply::json(&json!({
+          "refreshed": refreshed.is_some(),
+          "new_items": refreshed.unwrap_or(0),
+        }))
+      }).map_err(Rejection::from),
+  )
+}
+
+// feeds that fail or are being fetched already are counted as not refreshed
+pub fn refresh_all(
+  claims: Claims,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  get_subscribed_feed_ids(&claims.id)
+    .map_err(Rejection::from)
+    .into_future()
+    .and_then(|feed_ids| {
+      stream::iter_ok::<_, Rejection>(feed_ids)
+        .map(|feed_id| {
+          feed::refresh_feed(feed_id).then(move |refreshed| {
+            if let Err(ref e) = refreshed {
+              debug!("could not refresh feed {}: {}", feed_id, e);
+            }
+            Ok::<_, Rejection>(refreshed.ok().and_then(|r| r))
+          })
+        }).buffer_unordered(REFRESH_CONCURRENCY)
+        .collect()
+    }).map(|results: Vec<Option<usize>>| {
+      let refreshed: Vec<usize> = results.into_iter().filter_map(|r| r).collect();
+      warp::reply::json(&json!({
+        "refreshed": refreshed.len(),
+        "new_items": refreshed.iter().sum::<usize>(),
+      }))
     })
 }
+
+pub fn mark_feed_read(
+  claims: Claims,
+  feed_id: i32,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match mark_feed_as_read(feed_id, claims.id) {
+    Ok(n) => {
+      debug!("user {} read {} items in feed {}", claims.id, n, feed_id);
+      let detail = format!("feed {}", feed_id);
+      db::log_activity(claims.id, Action::MarkAllRead, Some(&detail));
+      ws_send_unseen_counts(&claims.id, &vec![feed_id], &state);
+      Ok(warp::reply::json(&json!({ "updated": n })))
+    }
+    Err(e) => {
+      error!("could not mark feed {} as read: {}", feed_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn mark_all_read(
+  claims: Claims,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match mark_all_as_read(claims.id) {
+    Ok(n) => {
+      debug!("user {} read {} items", claims.id, n);
+      db::log_activity(claims.id, Action::MarkAllRead, None);
+      ws_send_unseen_counts(&claims.id, &get_subscribed_feed_ids(&claims.id)?, &state);
+      Ok(warp::reply::json(&json!({ "updated": n })))
+    }
+    Err(e) => {
+      error!("could not mark all items as read: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+/// topics ///
+
+pub fn create_topic(
+  claims: Claims,
+  params: TopicParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let keywords: Vec<String> = params
+    .keywords
+    .iter()
+    .map(|k| k.trim().to_owned())
+    .filter(|k| !k.is_empty())
+    .collect();
+  if params.name.trim().is_empty() || keywords.is_empty() {
+    return Err(warp::reject::bad_request());
+  }
+  let topic = db::insert_topic(claims.id, params.name.trim(), &keywords, params.notify)?;
+  Ok(warp::reply::json(&topic))
+}
+
+pub fn show_topics(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_topics(claims.id)?))
+}
+
+pub fn delete_topic(claims: Claims, topic_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_topic(topic_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => {
+      let detail = format!("topic {}", topic_id);
+      db::log_activity(claims.id, Action::DeleteTopic, Some(&detail));
+      Ok(warp::reply::json(&json!({ "deleted": true })))
+    }
+    Err(e) => {
+      error!("could not delete topic {}: {}", topic_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_timeline(claims: Claims, topic_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  let topic = db::get_topic(topic_id, claims.id).map_err(reject)?;
+  Ok(warp::reply::json(&topics::timeline(&topic)?))
+}
+
+/// filters ///
+
+pub fn create_filter(
+  claims: Claims,
+  mut params: FilterParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let checked = filters::pattern_of(&params.pattern, params.keyword.as_ref().map(|k| k.as_str()))
+    .and_then(|pattern| {
+      params.pattern = pattern;
+      Rule::compile(&params.pattern, &params.match_field, &params.action)
+    });
+  quotas::check(claims.id, Resource::Filters, 1)?;
+  if let Err(e) = checked {
+    let locale = db::get_user_locale(claims.id)?;
+    let message = i18n::tr_args(&locale, e.code(), &[("detail", e.detail())]);
+    let body = json!({ "error": e.code(), "message": message });
+    return Ok(warp::reply::with_status(
+      warp::reply::json(&body),
+      StatusCode::BAD_REQUEST,
+    ));
+  }
+  match db::insert_filter(claims.id, &params) {
+    Ok(filter) => Ok(warp::reply::with_status(
+      warp::reply::json(&filter),
+      StatusCode::CREATED,
+    )),
+    Err(e) => {
+      error!("could not create filter: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_filters(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_user_filters(claims.id)?))
+}
+
+pub fn enable_filter(
+  claims: Claims,
+  filter_id: i32,
+  enabled: bool,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::set_filter_enabled(filter_id, claims.id, enabled) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "enabled": enabled }))),
+    Err(e) => {
+      error!("could not update filter {}: {}", filter_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn delete_filter(claims: Claims, filter_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_filter(filter_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete filter {}: {}", filter_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn preview_filter(claims: Claims, filter_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  match filters::preview(filter_id, claims.id) {
+    Ok(Ok(preview)) => Ok(warp::reply::json(&preview)),
+    Ok(Err(e)) => {
+      error!("filter {} can't be previewed: {}", filter_id, e);
+      Err(warp::reject::bad_request())
+    }
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not preview filter {}: {}", filter_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn apply_filter(
+  claims: Claims,
+  filter_id: i32,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match filters::apply(filter_id, claims.id) {
+    Ok(Ok(matched)) => {
+      let mut feed_ids = matched.iter().map(|i| i.feed_id).collect::<Vec<i32>>();
+      feed_ids.sort();
+      feed_ids.dedup();
+      if !feed_ids.is_empty() {
+        ws_send_unseen_counts(&claims.id, &feed_ids, &state);
+      }
+      Ok(warp::reply::json(&json!({ "matched": matched.len() })))
+    }
+    Ok(Err(e)) => {
+      error!("filter {} can't be applied: {}", filter_id, e);
+      Err(warp::reject::bad_request())
+    }
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not apply filter {}: {}", filter_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+/// push ///
+
+pub fn show_push_key() -> Result<impl warp::Reply, warp::Rejection> {
+  match push::public_key() {
+    Ok(key) => Ok(warp::reply::json(&json!({ "public_key": key }))),
+    Err(e) => {
+      error!("could not read the VAPID key: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+// pushes will come without a payload, see `push`
+pub fn push_subscribe(
+  claims: Claims,
+  params: PushSubscribeParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if Url::parse(&params.endpoint)
+    .map(|u| u.scheme() != "https")
+    .unwrap_or(true)
+  {
+    return Err(warp::reject::bad_request());
+  }
+  match db::upsert_push_subscription(claims.id, &params) {
+    Ok(_) => Ok(warp::reply::with_status(
+      warp::reply::json(&json!({ "subscribed": true })),
+      StatusCode::CREATED,
+    )),
+    Err(e) => {
+      error!("could not store push subscription: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn push_unsubscribe(
+  claims: Claims,
+  params: PushUnsubscribeParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_push_subscription(claims.id, &params.endpoint) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "subscribed": false }))),
+    Err(e) => {
+      error!("could not delete push subscription: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+/// webhooks ///
+
+pub fn create_webhook(
+  claims: Claims,
+  params: WebhookParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let valid = Url::parse(&params.url)
+    .map(|u| u.scheme() == "https" || u.scheme() == "http")
+    .unwrap_or(false);
+  if !valid {
+    return Err(warp::reject::bad_request());
+  }
+  quotas::check(claims.id, Resource::Webhooks, 1)?;
+  let secret = handlers::random_token(WEBHOOK_SECRET_BYTES).map_err(|e| {
+    error!("could not read random bytes: {}", e);
+    warp::reject::server_error()
+  })?;
+  match db::insert_webhook(claims.id, &params, &secret) {
+    Ok(webhook) => Ok(warp::reply::with_status(
+      warp::reply::json(&CreatedWebhook {
+        secret: secret,
+        webhook: webhook,
+      }),
+      StatusCode::CREATED,
+    )),
+    Err(e) => {
+      error!("could not create webhook: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_webhooks(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_webhooks(claims.id)?))
+}
+
+pub fn delete_webhook(
+  claims: Claims,
+  webhook_id: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_webhook(webhook_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete webhook {}: {}", webhook_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_webhook_deliveries(
+  claims: Claims,
+  webhook_id: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::get_webhook_deliveries(webhook_id, claims.id, MAX_WEBHOOK_DELIVERIES) {
+    Ok(deliveries) => Ok(warp::reply::json(&deliveries)),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not read deliveries of webhook {}: {}", webhook_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+/// mutes ///
+
+pub fn create_mute(
+  claims: Claims,
+  params: MuteParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if params.phrase.trim().is_empty() {
+    return Err(warp::reject::bad_request());
+  }
+  match db::insert_mute(claims.id, &params) {
+    Ok(mute) => Ok(warp::reply::with_status(
+      warp::reply::json(&mute),
+      StatusCode::CREATED,
+    )),
+    Err(e) => {
+      error!("could not create mute: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_mutes(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_user_mutes(claims.id)?))
+}
+
+pub fn delete_mute(claims: Claims, mute_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_mute(mute_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete mute {}: {}", mute_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_muted_items(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_muted_items(claims.id, MAX_MUTED_ITEMS)?))
+}
+
+/// instance ///
+
+// what a UI built once needs to know about the instance it is pointed at
+pub fn show_client_config() -> Result<impl warp::Reply, warp::Rejection> {
+  let client = json!({
+    "name": config::WEB.instance_name,
+    "logo_url": config::WEB.logo_url,
+    "api_base": config::WEB.api_base,
+    "registration": RegistrationMode::current().name(),
+    "features": {
+      "websocket": true,
+      "server_sent_events": true,
+      "fever": true,
+      "media_cache": *media::ENABLED,
+      "proxy_auth": !TRUSTED_PROXIES.is_empty(),
+    },
+  });
+  Ok(warp::reply::with_header(
+    warp::reply::json(&client),
+    "cache-control",
+    "max-age=300",
+  ))
+}
+
+/// users ///
+
+pub fn register(
+  params: RegisterParams,
+  accept_language: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let (status, body) = match handlers::register(&params) {
+    Ok(ref user) if user.approved => {
+      info!("registered user '{}'", user.username);
+      let token = generate_jwt(user).ok_or_else(|| warp::reject::server_error())?;
+      (StatusCode::CREATED, json!({ "token": token }))
+    }
+    Ok(user) => {
+      info!("registered user '{}', pending approval", user.username);
+      (StatusCode::ACCEPTED, json!({ "pending": true }))
+    }
+    Err(e) => {
+      let (status, reason) = match e {
+        RegisterError::Closed => (StatusCode::FORBIDDEN, "registration_closed"),
+        RegisterError::InvalidUsername => (StatusCode::BAD_REQUEST, "invalid_username"),
+        RegisterError::WeakPassword => (StatusCode::BAD_REQUEST, "weak_password"),
+        RegisterError::UsernameTaken => (StatusCode::CONFLICT, "username_taken"),
+        RegisterError::InvalidInvite => (StatusCode::FORBIDDEN, "invalid_invite"),
+        RegisterError::Failed => return Err(warp::reject::server_error()),
+      };
+      let locale = i18n::negotiate(accept_language.as_ref().map(|l| l.as_str()));
+      (status, json!({ "error": reason, "message": i18n::tr(locale, reason) }))
+    }
+  };
+  Ok(warp::reply::with_status(warp::reply::json(&body), status))
+}
+
+pub fn set_locale(
+  claims: Claims,
+  params: LocaleParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let locale = i18n::supported(&params.locale).ok_or_else(|| warp::reject::bad_request())?;
+  match db::set_user_locale(claims.id, locale) {
+    Ok(_) => {
+      db::log_activity(claims.id, Action::SetLocale, Some(locale));
+      Ok(warp::reply::json(&json!({ "locale": locale })))
+    }
+    Err(e) => {
+      error!("could not set locale for user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn set_rate_warning(
+  claims: Claims,
+  params: RateWarningParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let items_per_day = params.items_per_day;
+  if !items_per_day.is_finite() || items_per_day <= 0.0 {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_user_rate_warning(claims.id, items_per_day) {
+    Ok(_) => {
+      let detail = items_per_day.to_string();
+      db::log_activity(claims.id, Action::SetRateWarning, Some(&detail));
+      Ok(warp::reply::json(&json!({ "items_per_day": items_per_day })))
+    }
+    Err(e) => {
+      error!("could not set rate warning for user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn set_auto_read(
+  claims: Claims,
+  params: AutoReadParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if params.days.map(|d| d <= 0).unwrap_or(false) {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_user_auto_read(claims.id, params.days) {
+    Ok(_) => {
+      let detail = params.days.map(|d| d.to_string());
+      db::log_activity(claims.id, Action::SetAutoRead, detail.as_ref().map(|d| d.as_str()));
+      Ok(warp::reply::json(&json!({ "days": params.days })))
+    }
+    Err(e) => {
+      error!("could not set auto-read for user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_counts(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::get_unread_counts(claims.id) {
+    Ok(counts) => Ok(warp::reply::json(&UnreadCounts::new(counts))),
+    Err(e) => {
+      error!("could not count unread items of user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_retention_exemptions(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  let exemptions = db::get_retention_exemptions(claims.id)?;
+  Ok(warp::reply::json(&exemptions))
+}
+
+pub fn add_retention_exemption(
+  claims: Claims,
+  params: RetentionExemptionParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let tag = params.tag.as_ref().map(|t| t.trim());
+  let subscribed = match params.feed_id {
+    Some(feed_id) => db::is_subscribed(&claims.id, &feed_id)?,
+    None => false,
+  };
+  match (tag, params.feed_id) {
+    (Some(tag), None) if !tag.is_empty() => (),
+    (None, Some(_)) if subscribed => (),
+    _ => return Err(warp::reject::bad_request()),
+  };
+  match db::insert_retention_exemption(claims.id, tag, params.feed_id) {
+    Ok(exemption) => Ok(warp::reply::with_status(
+      warp::reply::json(&exemption),
+      StatusCode::CREATED,
+    )),
+    Err(e) => {
+      error!("could not add retention exemption for user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn delete_retention_exemption(
+  claims: Claims,
+  exemption_id: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_retention_exemption(exemption_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete retention exemption {}: {}", exemption_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_failed_deliveries(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  let failed = db::get_failed_deliveries(claims.id)?;
+  Ok(warp::reply::json(&failed))
+}
+
+// a failed replay stays in the list with its new error
+pub fn replay_delivery(
+  claims: Claims,
+  delivery_id: i32,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> + Send {
+  let failed = match db::get_failed_delivery(delivery_id, claims.id) {
+    Ok(failed) => failed,
+    Err(e) => return Either::A(future::err(reject(e))),
+  };
+  Either::B(
+    deliveries::replay(failed)
+      .map(|_| warp::reply::json(&json!({ "delivered": true })))
+      .map_err(Rejection::from),
+  )
+}
+
+pub fn dismiss_delivery(
+  claims: Claims,
+  delivery_id: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_failed_delivery(delivery_id, claims.id) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(e) => {
+      error!("could not delete failed delivery {}: {}", delivery_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_activity(
+  claims: Claims,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let connected = state.state.lock().unwrap().contains_key(&claims.id);
+  let log = db::get_activity(claims.id, MAX_ACTIVITY)?;
+  Ok(warp::reply::json(&AccountActivity::new(connected, log)))
+}
+
+pub fn show_schedule() -> Result<impl warp::Reply, warp::Rejection> {
+  let failing_feeds = db::get_failing_feeds()?;
+  let schedule: Vec<ScheduledFeed> = db::get_channel_urls_and_subscribers()?
+    .into_iter()
+    .map(|(feed_id, feed_link, _)| {
+      let offset = feed::fetch_offset(feed_id);
+      let failures = failing_feeds.get(&feed_id).cloned().unwrap_or(0);
+      ScheduledFeed {
+        feed_id: feed_id,
+        feed_link: feed_link,
+        offset_secs: offset.as_secs() as f64 + offset.subsec_millis() as f64 / 1000.0,
+        interval_secs: feed::UPDATE_SECS * feed::backoff_cycles(failures),
+      }
+    }).collect();
+  Ok(warp::reply::json(&schedule))
+}
+
+pub fn show_content_retries() -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&db::get_content_retries()?))
+}
+
+pub fn show_stats() -> Result<impl warp::Reply, warp::Rejection> {
+  Ok(warp::reply::json(&AdminStats {
+    database: db::get_db_stats(MAX_SLOW_QUERIES)?,
+    guid_cache: guid_cache::stats(),
+  }))
+}
+
+// /api/debug/items?as_of=&user=, the unread set a user's clients should have seen
+// back then, when one of them reports losing read states
+pub fn show_unread_as_of(
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let as_of = match query.get("as_of").map(|d| d.parse::<DateTime<Utc>>()) {
+    Some(Ok(t)) => t,
+    _ => return Err(warp::reject::bad_request()),
+  };
+  let user_id = match query.get("user") {
+    Some(u) => match u.parse::<i32>() {
+      Ok(id) => id,
+      Err(_) => db::get_user(u).map_err(reject)?.id,
+    },
+    None => return Err(warp::reject::bad_request()),
+  };
+  let unread = db::get_unread_as_of(user_id, as_of)?;
+  Ok(warp::reply::json(&UnreadSnapshot {
+    user_id: user_id,
+    as_of: as_of,
+    log_starts_at: db::get_item_state_log_start()?,
+    count: unread.len(),
+    unread: unread,
+  }))
+}
+
+pub fn create_invite(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  match handlers::create_invite(&claims) {
+    Ok(invite) => Ok(warp::reply::json(&invite)),
+    Err(_) => Err(warp::reject::server_error()),
+  }
+}
+
+pub fn approve_user(user_id: i32) -> Result<impl warp::Reply, warp::Rejection> {
+  match handlers::approve_user(user_id) {
+    Ok(true) => Ok(warp::reply::json(&json!({ "approved": true }))),
+    Ok(false) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not approve user {}: {}", user_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// an existing username is a bad request
+pub fn add_user(login: LoginParams) -> Result<impl warp::Reply, warp::Rejection> {
+  match handlers::add_user(&login) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "added": login.username }))),
+    Err(_) => Err(warp::reject::bad_request()),
+  }
+}
+
+// every user, with how many feeds they follow
+pub fn show_users(query: HashMap<String, String>) -> Result<impl warp::Reply, warp::Rejection> {
+  let page = parse_page(&query)?;
+  match db::get_user_summaries(page) {
+    Ok((users, total)) => Ok(with_total(warp::reply::json(&users), total)),
+    Err(e) => {
+      error!("could not list users: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+// a disabled user can't log in, and is logged out everywhere
+pub fn set_user_disabled(
+  claims: Claims,
+  user_id: i32,
+  disabled: bool,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if user_id == claims.id {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_user_disabled(user_id, disabled) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => {
+      if disabled {
+        end_sessions(user_id, "account_disabled", &state);
+        db::log_activity(user_id, Action::AccountDisabled, None);
+      } else {
+        db::log_activity(user_id, Action::AccountEnabled, None);
+      }
+      Ok(warp::reply::json(&json!({ "disabled": disabled })))
+    }
+    Err(e) => {
+      error!("could not change whether user {} is disabled: {}", user_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// the user is logged out everywhere, to log in again with the new password
+pub fn reset_password(
+  user_id: i32,
+  params: PasswordParams,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if !handlers::strong_password(&params.password) {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_password(user_id, &User::hash_pw(&params.password)) {
+    Ok(0) => Err(warp::reject::not_found()),
+    Ok(_) => {
+      end_sessions(user_id, "password_reset", &state);
+      db::log_activity(user_id, Action::PasswordReset, None);
+      Ok(warp::reply::json(&json!({ "password_reset": true })))
+    }
+    Err(e) => {
+      error!("could not reset the password of user {}: {}", user_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// everything the user has goes along; the admin's account can't be deleted
+pub fn delete_account(
+  claims: Claims,
+  params: PasswordParams,
+  state: UserWebsocketState,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let user = match User::check_user(&claims.name, &params.password) {
+    Ok(Some(ref user)) if user.id == claims.id => user.clone(),
+    Ok(_) => return Err(warp::reject::forbidden()),
+    Err(e) => {
+      error!("could not check the password of user {}: {}", claims.id, e);
+      return Err(e.into());
+    }
+  };
+  if user.is_admin {
+    return Err(warp::reject::bad_request());
+  }
+  if let Err(e) = db::revoke_token(&claims.jti, Utc.timestamp(claims.exp, 0)) {
+    error!("could not revoke token {}: {}", claims.jti, e);
+    return Err(e.into());
+  }
+  end_sessions(user.id, "account_deleted", &state);
+  match db::delete_user(user.id) {
+    Ok(_) => {
+      info!("user {} deleted their account", user.id);
+      let reply = warp::reply::json(&json!({ "deleted": true }));
+      Ok(warp::reply::with_header(reply, "set-cookie", config::WEB.expired_cookie()))
+    }
+    Err(e) => {
+      error!("could not delete user {}: {}", user.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_quota(claims: Claims) -> Result<impl warp::Reply, warp::Rejection> {
+  match quotas::usage(claims.id) {
+    Ok(usage) => Ok(warp::reply::json(&usage)),
+    Err(e) => {
+      error!("could not get the quota of user {}: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// `user_id` None sets everyone's default
+pub fn set_quota(
+  user_id: Option<i32>,
+  params: QuotaParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let resource = match Resource::from_name(&params.name) {
+    Some(resource) => resource,
+    None => return Err(warp::reject::bad_request()),
+  };
+  if params.limit.map(|l| l < 0).unwrap_or(false) {
+    return Err(warp::reject::bad_request());
+  }
+  match db::set_quota(user_id, resource.name(), params.limit) {
+    Ok(_) => Ok(warp::reply::json(
+      &json!({ "name": resource.name(), "limit": params.limit }),
+    )),
+    Err(e) => {
+      error!("could not set the {} quota: {}", resource.name(), e);
+      Err(e.into())
+    }
+  }
+}
+
+// the user gets the default limit again
+pub fn delete_quota(user_id: i32, name: String) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::delete_quota(user_id, &name) {
+    Ok(_) => Ok(warp::reply::json(&json!({ "deleted": true }))),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not delete the {} quota of user {}: {}", name, user_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn transfer_subscriptions(
+  user_id: i32,
+  params: TransferParams,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  if user_id == params.to_user_id {
+    return Err(warp::reject::bad_request());
+  }
+  match db::transfer_subscriptions(user_id, params.to_user_id, params.copy, params.with_state) {
+    Ok((feeds, items)) => {
+      let detail = format!(
+        "{} to user {}",
+        if params.copy { "copied" } else { "moved" },
+        params.to_user_id
+      );
+      db::log_activity(user_id, Action::TransferSubscriptions, Some(&detail));
+      Ok(warp::reply::json(&json!({ "feeds": feeds, "items": items })))
+    }
+    Err(e) => {
+      error!("could not transfer subscriptions of user {}: {}", user_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+/// notifications ///
+
+// `?unread=true` leaves out what was already read
+pub fn show_notifications(
+  claims: Claims,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  let page = parse_page(&query)?;
+  let unread = query.get("unread").map(|u| u == "true").unwrap_or(false);
+  let (notifications, total) = db::get_notifications(claims.id, unread, page)?;
+  Ok(with_total(warp::reply::json(&notifications), total))
+}
+
+// a single notification, or all of them
+pub fn read_notifications(
+  claims: Claims,
+  notification_id: Option<i32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+  match db::mark_notifications_seen(claims.id, notification_id) {
+    Ok(0) if notification_id.is_some() => Err(warp::reject::not_found()),
+    Ok(n) => Ok(warp::reply::json(&json!({ "read": n }))),
+    Err(e) => {
+      error!("could not mark notifications of {} as read: {}", claims.id, e);
+      Err(e.into())
+    }
+  }
+}
+
+// a system notice for every user
+pub fn create_notice(params: NoticeParams) -> Result<impl warp::Reply, warp::Rejection> {
+  if params.message.trim().is_empty() {
+    return Err(warp::reject::bad_request());
+  }
+  let detail = params.detail.as_ref().map(|d| d.as_str());
+  match db::notify_all(NotificationKind::System, params.message.trim(), detail) {
+    Ok(n) => Ok(warp::reply::json(&json!({ "notified": n }))),
+    Err(e) => {
+      error!("could not send notice: {}", e);
+      Err(e.into())
+    }
+  }
+}
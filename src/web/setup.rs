@@ -0,0 +1,84 @@
+use hyper::Body;
+use std::env;
+use warp::http::{Response, StatusCode};
+use warp::{self, Rejection};
+
+use super::handlers::{random_token, strong_password, valid_username, RegistrationMode};
+use super::jwt::generate_jwt;
+use super::types::SetupParams;
+use db::{complete_setup, has_users, JWT_SECRET_SETTING};
+use i18n;
+use models::User;
+
+// Until the first user exists, `/setup` creates the admin and stores the
+// settings that would otherwise have to come from the environment. Both
+// routes answer 404 afterwards.
+
+static SETUP_PAGE: &'static str = include_str!("setup.html");
+static MIN_SECRET_LEN: usize = 32;
+
+pub fn show_page() -> Result<Response<Body>, Rejection> {
+  if has_users()? {
+    return Err(warp::reject::not_found());
+  }
+  Ok(
+    Response::builder()
+      .header("content-type", "text/html; charset=utf-8")
+      .header("cache-control", "no-store")
+      .body(Body::from(SETUP_PAGE))
+      .unwrap(),
+  )
+}
+
+pub fn run(
+  params: SetupParams,
+  accept_language: Option<String>,
+) -> Result<impl warp::Reply, Rejection> {
+  if has_users()? {
+    return Err(warp::reject::not_found());
+  }
+  let locale = i18n::negotiate(accept_language.as_ref().map(|l| l.as_str()));
+  let invalid = |reason: &str| {
+    let body = json!({ "error": reason, "message": i18n::tr(locale, reason) });
+    Ok(warp::reply::with_status(warp::reply::json(&body), StatusCode::BAD_REQUEST))
+  };
+  if !valid_username(&params.username) {
+    return invalid("invalid_username");
+  }
+  if !strong_password(&params.password) {
+    return invalid("weak_password");
+  }
+
+  let mut settings = vec![];
+  if let Some(mode) = params.registration {
+    if RegistrationMode::parse(&mode).is_none() {
+      return invalid("invalid_registration");
+    }
+    settings.push(("registration", mode));
+  }
+  // a JWT_SECRET in the environment keeps precedence, so none is stored
+  if env::var("JWT_SECRET").is_err() {
+    let secret = match params.jwt_secret {
+      Some(ref s) if s.len() < MIN_SECRET_LEN => return invalid("weak_secret"),
+      Some(s) => s,
+      None => random_token(MIN_SECRET_LEN).map_err(|e| {
+        error!("could not read random bytes: {}", e);
+        warp::reject::server_error()
+      })?,
+    };
+    settings.push((JWT_SECRET_SETTING, secret));
+  }
+
+  let pwh = User::hash_pw(&params.password);
+  match complete_setup(&params.username, &pwh, &settings)? {
+    Some(user) => {
+      info!("setup done, '{}' is the admin", user.username);
+      let token = generate_jwt(&user).ok_or_else(|| warp::reject::server_error())?;
+      Ok(warp::reply::with_status(
+        warp::reply::json(&json!({ "token": token })),
+        StatusCode::CREATED,
+      ))
+    }
+    None => Err(warp::reject::not_found()),
+  }
+}
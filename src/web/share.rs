@@ -0,0 +1,208 @@
+use hyper::Body;
+use regex::Regex;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+use warp::http::Response;
+use warp::{self, Rejection};
+
+use super::handlers::random_token;
+use db;
+use error::reject;
+use models::{Claims, Item};
+use sanitize;
+
+// `GET /share/:token` is a public page for one item, for people without an
+// account. Chat apps unfurl such links from the Open Graph tags in the head:
+// the item's title, the start of its summary and its first image, taken from
+// an image enclosure or else from the first <img> of its content. A head is
+// rendered once per token and kept for an hour; the item itself is looked up
+// on every request, so revoked shares are gone at once.
+
+static TOKEN_BYTES: usize = 16;
+static DESCRIPTION_CHARS: usize = 200;
+static CACHE_SECS: u64 = 3600;
+static MAX_CACHED: usize = 1000;
+
+lazy_static! {
+  static ref HEADS: Mutex<HashMap<String, (Instant, String)>> = Mutex::new(HashMap::new());
+  static ref TAG: Regex = Regex::new(r"<[^>]*>").unwrap();
+  static ref SPACE: Regex = Regex::new(r"\s+").unwrap();
+  static ref IMG_SRC: Regex =
+    Regex::new(r#"(?i)<img\s[^>]*\bsrc\s*=\s*["']([^"']+)["']"#).unwrap();
+}
+
+pub fn create(claims: Claims, item_id: i32) -> Result<impl warp::Reply, Rejection> {
+  let item = db::get_items_by_ids(&vec![item_id])?
+    .into_iter()
+    .next()
+    .ok_or_else(|| warp::reject::not_found())?;
+  db::get_subscribed_feed(&claims.id, &item.feed_id).map_err(reject)?;
+  let token = random_token(TOKEN_BYTES).map_err(|e| {
+    error!("could not read random bytes: {}", e);
+    warp::reject::server_error()
+  })?;
+  let share = db::insert_item_share(claims.id, item_id, &token)?;
+  Ok(warp::reply::json(&share))
+}
+
+pub fn list(claims: Claims) -> Result<impl warp::Reply, Rejection> {
+  let shares = db::get_item_shares(claims.id)?;
+  Ok(warp::reply::json(&shares))
+}
+
+pub fn revoke(claims: Claims, share_id: i32) -> Result<impl warp::Reply, Rejection> {
+  match db::delete_item_share(share_id, claims.id) {
+    Ok(Some(token)) => {
+      HEADS.lock().unwrap().remove(&token);
+      Ok(warp::reply::json(&json!({ "deleted": true })))
+    }
+    Ok(None) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not delete share {}: {}", share_id, e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn show_page(token: String) -> Result<Response<Body>, Rejection> {
+  let (item, feed_title) = db::find_shared_item(&token).map_err(reject)?;
+  let head = cached_head(&token, &item, &feed_title);
+  // stored HTML is sanitized, but this page is public, so it's cleaned again
+  let base = Url::parse(&item.link).ok();
+  let content = item
+    .content
+    .as_ref()
+    .or(item.summary.as_ref())
+    .map(|c| sanitize::clean(c, base.as_ref()));
+  let page = format!(
+    "<!DOCTYPE html>\n<html>\n<head>\n{}</head>\n<body>\n<article>\n\
+     <h1><a href=\"{}\">{}</a></h1>\n<p>{}</p>\n{}\n</article>\n</body>\n</html>\n",
+    head,
+    escape(web_link(&item.link)),
+    escape(&item.title),
+    escape(&feed_title),
+    content.unwrap_or_default(),
+  );
+  Ok(
+    Response::builder()
+      .header("content-type", "text/html; charset=utf-8")
+      .body(Body::from(page))
+      .unwrap(),
+  )
+}
+
+fn cached_head(token: &str, item: &Item, feed_title: &str) -> String {
+  let mut heads = HEADS.lock().unwrap();
+  if let Some(&(ref rendered_at, ref head)) = heads.get(token) {
+    if rendered_at.elapsed() < Duration::from_secs(CACHE_SECS) {
+      return head.clone();
+    }
+  }
+  if heads.len() >= MAX_CACHED {
+    heads.clear();
+  }
+  let head = render_head(item, feed_title);
+  heads.insert(token.to_owned(), (Instant::now(), head.clone()));
+  head
+}
+
+fn render_head(item: &Item, feed_title: &str) -> String {
+  let mut head = format!(
+    "<meta charset=\"utf-8\">\n<meta name=\"robots\" content=\"noindex\">\n\
+     <title>{}</title>\n",
+    escape(&item.title)
+  );
+  meta(&mut head, "og:type", "article");
+  meta(&mut head, "og:title", &item.title);
+  meta(&mut head, "og:site_name", feed_title);
+  meta(&mut head, "og:url", web_link(&item.link));
+  if let Some(description) = item.summary.as_ref().or(item.content.as_ref()).map(|h| excerpt(h)) {
+    meta(&mut head, "og:description", &description);
+  }
+  match thumbnail(item) {
+    Some(image) => {
+      meta(&mut head, "og:image", &image);
+      meta(&mut head, "twitter:card", "summary_large_image");
+    }
+    None => meta(&mut head, "twitter:card", "summary"),
+  };
+  head
+}
+
+fn meta(head: &mut String, property: &str, content: &str) {
+  head.push_str(&format!(
+    "<meta property=\"{}\" content=\"{}\">\n",
+    property,
+    escape(content)
+  ));
+}
+
+// the first image enclosure, or the first image in the item's HTML
+fn thumbnail(item: &Item) -> Option<String> {
+  let enclosure = db::get_enclosures(&vec![item.id])
+    .unwrap_or_else(|e| {
+      error!("could not load the enclosures of item {}: {}", item.id, e);
+      HashMap::new()
+    }).remove(&item.id)
+    .unwrap_or_default()
+    .into_iter()
+    .find(|e| {
+      e.mime_type
+        .as_ref()
+        .map(|m| m.starts_with("image/"))
+        .unwrap_or(false)
+    });
+  if let Some(enclosure) = enclosure {
+    return Some(enclosure.url);
+  }
+  item
+    .content
+    .iter()
+    .chain(item.summary.iter())
+    .filter_map(|html| IMG_SRC.captures(html))
+    .map(|img| unescape(&img[1]))
+    .find(|src| src.starts_with("http://") || src.starts_with("https://"))
+}
+
+// the text of some HTML, cut at a word after DESCRIPTION_CHARS characters
+fn excerpt(html: &str) -> String {
+  let text = unescape(SPACE.replace_all(&TAG.replace_all(html, " "), " ").trim());
+  if text.chars().count() <= DESCRIPTION_CHARS {
+    return text;
+  }
+  let cut: String = text.chars().take(DESCRIPTION_CHARS).collect();
+  let cut = match cut.rfind(' ') {
+    Some(space) => &cut[..space],
+    None => cut.as_str(),
+  };
+  format!("{}…", cut.trim_right())
+}
+
+// feeds can link anywhere, `javascript:` included
+fn web_link(link: &str) -> &str {
+  match link.starts_with("http://") || link.starts_with("https://") {
+    true => link,
+    false => "#",
+  }
+}
+
+fn escape(text: &str) -> String {
+  text
+    .replace('&', "&amp;")
+    .replace('<', "&lt;")
+    .replace('>', "&gt;")
+    .replace('"', "&quot;")
+}
+
+// the entities the sanitizer writes
+fn unescape(html: &str) -> String {
+  html
+    .replace("&lt;", "<")
+    .replace("&gt;", ">")
+    .replace("&quot;", "\"")
+    .replace("&#39;", "'")
+    .replace("&nbsp;", " ")
+    .replace("&amp;", "&")
+}
@@ -0,0 +1,51 @@
+use futures::stream;
+use futures::sync::mpsc;
+use futures::Stream;
+use hyper::Body;
+use std::time::{Duration, Instant};
+use tokio::timer::Interval;
+use warp::http::Response;
+use warp::Rejection;
+
+use super::types::{Connection, UserWebsocketState};
+use db::get_session_id;
+use models::Claims;
+
+// `GET /events` is the one way half of `/ws` for deployments whose proxies
+// don't pass websockets through: every message the socket would get is sent
+// as the data of a Server-Sent Event, the same JSON. Commands go to the REST
+// API instead. A comment every KEEPALIVE_SECS keeps proxies from timing out
+// quiet responses.
+
+static KEEPALIVE_SECS: u64 = 30;
+
+pub fn connect(claims: Claims, users: UserWebsocketState) -> Result<Response<Body>, Rejection> {
+  let user_id = claims.id;
+  let session_id = get_session_id(&claims.jti)?.unwrap_or(0);
+  debug!("SSE: user connected: {} - {}", user_id, claims.name);
+  let (tx, rx) = mpsc::unbounded();
+  users.insert(user_id, session_id, Connection::Events(tx));
+
+  // None once the sender is dropped, e.g. because the session was revoked
+  let events = rx
+    .map(|data: String| Some(format!("data: {}\n\n", data)))
+    .chain(stream::once(Ok(None)));
+  let every = Duration::from_secs(KEEPALIVE_SECS);
+  let keepalive = Interval::new(Instant::now() + every, every)
+    .map(|_| Some(": keepalive\n\n".to_owned()))
+    .map_err(|e| error!("SSE: keepalive timer errored: {}", e));
+  let body = events
+    .select(keepalive)
+    .take_while(|event| Ok(event.is_some()))
+    .map(|event| event.unwrap_or_default())
+    .map_err(|_| "event stream ended".to_owned());
+  Ok(
+    Response::builder()
+      .header("content-type", "text/event-stream")
+      .header("cache-control", "no-cache")
+      // nginx buffers responses unless told otherwise
+      .header("x-accel-buffering", "no")
+      .body(Body::wrap_stream(body))
+      .unwrap(),
+  )
+}
@@ -1,25 +1,113 @@
+use chrono::{DateTime, Utc};
 use futures::stream::SplitSink;
+use futures::sync::mpsc::UnboundedSender;
+use futures::Sink;
 use regex::Regex;
 use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
-use warp::ws::WebSocket;
+use warp::ws::{Message, WebSocket};
 use warp::{self, Rejection};
 
-#[derive(Clone, Debug)]
+// a websocket, or the response of `GET /events` for clients whose proxies
+// don't pass websockets through
+pub enum Connection {
+  Socket(SplitSink<WebSocket>),
+  Events(UnboundedSender<String>),
+}
+impl Connection {
+  // false once the client has gone
+  pub fn send(&mut self, message: Message) -> bool {
+    match *self {
+      Connection::Socket(ref mut tx) => tx.start_send(message).is_ok(),
+      // close frames have no text, dropping the sender ends the response
+      Connection::Events(ref tx) => match message.to_str() {
+        Ok(text) => tx.unbounded_send(text.to_owned()).is_ok(),
+        Err(_) => true,
+      },
+    }
+  }
+}
+
+// tabs and devices on the same token share a session, so every connection
+// gets an id of its own
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+// open connections by user, then by connection, with the session of each
 pub struct UserWebsocketState {
-  pub state: Arc<Mutex<HashMap<i32, SplitSink<WebSocket>>>>,
+  pub state: Arc<Mutex<HashMap<i32, HashMap<usize, (i32, Connection)>>>>,
 }
 impl UserWebsocketState {
   pub fn clone(&self) -> Self {
     let s2 = Arc::clone(&self.state);
     UserWebsocketState { state: s2 }
   }
-  pub fn insert(&self, key: i32, val: SplitSink<WebSocket>) {
-    self.state.lock().unwrap().insert(key, val);
+  // returns the id to `remove` the connection by once its client is gone
+  pub fn insert(&self, key: i32, session: i32, val: Connection) -> usize {
+    let connection = NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed);
+    self
+      .state
+      .lock()
+      .unwrap()
+      .entry(key)
+      .or_insert_with(HashMap::new)
+      .insert(connection, (session, val));
+    connection
+  }
+  pub fn remove(&self, key: &i32, connection: &usize) -> Option<Connection> {
+    let mut state = self.state.lock().unwrap();
+    let val = state
+      .get_mut(key)
+      .and_then(|connections| connections.remove(connection))
+      .map(|(_, val)| val);
+    if state.get(key).map(|connections| connections.is_empty()).unwrap_or(false) {
+      state.remove(key);
+    }
+    val
+  }
+  // every connection of the session, e.g. once it was revoked
+  pub fn remove_session(&self, key: &i32, session: &i32) -> Vec<Connection> {
+    let mut state = self.state.lock().unwrap();
+    let ids: Vec<usize> = match state.get(key) {
+      Some(connections) => connections
+        .iter()
+        .filter(|&(_, &(s, _))| s == *session)
+        .map(|(id, _)| *id)
+        .collect(),
+      None => return vec![],
+    };
+    let vals = match state.get_mut(key) {
+      Some(connections) => ids
+        .iter()
+        .filter_map(|id| connections.remove(id))
+        .map(|(_, val)| val)
+        .collect(),
+      None => vec![],
+    };
+    if state.get(key).map(|connections| connections.is_empty()).unwrap_or(false) {
+      state.remove(key);
+    }
+    vals
+  }
+  pub fn connections(&self) -> usize {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .values()
+      .map(|connections| connections.len())
+      .sum()
   }
-  pub fn remove(&self, key: &i32) {
-    self.state.lock().unwrap().remove(key);
+  pub fn contains(&self, key: &i32, connection: &usize) -> bool {
+    self
+      .state
+      .lock()
+      .unwrap()
+      .get(key)
+      .map(|connections| connections.contains_key(connection))
+      .unwrap_or(false)
   }
 }
 
@@ -35,8 +123,14 @@ pub struct LoginParams {
 }
 
 #[derive(Deserialize, Debug)]
-pub struct ChangePasswordParams {
+pub struct RegisterParams {
   pub username: String,
+  pub password: String,
+  pub invite_code: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangePasswordParams {
   pub old_pass: String,
   pub new_pass: String,
 }
@@ -46,10 +140,210 @@ pub struct SubscribeParams {
   pub feed_url: String,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct FilterParams {
+  pub feed_id: Option<i32>,
+  #[serde(default)]
+  pub pattern: String,
+  // matched literally instead of `pattern`
+  pub keyword: Option<String>,
+  pub match_field: String,
+  pub action: String,
+  #[serde(default)]
+  pub enabled: bool,
+}
+
+// `feed_id: null` sends the new items of every feed
+#[derive(Deserialize, Debug)]
+pub struct WebhookParams {
+  pub url: String,
+  pub feed_id: Option<i32>,
+}
+
+// what the browser's `PushManager.subscribe()` resolves to, as JSON
+#[derive(Deserialize, Debug)]
+pub struct PushSubscribeParams {
+  pub endpoint: String,
+  pub keys: PushKeys,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PushKeys {
+  pub p256dh: String,
+  pub auth: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PushUnsubscribeParams {
+  pub endpoint: String,
+}
+
+// `feed_id: null` mutes the phrase in every feed
+#[derive(Deserialize, Debug)]
+pub struct MuteParams {
+  pub feed_id: Option<i32>,
+  pub phrase: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TagsParams {
+  pub tags: Vec<String>,
+}
+
+// `window: null` fetches the feed at any time again
+#[derive(Deserialize, Debug)]
+pub struct RefreshWindowParams {
+  pub window: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UnsubscribeParams {
+  pub feed_id: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AddFeedsParams {
+  pub urls: Vec<String>,
+}
+
+// one of the two
+#[derive(Deserialize, Debug)]
+pub struct SubscribeParams {
+  pub feed_id: Option<i32>,
+  pub feed_url: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct QuotaParams {
+  pub name: String,
+  // None removes the limit
+  pub limit: Option<i64>,
+}
+
+// `POST /api/sync`; the changes were made at `updated_at`, now when unset
+#[derive(Deserialize, Debug)]
+pub struct SyncParams {
+  pub since: DateTime<Utc>,
+  pub updated_at: Option<DateTime<Utc>>,
+  #[serde(default)]
+  pub read: Vec<i32>,
+  #[serde(default)]
+  pub unread: Vec<i32>,
+  #[serde(default)]
+  pub starred: Vec<i32>,
+  #[serde(default)]
+  pub unstarred: Vec<i32>,
+}
+
+// an entry of `PATCH /api/feeds/order`
+#[derive(Deserialize, Debug)]
+pub struct FeedOrderEntry {
+  pub feed_id: i32,
+  pub sort_order: Option<i32>,
+  pub pinned: Option<bool>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TopicParams {
+  pub name: String,
+  pub keywords: Vec<String>,
+  #[serde(default)]
+  pub notify: bool,
+}
+
+// one subscription in the JSON list format other readers export
+#[derive(Deserialize, Serialize, Debug)]
+pub struct SubscriptionEntry {
+  pub url: String,
+  #[serde(default)]
+  pub title: Option<String>,
+  #[serde(default)]
+  pub folder: Option<String>,
+  #[serde(default)]
+  pub state: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LocaleParams {
+  pub locale: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ApiTokenParams {
+  // what the token is for, to tell it apart from the others
+  pub name: String,
+}
+
+// a kiosk shows one tag, one feed, or every subscription if neither is given
+#[derive(Deserialize, Debug)]
+pub struct KioskParams {
+  pub tag: Option<String>,
+  pub feed_id: Option<i32>,
+  pub limit: Option<i32>,
+  pub ttl_days: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct RateWarningParams {
+  pub items_per_day: f64,
+}
+
+// `days: null` turns auto-read off
+#[derive(Deserialize, Debug)]
+pub struct AutoReadParams {
+  pub days: Option<i32>,
+}
+
+// `copy` keeps the source subscribed, `with_state` brings read and starred items along
+#[derive(Deserialize, Debug)]
+pub struct TransferParams {
+  pub to_user_id: i32,
+  #[serde(default)]
+  pub copy: bool,
+  #[serde(default)]
+  pub with_state: bool,
+}
+
+// exactly one of the two; a folder is a tag
+#[derive(Deserialize, Debug)]
+pub struct RetentionExemptionParams {
+  pub tag: Option<String>,
+  pub feed_id: Option<i32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NoticeParams {
+  pub message: String,
+  pub detail: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SetupParams {
+  pub username: String,
+  pub password: String,
+  pub registration: Option<String>,
+  pub jwt_secret: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FeverPasswordParams {
+  pub password: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PasswordParams {
+  pub password: String,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 pub enum IncomingMessageType {
+  Authenticate,
   MarkRead,
+  Star,
   Subscribe,
+  Unsubscribe,
+  OlderItems,
+  GetItemContent,
   AddUser,
   ChangePassword,
   ChangeSettings,
@@ -59,6 +353,25 @@ pub enum IncomingMessageType {
 pub struct IncomingMessage {
   pub msg_type: IncomingMessageType,
   pub data: String,
+  // echoed in the result, so clients can tell which command it answers
+  #[serde(default)]
+  pub id: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StarParams {
+  pub item_id: i32,
+  pub starred: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OlderItemsParams {
+  pub feed_id: i32,
+  pub before: Option<DateTime<Utc>>,
+  pub limit: Option<i64>,
+  // leaves `content` out, for `GetItemContent` to load when an item is opened
+  #[serde(default)]
+  pub omit_content: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -66,15 +379,72 @@ pub struct AccessToken {
   pub access_token: String,
 }
 
+lazy_static! {
+  // a single file name, what may be served is up to the asset index
+  static ref ASSET_FILE: Regex = Regex::new(r"^([A-Za-z0-9][A-Za-z0-9_.-]*)$").unwrap();
+}
+
+// the `warp::path::param` of asset routes
 pub struct AssetFile(pub String);
 impl FromStr for AssetFile {
   type Err = Rejection;
   fn from_str(s: &str) -> Result<AssetFile, Rejection> {
-    // let re = Regex::new(r"((?:src|favicon)\.\w+\.(?:css|js|png))").unwrap();
-    let re = Regex::new(r"((?:main|favicon2).(?:css|js|png))").unwrap();
-    match re.captures(&s) {
+    match ASSET_FILE.captures(&s) {
       Some(m) => Ok(AssetFile(m.get(1).unwrap().as_str().to_owned())),
       None => Err(warp::reject::not_found()),
     }
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{Connection, UserWebsocketState};
+  use futures::sync::mpsc;
+  use std::collections::HashMap;
+  use std::sync::{Arc, Mutex};
+
+  fn state() -> UserWebsocketState {
+    UserWebsocketState {
+      state: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  fn connection() -> Connection {
+    Connection::Events(mpsc::unbounded().0)
+  }
+
+  #[test]
+  fn connections_of_a_session_are_kept_apart() {
+    let users = state();
+    let first = users.insert(1, 7, connection());
+    let second = users.insert(1, 7, connection());
+    assert!(first != second);
+    assert_eq!(users.connections(), 2);
+    assert!(users.remove(&1, &first).is_some());
+    assert!(users.contains(&1, &second));
+    assert!(!users.contains(&1, &first));
+  }
+
+  #[test]
+  fn sessions_close_all_their_connections() {
+    let users = state();
+    users.insert(1, 7, connection());
+    users.insert(1, 7, connection());
+    let other = users.insert(1, 8, connection());
+    assert_eq!(users.remove_session(&1, &7).len(), 2);
+    assert_eq!(users.connections(), 1);
+    assert!(users.contains(&1, &other));
+    assert!(users.remove_session(&1, &7).is_empty());
+  }
+
+  #[test]
+  fn users_without_connections_are_dropped() {
+    let users = state();
+    let id = users.insert(1, 7, connection());
+    users.insert(2, 7, connection());
+    users.remove(&1, &id);
+    assert!(!users.state.lock().unwrap().contains_key(&1));
+    users.remove_session(&2, &7);
+    assert!(users.state.lock().unwrap().is_empty());
+  }
+}
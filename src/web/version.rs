@@ -0,0 +1,88 @@
+use futures::{Future, Poll};
+use std::cell::Cell;
+use warp::{self, Filter, Rejection};
+
+// The API answers at /api/v1/..., and for clients written before it had a
+// version, at /api/... as well. The two only differ in how replies are
+// written: v1 timestamps are RFC 3339 in UTC with microseconds, always
+// `2019-01-31T09:30:00.000000Z`, while legacy paths keep chrono's format,
+// whose fraction of a second comes and goes. Fields are snake_case on both.
+//
+// The version is kept with the connection's task while routes are matched,
+// and with the thread while `guard` runs a handler, since replies are also
+// written outside of requests, e.g. to websockets, which are not versioned.
+
+task_local! {
+  static V1: Cell<bool> = Cell::new(false)
+}
+
+thread_local! {
+  static CANONICAL: Cell<bool> = Cell::new(false)
+}
+
+// goes in front of all routes
+pub fn start() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  warp::any().map(|| V1.with(|v| v.set(false))).untuple_one()
+}
+
+// `/api` or `/api/v1`, where the routes of the API start
+pub fn api() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  warp::path("api")
+    .and(
+      warp::path("v1")
+        .map(|| true)
+        .or(warp::any().map(|| false))
+        .unify(),
+    ).map(|v1: bool| V1.with(|v| v.set(v1)))
+    .untuple_one()
+}
+
+// for routes that were served outside of `/api` before it had a version; they
+// answer at their old paths too, in the legacy format
+pub fn api_or_root() -> impl Filter<Extract = (), Error = Rejection> + Copy {
+  api().map(|| ()).or(warp::any().map(|| ())).unify().untuple_one()
+}
+
+pub fn is_v1() -> bool {
+  V1.with(|v| v.get())
+}
+
+// whether replies written now use the v1 format
+pub fn canonical() -> bool {
+  CANONICAL.with(|c| c.get())
+}
+
+struct Restore(bool);
+impl Drop for Restore {
+  fn drop(&mut self) {
+    CANONICAL.with(|c| c.set(self.0));
+  }
+}
+
+pub fn scoped<F: FnOnce() -> R, R>(v1: bool, f: F) -> R {
+  let _restore = Restore(CANONICAL.with(|c| c.replace(v1)));
+  f()
+}
+
+// a handler's future, which can also write its reply later
+pub struct Versioned<F> {
+  v1: bool,
+  inner: F,
+}
+impl<F> Versioned<F> {
+  pub fn new(v1: bool, inner: F) -> Self {
+    Versioned {
+      v1: v1,
+      inner: inner,
+    }
+  }
+}
+impl<F: Future> Future for Versioned<F> {
+  type Item = F::Item;
+  type Error = F::Error;
+
+  fn poll(&mut self) -> Poll<F::Item, F::Error> {
+    let inner = &mut self.inner;
+    scoped(self.v1, || inner.poll())
+  }
+}
@@ -0,0 +1,89 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::future;
+use futures::{stream, Future, Stream};
+use hyper::rt;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use db;
+use feed;
+
+// A user coming back after IDLE_SECS, with the first authenticated request or
+// websocket since, gets their most read feeds fetched right away instead of
+// at their turn in the schedule. Feeds are ranked by the items the user read
+// from them in the last READS_DAYS days. At most WARMUP_FEEDS are fetched, and
+// no more than PER_HOST_FEEDS of one host, so a site with many feeds neither
+// takes all the turns nor gets hit by several fetches at once.
+//
+// Each web node keeps track of the users it serves; feed leases keep nodes
+// from fetching the same feed twice.
+
+static IDLE_SECS: u64 = 30 * 60;
+static READS_DAYS: i64 = 30;
+static WARMUP_FEEDS: usize = 10;
+static PER_HOST_FEEDS: usize = 2;
+static CONCURRENCY: usize = 2;
+
+lazy_static! {
+  static ref LAST_SEEN: Mutex<HashMap<i32, Instant>> = Mutex::new(HashMap::new());
+}
+
+pub fn user_active(user_id: i32) {
+  let now = Instant::now();
+  let returning = {
+    let mut last_seen = LAST_SEEN.lock().unwrap();
+    let returning = last_seen
+      .get(&user_id)
+      .map(|seen| now.duration_since(*seen) >= Duration::from_secs(IDLE_SECS))
+      .unwrap_or(true);
+    last_seen.insert(user_id, now);
+    returning
+  };
+  if returning {
+    rt::spawn(future::lazy(move || warm_up(user_id)));
+  }
+}
+
+fn warm_up(user_id: i32) -> impl Future<Item = (), Error = ()> {
+  let since = Utc::now() - ChronoDuration::days(READS_DAYS);
+  let feeds = db::get_feeds_by_reads(user_id, since).unwrap_or_else(|e| {
+    error!("could not rank the feeds of user {}: {}", user_id, e);
+    vec![]
+  });
+  let feed_ids = pick_feeds(feeds);
+  debug!("refreshing {} feeds for returning user {}", feed_ids.len(), user_id);
+  stream::iter_ok(feed_ids)
+    .map(|feed_id| {
+      feed::refresh_feed(feed_id).then(move |refreshed| {
+        if let Err(e) = refreshed {
+          debug!("could not refresh feed {}: {}", feed_id, e);
+        }
+        Ok::<_, ()>(())
+      })
+    }).buffer_unordered(CONCURRENCY)
+    .for_each(|_| Ok(()))
+}
+
+// the first feeds in order, skipping those of hosts that have had their share
+fn pick_feeds(ranked: Vec<(i32, String)>) -> Vec<i32> {
+  let mut per_host: HashMap<String, usize> = HashMap::new();
+  let mut picked = vec![];
+  for (feed_id, feed_link) in ranked {
+    let host = Url::parse(&feed_link)
+      .ok()
+      .and_then(|u| u.host_str().map(|h| h.to_owned()))
+      .unwrap_or_default();
+    let taken = per_host.entry(host).or_insert(0);
+    if *taken >= PER_HOST_FEEDS {
+      continue;
+    }
+    *taken += 1;
+    picked.push(feed_id);
+    if picked.len() == WARMUP_FEEDS {
+      break;
+    }
+  }
+  picked
+}
@@ -0,0 +1,42 @@
+use bytes::Buf;
+use hyper::rt::Future;
+use std::collections::HashMap;
+use warp::body::FullBody;
+use warp::http::StatusCode;
+use warp::{self, Rejection};
+
+use error::HermesError;
+use websub;
+
+// The callback hubs call, `/websub/callback/:token`; the token is all the
+// authentication there is, see `websub`.
+
+pub fn verify(
+  token: String,
+  query: HashMap<String, String>,
+) -> Result<impl warp::Reply, Rejection> {
+  match websub::verify(&token, &query) {
+    Ok(challenge) => Ok(challenge),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not verify WebSub subscription: {}", e);
+      Err(e.into())
+    }
+  }
+}
+
+pub fn receive(
+  token: String,
+  signature: Option<String>,
+  body: FullBody,
+) -> impl Future<Item = impl warp::Reply, Error = Rejection> {
+  let body = body.bytes().to_vec();
+  websub::receive(&token, signature, body).then(|received| match received {
+    Ok(_) => Ok(warp::reply::with_status(warp::reply(), StatusCode::ACCEPTED)),
+    Err(HermesError::NotFound) => Err(warp::reject::not_found()),
+    Err(e) => {
+      error!("could not store pushed feed: {}", e);
+      Err(e.into())
+    }
+  })
+}
@@ -1,15 +1,56 @@
+use futures::future::Either;
 use futures::{Future, Sink, Stream};
+use serde::de::DeserializeOwned;
 use serde_json;
+use std::time::Duration;
+use tokio::timer::Timeout;
 use warp::ws::{Message, WebSocket};
 
 use super::types::{
-  ChangePasswordParams, IncomingMessage, IncomingMessageType, LoginParams, SettingsData,
-  SubscribeParams, UserWebsocketState,
+  Connection, IncomingMessage, IncomingMessageType, OlderItemsParams, StarParams, SubscribeParams,
+  UnsubscribeParams, UserWebsocketState,
 };
 
-use db::mark_subscribed_item_as_read;
+use db::{
+  get_item_content, get_session_id, get_subscribed_feed, get_subscribed_items, log_activity,
+  mark_item_as_read, set_item_starred, unsubscribe_feed,
+};
+use error::HermesError;
 use feed;
-use models::{Claims, OutgoingWebsocketMessage};
+use models::{Action, ActionOutput, Claims, CompositeItem, OutgoingWebsocketMessage, Page};
+
+static AUTHENTICATE_SECS: u64 = 10;
+
+// Sockets opened without a token, which browsers can't put in a header, have
+// to send `{"msg_type": "Authenticate", "data": "<JWT>"}` first and are
+// closed if they don't within AUTHENTICATE_SECS.
+pub fn ws_connected(
+  ws: WebSocket,
+  claims: Option<Claims>,
+  users: UserWebsocketState,
+) -> impl Future<Item = (), Error = ()> {
+  if let Some(claims) = claims {
+    return Either::A(ws_created(ws, claims, users));
+  }
+  let authenticated = Timeout::new(ws.into_future(), Duration::from_secs(AUTHENTICATE_SECS))
+    .map_err(|_| debug!("WS: no authentication received"))
+    .and_then(move |(msg, ws)| match msg.as_ref().and_then(ws_authentication) {
+      Some(claims) => Either::A(ws_created(ws, claims, users)),
+      None => {
+        debug!("WS: closing unauthenticated socket");
+        Either::B(ws.send(Message::close()).map(|_| ()).map_err(|_| ()))
+      }
+    });
+  Either::B(authenticated)
+}
+
+fn ws_authentication(msg: &Message) -> Option<Claims> {
+  let message = serde_json::from_str::<IncomingMessage>(msg.to_str().ok()?).ok()?;
+  match message.msg_type {
+    IncomingMessageType::Authenticate => super::make_claim(message.data).ok(),
+    _ => None,
+  }
+}
 
 pub fn ws_created(
   ws: WebSocket,
@@ -17,75 +58,194 @@ pub fn ws_created(
   users: UserWebsocketState,
 ) -> impl Future<Item = (), Error = ()> {
   let user_id = claims.id;
+  // tokens issued before sessions were tracked all share session 0
+  let session_id = get_session_id(&claims.jti)
+    .unwrap_or_else(|e| {
+      error!("could not find the session of user {}: {}", user_id, e);
+      None
+    }).unwrap_or(0);
   debug!("WS: user connected: {} - {}", user_id, claims.name);
   let (tx, rx) = ws.split();
-  users.insert(user_id, tx);
+  let connection_id = users.insert(user_id, session_id, Connection::Socket(tx));
   let users2 = users.clone();
 
   rx.for_each(move |msg| {
+    // the session may have been revoked since the socket was opened
+    if !users.contains(&user_id, &connection_id) {
+      return Ok(());
+    }
     Ok(match ws_incoming_msg(&claims, msg, &users) {
       Some(msg) => ws_send_message(&user_id, msg, &users),
       None => (),
     })
   }).then(move |result| {
-    ws_user_disconnected(&user_id, &users2);
+    ws_user_disconnected(&user_id, &connection_id, &users2);
     result
   }).map_err(move |e| {
     error!("WS: connect error uid={}: {}", &user_id, e);
   })
 }
 
+// Every command but `Authenticate` is answered with an `ActionResult` carrying
+// the message's `id`, `items` for `OlderItems` and `content` for
+// `GetItemContent`.
 pub fn ws_incoming_msg(
   claims: &Claims,
   msg: Message,
   users: &UserWebsocketState,
 ) -> Option<Message> {
+  // pings and binary frames
+  let text = msg.to_str().ok()?;
+  let message = match serde_json::from_str::<IncomingMessage>(text) {
+    Ok(message) => message,
+    Err(_) => {
+      error!("WS: could not parse {:?} as a IncomingMessage", msg);
+      return None;
+    }
+  };
+  if let IncomingMessageType::Authenticate = message.msg_type {
+    // only the first message of a socket opened without a token
+    return None;
+  }
+  let result = ws_command(claims, &message, users).map_err(|e| {
+    debug!("WS: {:?} of user {} failed: {}", message.msg_type, claims.id, e);
+    e.code().to_owned()
+  });
+  let reply = OutgoingWebsocketMessage::action_result(message.msg_type, message.id, result);
+  Some(reply.to_message())
+}
+
+fn ws_command(
+  claims: &Claims,
+  message: &IncomingMessage,
+  users: &UserWebsocketState,
+) -> Result<Option<ActionOutput>, HermesError> {
   let user_id = claims.id;
-  match serde_json::from_str::<IncomingMessage>(msg.to_str().unwrap()) {
-    Ok(message) => match message.msg_type {
-      IncomingMessageType::MarkRead => {
-        let data = message.data.parse::<i32>().unwrap();
-        info!("WS: user {} read item {}", user_id, data);
-        mark_subscribed_item_as_read(data);
-      }
-      IncomingMessageType::Subscribe => {
-        let data = serde_json::from_str::<SubscribeParams>(&message.data).unwrap();
-        info!("WS: user {} subscribed to {:?}", user_id, data);
-        feed::subscribe_feed(data, user_id, users.to_owned());
+  match message.msg_type {
+    IncomingMessageType::Authenticate => Ok(None),
+    IncomingMessageType::MarkRead => {
+      let item_id = message
+        .data
+        .parse::<i32>()
+        .map_err(|e| HermesError::Parse(e.to_string()))?;
+      let feed_id = mark_item_as_read(item_id, user_id)?.ok_or(HermesError::NotFound)?;
+      debug!("WS: user {} read item {}", user_id, item_id);
+      ws_send_unseen_counts(&user_id, &vec![feed_id], users);
+      Ok(None)
+    }
+    IncomingMessageType::Star => {
+      let data = parse::<StarParams>(&message.data)?;
+      match set_item_starred(data.item_id, user_id, data.starred)? {
+        0 => Err(HermesError::NotFound),
+        _ => Ok(None),
       }
-      IncomingMessageType::AddUser => {
-        let data = serde_json::from_str::<LoginParams>(&message.data).unwrap();
-        info!("WS: user {} added new user: {:?}", user_id, data);
-        let m = OutgoingWebsocketMessage::action_result(message.msg_type, true);
-        match super::handlers::add_user(&data, claims) {
-          Ok(_) => (),
-          Err(e) => (),
+    }
+    IncomingMessageType::Subscribe => {
+      let data = parse::<SubscribeParams>(&message.data)?;
+      info!("WS: user {} subscribed to {:?}", user_id, data);
+      // the feed and its items follow as `NewFeed` and `NewItems`
+      feed::subscribe_feed(data, user_id, users.to_owned());
+      Ok(None)
+    }
+    IncomingMessageType::Unsubscribe => {
+      let data = parse::<UnsubscribeParams>(&message.data)?;
+      match unsubscribe_feed(user_id, data.feed_id)? {
+        0 => Err(HermesError::NotFound),
+        _ => {
+          debug!("WS: user {} unsubscribed from {}", user_id, data.feed_id);
+          let detail = format!("feed {}", data.feed_id);
+          log_activity(user_id, Action::Unsubscribe, Some(&detail));
+          Ok(None)
         }
       }
-      IncomingMessageType::ChangePassword => {
-        let data = serde_json::from_str::<ChangePasswordParams>(&message.data).unwrap();
-        info!("WS: user {} changed password: {:?}", user_id, data);
-      }
-      IncomingMessageType::ChangeSettings => {
-        let data = serde_json::from_str::<SettingsData>(&message.data).unwrap();
-        info!("WS: user {} changed settings: {:?}", user_id, data);
-      }
-    },
-    Err(_) => error!("WS: could not parse {:?} as a IncomingMessage", msg),
-  };
-  None
+    }
+    IncomingMessageType::OlderItems => {
+      let data = parse::<OlderItemsParams>(&message.data)?;
+      let page = Page {
+        limit: data
+          .limit
+          .unwrap_or(Page::DEFAULT_LIMIT)
+          .max(1)
+          .min(Page::MAX_LIMIT),
+        offset: 0,
+      };
+      let items = get_subscribed_items(data.feed_id, user_id, data.before, page)?;
+      let composites = items
+        .iter()
+        .map(CompositeItem::from_subscribed)
+        .map(|item| match data.omit_content {
+          true => item.without_content(),
+          false => item,
+        }).collect();
+      Ok(Some(ActionOutput::Items(feed::with_enclosures(composites))))
+    }
+    IncomingMessageType::GetItemContent => {
+      let item_id = message
+        .data
+        .parse::<i32>()
+        .map_err(|e| HermesError::Parse(e.to_string()))?;
+      get_item_content(item_id, user_id).map(|content| Some(ActionOutput::Content(content)))
+    }
+    // through the API, which checks the current password or that the user is
+    // the admin
+    IncomingMessageType::AddUser
+    | IncomingMessageType::ChangePassword
+    | IncomingMessageType::ChangeSettings => {
+      Err(HermesError::Parse(format!("{:?} is not supported", message.msg_type)))
+    }
+  }
+}
+
+fn parse<T: DeserializeOwned>(data: &str) -> Result<T, HermesError> {
+  serde_json::from_str(data).map_err(|e| HermesError::Parse(e.to_string()))
 }
 
-pub fn ws_user_disconnected(user_id: &i32, users: &UserWebsocketState) {
+pub fn ws_user_disconnected(user_id: &i32, connection_id: &usize, users: &UserWebsocketState) {
   debug!("WS: user {} disconnected", user_id);
-  users.remove(user_id);
+  users.remove(user_id, connection_id);
 }
 
+// event streams are only noticed to be gone when sending to them fails
 pub fn ws_send_message(user_id: &i32, message: Message, state: &UserWebsocketState) {
-  match state.state.lock().unwrap().get_mut(user_id) {
-    Some(tx) => {
-      let _ = tx.start_send(message);
+  let mut state = state.state.lock().unwrap();
+  let gone = match state.get_mut(user_id) {
+    Some(connections) => {
+      connections.retain(|_, &mut (_, ref mut tx)| tx.send(message.clone()));
+      connections.is_empty()
     }
-    None => (),
+    None => false,
   };
+  if gone {
+    state.remove(user_id);
+  }
+}
+
+// tells the session's sockets why they go away, then closes them
+pub fn ws_close_session(
+  user_id: &i32,
+  session_id: &i32,
+  reason: &'static str,
+  state: &UserWebsocketState,
+) {
+  for mut tx in state.remove_session(user_id, session_id) {
+    let msg = OutgoingWebsocketMessage::session_revoked(reason);
+    tx.send(msg.to_message());
+    tx.send(Message::close());
+  }
+}
+
+// the counts view drops feeds without unseen items, so those report 0
+pub fn ws_send_unseen_counts(user_id: &i32, feed_ids: &Vec<i32>, state: &UserWebsocketState) {
+  for feed_id in feed_ids.iter() {
+    let count = match get_subscribed_feed(user_id, feed_id) {
+      Ok(feed) => feed.unseen_count,
+      Err(HermesError::NotFound) => 0,
+      Err(e) => {
+        error!("could not count unseen items of feed {}: {}", feed_id, e);
+        continue;
+      }
+    };
+    let msg = OutgoingWebsocketMessage::unseen_count(*feed_id, count);
+    ws_send_message(user_id, msg.to_message(), state);
+  }
 }
@@ -0,0 +1,95 @@
+use hyper::rt::{self, Future};
+use ring::{digest, hmac};
+use serde_json;
+
+use db;
+use deliveries::{self, Delivery};
+use feed::with_enclosures;
+use models::{CompositeItem, Item};
+
+// Outgoing webhooks: each new item of the feeds a webhook covers, all of its
+// user's without a `feed_id`, is POSTed to its URL as JSON, one item per
+// call. The body is signed with HMAC-SHA256 using the webhook's secret, in
+// hex as `X-Hermes-Signature: sha256=...`. Calls are retried and kept when
+// they fail by `deliveries`, and how each went is logged in
+// `webhook_deliveries` for LOG_RETENTION_DAYS.
+
+pub static LOG_RETENTION_DAYS: i64 = 14;
+static EVENT_NEW_ITEM: &'static str = "new_item";
+
+#[derive(Serialize)]
+struct Payload<'a> {
+  event: &'static str,
+  feed_id: i32,
+  item: &'a CompositeItem,
+}
+
+pub fn sign(secret: &str, payload: &str) -> String {
+  let key = hmac::SigningKey::new(&digest::SHA256, secret.as_bytes());
+  let signature = hmac::sign(&key, payload.as_bytes());
+  let hex = signature
+    .as_ref()
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect::<String>();
+  format!("sha256={}", hex)
+}
+
+// Calls the webhooks of the subscribers that cover the feed.
+pub fn notify_new(feed_id: i32, items: &[Item], subscribers: &[i32]) {
+  if items.is_empty() || subscribers.is_empty() {
+    return;
+  }
+  let webhooks = match db::get_feed_webhooks(feed_id, subscribers) {
+    Ok(webhooks) => webhooks,
+    Err(e) => {
+      error!("could not load the webhooks of feed {}: {}", feed_id, e);
+      return;
+    }
+  };
+  if webhooks.is_empty() {
+    return;
+  }
+  let composites = with_enclosures(items.iter().map(CompositeItem::from_item).collect());
+  for webhook in webhooks {
+    let secret = match db::open_webhook_secret(&webhook) {
+      Ok(secret) => secret,
+      Err(e) => {
+        error!("could not read the secret of webhook {}: {}", webhook.id, e);
+        continue;
+      }
+    };
+    for item in composites.iter() {
+      let payload = Payload {
+        event: EVENT_NEW_ITEM,
+        feed_id: feed_id,
+        item: item,
+      };
+      let payload = match serde_json::to_string(&payload) {
+        Ok(payload) => payload,
+        Err(e) => {
+          error!("could not serialize item {}: {}", item.id, e);
+          continue;
+        }
+      };
+      let delivery = Delivery {
+        kind: "webhook",
+        target: webhook.url.clone(),
+        headers: vec![
+          ("content-type".to_owned(), "application/json".to_owned()),
+          ("x-hermes-event".to_owned(), EVENT_NEW_ITEM.to_owned()),
+          ("x-hermes-signature".to_owned(), sign(&secret, &payload)),
+        ],
+        payload: payload,
+      };
+      let (webhook_id, item_id) = (webhook.id, item.id);
+      rt::spawn(
+        deliveries::deliver(webhook.user_id, delivery).map(move |outcome| {
+          if let Err(e) = db::insert_webhook_delivery(webhook_id, item_id, &outcome) {
+            error!("could not log delivery of webhook {}: {}", webhook_id, e);
+          }
+        }),
+      );
+    }
+  }
+}
@@ -0,0 +1,263 @@
+use chrono::{Duration as ChronoDuration, Utc};
+use futures::future::{self, Either};
+use hyper::rt::{self, Future};
+use ring::{digest, hmac};
+use std::collections::HashMap;
+use std::env;
+use url::form_urlencoded;
+
+use db;
+use deliveries;
+use error::HermesError;
+use feed::{self, FeedType};
+use models::WebsubSubscription;
+use paging;
+use web::handlers::random_token;
+
+// WebSub (formerly PubSubHubbub) subscriptions. A feed naming a hub, with an
+// `atom:link rel="hub"` like those of YouTube and Blogger, is subscribed to at
+// the hub when it's fetched. The hub confirms with a GET and then POSTs each
+// new version of the feed to `/websub/callback/:token`, signed with a secret
+// made for the subscription; those are stored like a fetch. Feeds a hub
+// pushes are still polled every POLL_SECS, in case pushes go missing.
+// Subscriptions are renewed a day before their lease runs out, and lapse
+// with it when their feed is gone.
+//
+// WEBSUB_CALLBACK_BASE  the instance's public URL, e.g. `https://hermes.example.com`,
+//                       which hubs must be able to reach; WebSub is off without it
+// WEBSUB_POLL_SECS      how often pushed feeds are polled, 21600 (6 hours) if unset
+
+static LEASE_SECS: i64 = 10 * 86400;
+static RENEW_BEFORE_DAYS: i64 = 1;
+static TOKEN_BYTES: usize = 24;
+static SECRET_BYTES: usize = 32;
+static HUB: &'static str = "hub";
+static SELF: &'static str = "self";
+
+lazy_static! {
+  static ref CALLBACK_BASE: Option<String> = env::var("WEBSUB_CALLBACK_BASE")
+    .ok()
+    .map(|base| base.trim_right_matches('/').to_owned());
+  pub static ref POLL_SECS: i64 = env::var("WEBSUB_POLL_SECS")
+    .ok()
+    .and_then(|v| v.parse().ok())
+    .unwrap_or(6 * 3600);
+}
+
+fn request(subscription: &WebsubSubscription) -> impl Future<Item = (), Error = HermesError> {
+  let base = match *CALLBACK_BASE {
+    Some(ref base) => base,
+    None => return Either::A(future::err(HermesError::NotFound)),
+  };
+  let secret = match db::open_websub_secret(subscription) {
+    Ok(secret) => secret,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let callback = format!("{}/websub/callback/{}", base, subscription.token);
+  let form = form_urlencoded::Serializer::new(String::new())
+    .append_pair("hub.mode", "subscribe")
+    .append_pair("hub.topic", &subscription.topic)
+    .append_pair("hub.callback", &callback)
+    .append_pair("hub.secret", &secret)
+    .append_pair("hub.lease_seconds", &LEASE_SECS.to_string())
+    .finish();
+  let headers = vec![(
+    "content-type".to_owned(),
+    "application/x-www-form-urlencoded".to_owned(),
+  )];
+  Either::B(deliveries::send(&subscription.hub, &headers, &form))
+}
+
+fn spawn_request(subscription: WebsubSubscription) {
+  let feed_id = subscription.feed_id;
+  rt::spawn(request(&subscription).then(move |requested| {
+    match requested {
+      Ok(_) => debug!("asked the hub of feed {} for updates", feed_id),
+      Err(e) => info!("could not subscribe to the hub of feed {}: {}", feed_id, e),
+    };
+    Ok::<(), ()>(())
+  }));
+}
+
+// Subscribes to the feed's hub, if it has one we aren't subscribed to yet.
+pub fn discover(feed_id: i32, data: &FeedType, url: &str) {
+  if CALLBACK_BASE.is_none() {
+    return;
+  }
+  let hub = match paging::link(data, url, HUB) {
+    Some(hub) => hub,
+    None => return,
+  };
+  let topic = paging::link(data, url, SELF).unwrap_or_else(|| url.to_owned());
+  match db::get_websub_subscription(feed_id) {
+    Ok(ref known) if known.hub == hub && known.topic == topic => return,
+    Ok(_) | Err(HermesError::NotFound) => (),
+    Err(e) => {
+      error!("could not load the WebSub subscription of feed {}: {}", feed_id, e);
+      return;
+    }
+  }
+  let keys = random_token(TOKEN_BYTES).and_then(|t| random_token(SECRET_BYTES).map(|s| (t, s)));
+  let (token, secret) = match keys {
+    Ok(keys) => keys,
+    Err(e) => {
+      error!("could not read random bytes: {}", e);
+      return;
+    }
+  };
+  match db::upsert_websub_subscription(feed_id, &hub, &topic, &token, &secret) {
+    Ok(subscription) => {
+      info!("feed {} has a WebSub hub at '{}'", feed_id, hub);
+      spawn_request(subscription);
+    }
+    Err(e) => error!("could not store the WebSub subscription of feed {}: {}", feed_id, e),
+  }
+}
+
+// renews the leases running out and asks again where the hub hasn't answered
+pub fn renew_due() {
+  if CALLBACK_BASE.is_none() {
+    return;
+  }
+  let now = Utc::now();
+  let due = db::get_due_websub_subscriptions(
+    now + ChronoDuration::days(RENEW_BEFORE_DAYS),
+    now - ChronoDuration::days(RENEW_BEFORE_DAYS),
+  );
+  let due = match due {
+    Ok(due) => due,
+    Err(e) => {
+      error!("could not load the WebSub subscriptions to renew: {}", e);
+      return;
+    }
+  };
+  for subscription in due {
+    if let Err(e) = db::touch_websub_request(subscription.feed_id) {
+      error!("could not renew the WebSub subscription of feed {}: {}", subscription.feed_id, e);
+      continue;
+    }
+    spawn_request(subscription);
+  }
+}
+
+// The hub checking that we asked for the subscription, answered with its
+// `hub.challenge`, or it telling us it won't have it.
+pub fn verify(token: &str, query: &HashMap<String, String>) -> Result<String, HermesError> {
+  let subscription = db::find_websub_subscription(token)?;
+  let param = |name: &str| query.get(name).map(|v| v.as_str());
+  if param("hub.topic") != Some(subscription.topic.as_str()) {
+    return Err(HermesError::NotFound);
+  }
+  match param("hub.mode") {
+    Some("subscribe") => {
+      let challenge = param("hub.challenge").ok_or(HermesError::NotFound)?;
+      let lease = param("hub.lease_seconds")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(LEASE_SECS);
+      db::confirm_websub_subscription(
+        subscription.feed_id,
+        Utc::now() + ChronoDuration::seconds(lease),
+      )?;
+      debug!("the hub of feed {} pushes for {}s", subscription.feed_id, lease);
+      Ok(challenge.to_owned())
+    }
+    Some("denied") => {
+      info!(
+        "the hub of feed {} denied the subscription: {}",
+        subscription.feed_id,
+        param("hub.reason").unwrap_or("no reason given")
+      );
+      db::delete_websub_subscription(subscription.feed_id)?;
+      Ok(String::new())
+    }
+    // we never unsubscribe
+    _ => Err(HermesError::NotFound),
+  }
+}
+
+// `X-Hub-Signature`, `<algorithm>=<hex HMAC of the body>`
+fn signed(secret: &str, signature: &str, body: &[u8]) -> bool {
+  let mut parts = signature.trim().splitn(2, '=');
+  let algorithm = match parts.next() {
+    Some("sha1") => &digest::SHA1,
+    Some("sha256") => &digest::SHA256,
+    Some("sha384") => &digest::SHA384,
+    Some("sha512") => &digest::SHA512,
+    _ => return false,
+  };
+  let hex = parts.next().unwrap_or("");
+  if hex.len() % 2 != 0 || !hex.is_ascii() {
+    return false;
+  }
+  let expected = (0..hex.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+    .collect::<Result<Vec<u8>, _>>();
+  match expected {
+    Ok(expected) => {
+      let key = hmac::SigningKey::new(algorithm, secret.as_bytes());
+      hmac::verify_with_own_key(&key, body, &expected).is_ok()
+    }
+    Err(_) => false,
+  }
+}
+
+// A new version of a feed from its hub. Unsigned or badly signed ones are
+// dropped, but still acknowledged, as WebSub asks.
+pub fn receive(
+  token: &str,
+  signature: Option<String>,
+  body: Vec<u8>,
+) -> impl Future<Item = (), Error = HermesError> {
+  let subscription = match db::find_websub_subscription(token) {
+    Ok(subscription) => subscription,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let secret = match db::open_websub_secret(&subscription) {
+    Ok(secret) => secret,
+    Err(e) => return Either::A(future::err(e)),
+  };
+  let feed_id = subscription.feed_id;
+  match signature {
+    Some(ref signature) if signed(&secret, signature, &body) => (),
+    _ => {
+      warn!("dropping a push for feed {} without a valid signature", feed_id);
+      return Either::A(future::ok(()));
+    }
+  }
+  Either::B(feed::receive_pushed(feed_id, &body).map(move |added| {
+    debug!("the hub of feed {} pushed {} new items", feed_id, added);
+  }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::signed;
+
+  static BODY: &'static [u8] = b"The quick brown fox jumps over the lazy dog";
+
+  #[test]
+  fn accepts_the_hmac_of_the_body() {
+    let sha256 = "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8";
+    assert!(signed("key", sha256, BODY));
+    assert!(signed("key", "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9", BODY));
+    assert!(signed("key", "sha1=DE7C9B85B8B78AA6BC8A7A36F70A90701C9DB4D9 ", BODY));
+  }
+
+  #[test]
+  fn rejects_other_secrets_and_bodies() {
+    let sha1 = "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9";
+    assert!(!signed("other key", sha1, BODY));
+    assert!(!signed("key", sha1, b"The quick brown fox jumps over the lazy cat"));
+  }
+
+  #[test]
+  fn rejects_malformed_signatures() {
+    assert!(!signed("key", "", BODY));
+    assert!(!signed("key", "de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9", BODY));
+    assert!(!signed("key", "md5=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9", BODY));
+    assert!(!signed("key", "sha1=de7c9b85b8b78aa6bc8a7a36f70a90701c9db4d", BODY));
+    assert!(!signed("key", "sha1=zz7c9b85b8b78aa6bc8a7a36f70a90701c9db4d9", BODY));
+    assert!(!signed("key", "sha1=", BODY));
+  }
+}
@@ -0,0 +1,112 @@
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use std::collections::{HashMap, HashSet};
+
+use db;
+use error::HermesError;
+
+// A subscription can keep its feed from being fetched outside of a window,
+// written like the hour and weekday fields of a cron line, in UTC:
+//
+//   9-17 mon-fri   business hours
+//   * sat,sun      weekends only
+//   22-6           nights, wrapping past midnight
+//
+// Each field is `*` or a comma separated list of values and ranges; weekdays
+// are names or 0-7, both 0 and 7 being Sunday, and are all of them when left
+// out. An hour range includes its last hour, so `9-17` ends at 17:59. The
+// scheduler skips a feed while the windows of all its subscriptions are
+// closed; refreshing it on request still works.
+
+static DAYS: [&'static str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+
+#[derive(Clone, Copy, Debug)]
+pub struct Window {
+  hours: u32,
+  days: u32,
+}
+impl Window {
+  // the window, and the way it is stored
+  pub fn parse(spec: &str) -> Result<(Window, String), HermesError> {
+    let spec = spec.trim().to_lowercase();
+    let fields: Vec<&str> = spec.split_whitespace().collect();
+    let (hours, days) = match fields.len() {
+      1 => (fields[0], "*"),
+      2 => (fields[0], fields[1]),
+      _ => return Err(invalid(&spec)),
+    };
+    let hour_bits = field(hours, 23, &[]).ok_or_else(|| invalid(&spec))?;
+    let day_bits = field(days, 7, &DAYS).ok_or_else(|| invalid(&spec))?;
+    let window = Window {
+      hours: hour_bits,
+      // 7 is another Sunday
+      days: (day_bits | day_bits >> 7) & 0x7f,
+    };
+    Ok((window, format!("{} {}", hours, days)))
+  }
+
+  pub fn contains(&self, at: DateTime<Utc>) -> bool {
+    (self.hours & 1 << at.hour()) != 0
+      && (self.days & 1 << at.weekday().num_days_from_sunday()) != 0
+  }
+}
+
+fn invalid(spec: &str) -> HermesError {
+  HermesError::Parse(format!("invalid refresh window '{}'", spec))
+}
+
+// a bit per value from 0 to max
+fn field(spec: &str, max: u32, names: &[&str]) -> Option<u32> {
+  let value = |v: &str| {
+    names
+      .iter()
+      .position(|n| *n == v)
+      .map(|p| p as u32)
+      .or_else(|| v.parse().ok())
+      .filter(|v| *v <= max)
+  };
+  let mut bits = 0;
+  for part in spec.split(',') {
+    let (first, last) = match (part, part.find('-')) {
+      ("*", _) => (0, max),
+      (_, Some(dash)) => (value(&part[..dash])?, value(&part[dash + 1..])?),
+      (_, None) => (value(part)?, value(part)?),
+    };
+    let mut v = first;
+    loop {
+      bits |= 1 << v;
+      if v == last {
+        break;
+      }
+      v = if v == max { 0 } else { v + 1 };
+    }
+  }
+  Some(bits)
+}
+
+// The windows of the feeds that have one on every subscription; feeds left out
+// are always fetched. Windows that don't parse anymore are left out too.
+pub fn load() -> HashMap<i32, Vec<Window>> {
+  let mut always = HashSet::new();
+  let mut windows: HashMap<i32, Vec<Window>> = HashMap::new();
+  let specs = db::get_refresh_windows().unwrap_or_else(|e| {
+    error!("could not load refresh windows: {}", e);
+    vec![]
+  });
+  for (feed_id, spec) in specs {
+    match spec.and_then(|s| Window::parse(&s).ok()) {
+      Some((window, _)) => windows.entry(feed_id).or_insert_with(Vec::new).push(window),
+      None => {
+        always.insert(feed_id);
+      }
+    }
+  }
+  windows.retain(|feed_id, _| !always.contains(feed_id));
+  windows
+}
+
+pub fn is_open(windows: &Option<Vec<Window>>, at: DateTime<Utc>) -> bool {
+  match *windows {
+    Some(ref windows) => windows.iter().any(|w| w.contains(at)),
+    None => true,
+  }
+}
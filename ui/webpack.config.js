@@ -38,7 +38,7 @@ module.exports = {
   },
   output: {
     path: path.resolve(__dirname, 'dist/static'),
-    filename: 'main.js',
+    filename: '[name].[contenthash:8].js',
     publicPath: '/'
   },
   plugins: [